@@ -1,6 +1,6 @@
 #!/bin/bash
 
-clawbang <<EOF
+clawbang - <<EOF
 fn main() {
   println!("hello world!");
 
@@ -0,0 +1,151 @@
+use crate::errors;
+use eyre::Result;
+use serde::Deserialize;
+use std::path::Path;
+use std::process::{ Command, Stdio };
+
+// A CVSS base score at least this high is critical.
+const CRITICAL: f64 = 9.0;
+
+#[derive(Deserialize)]
+struct Report {
+    vulnerabilities: Vulnerabilities,
+}
+
+#[derive(Deserialize)]
+struct Vulnerabilities {
+    list: Vec<Vulnerability>,
+}
+
+#[derive(Deserialize)]
+struct Vulnerability {
+    advisory: Advisory,
+    package: Package,
+}
+
+#[derive(Deserialize)]
+struct Advisory {
+    id: String,
+    title: String,
+    cvss: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Package {
+    name: String,
+    version: String,
+}
+
+/// Under `deny-advisories = true`: refuse to run a script whose dependencies, as its
+/// lockfile resolved them, have a critical RustSec advisory against them, by `cargo audit`.
+pub(crate) fn check(script_name: &str, cargo: &Path, lockfile: &Path) -> Result<()> {
+    if !lockfile.is_file() {
+        return Err(errors::ADVISORY.error(format!(
+            "deny-advisories is set, but there's no lockfile for {} to check; run it with --rebuild to resolve one here", script_name
+        )))
+    }
+    let _span = tracing::info_span!("advisories", lockfile = %lockfile.display()).entered();
+    let output = Command::new(cargo)
+        .args(["audit", "--json", "--file"])
+        .arg(lockfile)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|err| errors::ADVISORY.error(format!("Could not run cargo audit: {}", err)))?;
+    // it exits unsuccessfully when it finds anything, so the report is what says how it went
+    let report: Report = serde_json::from_slice(&output.stdout).map_err(|_| errors::ADVISORY.error(
+        "deny-advisories is set, but `cargo audit` didn't produce a report; is cargo-audit installed? (cargo install cargo-audit)"
+    ))?;
+
+    let critical: Vec<_> = report.vulnerabilities.list.iter()
+        .filter(|found| found.advisory.cvss.as_deref().and_then(base_score).is_some_and(|score| score >= CRITICAL))
+        .map(|found| format!("  {} in {} {}: {}", found.advisory.id, found.package.name, found.package.version, found.advisory.title))
+        .collect();
+    tracing::info!(found = report.vulnerabilities.list.len(), critical = critical.len(), "audited the lockfile");
+    if critical.is_empty() {
+        return Ok(())
+    }
+    Err(errors::ADVISORY.error(format!(
+        "{} depends on crates with critical advisories against them:\n{}\nUpdate them, or run it anyway with --allow-advisories",
+        script_name, critical.join("\n")
+    )))
+}
+
+// The base score of a CVSS 3.x vector, as the specification computes it; None for a vector
+// clawbang can't read.
+fn base_score(vector: &str) -> Option<f64> {
+    let metrics: Vec<_> = vector.strip_prefix("CVSS:3.")?.split('/').skip(1).filter_map(|metric| metric.split_once(':')).collect();
+    let get = |name| metrics.iter().find(|(metric, _)| *metric == name).map(|(_, value)| *value);
+    let changed = match get("S")? { "U" => false, "C" => true, _ => return None };
+    let attack_vector = match get("AV")? { "N" => 0.85, "A" => 0.62, "L" => 0.55, "P" => 0.2, _ => return None };
+    let complexity = match get("AC")? { "L" => 0.77, "H" => 0.44, _ => return None };
+    let privileges = match (get("PR")?, changed) {
+        ("N", _) => 0.85,
+        ("L", false) => 0.62,
+        ("L", true) => 0.68,
+        ("H", false) => 0.27,
+        ("H", true) => 0.5,
+        _ => return None,
+    };
+    let interaction = match get("UI")? { "N" => 0.85, "R" => 0.62, _ => return None };
+    let impact = |name| match get(name)? { "H" => Some(0.56), "L" => Some(0.22), "N" => Some(0.0), _ => None };
+    let severity = 1.0 - (1.0 - impact("C")?) * (1.0 - impact("I")?) * (1.0 - impact("A")?);
+
+    let impact = match changed {
+        false => 6.42 * severity,
+        true => 7.52 * (severity - 0.029) - 3.25 * (severity - 0.02f64).powi(15),
+    };
+    if impact <= 0.0 {
+        return Some(0.0)
+    }
+    let exploitability = 8.22 * attack_vector * complexity * privileges * interaction;
+    let score = match changed {
+        false => impact + exploitability,
+        true => 1.08 * (impact + exploitability),
+    };
+    Some(round_up(score.min(10.0)))
+}
+
+// Up to one decimal place, as CVSS 3.1 does it, without floating point's near misses.
+fn round_up(score: f64) -> f64 {
+    let scaled = (score * 100_000.0).round() as u64;
+    match scaled % 10_000 {
+        0 => scaled as f64 / 100_000.0,
+        _ => (scaled / 10_000 + 1) as f64 / 10.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scores_vectors_as_cvss_does() {
+        for (vector, score) in [
+            ("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H", 9.8),
+            ("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:C/C:H/I:H/A:H", 10.0),
+            ("CVSS:3.1/AV:L/AC:H/PR:H/UI:R/S:U/C:L/I:N/A:N", 1.8),
+            ("CVSS:3.1/AV:N/AC:L/PR:L/UI:N/S:C/C:L/I:L/A:N", 6.4),
+            ("CVSS:3.0/AV:A/AC:H/PR:L/UI:R/S:U/C:H/I:L/A:N", 5.1),
+            ("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:N/I:N/A:N", 0.0),
+        ] {
+            assert_eq!(base_score(vector), Some(score), "{}", vector);
+        }
+    }
+
+    #[test]
+    fn refuses_vectors_it_cant_score() {
+        for vector in ["AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H", "CVSS:2.0/AV:N/AC:L/Au:N/C:P/I:P/A:P", "CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:X/C:H/I:H/A:H", "CVSS:3.1/AV:N/AC:L"] {
+            assert_eq!(base_score(vector), None, "{}", vector);
+        }
+    }
+
+    #[test]
+    fn rounds_up_to_a_tenth() {
+        assert_eq!(round_up(4.0), 4.0);
+        assert_eq!(round_up(4.02), 4.1);
+        // floating point's 4.000000000000001 is still 4.0
+        assert_eq!(round_up(4.000_000_000_000_001), 4.0);
+        assert_eq!(round_up(9.81), 9.9);
+    }
+}
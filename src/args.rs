@@ -0,0 +1,408 @@
+use serde::Deserialize;
+use std::fmt::Write;
+
+/// The `[args]` frontmatter table: a declarative description of the script's command line,
+/// turned into a clap derive struct named `Args`.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct ArgsSpec {
+    name: Option<String>,
+    about: Option<String>,
+    version: Option<String>,
+    #[serde(default)]
+    positional: Vec<Arg>,
+    #[serde(default)]
+    option: Vec<Arg>,
+    #[serde(default)]
+    flag: Vec<Arg>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Arg {
+    name: String,
+    help: Option<String>,
+    short: Option<char>,
+    long: Option<String>,
+    #[serde(rename = "type")]
+    ty: Option<String>,
+    default: Option<String>,
+    #[serde(default)]
+    optional: bool,
+    #[serde(default)]
+    multiple: bool,
+}
+
+// the script's own `fn main(args: Args)` is renamed to this, and called from a generated main
+const SCRIPT_MAIN: &str = "clawbang_main";
+
+// Rust's keywords, strict and reserved, which a field can only be named as raw (`r#type`),
+// and the few that can't be raw either.
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let", "loop",
+    "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return", "static",
+    "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use", "virtual",
+    "where", "while", "yield",
+];
+const NOT_RAW: &[&str] = &["crate", "self", "Self", "super", "_"];
+
+impl ArgsSpec {
+    /// The clap dependency the generated code relies on.
+    pub(crate) fn dependency() -> toml::Value {
+        let mut dep = toml::value::Table::new();
+        dep.insert("version".to_string(), toml::Value::String("4".to_string()));
+        dep.insert("features".to_string(), toml::Value::Array(vec![toml::Value::String("derive".to_string())]));
+        toml::Value::Table(dep)
+    }
+
+    /// The completion generator `clawbang completions-for` builds the script with.
+    pub(crate) fn completions_dependency() -> toml::Value {
+        toml::Value::String("4".to_string())
+    }
+
+    /// Generate the `Args` struct (and, if the script's main wants it, the real `main`).
+    pub(crate) fn generate(&self, default_name: &str, wraps_main: bool) -> String {
+        let mut out = self.generate_struct(default_name);
+        if wraps_main {
+            let _ = writeln!(out);
+            let _ = writeln!(out, "fn main() -> impl std::process::Termination {{");
+            let _ = writeln!(out, "    {}(<Args as clap::Parser>::parse())", SCRIPT_MAIN);
+            let _ = writeln!(out, "}}");
+        }
+
+        out
+    }
+
+    /// For `clawbang completions-for`: the `Args` struct, and a `main` that prints the
+    /// completions for the shell it's given in place of running the script, whose own main
+    /// has been renamed if `renamed`.
+    pub(crate) fn generate_completions(&self, default_name: &str, renamed: bool) -> String {
+        let mut out = self.generate_struct(default_name);
+        let _ = writeln!(out);
+        let _ = writeln!(out, "fn main() {{");
+        if renamed {
+            let _ = writeln!(out, "    let _ = {};", SCRIPT_MAIN);
+        }
+        let _ = writeln!(out, "    let shell: clap_complete::Shell = std::env::args().nth(1).and_then(|shell| shell.parse().ok()).expect(\"a shell\");");
+        let _ = writeln!(out, "    clap_complete::generate(shell, &mut <Args as clap::CommandFactory>::command(), {:?}, &mut std::io::stdout());", self.command_name(default_name));
+        let _ = writeln!(out, "}}");
+        out
+    }
+
+    /// The command's name, as clap knows it: `name`, or the script's.
+    pub(crate) fn command_name<'a>(&'a self, default_name: &'a str) -> &'a str {
+        self.name.as_deref().unwrap_or(default_name)
+    }
+
+    pub(crate) fn about(&self) -> Option<&str> {
+        self.about.as_deref()
+    }
+
+    pub(crate) fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// Refuse an argument whose name couldn't be the field it's generated as. Dashes become
+    /// underscores, and keywords raw identifiers, so `my-flag` and `type` are fine.
+    pub(crate) fn check(&self) -> Result<(), String> {
+        match self.positional.iter().chain(&self.option).chain(&self.flag).find(|arg| arg.field().is_none()) {
+            Some(arg) => Err(format!(
+                "\"{}\" can't be an argument's name; names are letters, digits, '_', and '-', not starting with a digit",
+                arg.name
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn generate_struct(&self, default_name: &str) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out);
+        let _ = writeln!(out, "#[derive(clap::Parser, Debug)]");
+
+        let mut command = vec![format!("name = {:?}", self.command_name(default_name))];
+        if let Some(about) = &self.about {
+            command.push(format!("about = {:?}", about));
+        }
+        if let Some(version) = &self.version {
+            command.push(format!("version = {:?}", version));
+        }
+        let _ = writeln!(out, "#[command({})]", command.join(", "));
+        let _ = writeln!(out, "struct Args {{");
+
+        for arg in &self.positional {
+            arg.write_field(&mut out, Kind::Positional);
+        }
+        for arg in &self.option {
+            arg.write_field(&mut out, Kind::Option);
+        }
+        for arg in &self.flag {
+            arg.write_field(&mut out, Kind::Flag);
+        }
+
+        let _ = writeln!(out, "}}");
+        out
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Positional,
+    Option,
+    Flag,
+}
+
+impl Arg {
+    // The name as a struct field, if it can be one.
+    fn field(&self) -> Option<String> {
+        let field = self.name.replace('-', "_");
+        let mut chars = field.chars();
+        let starts = chars.next().is_some_and(|first| first.is_alphabetic() || first == '_');
+        if !starts || !chars.all(|c| c.is_alphanumeric() || c == '_') || NOT_RAW.contains(&field.as_str()) {
+            return None
+        }
+        match KEYWORDS.contains(&field.as_str()) {
+            true => Some(format!("r#{}", field)),
+            false => Some(field),
+        }
+    }
+
+    // as clap shows the value, from the field's name
+    fn value_name(&self) -> String {
+        self.name.replace('-', "_").to_uppercase()
+    }
+
+    fn write_help(&self, out: &mut String) {
+        let mut help = self.help.as_deref().map(roff).unwrap_or_default();
+        if let Some(default) = &self.default {
+            help = format!("{} [default: {}]", help, roff(default)).trim_start().to_string();
+        }
+        let _ = writeln!(out, "{}", help);
+    }
+
+    fn write_field(&self, out: &mut String, kind: Kind) {
+        let mut attrs = Vec::new();
+        if kind != Kind::Positional {
+            if let Some(short) = self.short {
+                attrs.push(format!("short = {:?}", short));
+            }
+            attrs.push(format!("long = {:?}", self.long.as_deref().unwrap_or(&self.name)));
+        }
+        if let Some(help) = &self.help {
+            attrs.push(format!("help = {:?}", help));
+        }
+        if let Some(default) = &self.default {
+            attrs.push(format!("default_value = {:?}", default));
+        }
+
+        let ty = match kind {
+            Kind::Flag => "bool".to_string(),
+            _ => {
+                let ty = self.ty.as_deref().unwrap_or("String");
+                if self.multiple {
+                    format!("Vec<{}>", ty)
+                } else if self.optional {
+                    format!("Option<{}>", ty)
+                } else {
+                    ty.to_string()
+                }
+            }
+        };
+
+        let _ = writeln!(out, "    #[arg({})]", attrs.join(", "));
+        // ArgsSpec::check has made sure there's a field name
+        let _ = writeln!(out, "    {}: {},", self.field().unwrap_or_default(), ty);
+    }
+}
+
+/// A man page for the command `name`, in roff: its synopsis and options as `args` describes
+/// them, or for a script without `[args]`, just what it's for.
+pub(crate) fn man_page(args: Option<&ArgsSpec>, name: &str, version: &str, summary: &str) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, ".TH {} 1 \"\" {} \"\"", roff(&name.to_uppercase()), roff(&format!("\"{} {}\"", name, version)));
+    let _ = writeln!(out, ".SH NAME");
+    let _ = writeln!(out, "{} \\- {}", roff(name), roff(summary));
+    let _ = writeln!(out, ".SH SYNOPSIS");
+    let _ = writeln!(out, ".B {}", roff(name));
+    let args = match args {
+        Some(args) => args,
+        None => {
+            let _ = writeln!(out, "[\\fIARGS\\fR]...");
+            return out
+        },
+    };
+    let mut synopsis = Vec::new();
+    if !args.option.is_empty() || !args.flag.is_empty() {
+        synopsis.push("[\\fIOPTIONS\\fR]".to_string());
+    }
+    for arg in &args.positional {
+        let value = format!("<\\fI{}\\fR>", roff(&arg.value_name()));
+        synopsis.push(match (arg.multiple, arg.optional || arg.default.is_some()) {
+            (true, _) => format!("[{}]...", value),
+            (false, true) => format!("[{}]", value),
+            (false, false) => value,
+        });
+    }
+    let _ = writeln!(out, "{}", synopsis.join(" "));
+    if let Some(about) = &args.about {
+        let _ = writeln!(out, ".SH DESCRIPTION");
+        let _ = writeln!(out, "{}", roff(about));
+    }
+    if !args.positional.is_empty() {
+        let _ = writeln!(out, ".SH ARGUMENTS");
+        for arg in &args.positional {
+            let _ = writeln!(out, ".TP");
+            let _ = writeln!(out, "<\\fI{}\\fR>", roff(&arg.value_name()));
+            arg.write_help(&mut out);
+        }
+    }
+    let _ = writeln!(out, ".SH OPTIONS");
+    for (arg, kind) in args.option.iter().map(|arg| (arg, Kind::Option)).chain(args.flag.iter().map(|arg| (arg, Kind::Flag))) {
+        let mut names = Vec::new();
+        if let Some(short) = arg.short {
+            names.push(format!("\\fB\\-{}\\fR", roff(&short.to_string())));
+        }
+        names.push(format!("\\fB\\-\\-{}\\fR", roff(arg.long.as_deref().unwrap_or(&arg.name))));
+        let mut line = names.join(", ");
+        if kind == Kind::Option {
+            line.push_str(&format!(" <\\fI{}\\fR>", roff(&arg.value_name())));
+        }
+        let _ = writeln!(out, ".TP");
+        let _ = writeln!(out, "{}", line);
+        arg.write_help(&mut out);
+    }
+    let _ = writeln!(out, ".TP");
+    let _ = writeln!(out, "\\fB\\-h\\fR, \\fB\\-\\-help\\fR");
+    let _ = writeln!(out, "Print help");
+    if args.version.is_some() {
+        let _ = writeln!(out, ".TP");
+        let _ = writeln!(out, "\\fB\\-V\\fR, \\fB\\-\\-version\\fR");
+        let _ = writeln!(out, "Print version");
+    }
+    out
+}
+
+// Text as roff takes it literally.
+fn roff(text: &str) -> String {
+    let escaped = text.replace('\\', "\\e").replace('-', "\\-").replace('\n', " ");
+    match escaped.starts_with(['.', '\'']) {
+        true => format!("\\&{}", escaped),
+        false => escaped,
+    }
+}
+
+/// If the script declares `fn main(args: Args)`, rename it so a generated `main` can parse
+/// the command line and hand it over. Returns whether a rename happened.
+pub(crate) fn wrap_main(main_rs: &mut String) -> bool {
+    rename_main(main_rs, true)
+}
+
+/// For `clawbang completions-for`: rename the script's main, whatever it takes, so the
+/// generated one stands in for it. Returns whether a rename happened.
+pub(crate) fn replace_main(main_rs: &mut String) -> bool {
+    rename_main(main_rs, false)
+}
+
+fn rename_main(main_rs: &mut String, needs_args: bool) -> bool {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let mut search = 0;
+    while let Some(idx) = main_rs[search..].find("fn").map(|idx| idx + search) {
+        search = idx + 1;
+        // `fn`, whitespace, `main`, and the `(` of its parameters, however they're spaced
+        let at_word_start = main_rs[..idx].chars().next_back().is_none_or(|c| !is_ident(c));
+        let rest = &main_rs[idx + 2..];
+        let name = rest.trim_start();
+        if !at_word_start || name.len() == rest.len() || !name.starts_with("main") {
+            continue
+        }
+        let Some(params) = name["main".len()..].trim_start().strip_prefix('(') else {
+            continue
+        };
+        let takes_args = !params.trim_start().starts_with(')');
+        if takes_args || !needs_args {
+            let start = main_rs.len() - name.len();
+            main_rs.replace_range(start..start + "main".len(), SCRIPT_MAIN);
+            return true
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(frontmatter: &str) -> ArgsSpec {
+        toml::from_str(frontmatter).unwrap()
+    }
+
+    #[test]
+    fn names_fields_after_arguments_whatever_they_are() {
+        let spec = spec(r#"
+            positional = [{ name = "match" }]
+            option = [{ name = "type" }, { name = "my-flag", short = "m" }]
+            flag = [{ name = "dry_run" }, { name = "naïve" }]
+        "#);
+        assert_eq!(spec.check(), Ok(()));
+        let generated = spec.generate_struct("tool");
+        for field in [
+            "    #[arg()]\n    r#match: String,",
+            "    #[arg(long = \"type\")]\n    r#type: String,",
+            "    #[arg(short = 'm', long = \"my-flag\")]\n    my_flag: String,",
+            "    #[arg(long = \"dry_run\")]\n    dry_run: bool,",
+            "    #[arg(long = \"naïve\")]\n    naïve: bool,",
+        ] {
+            assert!(generated.contains(field), "{}\nin\n{}", field, generated);
+        }
+    }
+
+    #[test]
+    fn refuses_names_that_cant_be_fields() {
+        for name in ["my flag", "1st", "self", "_", "-", "", "a.b"] {
+            let spec = spec(&format!("option = [{{ name = {:?} }}]", name));
+            assert_eq!(
+                spec.check(),
+                Err(format!("\"{}\" can't be an argument's name; names are letters, digits, '_', and '-', not starting with a digit", name)),
+            );
+        }
+    }
+
+    #[test]
+    fn wraps_main_taking_args_however_spaced() {
+        for (script, renamed) in [
+            ("fn main(args: Args) {}", "fn clawbang_main(args: Args) {}"),
+            ("fn main (args: Args) {}", "fn clawbang_main (args: Args) {}"),
+            ("pub fn  main\n(args: Args) {}", "pub fn  clawbang_main\n(args: Args) {}"),
+        ] {
+            let mut main_rs = script.to_string();
+            assert!(wrap_main(&mut main_rs), "{:?}", script);
+            assert_eq!(main_rs, renamed);
+        }
+    }
+
+    #[test]
+    fn leaves_main_without_args_to_run_as_is() {
+        for script in ["fn main() {}", "fn main () {}", "fn main( ) {}"] {
+            let mut main_rs = script.to_string();
+            assert!(!wrap_main(&mut main_rs));
+            assert_eq!(main_rs, script);
+        }
+    }
+
+    #[test]
+    fn replaces_main_whatever_it_takes() {
+        for (script, renamed) in [("fn main() {}", "fn clawbang_main() {}"), ("fn main () {}", "fn clawbang_main () {}")] {
+            let mut main_rs = script.to_string();
+            assert!(replace_main(&mut main_rs));
+            assert_eq!(main_rs, renamed);
+        }
+    }
+
+    #[test]
+    fn ignores_what_only_looks_like_main() {
+        for script in ["fn mainly() {}", "fn main_loop() {}", "fnmain() {}", "xfn main() {}", "// fn main"] {
+            let mut main_rs = script.to_string();
+            assert!(!replace_main(&mut main_rs), "{:?}", script);
+        }
+    }
+}
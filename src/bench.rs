@@ -0,0 +1,88 @@
+use eyre::Result;
+use crate::profile::seconds;
+use std::process::{ Command, Stdio };
+use std::time::{ Duration, Instant };
+
+/// `clawbang bench-run`: the usual options, plus how many times to run the script.
+#[derive(clap::Args)]
+pub(crate) struct BenchOptions {
+    /// How many timed runs to make
+    #[clap(long, default_value = "10")]
+    pub(crate) runs: usize,
+
+    /// How many untimed runs to make first, to warm the disk cache
+    #[clap(long, default_value = "1")]
+    pub(crate) warmup: usize,
+}
+
+/// Run the built script `runs` times with its output discarded, then print the wall-clock
+/// time (and on Unix, the CPU time) each run took. Every run has to succeed; a failure
+/// ends the benchmark with the script's status.
+pub(crate) fn run(mut command: Command, script_name: &str, bench: &BenchOptions) -> Result<()> {
+    if bench.runs == 0 {
+        return Err(eyre::eyre!("--runs must be at least 1"))
+    }
+    command.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+
+    for _ in 0..bench.warmup {
+        once(&mut command, script_name)?;
+    }
+
+    let mut wall = Vec::with_capacity(bench.runs);
+    let mut cpu = Vec::with_capacity(bench.runs);
+    for _ in 0..bench.runs {
+        let (elapsed, cpu_time) = once(&mut command, script_name)?;
+        wall.push(elapsed);
+        cpu.extend(cpu_time);
+    }
+
+    println!("{}: {} runs", script_name, bench.runs);
+    println!("  wall  {}", summary(&wall));
+    if !cpu.is_empty() {
+        println!("  cpu   {}", summary(&cpu));
+    }
+    Ok(())
+}
+
+// One run: its wall-clock time and, where we can measure it, its user plus system time.
+fn once(command: &mut Command, script_name: &str) -> Result<(Duration, Option<Duration>)> {
+    let cpu_before = children_cpu();
+    let started = Instant::now();
+    let status = command.status()?;
+    let elapsed = started.elapsed();
+    if !status.success() {
+        eprintln!("{} {} failed while being benchmarked ({})", crate::color::label("error"), script_name, status);
+        std::process::exit(status.code().unwrap_or(1));
+    }
+    let cpu = cpu_before.zip(children_cpu()).map(|(before, after)| after.saturating_sub(before));
+    Ok((elapsed, cpu))
+}
+
+fn summary(samples: &[Duration]) -> String {
+    let secs: Vec<f64> = samples.iter().map(Duration::as_secs_f64).collect();
+    let mean = secs.iter().sum::<f64>() / secs.len() as f64;
+    // the sample standard deviation; a single run has none
+    let stddev = match secs.len() {
+        1 => 0.0,
+        len => (secs.iter().map(|secs| (secs - mean).powi(2)).sum::<f64>() / (len - 1) as f64).sqrt(),
+    };
+    let min = secs.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = secs.iter().cloned().fold(0.0, f64::max);
+    format!("mean {} ± {}   min {}   max {}", seconds(mean), seconds(stddev), seconds(min), seconds(max))
+}
+
+// The total CPU time of every child we've waited for so far.
+#[cfg(unix)]
+fn children_cpu() -> Option<Duration> {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage) } != 0 {
+        return None
+    }
+    let time = |time: libc::timeval| Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64);
+    Some(time(usage.ru_utime) + time(usage.ru_stime))
+}
+
+#[cfg(not(unix))]
+fn children_cpu() -> Option<Duration> {
+    None
+}
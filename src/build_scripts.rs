@@ -0,0 +1,45 @@
+use crate::errors;
+use crate::metadata::Metadata;
+use eyre::Result;
+use serde::Deserialize;
+
+/// The settings file's `[build-scripts]`: whether scripts' dependencies may run code of
+/// their own while they build, as build scripts and proc macros do, and which may anyway.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Policy {
+    #[serde(default)]
+    pub(crate) deny: bool,
+    #[serde(default)]
+    pub(crate) allow: Vec<String>,
+}
+
+/// Refuse to build a script when any of the dependencies it'd build has a build script or
+/// is a proc macro, unless it's in `allow`. Dependencies for other platforms were left out
+/// when they were resolved, and dev-dependencies aren't built.
+pub(crate) fn check(script_name: &str, metadata: &Metadata, allow: &[String]) -> Result<()> {
+    let built = metadata.built();
+
+    let mut refused: Vec<_> = metadata.packages.iter()
+        .filter(|package| built.contains(package.id.as_str()) && !allow.contains(&package.name))
+        .filter_map(|package| {
+            let runs = match (package.has_target("custom-build"), package.has_target("proc-macro")) {
+                (true, true) => "a build script, and is a proc macro",
+                (true, false) => "a build script",
+                (false, true) => "a proc macro",
+                (false, false) => return None,
+            };
+            Some(format!("  {} {}: {}", package.name, package.version, runs))
+        })
+        .collect();
+    tracing::info!(packages = built.len(), refused = refused.len(), "checked dependencies for build scripts");
+    if refused.is_empty() {
+        return Ok(())
+    }
+    refused.sort();
+    Err(errors::BUILD_SCRIPT.error(format!(
+        "{} has dependencies that would run their own code as it builds, which isn't allowed:\n{}\n\
+         Allow them by name under [build-scripts] in the settings file, with `allow = [...]`",
+        script_name, refused.join("\n")
+    )))
+}
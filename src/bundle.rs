@@ -0,0 +1,260 @@
+use crate::{ Project, manifest };
+use eyre::Result;
+use sha2::{ Digest, Sha256 };
+use std::io::{ BufRead, Write };
+use std::path::{ Path, PathBuf };
+use subprocess::Exec;
+
+/// `clawbang bundle-multi`: several scripts built into one binary, busybox-style.
+#[derive(clap::Args)]
+pub(crate) struct BundleOptions {
+    /// The scripts to bundle, as files or directories; each becomes a tool named after it
+    #[clap(required = true, parse(from_os_str))]
+    scripts: Vec<PathBuf>,
+
+    /// Where to write the bundled binary
+    #[clap(short, long, parse(from_os_str))]
+    output: PathBuf,
+
+    #[clap(flatten)]
+    cache: crate::CacheOptions,
+}
+
+// Package sections that hold one entry per crate, where scripts may each add their own.
+const PER_CRATE: &[&str] = &["dependencies", "build-dependencies", "dev-dependencies", "patch", "replace"];
+
+/// A script read from disk, with its source normalized and ready to split.
+struct Source {
+    path: PathBuf,
+    tree: Option<PathBuf>,
+    name: String,
+    text: String,
+    data: Option<Vec<u8>>,
+}
+
+/// Build every script into a single binary that picks which one to run from the name it was
+/// invoked under (so it can be symlinked as each tool) or, failing that, its first argument.
+///
+/// The scripts share one cargo project, so their dependencies have to agree; frontmatter that
+/// only clawbang acts on at run time, like `[env]` and `[limits]`, doesn't carry over.
+pub(crate) fn run(opts: &BundleOptions) -> Result<()> {
+    let config = crate::config::load(opts.cache.config.as_deref())?;
+
+    let mut sources = Vec::new();
+    for path in &opts.scripts {
+        let tree = path.is_dir().then(|| path.clone());
+        let script_path = match &tree {
+            Some(tree) => tree.join("main.rs"),
+            None => path.clone(),
+        };
+        let name = path.canonicalize()?.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        if sources.iter().any(|source: &Source| source.name == name) {
+            return Err(eyre::eyre!("Two of the scripts would both be called \"{}\"; rename one", name))
+        }
+        let input = std::fs::read(&script_path)
+            .map_err(|err| eyre::eyre!("Could not read {}: {}", script_path.display(), err))?;
+        let (text, data) = manifest::split_data(&input);
+        let text = std::str::from_utf8(text).map_err(|err| eyre::eyre!(
+            "{} isn't valid UTF-8 (at byte {}); only its __DATA__ section may hold other bytes", script_path.display(), err.valid_up_to()
+        ))?;
+        sources.push(Source { text: manifest::normalize(text).into_owned(), data: data.map(<[u8]>::to_vec), path: script_path, tree, name });
+    }
+
+    let mut projects = Vec::new();
+    for source in &sources {
+        let script_name = source.path.to_string_lossy();
+        let mut script = manifest::split_script(&source.text)
+            .map_err(|err| crate::errors::FRONTMATTER.error(err.render(&source.text, &script_name)))?;
+        script.data = source.data.as_deref();
+        let manifest = manifest::parse(&script, &config.defaults)
+            .map_err(|err| crate::errors::FRONTMATTER.error(err.render(&source.text, &script_name)))?;
+        for warning in &manifest.warnings {
+            eprintln!("{} {}: {}", crate::color::label("warning"), script_name, warning);
+        }
+        if !manifest.env.is_empty() || !manifest.env_files.is_empty() || manifest.limits.is_some() || manifest.cwd != manifest::WorkingDir::Caller {
+            eprintln!("{} {}: [env], env-files, [limits], and cwd are applied by clawbang at run time, so they don't carry over into a bundle", crate::color::label("warning"), script_name);
+        }
+
+        let script_dir = source.path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let mut includes = crate::collect_includes(Some(&script_dir), &script, &manifest)?;
+        if let Some(tree) = &source.tree {
+            crate::collect_tree(tree, &mut includes)?;
+        }
+        projects.push(Project {
+            name: source.name.clone(),
+            file_name: source.path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+            source: &source.text,
+            wrap: None,
+            script,
+            manifest,
+            includes,
+            incremental: false,
+            completions: false,
+            build_scripts: None,
+            secrets: Vec::new(),
+            hooked: None,
+        });
+    }
+
+    let mut cargo = toml::value::Table::new();
+    for (project, source) in projects.iter().zip(&sources) {
+        if projects[0].manifest.rustflags != project.manifest.rustflags {
+            return Err(eyre::eyre!("{} sets different [build] rustflags from {}", source.path.display(), sources[0].path.display()))
+        }
+        let table = project.manifest.cargo.as_table().cloned().unwrap_or_default();
+        merge(&mut cargo, table, &source.path)?;
+    }
+
+    let tempdir = tempfile::tempdir()?;
+    std::fs::write(tempdir.path().join("Cargo.toml"), toml::to_string_pretty(&toml::Value::Table(cargo))?)?;
+    let src = tempdir.path().join("src");
+    for (idx, project) in projects.iter().enumerate() {
+        let tool = src.join(format!("tool_{}", idx));
+        crate::write_sources(&tool, project)?;
+        // the script's main is private to its module, so it's reached through an entry point
+        let mut main_rs = std::fs::OpenOptions::new().append(true).open(tool.join("main.rs"))?;
+        main_rs.write_all(b"\npub(crate) fn __clawbang_entry() -> std::process::ExitCode {\n    std::process::Termination::report(main())\n}\n")?;
+    }
+    std::fs::write(src.join("main.rs"), dispatcher(&projects))?;
+
+    // kept between runs, so rebuilding a bundle only compiles what changed
+    let target = crate::absolute(&opts.cache.cache_dir).join("bundle")
+        .join(&hex::encode(Sha256::digest(crate::absolute(&opts.output).as_os_str().as_encoded_bytes()))[..16]);
+    let mut cargo = Exec::cmd(crate::find_cargo()?).env("CARGO_TARGET_DIR", &target);
+    if !projects[0].manifest.rustflags.is_empty() {
+        cargo = cargo.env("CARGO_ENCODED_RUSTFLAGS", projects[0].manifest.rustflags.join("\x1f"));
+    }
+    let mut popen = cargo
+        .args(&["--color", "always", "build", "--release", "--message-format", "json-diagnostic-rendered-ansi"])
+        .stdout(subprocess::Redirection::Pipe)
+        .stderr(subprocess::Redirection::Merge)
+        .cwd(tempdir.path())
+        .popen()?;
+    let mut output = Vec::new();
+    if let Some(stdout) = popen.stdout.take() {
+        for line in std::io::BufReader::new(stdout).split(b'\n') {
+            output.extend(crate::diagnostics::render_cargo_line(&line?));
+        }
+    }
+    if !popen.wait()?.success() {
+        // line and column numbers already match the scripts; only the paths need fixing
+        let mut output = String::from_utf8_lossy(&output).into_owned();
+        if !crate::color::stderr_enabled() {
+            output = crate::color::strip(&output);
+        }
+        for (idx, source) in sources.iter().enumerate() {
+            output = output.replace(&format!("src/tool_{}/main.rs:", idx), &format!("{}:", source.path.display()));
+        }
+        std::io::stderr().write_all(output.as_bytes())?;
+        return Err(eyre::eyre!("Could not build the bundle"))
+    }
+
+    let binary = target.join("release").join(format!("bin{}", std::env::consts::EXE_SUFFIX));
+    std::fs::copy(&binary, &opts.output)
+        .map_err(|err| eyre::eyre!("Could not write {}: {}", opts.output.display(), err))?;
+    let names: Vec<&str> = projects.iter().map(|project| project.name.as_str()).collect();
+    eprintln!("bundled {} into {}", names.join(", "), opts.output.display());
+    Ok(())
+}
+
+// Fold one script's package settings into the bundle's. Dependency tables are combined entry
+// by entry; everything else has to match whatever an earlier script set.
+fn merge(into: &mut toml::value::Table, from: toml::value::Table, script: &Path) -> Result<()> {
+    for (key, value) in from {
+        match (into.get_mut(&key), value) {
+            (None, value) => {
+                into.insert(key, value);
+            },
+            (Some(toml::Value::Table(existing)), toml::Value::Table(entries)) if PER_CRATE.contains(&key.as_str()) => {
+                for (name, spec) in entries {
+                    match existing.get(&name) {
+                        Some(other) if *other != spec => return Err(eyre::eyre!(
+                            "{} asks for {} = {}, but an earlier script asks for {}; the scripts in a bundle have to agree",
+                            script.display(), name, spec, other
+                        )),
+                        Some(_) => {},
+                        None => {
+                            existing.insert(name, spec);
+                        },
+                    }
+                }
+            },
+            (Some(existing), value) if *existing == value => {},
+            (Some(_), _) => return Err(eyre::eyre!(
+                "{} sets [{}] differently from an earlier script; the scripts in a bundle have to agree",
+                script.display(), key
+            )),
+        }
+    }
+    Ok(())
+}
+
+// The bundle's own main: find the tool by the name the binary was run as, or its first
+// argument. In the latter case the binary starts over as that tool, so the tool sees the
+// command line it would have as a binary of its own.
+fn dispatcher(projects: &[Project]) -> String {
+    let mut main_rs = String::new();
+    for idx in 0..projects.len() {
+        main_rs.push_str(&format!("#[path = \"tool_{0}/main.rs\"]\nmod tool_{0};\n", idx));
+    }
+    let names: Vec<String> = projects.iter().map(|project| format!("{:?}", project.name)).collect();
+    let arms: String = projects.iter().enumerate()
+        .map(|(idx, project)| format!("        {:?} => return tool_{}::__clawbang_entry(),\n", project.name, idx))
+        .collect();
+
+    main_rs.push_str(&format!(r#"
+const TOOLS: &[&str] = &[{names}];
+
+fn main() -> std::process::ExitCode {{
+    let invoked = std::env::args_os().next()
+        .and_then(|arg0| std::path::Path::new(&arg0).file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+    #[cfg(not(unix))]
+    let invoked = match std::env::var("CLAWBANG_BUNDLE_TOOL") {{
+        Ok(tool) => {{
+            #[allow(unused_unsafe)]
+            unsafe {{ std::env::remove_var("CLAWBANG_BUNDLE_TOOL") }};
+            tool
+        }},
+        Err(_) => invoked,
+    }};
+    match invoked.as_str() {{
+{arms}        _ => {{}},
+    }}
+
+    let mut args = std::env::args_os().skip(1);
+    let tool = match args.next() {{
+        Some(tool) if TOOLS.contains(&&*tool.to_string_lossy()) => tool,
+        _ => {{
+            eprintln!("usage: {{}} <tool> [args...]\n\ntools: {{}}", invoked, TOOLS.join(", "));
+            return std::process::ExitCode::from(2)
+        }},
+    }};
+    let exe = match std::env::current_exe() {{
+        Ok(exe) => exe,
+        Err(err) => {{
+            eprintln!("error: could not find this executable to restart it: {{}}", err);
+            return std::process::ExitCode::from(126)
+        }},
+    }};
+    let mut command = std::process::Command::new(exe);
+    command.args(args);
+    #[cfg(unix)]
+    {{
+        use std::os::unix::process::CommandExt;
+        let err = command.arg0(&tool).exec();
+        eprintln!("error: could not start {{}}: {{}}", tool.to_string_lossy(), err);
+        std::process::ExitCode::from(126)
+    }}
+    #[cfg(not(unix))]
+    match command.env("CLAWBANG_BUNDLE_TOOL", &tool).status() {{
+        Ok(status) => std::process::ExitCode::from(status.code().unwrap_or(1) as u8),
+        Err(err) => {{
+            eprintln!("error: could not start {{}}: {{}}", tool.to_string_lossy(), err);
+            std::process::ExitCode::from(126)
+        }},
+    }}
+}}
+"#, names = names.join(", "), arms = arms));
+    main_rs
+}
@@ -0,0 +1,145 @@
+/// Evaluate a Cargo-style `cfg(...)` target key against the host clawbang is running on.
+/// Returns `None` if the expression can't be parsed.
+pub(crate) fn matches_host(key: &str) -> Option<bool> {
+    let inner = key.trim().strip_prefix("cfg(")?.strip_suffix(')')?;
+    let mut parser = Parser { rest: inner };
+    let result = parser.expr()?;
+    parser.skip_ws();
+    parser.rest.is_empty().then_some(result)
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_ws(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_ws();
+        match self.rest.strip_prefix(token) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            },
+            None => false
+        }
+    }
+
+    fn ident(&mut self) -> Option<&'a str> {
+        self.skip_ws();
+        let end = self.rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(self.rest.len());
+        if end == 0 {
+            return None
+        }
+        let (ident, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Some(ident)
+    }
+
+    fn string(&mut self) -> Option<&'a str> {
+        self.skip_ws();
+        let rest = self.rest.strip_prefix('"')?;
+        let end = rest.find('"')?;
+        self.rest = &rest[end + 1..];
+        Some(&rest[..end])
+    }
+
+    fn list(&mut self) -> Option<Vec<bool>> {
+        if !self.eat("(") {
+            return None
+        }
+        let mut items = Vec::new();
+        loop {
+            if self.eat(")") {
+                return Some(items)
+            }
+            items.push(self.expr()?);
+            if !self.eat(",") {
+                return self.eat(")").then_some(items)
+            }
+        }
+    }
+
+    fn expr(&mut self) -> Option<bool> {
+        let ident = self.ident()?;
+        match ident {
+            "all" => Some(self.list()?.into_iter().all(|item| item)),
+            "any" => Some(self.list()?.into_iter().any(|item| item)),
+            "not" => {
+                let items = self.list()?;
+                (items.len() == 1).then(|| !items[0])
+            },
+            _ if self.eat("=") => {
+                let value = self.string()?;
+                Some(host_value(ident).contains(&value))
+            },
+            "unix" => Some(cfg!(unix)),
+            "windows" => Some(cfg!(windows)),
+            _ => Some(false),
+        }
+    }
+}
+
+fn host_value(name: &str) -> Vec<&'static str> {
+    match name {
+        "target_os" => vec![std::env::consts::OS],
+        "target_family" => vec![std::env::consts::FAMILY],
+        "target_arch" => vec![std::env::consts::ARCH],
+        "target_pointer_width" => vec![if cfg!(target_pointer_width = "64") { "64" } else { "32" }],
+        "target_endian" => vec![if cfg!(target_endian = "little") { "little" } else { "big" }],
+        "target_env" => vec![
+            if cfg!(target_env = "gnu") { "gnu" }
+            else if cfg!(target_env = "musl") { "musl" }
+            else if cfg!(target_env = "msvc") { "msvc" }
+            else { "" }
+        ],
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_value_predicates_match_the_host() {
+        let os = std::env::consts::OS;
+        assert_eq!(matches_host(&format!("cfg(target_os = \"{}\")", os)), Some(true));
+        assert_eq!(matches_host("cfg(target_os = \"plan9\")"), Some(false));
+        assert_eq!(matches_host(&format!("cfg(target_arch=\"{}\")", std::env::consts::ARCH)), Some(true));
+        // keys it doesn't know of match nothing
+        assert_eq!(matches_host("cfg(target_vendor = \"apple\")"), Some(false));
+    }
+
+    #[test]
+    fn bare_predicates() {
+        assert_eq!(matches_host("cfg(unix)"), Some(cfg!(unix)));
+        assert_eq!(matches_host("cfg(windows)"), Some(cfg!(windows)));
+        assert_eq!(matches_host("cfg(test)"), Some(false));
+    }
+
+    #[test]
+    fn all_any_and_not_nest() {
+        let os = std::env::consts::OS;
+        assert_eq!(matches_host("cfg(any(unix, windows))"), Some(true));
+        assert_eq!(matches_host("cfg(all(unix, windows))"), Some(false));
+        assert_eq!(matches_host("cfg(not(all(unix, windows)))"), Some(true));
+        assert_eq!(matches_host(&format!("cfg(all(not(target_os = \"plan9\"), any(target_os = \"{}\", windows)))", os)), Some(true));
+        assert_eq!(matches_host(" cfg( any( ) ) "), Some(false));
+        assert_eq!(matches_host("cfg(all())"), Some(true));
+        assert_eq!(matches_host("cfg(any(unix, windows,))"), Some(true));
+    }
+
+    #[test]
+    fn malformed_expressions_are_none() {
+        for key in [
+            "x86_64-unknown-linux-gnu", "cfg(", "cfg()", "cfg(unix", "cfg(unix windows)", "cfg(not(unix, windows))",
+            "cfg(target_os = linux)", "cfg(target_os = \"linux)", "cfg(any(unix)", "cfg(all(unix), )",
+        ] {
+            assert_eq!(matches_host(key), None, "{}", key);
+        }
+    }
+}
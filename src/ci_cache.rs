@@ -0,0 +1,239 @@
+use crate::tar::{ self, Tar };
+use eyre::Result;
+use serde_json::json;
+use sha2::{ Digest, Sha256 };
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::Read;
+use std::path::PathBuf;
+
+// What the GitHub Actions cache knows clawbang's archives by, beside their keys; a new
+// layout would need a new one.
+const VERSION: &str = "clawbang ci-cache 1";
+
+/// `clawbang ci-cache`: keep scripts' builds from one CI run to the next.
+#[derive(clap::Args)]
+pub(crate) struct CiCacheOptions {
+    /// key (what to cache the builds under), paths (what to persist of the cache directory),
+    /// restore or save (the builds, by way of the GitHub Actions cache)
+    action: Action,
+
+    /// The scripts whose builds to keep: files, directories, URLs, or git references
+    #[clap(parse(from_os_str))]
+    scripts: Vec<PathBuf>,
+
+    #[clap(flatten)]
+    cache: crate::CacheOptions,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Action {
+    Key,
+    Paths,
+    Restore,
+    Save,
+}
+
+impl std::str::FromStr for Action {
+    type Err = eyre::Report;
+
+    fn from_str(action: &str) -> Result<Self> {
+        match action {
+            "key" => Ok(Action::Key),
+            "paths" => Ok(Action::Paths),
+            "restore" => Ok(Action::Restore),
+            "save" => Ok(Action::Save),
+            _ => Err(eyre::eyre!("Unknown action {:?}; expected key, paths, restore, or save", action)),
+        }
+    }
+}
+
+// A script as the cache knows it.
+struct Script {
+    name: String,
+    opts: crate::Options,
+    cache_key: String,
+    lockfile: PathBuf,
+}
+
+/// Print or act on what keeps the scripts' builds between CI runs. The key changes whenever
+/// one of their cache keys does; restoring falls back to the newest archive saved on this
+/// OS and architecture, which still has the builds of whichever scripts haven't changed.
+pub(crate) fn run(ci: &CiCacheOptions) -> Result<()> {
+    let cache_dir = crate::absolute(&ci.cache.cache_dir);
+    if ci.action == Action::Paths {
+        // the builds and the lockfiles for them; the rest is cheap to make again, or local
+        for dir in ["content-v2", "index-v5", "lockfiles"] {
+            println!("{}", cache_dir.join(dir).display());
+        }
+        return Ok(())
+    }
+    if ci.scripts.is_empty() {
+        return Err(eyre::eyre!("Name the scripts whose builds to keep, as in `clawbang ci-cache key scripts/*.rs`"))
+    }
+    let config = crate::config::load(ci.cache.config.as_deref())?;
+    let service = match ci.action {
+        Action::Restore | Action::Save => Some(Service::from_env()?),
+        _ => None,
+    };
+
+    let mut scripts = Vec::new();
+    for script in &ci.scripts {
+        let mut args: Vec<OsString> = vec!["clawbang".into(), "--build-only".into(), "--cache-dir".into(), cache_dir.clone().into()];
+        if let Some(config) = &ci.cache.config {
+            args.extend(["--config".into(), config.clone().into()]);
+        }
+        args.extend(["--".into(), script.clone().into()]);
+        let opts = <crate::Cli as clap::Parser>::parse_from(args).run;
+        let (name, cache_key, lockfile) = crate::plan(&opts, &config, |planned| {
+            let lockfile = crate::stored_lockfile(opts.cache_dir.as_path(), &planned.project.cargo_toml()?);
+            Ok((planned.script_name, planned.cache_key, lockfile))
+        })?;
+        scripts.push(Script { name, opts, cache_key, lockfile });
+    }
+    let prefix = format!("clawbang-{}-{}-", std::env::consts::OS, std::env::consts::ARCH);
+    let mut keys: Vec<&str> = scripts.iter().map(|script| script.cache_key.as_str()).collect();
+    keys.sort_unstable();
+    let key = format!("{}{}", prefix, &hex::encode(Sha256::digest(keys.join("\n").as_bytes()))[..16]);
+
+    let result = match (ci.action, service) {
+        (Action::Restore, Some(service)) => restore(&service, &key, &prefix, &scripts),
+        (Action::Save, Some(service)) => save(&service, &key, &config, &scripts),
+        _ => {
+            println!("{}", key);
+            return Ok(())
+        },
+    };
+    // as with actions/cache, the job goes on without the cache rather than failing over it
+    if let Err(err) = result {
+        eprintln!("{} {}", crate::color::label("warning"), err);
+    }
+    Ok(())
+}
+
+fn restore(service: &Service, key: &str, prefix: &str, scripts: &[Script]) -> Result<()> {
+    let reply = service.call("GetCacheEntryDownloadURL", json!({ "key": key, "restore_keys": [prefix], "version": version() }), &[404])?;
+    let found = |field| reply.as_ref().and_then(|reply| text(reply, field));
+    let (Some(url), Some(matched)) = (found("signed_download_url"), found("matched_key")) else {
+        eprintln!("{} nothing is cached for these scripts yet; `clawbang ci-cache save` keeps their builds for the next run", crate::color::label("note"));
+        return Ok(())
+    };
+    let mut archive = Vec::new();
+    ureq::get(&url).call().map_err(|err| eyre::eyre!("Could not download the cached builds: {}", err))?
+        .into_reader().read_to_end(&mut archive)?;
+
+    let files: HashMap<String, &[u8]> = tar::files(&archive)?.into_iter().collect();
+    let store = crate::cache_store(&scripts[0].opts);
+    let mut restored = 0;
+    for script in scripts {
+        let lockfile = format!("lockfiles/{}", script.lockfile.file_name().unwrap_or_default().to_string_lossy());
+        if let (Some(contents), false) = (files.get(&lockfile), script.lockfile.exists()) {
+            std::fs::create_dir_all(script.lockfile.parent().unwrap_or(&script.opts.cache_dir))?;
+            std::fs::write(&script.lockfile, contents)?;
+        }
+        if store.metadata(&script.cache_key)?.is_some_and(|build| build.exit_code == 0) {
+            continue
+        }
+        let build = |file: &str| files.get(&format!("builds/{}/{}", script.cache_key, file));
+        if let (Some(binary), Some(output)) = (build("binary"), build("output")) {
+            let file = tempfile::NamedTempFile::new()?;
+            std::fs::write(file.path(), binary)?;
+            store.write_artifact(&script.cache_key, 0, output, Some(file.path()))?;
+            restored += 1;
+        }
+    }
+    eprintln!("{} restored {} of {} builds from {}", crate::color::label("note"), restored, scripts.len(), matched);
+    Ok(())
+}
+
+// Build whatever isn't cached yet, then archive just these scripts' builds, rather than the
+// whole cache directory and everything else it's ever held.
+fn save(service: &Service, key: &str, config: &crate::config::Config, scripts: &[Script]) -> Result<()> {
+    let mut archive = Tar::new(0);
+    let mut saved = 0;
+    for script in scripts {
+        let store = crate::cache_store(&script.opts);
+        let build = match store.metadata(&script.cache_key)? {
+            Some(build) if build.exit_code == 0 => build,
+            _ => match crate::prepare(&script.opts, config)? {
+                crate::Build::Ready(_) => match store.metadata(&script.cache_key)? {
+                    Some(build) => build,
+                    None => continue,
+                },
+                crate::Build::Failed { .. } => {
+                    eprintln!("{} {} doesn't build, so there's nothing of it to save", crate::color::label("warning"), script.name);
+                    continue
+                },
+            },
+        };
+        let binary = tempfile::NamedTempFile::new()?;
+        store.read_artifact(&script.cache_key, binary.path())?;
+        archive.file(&format!("builds/{}/binary", script.cache_key), 0o755, &std::fs::read(binary.path())?);
+        archive.file(&format!("builds/{}/output", script.cache_key), 0o644, &store.read_output(&build)?);
+        if let (Ok(lockfile), Some(name)) = (std::fs::read(&script.lockfile), script.lockfile.file_name()) {
+            archive.file(&format!("lockfiles/{}", name.to_string_lossy()), 0o644, &lockfile);
+        }
+        saved += 1;
+    }
+    let archive = archive.finish();
+
+    // keys can't be overwritten: this one was saved by an earlier run (and maybe restored
+    // just now), or another job is saving it
+    let reply = service.call("CreateCacheEntry", json!({ "key": key, "version": version() }), &[409])?;
+    let Some(url) = reply.as_ref().and_then(|reply| text(reply, "signed_upload_url")) else {
+        eprintln!("{} the builds are already saved as {}", crate::color::label("note"), key);
+        return Ok(())
+    };
+    ureq::put(&url)
+        .set("x-ms-blob-type", "BlockBlob")
+        .send_bytes(&archive)
+        .map_err(|err| eyre::eyre!("Could not upload the builds: {}", err))?;
+    service.call("FinalizeCacheEntryUpload", json!({ "key": key, "version": version(), "size_bytes": archive.len().to_string() }), &[])?;
+    eprintln!("{} saved {} of {} builds as {} ({} KiB)", crate::color::label("note"), saved, scripts.len(), key, archive.len() / 1024);
+    Ok(())
+}
+
+fn version() -> String {
+    hex::encode(Sha256::digest(VERSION.as_bytes()))
+}
+
+fn text(reply: &serde_json::Value, field: &str) -> Option<String> {
+    reply.get(field).and_then(serde_json::Value::as_str).filter(|value| !value.is_empty()).map(String::from)
+}
+
+// The cache service a GitHub Actions job talks to. The runner only hands its address and
+// token to actions, so a `run` step needs them passed along (as the README shows).
+struct Service {
+    url: String,
+    token: String,
+}
+
+impl Service {
+    fn from_env() -> Result<Service> {
+        match (std::env::var("ACTIONS_RESULTS_URL"), std::env::var("ACTIONS_RUNTIME_TOKEN")) {
+            (Ok(url), Ok(token)) => Ok(Service { url: url.trim_end_matches('/').to_string(), token }),
+            _ => Err(eyre::eyre!(
+                "ci-cache restore and save need the GitHub Actions cache, by way of ACTIONS_RESULTS_URL and ACTIONS_RUNTIME_TOKEN; \
+                 with another CI, cache the paths `clawbang ci-cache paths` prints, under the key `clawbang ci-cache key` prints"
+            )),
+        }
+    }
+
+    // The service's reply, or `None` for one of the statuses in `expected`.
+    fn call(&self, method: &str, body: serde_json::Value, expected: &[u16]) -> Result<Option<serde_json::Value>> {
+        let url = format!("{}/twirp/github.actions.results.api.v1.CacheService/{}", self.url, method);
+        let response = ureq::post(&url)
+            .set("Authorization", &format!("Bearer {}", self.token))
+            .set("Content-Type", "application/json")
+            .set("Accept", "application/json")
+            .send_string(&body.to_string());
+        match response {
+            Ok(response) => Ok(Some(serde_json::from_reader(response.into_reader())?)),
+            Err(ureq::Error::Status(status, _)) if expected.contains(&status) => Ok(None),
+            Err(ureq::Error::Status(status, response)) => Err(eyre::eyre!(
+                "The Actions cache answered {} to {}: {}", status, method, response.into_string().unwrap_or_default().trim()
+            )),
+            Err(err) => Err(eyre::eyre!("Could not reach the Actions cache: {}", err)),
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+static STDERR: OnceLock<bool> = OnceLock::new();
+
+/// `--color`: whether output is colored. `auto` colors output bound for a terminal, unless
+/// NO_COLOR is set.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub(crate) enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("unknown color choice \"{}\"; expected auto, always, or never", value)),
+        }
+    }
+}
+
+impl ColorChoice {
+    /// Whether to color output bound for a stream, given whether that stream is a terminal.
+    pub(crate) fn applies(self, terminal: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => terminal && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()),
+        }
+    }
+}
+
+/// Settle whether clawbang's messages on stderr are colored; until this is called, it's
+/// as if `--color auto` were given.
+pub(crate) fn init(choice: ColorChoice) {
+    let _ = STDERR.set(choice.applies(std::io::stderr().is_terminal()));
+}
+
+pub(crate) fn stderr_enabled() -> bool {
+    *STDERR.get_or_init(|| ColorChoice::Auto.applies(std::io::stderr().is_terminal()))
+}
+
+/// The label that starts one of clawbang's messages (`error:`, `warning:`, and so on), in
+/// the colors cargo uses for its own when stderr is colored.
+pub(crate) fn label(kind: &str) -> String {
+    if !stderr_enabled() {
+        return format!("{}:", kind)
+    }
+    let code = match kind {
+        "error" => "1;31",
+        "warning" => "1;33",
+        _ => "1;36",
+    };
+    format!("\x1b[{}m{}:\x1b[0m", code, kind)
+}
+
+/// Remove terminal escape sequences, for output that was recorded with color but is going
+/// somewhere that shouldn't get it.
+pub(crate) fn strip(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\x1b' {
+            // skip to the end of the escape sequence
+            for ch in chars.by_ref() {
+                if ch.is_ascii_alphabetic() {
+                    break
+                }
+            }
+        } else {
+            plain.push(ch);
+        }
+    }
+    plain
+}
@@ -0,0 +1,63 @@
+use eyre::{ Result, WrapErr };
+use serde::Deserialize;
+use std::path::{ Path, PathBuf };
+
+/// User-wide settings, read from `--config`, `$CLAWBANG_CONFIG`, or `~/.clawbang.toml`.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Config {
+    /// Cargo.toml values merged beneath every script's frontmatter; the script wins on conflict.
+    #[serde(default)]
+    pub(crate) defaults: toml::value::Table,
+
+    /// Commands that change each generated Cargo.toml and main.rs before they build.
+    #[serde(default)]
+    pub(crate) hooks: crate::hooks::HookCommands,
+
+    /// Whether scripts have to be signed, and the keys they may be signed with.
+    #[serde(default)]
+    pub(crate) signatures: crate::signature::Trusted,
+
+    /// Where scripts' dependencies may come from.
+    #[serde(default)]
+    pub(crate) dependency_sources: crate::sources::Policy,
+
+    /// Variables whose values are kept out of recorded build output, besides those named
+    /// like secrets.
+    #[serde(default)]
+    pub(crate) redact: crate::redact::Redact,
+
+    /// Whether scripts' dependencies may run code as they build, and which may anyway.
+    #[serde(default)]
+    pub(crate) build_scripts: crate::build_scripts::Policy,
+
+    /// Whether to refuse scripts that someone else could have changed.
+    #[serde(default)]
+    pub(crate) refuse_unsafe_scripts: bool,
+
+    /// Whether to refuse running scripts whose dependencies have critical advisories.
+    #[serde(default)]
+    pub(crate) deny_advisories: bool,
+}
+
+fn get_default_config_path() -> Option<PathBuf> {
+    let mut pb = home::home_dir()?;
+    pb.push(".clawbang.toml");
+    Some(pb)
+}
+
+pub(crate) fn load(path: Option<&Path>) -> Result<Config> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => match get_default_config_path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Config::default()),
+        }
+    };
+
+    let contents = std::fs::read_to_string(&path)
+        .wrap_err_with(|| format!("Could not read config file {}", path.display()))?;
+
+    toml::from_str(&contents)
+        .wrap_err_with(|| format!("Could not parse config file {}", path.display()))
+}
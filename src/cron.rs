@@ -0,0 +1,145 @@
+use eyre::Result;
+use sha2::{ Digest, Sha256 };
+use std::fs::File;
+use std::io::{ BufRead, BufReader, Write };
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::sync::Mutex;
+
+// A log file is rotated as a run starts once it's grown past this, keeping this many old
+// ones beside it as log.1 (the newest) to log.5.
+const ROTATE_AT: u64 = 10 << 20;
+const KEEP: usize = 5;
+
+/// Held while a script runs under `--cron`, so the next run of it doesn't start alongside
+/// this one. It's released as clawbang exits.
+pub(crate) struct Instance {
+    _lock: File,
+}
+
+#[derive(Clone, Copy)]
+enum Stream {
+    Out,
+    Err,
+}
+
+// Where the lines go: a log file, syslog (which journald reads too), or, with no syslog to
+// be had, the stderr cron gave us.
+enum Sink {
+    File(File),
+    Syslog { socket: UnixDatagram, tag: String, pid: u32 },
+    Stderr,
+}
+
+/// Set up a run under `--cron`: take the script's single-instance lock, then hand
+/// clawbang's stdout and stderr (and so the build's and the script's) to a process of its
+/// own, which timestamps each line into `log_file`, or sends it to syslog without one.
+/// `script` is what the lock is for: the script's absolute path, or its URL or code.
+/// `None` means an earlier run still holds the lock, which the log is told about.
+pub(crate) fn start(cache: &Path, script: &str, script_name: &str, log_file: Option<&Path>) -> Result<Option<Instance>> {
+    let tag = Path::new(script_name).file_stem().map_or("clawbang".into(), |stem| stem.to_string_lossy());
+    let dir = cache.join("locks");
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("cron-{}", &hex::encode(Sha256::digest(script.as_bytes()))[..16]));
+    let lock = std::fs::OpenOptions::new().create(true).truncate(false).write(true).open(&path)
+        .map_err(|err| crate::errors::LOCK.error(format!("Could not open {}: {}", path.display(), err)))?;
+    match lock.try_lock() {
+        Ok(()) => {},
+        Err(std::fs::TryLockError::WouldBlock) => {
+            // the running instance is writing to the log, so it's left unrotated
+            let mut sink = Sink::open(log_file, &tag, false)?;
+            sink.write(Stream::Err, &format!("{} {} is still running from an earlier start, so this one was skipped", crate::color::label("note"), script_name));
+            return Ok(None)
+        },
+        Err(std::fs::TryLockError::Error(err)) => return Err(crate::errors::LOCK.error(format!("Could not lock {}: {}", path.display(), err))),
+    }
+    let sink = Sink::open(log_file, &tag, true)?;
+
+    let (out_read, out_write) = crate::daemon::pipe()?;
+    let (err_read, err_write) = crate::daemon::pipe()?;
+    match unsafe { libc::fork() } {
+        -1 => Err(std::io::Error::last_os_error().into()),
+        0 => {
+            // the logger outlasts whatever signals stop the script, to write down how it ended
+            drop((lock, out_write, err_write));
+            unsafe {
+                for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+                    libc::signal(signal, libc::SIG_IGN);
+                }
+            }
+            let sink = Mutex::new(sink);
+            std::thread::scope(|scope| {
+                scope.spawn(|| relay(out_read, Stream::Out, &sink));
+                relay(err_read, Stream::Err, &sink);
+            });
+            unsafe { libc::_exit(0) }
+        },
+        _ => {
+            unsafe {
+                libc::dup2(out_write.as_raw_fd(), libc::STDOUT_FILENO);
+                libc::dup2(err_write.as_raw_fd(), libc::STDERR_FILENO);
+            }
+            Ok(Some(Instance { _lock: lock }))
+        },
+    }
+}
+
+// Until everything writing to the pipe is gone: clawbang, cargo, and the script.
+fn relay(pipe: File, stream: Stream, sink: &Mutex<Sink>) {
+    let mut reader = BufReader::new(pipe);
+    let mut line = Vec::new();
+    while matches!(reader.read_until(b'\n', &mut line), Ok(read) if read > 0) {
+        let text = String::from_utf8_lossy(&line);
+        if let Ok(mut sink) = sink.lock() {
+            sink.write(stream, text.trim_end_matches(['\n', '\r']));
+        }
+        line.clear();
+    }
+}
+
+impl Sink {
+    // Rotating a log is left to whoever holds the instance lock.
+    fn open(log_file: Option<&Path>, tag: &str, rotate: bool) -> Result<Sink> {
+        let path = match log_file {
+            Some(path) => path,
+            None => return Ok(match UnixDatagram::unbound().and_then(|socket| socket.connect("/dev/log").map(|_| socket)) {
+                Ok(socket) => Sink::Syslog { socket, tag: tag.to_string(), pid: std::process::id() },
+                Err(_) => Sink::Stderr,
+            }),
+        };
+        if rotate && std::fs::metadata(path).is_ok_and(|metadata| metadata.len() > ROTATE_AT) {
+            let rotated = |n: usize| format!("{}.{}", path.display(), n);
+            for n in (1..KEEP).rev() {
+                let _ = std::fs::rename(rotated(n), rotated(n + 1));
+            }
+            let _ = std::fs::rename(path, rotated(1));
+        }
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(path)
+            .map_err(|err| eyre::eyre!("Could not open log file {}: {}", path.display(), err))?;
+        Ok(Sink::File(file))
+    }
+
+    fn write(&mut self, stream: Stream, line: &str) {
+        let stamped = || {
+            let millis = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_millis();
+            match stream {
+                Stream::Out => format!("{} {}\n", crate::rfc3339(millis), line),
+                Stream::Err => format!("{} [stderr] {}\n", crate::rfc3339(millis), line),
+            }
+        };
+        // nothing's left to tell if the log can't be written
+        let _ = match self {
+            Sink::File(file) => file.write_all(stamped().as_bytes()),
+            Sink::Stderr => std::io::stderr().write_all(stamped().as_bytes()),
+            Sink::Syslog { socket, tag, pid } => {
+                // the user facility, at info for stdout and err for stderr; syslog adds the time
+                let priority = match stream {
+                    Stream::Out => 8 + 6,
+                    Stream::Err => 8 + 3,
+                };
+                socket.send(format!("<{}>{}[{}]: {}", priority, tag, pid, line).as_bytes()).map(|_| ())
+            },
+        };
+    }
+}
@@ -0,0 +1,123 @@
+use eyre::Result;
+use std::fs::File;
+use std::io::{ Read, Write };
+use std::os::unix::io::{ AsRawFd, FromRawFd };
+use std::path::Path;
+
+/// Held by the detached process until the script starts; the clawbang that was invoked
+/// waits for it to be closed before reporting back and exiting. A successful exec closes it
+/// without a word; otherwise it says what went wrong: the errno from a failed exec, or, had
+/// the detached process given up on the script before that, nothing more than that it did.
+pub(crate) struct Detached {
+    report: Option<File>,
+}
+
+// What follows the pid on the report pipe when the script didn't start.
+const EXEC_FAILED: &str = "errno";
+const GAVE_UP: &str = "exited";
+
+impl Detached {
+    /// The script runs as our child, so this process is the one that's running.
+    pub(crate) fn started(mut self) {
+        self.report.take();
+    }
+
+    /// Exec failed, so the script never started; the errno goes back with the news.
+    pub(crate) fn exec_failed(mut self, err: &std::io::Error) {
+        if let Some(mut report) = self.report.take() {
+            let _ = writeln!(report, "{} {}", EXEC_FAILED, err.raw_os_error().unwrap_or(0));
+        }
+    }
+}
+
+impl Drop for Detached {
+    fn drop(&mut self) {
+        if let Some(mut report) = self.report.take() {
+            let _ = writeln!(report, "{}", GAVE_UP);
+        }
+    }
+}
+
+/// Turn this process into a daemon: fork twice so it's reparented away from our caller,
+/// start a new session so it has no controlling terminal, and point stdio at `log_file`
+/// (or the null device). The pid is written to `pid_file` before this returns.
+///
+/// Only the detached process returns; the original exits once the script has started.
+pub(crate) fn detach(script_name: &str, pid_file: Option<&Path>, log_file: Option<&Path>) -> Result<Detached> {
+    // everything that can fail is opened first, while there's still someone to tell
+    let null = std::fs::OpenOptions::new().read(true).write(true).open("/dev/null")?;
+    let log = match log_file {
+        Some(path) => std::fs::OpenOptions::new().create(true).append(true).open(path)
+            .map_err(|err| eyre::eyre!("Could not open log file {}: {}", path.display(), err))?,
+        None => null.try_clone()?,
+    };
+    let pid_file = match pid_file {
+        Some(path) => Some(std::fs::OpenOptions::new().create(true).write(true).truncate(true).open(path)
+            .map_err(|err| eyre::eyre!("Could not open pid file {}: {}", path.display(), err))?),
+        None => None,
+    };
+
+    // close-on-exec, so a successful exec is what tells the waiting clawbang we're done
+    let (mut report_read, report_write) = pipe()?;
+    match unsafe { libc::fork() } {
+        -1 => return Err(std::io::Error::last_os_error().into()),
+        0 => {},
+        child => {
+            drop(report_write);
+            unsafe { libc::waitpid(child, std::ptr::null_mut(), 0) };
+            let mut report = String::new();
+            let _ = report_read.read_to_string(&mut report);
+            let mut lines = report.lines();
+            let pid = lines.next().and_then(|pid| pid.parse::<libc::pid_t>().ok());
+            let outcome = lines.next().map(|line| line.split_once(' ').unwrap_or((line, "")));
+            match (pid, outcome) {
+                (Some(pid), None) => {
+                    eprintln!("{} is running in the background as pid {}", script_name, pid);
+                    std::process::exit(0)
+                },
+                (Some(_), Some((EXEC_FAILED, errno))) => {
+                    let err = std::io::Error::from_raw_os_error(errno.parse().unwrap_or(0));
+                    eprintln!("{} {} did not start: {}", crate::color::label("error"), script_name, err);
+                    std::process::exit(1)
+                },
+                _ => {
+                    eprintln!("{} {} did not start; see its log for why", crate::color::label("error"), script_name);
+                    std::process::exit(1)
+                },
+            }
+        },
+    }
+
+    drop(report_read);
+    unsafe {
+        libc::setsid();
+        match libc::fork() {
+            -1 => libc::_exit(1),
+            0 => {},
+            _ => libc::_exit(0),
+        }
+        libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO);
+        libc::dup2(log.as_raw_fd(), libc::STDOUT_FILENO);
+        libc::dup2(log.as_raw_fd(), libc::STDERR_FILENO);
+    }
+
+    let pid = std::process::id();
+    if let Some(mut pid_file) = pid_file {
+        writeln!(pid_file, "{}", pid)?;
+    }
+    let mut report = report_write;
+    writeln!(report, "{}", pid)?;
+    Ok(Detached { report: Some(report) })
+}
+
+/// A pipe, both ends of which are closed on exec.
+pub(crate) fn pipe() -> Result<(File, File)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error().into())
+    }
+    for fd in fds {
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+    Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+}
@@ -0,0 +1,508 @@
+use serde::Deserialize;
+use crate::color::ColorChoice;
+use std::io::Write;
+
+// The path cargo reports for the script body inside the generated project.
+const GENERATED_SOURCE: &str = "src/main.rs:";
+
+#[derive(Deserialize)]
+struct CargoLine {
+    reason: String,
+    message: Option<CompilerMessage>,
+}
+
+#[derive(Deserialize)]
+struct CompilerMessage {
+    rendered: Option<String>,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    level: String,
+    code: Option<ErrorCode>,
+    #[serde(default)]
+    spans: Vec<Span>,
+}
+
+#[derive(Deserialize)]
+struct Span {
+    label: Option<String>,
+    #[serde(default)]
+    file_name: String,
+    #[serde(default)]
+    is_primary: bool,
+    #[serde(default)]
+    line_start: usize,
+    #[serde(default)]
+    line_end: usize,
+    #[serde(default)]
+    column_start: usize,
+    #[serde(default)]
+    column_end: usize,
+}
+
+#[derive(Deserialize)]
+struct ErrorCode {
+    code: String,
+}
+
+/// A compiler message from a build, for
+/// [`BuildOutcome::messages`](crate::BuildOutcome::messages).
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub level: DiagnosticLevel,
+    pub message: String,
+    /// rustc's code for it, like `E0308`, if it has one.
+    pub code: Option<String>,
+    /// Where it points, if that's somewhere in the script (or a directory script's files).
+    pub span: Option<DiagnosticSpan>,
+    /// The message as a terminal would show it (without color), pointed at the script.
+    pub rendered: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum DiagnosticLevel {
+    Error,
+    Warning,
+    Note,
+    Help,
+}
+
+/// The part of the script a [`Diagnostic`] points at. Lines and columns count from 1, and
+/// columns in characters, as rustc counts them; they're the script's own, frontmatter and
+/// all.
+#[derive(Clone, Debug)]
+pub struct DiagnosticSpan {
+    /// The script's name, as clawbang shows it, or for a directory script, the file's path.
+    pub file: String,
+    pub line_start: usize,
+    pub column_start: usize,
+    pub line_end: usize,
+    pub column_end: usize,
+    /// What rustc says about this spot, like "expected `u32`, found `&str`".
+    pub label: Option<String>,
+}
+
+/// A build's compiler messages, when the library is collecting them rather than showing them.
+#[derive(Default)]
+pub(crate) struct Captured {
+    pub(crate) rendered: String,
+    pub(crate) messages: Vec<Diagnostic>,
+}
+
+pub(crate) fn capture(output: &[u8], script_name: &str, tree: Option<&str>) -> Captured {
+    Captured { rendered: rendered(output, script_name, tree), messages: messages(output, script_name, tree) }
+}
+
+// How many diagnostics a person sees from a failed build without --full-errors.
+const SHOWN_DIAGNOSTICS: usize = 5;
+
+// Mistakes people make writing scripts, which rustc's own advice doesn't quite cover: the
+// error code (if any), a fragment of the message that picks it out, and what to say.
+const HINTS: &[(Option<&str>, &str, &str)] = &[
+    (Some("E0601"), "", "a script needs a `fn main() { ... }` to start from; `clawbang -e '...'` wraps a snippet in one for you"),
+    (None, "expected item, found", "statements have to go inside `fn main() { ... }`; only items like `fn`, `use`, and `struct` can sit at the top level"),
+    (None, "macro expansion ignores", "statements have to go inside `fn main() { ... }`; only items like `fn`, `use`, and `struct` can sit at the top level"),
+    (Some("E0277"), "the `?` operator can only be used in a function that returns", "`?` needs the function it's in to return a `Result`; for main, declare `fn main() -> Result<(), Box<dyn std::error::Error>>` and end it with `Ok(())`"),
+];
+
+/// Turn one line of `--message-format json-diagnostic-rendered-ansi` output into
+/// the text a person would have seen from a plain `cargo build`. Lines that
+/// aren't JSON (cargo's own status output) pass through untouched; artifact and
+/// build-finished notices render as nothing.
+pub(crate) fn render_cargo_line(line: &[u8]) -> Vec<u8> {
+    let parsed: CargoLine = match serde_json::from_slice(line) {
+        Ok(parsed) => parsed,
+        Err(_) => {
+            let mut out = line.to_vec();
+            out.push(b'\n');
+            return out
+        }
+    };
+
+    if parsed.reason != "compiler-message" {
+        return Vec::new()
+    }
+
+    parsed.message
+        .and_then(|message| message.rendered)
+        .map(String::into_bytes)
+        .unwrap_or_default()
+}
+
+/// How build output reaches the user: rendered for people on stderr, or as cargo's own
+/// JSON messages on stdout, for editors and CI annotators to read.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub(crate) enum MessageFormat {
+    #[default]
+    Human,
+    Json,
+}
+
+impl std::str::FromStr for MessageFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            _ => Err(format!("unknown message format \"{}\"; expected human or json", value)),
+        }
+    }
+}
+
+/// Show the output recorded from a build, as the lines cargo printed, in `format`. Output
+/// cached before clawbang recorded raw lines is already rendered, and shows as it is.
+///
+/// Unless `full` is set, people are only shown the first few diagnostics: past that, they're
+/// mostly knock-on effects of the first, and thousands of lines of them make a terminal useless.
+pub(crate) fn report(output: &[u8], format: MessageFormat, color: ColorChoice, full: bool, script_name: &str, tree: Option<&str>) -> std::io::Result<()> {
+    if output.is_empty() {
+        return Ok(())
+    }
+    let lines = output.strip_suffix(b"\n").unwrap_or(output).split(|byte| *byte == b'\n');
+    match format {
+        MessageFormat::Human => {
+            let (mut rendered, hidden) = render(output, full, script_name, tree);
+            if !crate::color::stderr_enabled() {
+                rendered = crate::color::strip(&String::from_utf8_lossy(&rendered)).into_bytes();
+            }
+            let mut stderr = std::io::stderr().lock();
+            stderr.write_all(&rendered)?;
+            if hidden > 0 {
+                writeln!(stderr, "… and {} more (run clawbang with --full-errors to see them all)", hidden)?;
+            }
+            drop(stderr);
+            print_hints(output)
+        },
+        MessageFormat::Json => {
+            let mut stdout = std::io::stdout().lock();
+            for line in lines {
+                // cargo's own JSON renders without color unless asked
+                if let Some(message) = remap_message(line, script_name, tree, color == ColorChoice::Always) {
+                    writeln!(stdout, "{}", message)?;
+                }
+            }
+            stdout.flush()
+        },
+    }
+}
+
+/// The output recorded from a build as a person would read it, without color: every
+/// diagnostic, pointed at the script, then the hints for them. For ScriptRunner::build.
+pub(crate) fn rendered(output: &[u8], script_name: &str, tree: Option<&str>) -> String {
+    let (rendered, _) = render(output, true, script_name, tree);
+    let mut rendered = crate::color::strip(&String::from_utf8_lossy(&rendered));
+    for hint in hints(output) {
+        rendered.push_str(&format!("hint: {}\n", hint));
+    }
+    rendered
+}
+
+// The compiler messages in a build's output that point somewhere; summaries like "aborting
+// due to 2 previous errors" don't.
+fn messages(output: &[u8], script_name: &str, tree: Option<&str>) -> Vec<Diagnostic> {
+    output.split(|byte| *byte == b'\n').filter_map(|line| {
+        let message = match serde_json::from_slice::<CargoLine>(line) {
+            Ok(CargoLine { reason, message: Some(message) }) if reason == "compiler-message" && !message.spans.is_empty() => message,
+            _ => return None,
+        };
+        let level = match message.level.as_str() {
+            "error" | "error: internal compiler error" => DiagnosticLevel::Error,
+            "warning" => DiagnosticLevel::Warning,
+            "note" => DiagnosticLevel::Note,
+            "help" => DiagnosticLevel::Help,
+            _ => return None,
+        };
+        let span = message.spans.iter().find(|span| span.is_primary).and_then(|span| Some(DiagnosticSpan {
+            file: script_file(&span.file_name, script_name, tree)?,
+            line_start: span.line_start,
+            column_start: span.column_start,
+            line_end: span.line_end,
+            column_end: span.column_end,
+            label: span.label.clone(),
+        }));
+        let rendered = message.rendered.unwrap_or_default();
+        Some(Diagnostic {
+            level,
+            message: message.message,
+            code: message.code.map(|code| code.code),
+            span,
+            rendered: crate::color::strip(&String::from_utf8_lossy(&remap(rendered.as_bytes(), script_name, tree))),
+        })
+    }).collect()
+}
+
+// Render and remap the lines of a build's output, leaving out diagnostics past the first few
+// unless `full` is set; returns how many were left out, too.
+fn render(output: &[u8], full: bool, script_name: &str, tree: Option<&str>) -> (Vec<u8>, usize) {
+    let lines = output.strip_suffix(b"\n").unwrap_or(output).split(|byte| *byte == b'\n');
+    let (mut shown, mut hidden) = (0, 0);
+    let rendered: Vec<u8> = lines.flat_map(|line| {
+        if !full && is_diagnostic(line) {
+            if shown == SHOWN_DIAGNOSTICS {
+                hidden += 1;
+                return Vec::new()
+            }
+            shown += 1;
+        }
+        render_cargo_line(line)
+    }).collect();
+    (remap(&rendered, script_name, tree), hidden)
+}
+
+/// Cargo's output as it happens, for `-v`: each line cargo prints is shown on stderr as it
+/// would be after the build, but as soon as it's complete.
+pub(crate) struct Stream<'a> {
+    pending: Vec<u8>,
+    script_name: &'a str,
+    tree: Option<&'a str>,
+}
+
+impl<'a> Stream<'a> {
+    pub(crate) fn new(script_name: &'a str, tree: Option<&'a str>) -> Self {
+        Stream { pending: Vec::new(), script_name, tree }
+    }
+}
+
+impl Write for Stream<'_> {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.pending.extend_from_slice(bytes);
+        while let Some(end) = self.pending.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            let mut rendered = remap(&render_cargo_line(&line[..end]), self.script_name, self.tree);
+            if !crate::color::stderr_enabled() {
+                rendered = crate::color::strip(&String::from_utf8_lossy(&rendered)).into_bytes();
+            }
+            std::io::stderr().write_all(&rendered)?;
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stderr().flush()
+    }
+}
+
+/// Follow a build's output with the hints for it, for when the output itself has already
+/// been shown.
+pub(crate) fn print_hints(output: &[u8]) -> std::io::Result<()> {
+    let mut stderr = std::io::stderr().lock();
+    for hint in hints(output) {
+        writeln!(stderr, "{} {}", crate::color::label("hint"), hint)?;
+    }
+    Ok(())
+}
+
+/// Whether a build got as far as compiling anything: a build that failed without a word from
+/// the compiler failed in cargo, resolving or fetching dependencies.
+pub(crate) fn compiled(output: &[u8]) -> bool {
+    output.split(|byte| *byte == b'\n').any(|line| {
+        matches!(serde_json::from_slice::<CargoLine>(line), Ok(CargoLine { reason, .. }) if reason == "compiler-message" || reason == "compiler-artifact")
+    })
+}
+
+// A message about a place in the code, as opposed to a summary like "aborting due to 3
+// previous errors".
+fn is_diagnostic(line: &[u8]) -> bool {
+    matches!(serde_json::from_slice::<CargoLine>(line), Ok(CargoLine { message: Some(message), .. }) if !message.spans.is_empty())
+}
+
+// A compiler message from cargo's JSON output, with every span and rendering pointed at
+// the original script; anything else cargo said is left out.
+fn remap_message(line: &[u8], script_name: &str, tree: Option<&str>, color: bool) -> Option<serde_json::Value> {
+    let mut parsed: serde_json::Value = serde_json::from_slice(line).ok()?;
+    if parsed.get("reason")?.as_str()? != "compiler-message" {
+        return None
+    }
+    remap_value(parsed.get_mut("message")?, script_name, tree, color);
+    Some(parsed)
+}
+
+// Spans nest (in children, and in macro expansions), so the whole message is walked.
+fn remap_value(value: &mut serde_json::Value, script_name: &str, tree: Option<&str>, color: bool) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                match (key.as_str(), &field) {
+                    ("file_name", serde_json::Value::String(file)) => {
+                        if let Some(remapped) = script_file(file, script_name, tree) {
+                            *field = serde_json::Value::String(remapped);
+                        }
+                    },
+                    ("rendered", serde_json::Value::String(rendered)) => {
+                        let remapped = String::from_utf8_lossy(&remap(rendered.as_bytes(), script_name, tree)).into_owned();
+                        *field = serde_json::Value::String(if color { remapped } else { crate::color::strip(&remapped) });
+                    },
+                    _ => remap_value(field, script_name, tree, color),
+                }
+            }
+        },
+        serde_json::Value::Array(items) => {
+            for item in items {
+                remap_value(item, script_name, tree, color);
+            }
+        },
+        _ => {},
+    }
+}
+
+// The script's name for a file in the generated project, if it's one of the script's.
+fn script_file(file: &str, script_name: &str, tree: Option<&str>) -> Option<String> {
+    match tree {
+        Some(tree) => file.strip_prefix("src/").map(|rest| format!("{}/{}", tree.trim_end_matches('/'), rest)),
+        None => (file == "src/main.rs").then(|| script_name.to_string()),
+    }
+}
+
+// Crates whose names on crates.io are spelled with hyphens, which code can't spell.
+const HYPHENATED: &[&str] = &[
+    "async-trait", "futures-util", "proc-macro2", "tokio-stream", "tokio-util", "tower-http",
+    "tracing-subscriber", "wasm-bindgen",
+];
+
+/// Advice for the failures in a build's recorded output that clawbang recognizes, each
+/// given once.
+pub(crate) fn hints(output: &[u8]) -> Vec<String> {
+    let mut found = Vec::new();
+    let mut missing = Vec::new();
+    for line in output.split(|byte| *byte == b'\n') {
+        let message = match serde_json::from_slice::<CargoLine>(line) {
+            Ok(CargoLine { message: Some(message), .. }) => message,
+            _ => continue,
+        };
+        let code = message.code.as_ref().map(|code| code.code.as_str());
+        for (hint_code, fragment, hint) in HINTS {
+            if hint_code.is_none_or(|hint_code| Some(hint_code) == code) && message.message.contains(fragment) && !found.iter().any(|found| found == hint) {
+                found.push(hint.to_string());
+            }
+        }
+        if matches!(code, Some("E0432" | "E0433")) {
+            for krate in message.spans.iter().filter_map(|span| unlinked_crate(span.label.as_deref()?)) {
+                if !missing.contains(&krate) {
+                    missing.push(krate);
+                }
+            }
+        }
+    }
+
+    // rustc suggests `cargo add`, which doesn't apply to a script
+    if !missing.is_empty() {
+        let names: Vec<String> = missing.iter().map(|krate| format!("`{}`", krate.replace('-', "_"))).collect();
+        let entries: String = missing.iter().map(|krate| format!("    {} = \"*\"\n", krate)).collect();
+        found.push(format!(
+            "{} {} not among the script's dependencies; add {} to its frontmatter:\n    +++\n    [dependencies]\n{}    +++",
+            names.join(", "),
+            if missing.len() == 1 { "is" } else { "are" },
+            if missing.len() == 1 { "it" } else { "them" },
+            entries
+        ));
+    }
+    found
+}
+
+// The crate a path failed to resolve through, as its crates.io name, from the label rustc
+// puts on the path ("use of unresolved module or unlinked crate `foo`"; older versions say
+// "undeclared crate or module").
+fn unlinked_crate(label: &str) -> Option<String> {
+    if !(label.contains("unlinked crate") || label.contains("undeclared crate")) {
+        return None
+    }
+    let ident = label.rsplit_once('`')?.0.rsplit_once('`')?.1;
+    if ident.is_empty() || ["std", "core", "alloc", "crate", "self", "super"].contains(&ident) {
+        return None
+    }
+    let hyphenated = ident.replace('_', "-");
+    Some(match HYPHENATED.contains(&hyphenated.as_str()) {
+        true => hyphenated,
+        false => ident.to_string(),
+    })
+}
+
+/// Point diagnostics at the original script instead of the generated project. For
+/// directory-mode scripts, `tree` names the directory that was mirrored into `src/`.
+///
+/// Line and column numbers already match because the generated `main.rs` keeps the
+/// shebang and frontmatter as whitespace, so only the path needs to be swapped. Cargo's
+/// `Compiling bin v0.0.1 (/tmp/...)` names the script instead, too.
+pub(crate) fn remap(output: &[u8], script_name: &str, tree: Option<&str>) -> Vec<u8> {
+    let output = rename_package(output, script_name);
+    let (pattern, replacement) = match tree {
+        Some(tree) => ("src/", format!("{}/", tree.trim_end_matches('/'))),
+        None => (GENERATED_SOURCE, format!("{}:", script_name)),
+    };
+
+    let text = String::from_utf8_lossy(&output);
+    let mut remapped = String::with_capacity(text.len());
+    let mut rest = &text[..];
+
+    while let Some(idx) = rest.find(pattern) {
+        let (before, after) = rest.split_at(idx);
+        remapped.push_str(before);
+
+        let preceding = before.trim_end_matches("\u{1b}[0m");
+        if preceding.ends_with("--> ") || preceding.ends_with("::: ") {
+            remapped.push_str(&replacement);
+        } else {
+            remapped.push_str(pattern);
+        }
+        rest = &after[pattern.len()..];
+    }
+    remapped.push_str(rest);
+
+    remapped.into_bytes()
+}
+
+// The package cargo builds is the generated project, in a temporary directory; where cargo
+// names it, with where it lives, name the script.
+fn rename_package(output: &[u8], script_name: &str) -> Vec<u8> {
+    const PACKAGE: &str = " bin v0.0.1 (";
+    let text = String::from_utf8_lossy(output);
+    let mut renamed = String::with_capacity(text.len());
+    let mut rest = &text[..];
+
+    while let Some(idx) = rest.find(PACKAGE) {
+        let (before, after) = rest.split_at(idx);
+        renamed.push_str(before);
+        let path = &after[PACKAGE.len()..];
+        match path.find(')').filter(|end| !path[..*end].contains('\n')) {
+            Some(end) => {
+                renamed.push(' ');
+                renamed.push_str(script_name);
+                rest = &path[end + 1..];
+            },
+            None => {
+                renamed.push_str(PACKAGE);
+                rest = path;
+            },
+        }
+    }
+    renamed.push_str(rest);
+
+    renamed.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_the_script_where_cargo_names_the_package() {
+        let output = b"\x1b[1m\x1b[32m   Compiling\x1b[0m bin v0.0.1 (/tmp/.tmpYB4XYy)\nerror: could not compile `bin`\n";
+        assert_eq!(
+            String::from_utf8(remap(output, "greet.rs", None)).unwrap(),
+            "\x1b[1m\x1b[32m   Compiling\x1b[0m greet.rs\nerror: could not compile `bin`\n",
+        );
+    }
+
+    #[test]
+    fn points_diagnostics_at_the_script() {
+        let output = b"error[E0425]: cannot find value `x` in this scope\n --> src/main.rs:4:5\n  |\n";
+        assert_eq!(
+            String::from_utf8(remap(output, "greet.rs", None)).unwrap(),
+            "error[E0425]: cannot find value `x` in this scope\n --> greet.rs:4:5\n  |\n",
+        );
+        let output = b" --> src/args.rs:1:1\n";
+        assert_eq!(String::from_utf8(remap(output, "greet.rs", Some("tool/"))).unwrap(), " --> tool/args.rs:1:1\n");
+    }
+}
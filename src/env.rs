@@ -0,0 +1,162 @@
+/// Expand `${VAR}` and `${VAR:-default}` references against the caller's environment.
+/// Unset variables expand to nothing; `$$` produces a literal `$`.
+pub(crate) fn expand(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(idx) = rest.find('$') {
+        out.push_str(&rest[..idx]);
+        let after = &rest[idx + 1..];
+
+        if let Some(after) = after.strip_prefix('$') {
+            out.push('$');
+            rest = after;
+            continue
+        }
+
+        let reference = after.strip_prefix('{').and_then(|inner| inner.find('}').map(|end| (&inner[..end], &inner[end + 1..])));
+        match reference {
+            Some((reference, after)) => {
+                let (name, default) = match reference.split_once(":-") {
+                    Some((name, default)) => (name, Some(default)),
+                    None => (reference, None),
+                };
+                match std::env::var(name) {
+                    Ok(value) if !value.is_empty() || default.is_none() => out.push_str(&value),
+                    _ => out.push_str(default.unwrap_or("")),
+                }
+                rest = after;
+            },
+            None => {
+                out.push('$');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Read a `.env` file: `KEY=value` lines, optionally prefixed with `export`. Values may be
+/// single-quoted (taken literally) or double-quoted (`\n`, `\t`, `\"`, and `\\` escapes);
+/// unquoted values end at a ` #` comment. Blank lines and `#` lines are skipped.
+pub(crate) fn parse_dotenv(contents: &str) -> Result<Vec<(String, String)>, String> {
+    let mut vars = Vec::new();
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue
+        }
+
+        let line = line.strip_prefix("export ").map(str::trim_start).unwrap_or(line);
+        let (name, value) = line.split_once('=')
+            .ok_or_else(|| format!("line {}: expected KEY=value", idx + 1))?;
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("line {}: \"{}\" is not a valid variable name", idx + 1, name))
+        }
+
+        let value = value.trim();
+        let value = if let Some(quoted) = value.strip_prefix('\'') {
+            let end = quoted.find('\'')
+                .ok_or_else(|| format!("line {}: unterminated single quote", idx + 1))?;
+            after_quote(&quoted[end + 1..], idx)?;
+            quoted[..end].to_string()
+        } else if let Some(quoted) = value.strip_prefix('"') {
+            // the closing quote is the first one that isn't escaped
+            let mut escaped = false;
+            let end = quoted.char_indices().find(|&(_, c)| {
+                let closes = c == '"' && !escaped;
+                escaped = c == '\\' && !escaped;
+                closes
+            });
+            let (end, _) = end.ok_or_else(|| format!("line {}: unterminated double quote", idx + 1))?;
+            after_quote(&quoted[end + 1..], idx)?;
+            unescape(&quoted[..end])
+        } else {
+            match value.find(" #") {
+                Some(comment) => value[..comment].trim_end().to_string(),
+                None => value.to_string(),
+            }
+        };
+
+        vars.push((name.to_string(), value));
+    }
+    Ok(vars)
+}
+
+// After a quoted value, there's only room for a comment.
+fn after_quote(rest: &str, idx: usize) -> Result<(), String> {
+    match rest.trim_start() {
+        rest if rest.is_empty() || rest.starts_with('#') => Ok(()),
+        _ => Err(format!("line {}: unexpected text after the closing quote", idx + 1)),
+    }
+}
+
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(contents: &str) -> Vec<(String, String)> {
+        parse_dotenv(contents).unwrap()
+    }
+
+    fn var(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn skips_blank_lines_and_comments() {
+        assert_eq!(parsed("\n# a comment\n   \nA=1\n\n  # indented\nB=2\n"), [var("A", "1"), var("B", "2")]);
+    }
+
+    #[test]
+    fn takes_the_export_prefix() {
+        assert_eq!(parsed("export A=1\nexport   B = 2"), [var("A", "1"), var("B", "2")]);
+        assert_eq!(parsed("exported=1"), [var("exported", "1")]);
+    }
+
+    #[test]
+    fn single_quotes_are_literal() {
+        assert_eq!(parsed(r"A='a \n $B # not a comment'"), [var("A", r"a \n $B # not a comment")]);
+    }
+
+    #[test]
+    fn double_quotes_unescape() {
+        assert_eq!(parsed(r#"A="line\nnext\ttab \"quoted\" back\\slash""#), [var("A", "line\nnext\ttab \"quoted\" back\\slash")]);
+        assert_eq!(parsed(r#"A="ends in a backslash\\""#), [var("A", "ends in a backslash\\")]);
+    }
+
+    #[test]
+    fn inline_comments() {
+        assert_eq!(parsed("A=1 # one\nB=x#y\nC='c' # see\nD=\"d\"# dee"), [var("A", "1"), var("B", "x#y"), var("C", "c"), var("D", "d")]);
+    }
+
+    #[test]
+    fn reports_bad_lines_by_number() {
+        assert_eq!(parse_dotenv("A=1\nnot a variable"), Err("line 2: expected KEY=value".to_string()));
+        assert_eq!(parse_dotenv("A B=1"), Err("line 1: \"A B\" is not a valid variable name".to_string()));
+        assert_eq!(parse_dotenv("A='open"), Err("line 1: unterminated single quote".to_string()));
+        assert_eq!(parse_dotenv(r#"A="open\""#), Err("line 1: unterminated double quote".to_string()));
+        assert_eq!(parse_dotenv("A=\"x\" y"), Err("line 1: unexpected text after the closing quote".to_string()));
+    }
+}
@@ -0,0 +1,178 @@
+use eyre::Result;
+
+/// One of clawbang's own failure modes (as opposed to the script's build failing), with a
+/// stable code that's printed with the error: something to search for, or to match on when
+/// scripting around clawbang. `clawbang explain <code>` describes each at length.
+pub(crate) struct Code {
+    pub(crate) code: &'static str,
+    title: &'static str,
+    explanation: &'static str,
+}
+
+pub(crate) const FRONTMATTER: Code = Code {
+    code: "CB0001",
+    title: "the script's frontmatter couldn't be read",
+    explanation: "\
+The frontmatter between the script's `+++` lines has to be valid TOML, made of tables
+clawbang knows about: Cargo.toml's own keys, plus clawbang's like [env], [limits], and
+[permissions]. The error points at the line and column that couldn't be understood.
+
+Common causes:
+  - a `+++` that opens the frontmatter without one closing it
+  - a string missing its quotes: `foo = 1.2` where `foo = \"1.2\"` was meant
+  - a misspelled table or key; clawbang suggests the closest one it knows",
+};
+
+pub(crate) const CACHE: Code = Code {
+    code: "CB0002",
+    title: "the cache is damaged",
+    explanation: "\
+Something clawbang wrote to its cache couldn't be read back, or no longer matches the
+checksum it was written with (as `clawbang verify` checks). Usually that's a disk that
+filled up mid-write, files removed or edited by hand, or two versions of clawbang sharing a
+cache directory.
+
+Cached builds can always be made again, so the fix is to clear the cache: remove the
+directory named in the error (by default ~/.clawbang-cache), or point --cache-dir (or
+CLAWBANG_DIR) somewhere new. `--rebuild` replaces the one build in question.",
+};
+
+pub(crate) const TOOLCHAIN: Code = Code {
+    code: "CB0003",
+    title: "the Rust toolchain couldn't be found",
+    explanation: "\
+Building a script takes cargo, which clawbang looks for on PATH and then in rustup's
+~/.cargo/bin. Running one takes it too, even from the cache: the rustc beside it says which
+toolchain it is, and that's part of every script's cache key. (clawbang remembers the answer
+until the toolchain changes, so rustc isn't run every time.) A rustup with no default
+toolchain set fails here as well.
+
+To fix it, install Rust with rustup (https://rustup.rs). If cargo is installed somewhere
+else, or clawbang is run with a PATH that leaves it out (as from cron or a service manager),
+set CLAWBANG_CARGO to the cargo binary to use.",
+};
+
+pub(crate) const LOCK: Code = Code {
+    code: "CB0004",
+    title: "the script's build lock couldn't be taken",
+    explanation: "\
+Only one clawbang builds a given script at a time; the others wait for it, holding a lock
+file under the cache's locks/ directory. This fails when that directory can't be written to,
+or when a build would end up waiting on itself: a build script (or something it runs) that
+runs the very script being built through clawbang.
+
+Check that the cache directory is writable by the user running clawbang. For a build that
+runs itself, build whatever the build script needs some other way.",
+};
+
+pub(crate) const SIGNATURE: Code = Code {
+    code: "CB0005",
+    title: "the script isn't signed by a trusted key",
+    explanation: "\
+With --require-signature, or `require = true` under [signatures] in the settings file, a
+script only runs if the file beside it with `.sig` added (script.rs.sig, or for a URL, the
+URL with .sig added) is a signature of it by one of the keys [signatures] trusts: a minisign
+signature, checked against `minisign-keys`, or an SSH one (from `ssh-keygen -Y sign -n
+file`), checked against the `ssh-allowed-signers` file.
+
+Common causes:
+  - the script was edited after it was signed; sign it again
+  - it was signed with a key the settings file doesn't list
+  - it comes from stdin, -e, or a directory, or includes other files, none of which a
+    signature beside the script covers",
+};
+
+pub(crate) const ADVISORY: Code = Code {
+    code: "CB0006",
+    title: "the script depends on crates with critical advisories",
+    explanation: "\
+With `deny-advisories = true` in the settings file, clawbang checks the lockfile a script
+was built with against the RustSec advisory database (using `cargo audit`) before running
+it, and refuses if any of its dependencies has an advisory with a CVSS score of 9.0 or more.
+
+Common causes:
+  - a dependency needs updating: change its version requirement in the frontmatter, or
+    delete the cache's lockfiles/ so the next build resolves newer releases
+  - cargo-audit isn't installed, or the advisory database couldn't be fetched
+  - the build came from a shared cache, without a lockfile here; run with --rebuild
+  - in an emergency, --allow-advisories runs the script regardless",
+};
+
+pub(crate) const SOURCE: Code = Code {
+    code: "CB0007",
+    title: "a dependency comes from a source that isn't allowed",
+    explanation: "\
+The settings file's [dependency-sources] limits where scripts' dependencies may come from:
+`registries` lists the registries allowed (crates.io is \"crates-io\"), `git` is false to
+refuse git dependencies or a list of the URL prefixes they have to start with, and `path`
+is false to refuse path dependencies. Each script's generated Cargo.toml (after [defaults]
+and hooks) is checked before it's built or run, including its [patch] and [replace].
+
+Common causes:
+  - the script depends on a fork, by git URL, in place of a crate from a registry
+  - a `registry = \"...\"` key names a registry the settings don't list
+  - [defaults] or a hook adds a dependency the policy doesn't allow either",
+};
+
+pub(crate) const OWNERSHIP: Code = Code {
+    code: "CB0008",
+    title: "someone else could change the script",
+    explanation: "\
+With `refuse-unsafe-scripts = true` in the settings file, clawbang won't run a script that
+anyone but the user running it (or root) could have changed: one that's writable by
+anyone, or owned by another user, or that sits in a directory that is (unless it's a
+sticky directory like /tmp). Scripts run from root's crontab are the ones this matters for.
+
+Common causes:
+  - the script was made with a permissive umask; `chmod o-w script.rs`
+  - it belongs to the user who wrote it, and root is running it; `chown root script.rs`
+  - it's in a shared directory; move it somewhere only its owner can write
+  - --insecure-ok runs it regardless",
+};
+
+pub(crate) const BUILD_SCRIPT: Code = Code {
+    code: "CB0009",
+    title: "a dependency would run its own code as the script builds",
+    explanation: "\
+With --no-build-scripts, or `deny = true` under [build-scripts] in the settings file,
+clawbang resolves a script's dependencies before compiling anything, and refuses to build
+it if any crate it would build has a build script (build.rs) or is a proc macro, since
+either runs arbitrary code on the building machine. Dependencies for other platforms, and
+dev-dependencies, aren't built, so aren't counted.
+
+Common causes:
+  - a derive macro, like serde's `derive` feature, which pulls in serde_derive
+  - a crate that probes the system or compiles C code, like libc or ring
+  - to let ones you've reviewed build, list them under [build-scripts] as
+    `allow = [\"serde_derive\", \"proc-macro2\"]`",
+};
+
+const CODES: &[&Code] = &[&FRONTMATTER, &CACHE, &TOOLCHAIN, &LOCK, &SIGNATURE, &ADVISORY, &SOURCE, &OWNERSHIP, &BUILD_SCRIPT];
+
+impl Code {
+    /// An error of this kind, with a pointer to its explanation.
+    #[track_caller]
+    pub(crate) fn error(&'static self, message: impl std::fmt::Display) -> eyre::Report {
+        eyre::eyre!("[{}] {}\n(run `clawbang explain {}` for causes and fixes)", self.code, message, self.code)
+    }
+}
+
+/// `clawbang explain`: describe one code, or list them all.
+pub(crate) fn explain(code: Option<&str>) -> Result<()> {
+    let code = match code {
+        Some(code) => code,
+        None => {
+            for code in CODES {
+                println!("{}  {}", code.code, code.title);
+            }
+            return Ok(())
+        },
+    };
+    match CODES.iter().find(|known| known.code.eq_ignore_ascii_case(code)) {
+        Some(code) => {
+            println!("{}: {}\n\n{}", code.code, code.title, code.explanation);
+            Ok(())
+        },
+        None => Err(eyre::eyre!("{} isn't one of clawbang's error codes; `clawbang explain` lists them", code)),
+    }
+}
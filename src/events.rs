@@ -0,0 +1,142 @@
+use serde::Deserialize;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Something that happened while a script was built, for
+/// [`ScriptRunner::on_event`](crate::ScriptRunner::on_event).
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum BuildEvent {
+    /// cargo started compiling a package: one of the script's dependencies, or (as `bin`)
+    /// the script itself.
+    Compiling { package: String, version: String },
+    /// cargo finished compiling a package.
+    Compiled { package: String, version: String },
+    /// A compiler warning, with the text a terminal would have shown (without color),
+    /// pointed at the script.
+    Warning { message: String, rendered: String },
+    /// A compiler error, likewise.
+    Error { message: String, rendered: String },
+    /// The script's binary is ready to run, whether it was just built or the cache had it.
+    ArtifactReady { binary: PathBuf, cache_hit: bool },
+}
+
+pub(crate) type Callback = Arc<dyn Fn(&BuildEvent) + Send + Sync>;
+
+#[derive(Deserialize)]
+struct CargoLine {
+    reason: String,
+    package_id: Option<String>,
+    target: Option<Target>,
+    message: Option<Message>,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    #[serde(default)]
+    kind: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    #[serde(default)]
+    message: String,
+    level: Option<String>,
+    rendered: Option<String>,
+    #[serde(default)]
+    spans: Vec<serde_json::Value>,
+}
+
+/// Cargo's output on its way to `inner`, with each line turned into events as it completes.
+pub(crate) struct Observer<'a, W: Write> {
+    inner: W,
+    pending: Vec<u8>,
+    on_event: &'a dyn Fn(&BuildEvent),
+    script_name: &'a str,
+    tree: Option<&'a str>,
+}
+
+impl<'a, W: Write> Observer<'a, W> {
+    pub(crate) fn new(inner: W, on_event: &'a dyn Fn(&BuildEvent), script_name: &'a str, tree: Option<&'a str>) -> Self {
+        Observer { inner, pending: Vec::new(), on_event, script_name, tree }
+    }
+}
+
+impl<W: Write> Write for Observer<'_, W> {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.pending.extend_from_slice(bytes);
+        while let Some(end) = self.pending.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            if let Some(event) = event(&line[..end], self.script_name, self.tree) {
+                (self.on_event)(&event);
+            }
+        }
+        self.inner.write_all(bytes)?;
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The events in the output recorded from a build, as if it were running now; for a failed
+/// build replayed from the cache.
+pub(crate) fn replay(output: &[u8], on_event: &dyn Fn(&BuildEvent), script_name: &str, tree: Option<&str>) {
+    for line in output.split(|byte| *byte == b'\n') {
+        if let Some(event) = event(line, script_name, tree) {
+            on_event(&event);
+        }
+    }
+}
+
+// What one line of cargo's output (its JSON messages, with its status lines mixed in) says,
+// if it's anything a progress display would care about.
+fn event(line: &[u8], script_name: &str, tree: Option<&str>) -> Option<BuildEvent> {
+    let parsed: CargoLine = match serde_json::from_slice(line) {
+        Ok(parsed) => parsed,
+        Err(_) => {
+            let line = crate::color::strip(&String::from_utf8_lossy(line));
+            let mut words = line.trim_start().strip_prefix("Compiling ")?.split_whitespace();
+            let package = words.next()?.to_string();
+            let version = words.next().unwrap_or_default().trim_start_matches('v').to_string();
+            return Some(BuildEvent::Compiling { package, version })
+        },
+    };
+
+    match parsed.reason.as_str() {
+        // a package with a build script reports the script first, and then the package
+        "compiler-artifact" if !parsed.target?.kind.iter().any(|kind| kind == "custom-build") => {
+            let (package, version) = package(&parsed.package_id?)?;
+            Some(BuildEvent::Compiled { package, version })
+        },
+        // summaries like "aborting due to 2 previous errors" point nowhere
+        "compiler-message" => {
+            let message = parsed.message.filter(|message| !message.spans.is_empty())?;
+            let rendered = message.rendered.unwrap_or_default();
+            let rendered = crate::color::strip(&String::from_utf8_lossy(&crate::diagnostics::remap(rendered.as_bytes(), script_name, tree)));
+            match message.level.as_deref()? {
+                "warning" => Some(BuildEvent::Warning { message: message.message, rendered }),
+                "error" => Some(BuildEvent::Error { message: message.message, rendered }),
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}
+
+// A package's name and version from its cargo id: `registry+https://...#serde@1.0.200`, or
+// `path+file:///.../name#0.1.0` where the name is the directory's; older cargos said
+// `serde 1.0.200 (registry+https://...)`.
+fn package(id: &str) -> Option<(String, String)> {
+    if let Some((name, rest)) = id.split_once(' ') {
+        let version = rest.split_whitespace().next()?;
+        return Some((name.to_string(), version.to_string()))
+    }
+    let (url, fragment) = id.rsplit_once('#')?;
+    match fragment.split_once('@') {
+        Some((name, version)) => Some((name.to_string(), version.to_string())),
+        None => Some((url.trim_end_matches('/').rsplit('/').next()?.to_string(), fragment.to_string())),
+    }
+}
@@ -0,0 +1,207 @@
+use crate::Project;
+use eyre::Result;
+use std::path::{ Path, PathBuf };
+
+// Marks a flake as one of ours, so exporting again never clobbers anything else.
+const MARKER: &str = "exported by `clawbang export`";
+
+/// `clawbang export`: the usual options, plus what to export the script as.
+#[derive(clap::Args)]
+pub(crate) struct ExportOptions {
+    /// Write a Nix flake that builds the script, from the lockfile its builds here use
+    #[clap(long)]
+    pub(crate) nix: bool,
+
+    /// The directory to write it to [default: <script>-flake]
+    #[clap(long, parse(from_os_str))]
+    pub(crate) dest: Option<PathBuf>,
+}
+
+/// Write the script's project out to `dest` as clawbang builds it, with the lockfile kept
+/// for its dependencies, and a flake.nix that builds it with nixpkgs' Rust under the
+/// script's own name. Returns what the flake can't do as clawbang would, to warn about.
+pub(crate) fn nix(dest: &Path, project: &Project, lockfile: &Path) -> Result<Vec<String>> {
+    let lock = self::lockfile(lockfile)?;
+    match std::fs::read_to_string(dest.join("flake.nix")) {
+        Ok(existing) if existing.contains(MARKER) => {
+            // sources the script no longer has mustn't linger
+            let _ = std::fs::remove_dir_all(dest.join("src"));
+        },
+        Ok(_) => return Err(eyre::eyre!("{} already has a flake.nix that clawbang didn't write", dest.display())),
+        Err(_) if std::fs::read_dir(dest).is_ok_and(|mut entries| entries.next().is_some()) => {
+            return Err(eyre::eyre!("{} already exists and isn't empty", dest.display()))
+        },
+        Err(_) => {},
+    }
+    std::fs::create_dir_all(dest)?;
+    let name = binary_name(project);
+    let cargo = write_project(dest, project, &name, &lock)?;
+
+    let mut unsupported = Vec::new();
+    if has_path_dependency(&cargo) {
+        unsupported.push("its path dependencies point outside the flake, which a pure build can't read; copy them in and point at those".to_string());
+    }
+    let git: Vec<String> = git_packages(&lock);
+    if !git.is_empty() {
+        unsupported.push(format!("nix needs hashes for its git dependencies ({}); build the flake once and copy the ones it reports into outputHashes", git.join(", ")));
+    }
+    if !project.manifest.env_files.is_empty() || project.manifest.limits.is_some() || project.manifest.cwd != crate::manifest::WorkingDir::Caller {
+        unsupported.push("its env-files, [limits], and cwd aren't carried over; only [env] is".to_string());
+    }
+    std::fs::write(dest.join("flake.nix"), flake(&name, project, &git))?;
+    Ok(unsupported)
+}
+
+/// What the script's binary is called outside clawbang: the command its `[args]` names, or
+/// the script's own name, in characters any package manager takes.
+pub(crate) fn binary_name(project: &Project) -> String {
+    let name = project.manifest.args.as_ref().map_or(project.name.as_str(), |args| args.command_name(&project.name));
+    name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '-' }).collect()
+}
+
+/// Write the project clawbang generates for the script to `dir`, with `lock` as its
+/// Cargo.lock, building a binary called `name`. Returns its Cargo.toml.
+pub(crate) fn write_project(dir: &Path, project: &Project, name: &str, lock: &str) -> Result<toml::Value> {
+    crate::generate(dir, project)?;
+    // the package keeps the name the lockfile has for it; only the binary takes the script's
+    let mut cargo_toml = project.cargo_toml()?;
+    let cargo: toml::Value = toml::from_str(&cargo_toml)?;
+    if cargo.get("bin").is_none() {
+        cargo_toml.push_str(&format!("\n[[bin]]\nname = {}\npath = \"src/main.rs\"\n", toml::Value::String(name.to_string())));
+    }
+    std::fs::write(dir.join("Cargo.toml"), cargo_toml)?;
+    std::fs::write(dir.join("Cargo.lock"), lock)?;
+    Ok(cargo)
+}
+
+/// The lockfile kept for the script's dependencies, which an earlier build has to have made.
+pub(crate) fn lockfile(lockfile: &Path) -> Result<String> {
+    std::fs::read_to_string(lockfile)
+        .map_err(|_| eyre::eyre!("No lockfile is kept for the script's dependencies; run it with --rebuild to make one"))
+}
+
+fn flake(name: &str, project: &Project, git: &[String]) -> String {
+    let mut package = vec![
+        format!("pname = {};", string(name)),
+        "version = \"0.0.1\";".to_string(),
+        "src = ./.;".to_string(),
+    ];
+    if git.is_empty() {
+        package.push("cargoLock.lockFile = ./Cargo.lock;".to_string());
+    } else {
+        package.push("cargoLock = {".to_string());
+        package.push("  lockFile = ./Cargo.lock;".to_string());
+        package.push("  outputHashes = {".to_string());
+        package.extend(git.iter().map(|package| format!("    {} = pkgs.lib.fakeHash;", string(package))));
+        package.push("  };".to_string());
+        package.push("};".to_string());
+    }
+    if !project.manifest.rustflags.is_empty() {
+        package.push(format!("RUSTFLAGS = {};", string(&project.manifest.rustflags.join(" "))));
+    }
+    // the script's [env], as clawbang would set it: its ${VAR}s against the environment the
+    // program is run from, as the wrapper's shell has them, rather than the one exported from
+    if !project.manifest.env.is_empty() {
+        let mut args = Vec::new();
+        for (name, value) in &project.manifest.env {
+            match value.contains("${") {
+                true => args.extend(["--run".to_string(), format!("export {}={}", name, shell(value))]),
+                false => args.extend(["--set".to_string(), name.clone(), crate::env::expand(value)]),
+            }
+        }
+        let args: Vec<String> = args.iter().map(|arg| string(arg)).collect();
+        package.push("nativeBuildInputs = [ pkgs.makeWrapper ];".to_string());
+        package.push("postInstall = ''".to_string());
+        package.push(format!("  wrapProgram $out/bin/{} ${{pkgs.lib.escapeShellArgs [ {} ]}}", name, args.join(" ")));
+        package.push("'';".to_string());
+    }
+    // scripts have no tests, and dev-dependencies needn't be built for none
+    package.push("doCheck = false;".to_string());
+
+    let package: Vec<String> = package.iter().map(|line| format!("          {}", line)).collect();
+    format!(
+        r#"# {marker}; export the script again rather than editing this.
+{{
+  description = {description};
+
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+
+  outputs = {{ self, nixpkgs }}:
+    let
+      forAllSystems = f: nixpkgs.lib.genAttrs [ "x86_64-linux" "aarch64-linux" "x86_64-darwin" "aarch64-darwin" ]
+        (system: f nixpkgs.legacyPackages.${{system}});
+    in {{
+      packages = forAllSystems (pkgs: {{
+        default = pkgs.rustPlatform.buildRustPackage {{
+{package}
+        }};
+      }});
+      apps = forAllSystems (pkgs: {{
+        default = {{
+          type = "app";
+          program = "${{self.packages.${{pkgs.system}}.default}}/bin/{name}";
+        }};
+      }});
+    }};
+}}
+"#,
+        marker = MARKER, description = string(&format!("{}, built as clawbang builds it", name)), package = package.join("\n"), name = name,
+    )
+}
+
+// A Nix string literal.
+fn string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', r"\\").replace('"', "\\\"").replace("${", "\\${").replace('\n', r"\n"))
+}
+
+/// An [env] value as a double-quoted shell word, which expands `${VAR}` and `${VAR:-default}`
+/// as clawbang does; anything else is taken literally, and `$$` is a `$`.
+pub(crate) fn shell(value: &str) -> String {
+    let mut out = String::from("\"");
+    let mut rest = value;
+    while let Some(c) = rest.chars().next() {
+        let reference = rest.strip_prefix("${").and_then(|inner| inner.find('}')).map(|end| end + 3);
+        match (c, reference) {
+            ('$', Some(end)) => {
+                out.push_str(&rest[..end]);
+                rest = &rest[end..];
+                continue
+            },
+            ('$', None) => out.push_str("\\$"),
+            ('"' | '\\' | '`', _) => {
+                out.push('\\');
+                out.push(c);
+            },
+            _ => out.push(c),
+        }
+        rest = &rest[c.len_utf8()..];
+        if c == '$' && rest.starts_with('$') {
+            rest = &rest[1..];
+        }
+    }
+    out.push('"');
+    out
+}
+
+// The git packages a lockfile pins, as buildRustPackage's outputHashes names them.
+fn git_packages(lock: &str) -> Vec<String> {
+    let lock: toml::Value = match toml::from_str(lock) {
+        Ok(lock) => lock,
+        Err(_) => return Vec::new(),
+    };
+    let text = |package: &toml::Value, key| package.get(key).and_then(toml::Value::as_str).map(String::from);
+    lock.get("package").and_then(toml::Value::as_array).into_iter().flatten()
+        .filter(|package| text(package, "source").is_some_and(|source| source.starts_with("git+")))
+        .filter_map(|package| Some(format!("{}-{}", text(package, "name")?, text(package, "version")?)))
+        .collect()
+}
+
+/// Whether any of a Cargo.toml's dependencies, for any target, is by path.
+pub(crate) fn has_path_dependency(cargo: &toml::Value) -> bool {
+    let in_table = |table: Option<&toml::Value>| table.and_then(toml::Value::as_table).into_iter().flatten()
+        .any(|(_, dependency)| dependency.get("path").is_some());
+    let direct = ["dependencies", "build-dependencies", "dev-dependencies"].iter().any(|key| in_table(cargo.get(key)));
+    let targeted = cargo.get("target").and_then(toml::Value::as_table).into_iter().flatten()
+        .any(|(_, target)| ["dependencies", "build-dependencies", "dev-dependencies"].iter().any(|key| in_table(target.get(key))));
+    direct || targeted
+}
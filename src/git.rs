@@ -0,0 +1,119 @@
+use eyre::Result;
+use sha2::{ Digest, Sha256 };
+use std::path::{ Path, PathBuf };
+use std::process::Command;
+
+/// A script inside a git repository: `gh:user/repo/path/script.rs@rev`, or for any other
+/// host, `git+<clone url>#path/script.rs@rev`. Without a rev, the default branch is used.
+pub(crate) struct Reference {
+    pub(crate) repo: String,
+    pub(crate) path: String,
+    pub(crate) rev: Option<String>,
+}
+
+impl Reference {
+    pub(crate) fn parse(spec: &str) -> Option<Reference> {
+        let (repo, path) = if let Some(rest) = spec.strip_prefix("gh:") {
+            let mut parts = rest.splitn(3, '/');
+            let (user, repo, path) = (parts.next()?, parts.next()?, parts.next()?);
+            (format!("https://github.com/{}/{}.git", user, repo), path)
+        } else {
+            let (repo, path) = spec.strip_prefix("git+")?.split_once('#')?;
+            (repo.to_string(), path)
+        };
+
+        let (path, rev) = match path.rsplit_once('@') {
+            Some((path, rev)) => (path, Some(rev.to_string())),
+            None => (path, None),
+        };
+        if path.is_empty() {
+            return None
+        }
+        Some(Reference { repo, path: path.trim_matches('/').to_string(), rev })
+    }
+
+    /// A full commit hash names exactly one tree, so it pins the script as well as
+    /// `--sha256` would; branch and tag names can move.
+    pub(crate) fn is_pinned(&self) -> bool {
+        self.rev.as_deref().map(|rev| rev.len() == 40 && rev.chars().all(|c| c.is_ascii_hexdigit())).unwrap_or(false)
+    }
+}
+
+/// Check the reference out of a local mirror of its repository, fetching only when the
+/// mirror doesn't have it yet or the rev could have moved. Returns the script's path in
+/// a checkout shared by every script at that commit.
+pub(crate) fn checkout(cache: &Path, reference: &Reference) -> Result<PathBuf> {
+    let git_dir = cache.join("git").join("repos").join(&hex::encode(Sha256::digest(reference.repo.as_bytes()))[..16]);
+    if !git_dir.exists() {
+        std::fs::create_dir_all(git_dir.parent().unwrap_or(cache))?;
+        let partial = tempfile::tempdir_in(git_dir.parent().unwrap_or(cache))?;
+        git(None, &["clone", "--bare", "--quiet", &reference.repo, &partial.path().to_string_lossy()])?;
+        std::fs::rename(partial.into_path(), &git_dir)?;
+    }
+
+    let rev = reference.rev.as_deref().unwrap_or("HEAD");
+    let commit = match resolve(&git_dir, rev) {
+        Some(commit) if reference.is_pinned() => commit,
+        known => {
+            let fetched = git(Some(&git_dir), &[
+                "fetch", "--quiet", "--force", "--prune", "origin",
+                "+refs/heads/*:refs/heads/*", "+refs/tags/*:refs/tags/*",
+            ]);
+            match (fetched, known) {
+                (Ok(_), _) => resolve(&git_dir, rev),
+                (Err(err), Some(commit)) => {
+                    eprintln!("{} could not update {}: {}; using the copy fetched earlier", crate::color::label("warning"), reference.repo, err);
+                    Some(commit)
+                },
+                (Err(err), None) => return Err(err),
+            }.ok_or_else(|| eyre::eyre!("{} has no commit, branch, or tag named \"{}\"", reference.repo, rev))?
+        },
+    };
+
+    let checkouts = cache.join("git").join("checkouts");
+    let checkout = checkouts.join(&commit);
+    if !checkout.exists() {
+        std::fs::create_dir_all(&checkouts)?;
+        let partial = tempfile::tempdir_in(&checkouts)?;
+        // the mirror's own index is left alone, so concurrent checkouts don't trip over it
+        let scratch = tempfile::tempdir_in(&checkouts)?;
+        let status = Command::new("git")
+            .arg("--git-dir").arg(&git_dir)
+            .arg("--work-tree").arg(partial.path())
+            .args(["checkout", "--quiet", "--force", &commit, "--", "."])
+            .env("GIT_INDEX_FILE", scratch.path().join("index"))
+            .status()?;
+        if !status.success() {
+            return Err(eyre::eyre!("Could not check out {} from {}", commit, reference.repo))
+        }
+
+        // another clawbang may have won the race to the same checkout, which is just as good
+        let partial = partial.into_path();
+        if std::fs::rename(&partial, &checkout).is_err() {
+            let _ = std::fs::remove_dir_all(&partial);
+        }
+    }
+
+    let script = checkout.join(&reference.path);
+    if !script.exists() {
+        return Err(eyre::eyre!("{} has no \"{}\" at {}", reference.repo, reference.path, commit))
+    }
+    Ok(script)
+}
+
+fn resolve(git_dir: &Path, rev: &str) -> Option<String> {
+    git(Some(git_dir), &["rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", rev)]).ok()
+}
+
+fn git(git_dir: Option<&Path>, args: &[&str]) -> Result<String> {
+    let mut command = Command::new("git");
+    if let Some(git_dir) = git_dir {
+        command.arg("--git-dir").arg(git_dir);
+    }
+    let output = command.args(args).output()
+        .map_err(|err| eyre::eyre!("Could not run git, which scripts from git repositories need: {}", err))?;
+    if !output.status.success() {
+        return Err(eyre::eyre!("git {} failed: {}", args[0], String::from_utf8_lossy(&output.stderr).trim()))
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
@@ -0,0 +1,84 @@
+use eyre::{ Result, WrapErr };
+use serde::Deserialize;
+use std::io::Write;
+use std::process::{ Command, Stdio };
+
+/// The Cargo.toml and main.rs clawbang generates for a script, as a [`ProjectHook`] sees
+/// them, before they're written out and built.
+#[derive(Clone, Debug)]
+pub struct GeneratedProject {
+    /// The script's name, as clawbang shows it: its path, a URL, `<stdin>`, or `<eval>`.
+    pub script_name: String,
+    pub cargo_toml: String,
+    pub main_rs: String,
+}
+
+/// Something that changes the generated project before it builds, say to add a company's
+/// prelude crate to every script, or a telemetry shim to every `main`. Hooks run on every
+/// run, cache hits included, since what they make is part of the script's cache key: a hook
+/// whose output changes rebuilds the scripts it touches. It had best make the same output
+/// from the same project, or nothing will ever be a cache hit.
+///
+/// Closures taking a `&mut GeneratedProject` are hooks.
+pub trait ProjectHook: Send + Sync {
+    fn apply(&self, project: &mut GeneratedProject) -> Result<()>;
+}
+
+impl<F> ProjectHook for F
+where
+    F: Fn(&mut GeneratedProject) -> Result<()> + Send + Sync,
+{
+    fn apply(&self, project: &mut GeneratedProject) -> Result<()> {
+        self(project)
+    }
+}
+
+/// The settings file's `[hooks]`: commands that are handed a generated file on stdin and
+/// print what should replace it.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct HookCommands {
+    #[serde(default)]
+    cargo_toml: Option<Vec<String>>,
+    #[serde(default)]
+    main_rs: Option<Vec<String>>,
+}
+
+impl HookCommands {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.cargo_toml.is_none() && self.main_rs.is_none()
+    }
+}
+
+impl ProjectHook for HookCommands {
+    fn apply(&self, project: &mut GeneratedProject) -> Result<()> {
+        if let Some(command) = &self.cargo_toml {
+            project.cargo_toml = filter(command, "cargo-toml", &project.script_name, &project.cargo_toml)?;
+        }
+        if let Some(command) = &self.main_rs {
+            project.main_rs = filter(command, "main-rs", &project.script_name, &project.main_rs)?;
+        }
+        Ok(())
+    }
+}
+
+// Run a hook command with `input` on its stdin, and take its stdout in its place.
+fn filter(command: &[String], hook: &str, script_name: &str, input: &str) -> Result<String> {
+    let (program, args) = command.split_first()
+        .ok_or_else(|| eyre::eyre!("The {} hook in the settings file is an empty command", hook))?;
+    let mut child = Command::new(program)
+        .args(args)
+        .env("CLAWBANG_SCRIPT_NAME", script_name)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .wrap_err_with(|| format!("Could not run the {} hook, {}", hook, program))?;
+    // a hook that stops reading early is judged by its exit status, not the broken pipe
+    let _ = child.stdin.take().expect("stdin is piped").write_all(input.as_bytes());
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(eyre::eyre!("The {} hook, {}, failed on {} ({})", hook, program, script_name, output.status))
+    }
+    String::from_utf8(output.stdout).map_err(|_| eyre::eyre!("The {} hook, {}, printed something other than UTF-8", hook, program))
+}
@@ -0,0 +1,88 @@
+use crate::Project;
+use eyre::Result;
+use std::path::{ Path, PathBuf };
+
+// Where the projects go, beside the scripts; cargo, git, and tree scripts all skip it.
+const HIDDEN: &str = ".clawbang";
+
+/// Where `clawbang ide` puts the project for the script at `file` (a file, or a directory
+/// holding a main.rs): hidden beside it, named for it.
+pub(crate) fn dir(file: &Path) -> PathBuf {
+    let file = crate::absolute(file);
+    let parent = file.parent().map(Path::to_path_buf).unwrap_or_default();
+    parent.join(HIDDEN).join(file.file_name().unwrap_or_default())
+}
+
+/// Write out the project rust-analyzer opens for the script at `file`: its Cargo.toml, with
+/// the script itself as the binary's source, so what the editor shows is the file being
+/// edited. The lockfile kept for its dependencies goes with it, so it resolves the versions
+/// the script builds with. Returns whether anything changed.
+pub(crate) fn write(file: &Path, project: &Project, cache: &Path) -> Result<bool> {
+    let dir = dir(file);
+    let source = match file.is_dir() {
+        true => format!("../../{}/main.rs", file_name(file)),
+        false => format!("../../{}", file_name(file)),
+    };
+
+    let generated = project.cargo_toml()?;
+    let mut cargo: toml::value::Table = toml::from_str(&generated)?;
+    let mut bin = toml::value::Table::new();
+    bin.insert("name".to_string(), toml::Value::String("bin".to_string()));
+    bin.insert("path".to_string(), toml::Value::String(source));
+    cargo.insert("bin".to_string(), toml::Value::Array(vec![toml::Value::Table(bin)]));
+    // kept out of any workspace the script happens to sit in
+    cargo.entry("workspace").or_insert_with(|| toml::Value::Table(toml::value::Table::new()));
+    let cargo_toml = format!(
+        "# Written by `clawbang ide` for {} from its frontmatter, and rewritten as that changes.\n{}",
+        file_name(file), toml::to_string_pretty(&cargo)?
+    );
+
+    let manifest = dir.join("Cargo.toml");
+    if std::fs::read_to_string(&manifest).ok().as_deref() == Some(cargo_toml.as_str()) {
+        return Ok(false)
+    }
+    std::fs::create_dir_all(&dir)?;
+    let gitignore = dir.parent().unwrap_or(&dir).join(".gitignore");
+    if !gitignore.exists() {
+        std::fs::write(gitignore, "*\n")?;
+    }
+    std::fs::write(&manifest, cargo_toml)?;
+    if std::fs::copy(crate::stored_lockfile(cache, &generated), dir.join("Cargo.lock")).is_err() {
+        let _ = std::fs::remove_file(dir.join("Cargo.lock"));
+    }
+    tracing::debug!(project = %dir.display(), "wrote the script's project for rust-analyzer");
+    Ok(true)
+}
+
+/// Rewrite the script's project, if `clawbang ide` has made one, as its frontmatter changes.
+pub(crate) fn refresh(file: &Path, project: &Project, cache: &Path) {
+    if !dir(file).join("Cargo.toml").exists() {
+        return
+    }
+    if let Err(err) = write(file, project, cache) {
+        tracing::debug!(%err, "couldn't rewrite the script's project for rust-analyzer");
+    }
+}
+
+/// What rust-analyzer won't see the way clawbang builds it, to warn about.
+pub(crate) fn unseen(project: &Project) -> Vec<&'static str> {
+    let mut unseen = Vec::new();
+    // the frontmatter's only valid Rust inside a comment
+    if project.source.lines().find(|line| !line.starts_with("#!")).map(str::trim_end) == Some("+++") {
+        unseen.push("its frontmatter isn't in a comment, so it reads as a syntax error; wrap it in /* +++ ... +++ */ to keep the file valid Rust");
+    }
+    if project.manifest.args.is_some() {
+        unseen.push("the parser clawbang generates for its `args` isn't there");
+    }
+    if project.script.data.is_some() {
+        unseen.push("CLAWBANG_DATA, for its __DATA__ section, isn't defined");
+    }
+    if project.hooked.is_some() {
+        unseen.push("changes the settings file's hooks make to its main.rs aren't there");
+    }
+    unseen
+}
+
+fn file_name(file: &Path) -> String {
+    crate::absolute(file).file_name().unwrap_or_default().to_string_lossy().into_owned()
+}
@@ -0,0 +1,69 @@
+use sha2::{ Digest, Sha256 };
+use std::path::{ Path, PathBuf };
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
+
+// A file changed this recently might change again within the same mtime tick, without its
+// size changing, so it isn't worth remembering yet.
+const SETTLE: Duration = Duration::from_secs(2);
+
+/// The SHA-256 of a local script's source, remembered by the script's absolute path,
+/// modification time, and size (and inode, on Unix) under the cache's `sources/`, so running
+/// an unchanged script again needn't hash it again. Anything that doesn't match is hashed,
+/// and remembered for next time.
+pub(crate) fn source_digest(cache: &Path, path: &Path, metadata: &std::fs::Metadata, contents: &[u8]) -> String {
+    let stamp = stamp(metadata);
+    let entry = entry(cache, "sources", path);
+    if let (Some(stamp), Ok(recorded)) = (&stamp, std::fs::read_to_string(&entry)) {
+        if let Some((recorded_stamp, digest)) = recorded.trim_end().rsplit_once(' ') {
+            if recorded_stamp == stamp {
+                tracing::debug!(path = %path.display(), "reusing the source digest from the index");
+                return digest.to_string()
+            }
+        }
+    }
+
+    let digest = hex::encode(Sha256::digest(contents));
+    let settled = metadata.modified().ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age >= SETTLE);
+    if let (Some(stamp), true) = (stamp, settled) {
+        // the index only ever saves work, so failing to update it doesn't matter
+        let _ = record(&entry, &format!("{} {}\n", stamp, digest));
+    }
+    digest
+}
+
+/// The cache key of the last good build of the local script at `path`, for a script that
+/// runs that build while a changed copy rebuilds (`[build] background = true`).
+pub(crate) fn latest_build(cache: &Path, path: &Path) -> Option<String> {
+    let recorded = std::fs::read_to_string(entry(cache, "latest", path)).ok()?;
+    Some(recorded.trim_end().to_string())
+}
+
+pub(crate) fn record_build(cache: &Path, path: &Path, cache_key: &str) {
+    if latest_build(cache, path).as_deref() != Some(cache_key) {
+        let _ = record(&entry(cache, "latest", path), &format!("{}\n", cache_key));
+    }
+}
+
+fn entry(cache: &Path, kind: &str, path: &Path) -> PathBuf {
+    cache.join(kind).join(hex::encode(Sha256::digest(crate::absolute(path).as_os_str().as_encoded_bytes())))
+}
+
+pub(crate) fn stamp(metadata: &std::fs::Metadata) -> Option<String> {
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    #[cfg(unix)]
+    let inode = std::os::unix::fs::MetadataExt::ino(metadata);
+    #[cfg(not(unix))]
+    let inode = 0;
+    Some(format!("{}.{:09} {} {}", modified.as_secs(), modified.subsec_nanos(), metadata.len(), inode))
+}
+
+pub(crate) fn record(entry: &Path, line: &str) -> std::io::Result<()> {
+    let dir = entry.parent().expect("index entries live in a directory");
+    std::fs::create_dir_all(dir)?;
+    let mut partial = tempfile::NamedTempFile::new_in(dir)?;
+    std::io::Write::write_all(&mut partial, line.as_bytes())?;
+    partial.persist(entry).map_err(|err| err.error)?;
+    Ok(())
+}
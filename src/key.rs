@@ -0,0 +1,71 @@
+use eyre::Result;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// What, besides its source, decides a script's cache key, for [`cache_key`]: where the
+/// script would run from, the settings and hooks it would build with, and the toolchain.
+///
+/// ```no_run
+/// let source = std::fs::read("tools/report.rs")?;
+/// let key = clawbang::cache_key(&source, &clawbang::BuildConfig::new("tools/report.rs"))?;
+/// # Ok::<(), eyre::Report>(())
+/// ```
+#[derive(Clone)]
+pub struct BuildConfig {
+    script: PathBuf,
+    cache_dir: Option<PathBuf>,
+    config: Option<PathBuf>,
+    hooks: Vec<Arc<dyn crate::ProjectHook>>,
+    toolchain: Option<String>,
+}
+
+impl BuildConfig {
+    /// The script's path: its file name is compiled into it, and the files it includes are
+    /// read from beside it. Nothing need be there but those.
+    pub fn new(script: impl Into<PathBuf>) -> Self {
+        Self { script: script.into(), cache_dir: None, config: None, hooks: Vec::new(), toolchain: None }
+    }
+
+    /// The cache directory, where the toolchain's version is remembered between runs;
+    /// $CLAWBANG_DIR, or `~/.clawbang-cache`, by default.
+    pub fn cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(dir.into());
+        self
+    }
+
+    /// Read settings from this file instead of ~/.clawbang.toml.
+    pub fn config(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config = Some(path.into());
+        self
+    }
+
+    /// A hook the build would run, as [`ScriptRunner::hook`](crate::ScriptRunner::hook).
+    pub fn hook(mut self, hook: impl crate::ProjectHook + 'static) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Key for the toolchain `rustc -vV` describes like this, rather than the one this
+    /// machine would build with; for filling a cache that other machines read.
+    pub fn toolchain(mut self, version: impl Into<String>) -> Self {
+        self.toolchain = Some(version.into().trim_end().to_string());
+        self
+    }
+}
+
+/// The key a build of `source` is cached under, as `clawbang key` prints it, without
+/// building anything.
+pub fn cache_key(source: impl AsRef<[u8]>, config: &BuildConfig) -> Result<String> {
+    let mut opts = crate::Options::library(config.script.clone());
+    if let Some(cache_dir) = &config.cache_dir {
+        opts.cache_dir = cache_dir.clone();
+    }
+    if let Some(path) = &config.config {
+        opts.config = Some(path.clone());
+    }
+    opts.hooks = config.hooks.clone();
+    opts.source = Some(source.as_ref().to_vec());
+    opts.toolchain = config.toolchain.clone();
+    let settings = crate::config::load(opts.config.as_deref())?;
+    crate::plan(&opts, &settings, |planned| Ok(planned.cache_key))
+}
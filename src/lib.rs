@@ -0,0 +1,2450 @@
+//! Clawbang builds Rust scripts with cargo, caches the builds by their source, and runs
+//! them. The `clawbang` binary is [`cli`], under the default `cli` feature;
+//! [`ScriptRunner`] does the same from other programs.
+
+// Without the `cli` feature, the parts of running a script only the command uses (timeouts,
+// restarts, terminals, profiling) are still compiled, for the options that name them.
+#![cfg_attr(not(feature = "cli"), allow(dead_code))]
+
+use eyre::Result;
+use std::ffi::OsString;
+use std::io::{ BufRead, Read, Write };
+use std::process;
+#[cfg(feature = "cli")]
+use clap::Parser;
+use std::path::{ Path ,PathBuf };
+use std::sync::Arc;
+use std::time::{ Duration, Instant };
+use subprocess::Exec;
+use sha2::{Sha256, Digest};
+
+mod advisories;
+mod args;
+#[cfg(feature = "cli")]
+mod bench;
+mod build_scripts;
+#[cfg(feature = "cli")]
+mod bundle;
+mod cfg;
+#[cfg(feature = "cli")]
+mod ci_cache;
+mod color;
+mod config;
+#[cfg(all(unix, feature = "cli"))]
+mod cron;
+#[cfg(all(unix, feature = "cli"))]
+mod daemon;
+mod diagnostics;
+mod env;
+mod errors;
+mod events;
+#[cfg(feature = "cli")]
+mod export;
+mod git;
+mod hooks;
+mod ide;
+mod index;
+mod key;
+mod limits;
+mod lock;
+#[cfg(feature = "cli")]
+mod logging;
+#[cfg(feature = "cli")]
+mod lsp;
+pub mod manifest;
+mod metadata;
+mod netns;
+#[cfg(feature = "cli")]
+mod oci;
+mod ownership;
+#[cfg(feature = "cli")]
+mod package;
+mod permissions;
+#[cfg(feature = "cli")]
+mod prewarm;
+mod priority;
+mod profile;
+mod progress;
+#[cfg(unix)]
+mod pty;
+mod redact;
+mod remote;
+mod report;
+mod restart;
+#[cfg(feature = "cli")]
+mod rpm;
+mod runner;
+mod sandbox;
+mod service;
+#[cfg(feature = "cli")]
+mod sbom;
+#[cfg(feature = "cli")]
+mod shim;
+#[cfg(unix)]
+mod signals;
+mod signature;
+mod sources;
+mod store;
+#[cfg(feature = "cli")]
+mod systemd;
+#[cfg(feature = "cli")]
+mod tar;
+mod toolchain;
+#[cfg(unix)]
+mod user;
+mod verify;
+#[cfg(feature = "cli")]
+mod watch;
+mod wrap;
+
+pub use diagnostics::{ Diagnostic, DiagnosticLevel, DiagnosticSpan };
+pub use events::BuildEvent;
+pub use hooks::{ GeneratedProject, ProjectHook };
+pub use key::{ cache_key, BuildConfig };
+pub use runner::{ BuildOutcome, ScriptRunner };
+pub use store::{ CacacheStore, CacheStore, CachedBuild };
+
+fn get_default_cache_dir() -> &'static str {
+    let mut pb = home::home_dir().expect("Cannot operate without a home directory");
+    pb.push(".clawbang-cache");
+    let f = pb.to_string_lossy().into_owned();
+    Box::leak(f.into_boxed_str())
+}
+
+// The cargo that builds scripts: $CLAWBANG_CARGO, or the first on PATH. Scripts run from
+// cron and the like often get a PATH without ~/.cargo/bin, so rustup's is tried last.
+fn find_cargo() -> Result<PathBuf> {
+    if let Some(cargo) = std::env::var_os("CLAWBANG_CARGO") {
+        let cargo = PathBuf::from(cargo);
+        if !cargo.is_file() {
+            return Err(errors::TOOLCHAIN.error(format!("CLAWBANG_CARGO is set to {}, but there's no file there", cargo.display())))
+        }
+        return Ok(cargo)
+    }
+
+    let name = format!("cargo{}", std::env::consts::EXE_SUFFIX);
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let rustup = home::cargo_home().ok().map(|cargo_home| cargo_home.join("bin"));
+    std::env::split_paths(&path)
+        .chain(rustup)
+        .map(|dir| dir.join(&name))
+        .find(|cargo| cargo.is_file())
+        .ok_or_else(|| errors::TOOLCHAIN.error(
+            "Clawbang needs cargo to build scripts, and couldn't find it on PATH.\n\
+             Install Rust with rustup (https://rustup.rs), or set CLAWBANG_CARGO to the cargo to use."
+        ))
+}
+
+#[cfg(feature = "cli")]
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    #[clap(flatten)]
+    run: Options,
+}
+
+/// The options of a subcommand that builds a script, or looks it up, without running it: a
+/// run's, less those that only matter to running it, which are left out of its help and
+/// refused. `IDE` keeps --watch, which `clawbang ide` has a use for.
+#[cfg(feature = "cli")]
+struct BuildOptions<const IDE: bool = false>(Options);
+
+// What building a script takes of a run's options; the rest are only for running it.
+#[cfg(feature = "cli")]
+const BUILD_OPTIONS: &[&str] = &[
+    "verbose", "log_json", "quiet", "explain", "rebuild", "full_errors", "color", "message_format", "cache_dir",
+    "strict_permissions", "no_net_build", "config", "no_build_scripts", "insecure_ok", "require_signature",
+    "allow_advisories", "trust", "sha256", "eval", "lines", "print_lines", "file", "rest",
+];
+
+#[cfg(feature = "cli")]
+impl<const IDE: bool> BuildOptions<IDE> {
+    // The run's options this leaves out, by id.
+    fn run_only() -> impl Iterator<Item = clap::Arg<'static>> {
+        // clap's own, like --help, come with any command
+        let clap: Vec<&str> = clap::Command::new("clawbang").get_arguments().map(clap::Arg::get_id).collect();
+        let options = <Options as clap::Args>::augment_args(clap::Command::new("clawbang"));
+        let args: Vec<clap::Arg<'static>> = options.get_arguments().cloned().collect();
+        args.into_iter().filter(move |arg| {
+            let id = arg.get_id();
+            !(clap.contains(&id) || BUILD_OPTIONS.contains(&id) || (IDE && id == "watch"))
+        })
+    }
+}
+
+#[cfg(feature = "cli")]
+impl<const IDE: bool> clap::Args for BuildOptions<IDE> {
+    fn augment_args(cmd: clap::Command<'_>) -> clap::Command<'_> {
+        Self::run_only().fold(Options::augment_args(cmd), |cmd, arg| cmd.mut_arg(arg.get_id(), |arg| arg.hide(true)))
+    }
+
+    fn augment_args_for_update(cmd: clap::Command<'_>) -> clap::Command<'_> {
+        Self::augment_args(cmd)
+    }
+}
+
+#[cfg(feature = "cli")]
+impl<const IDE: bool> clap::FromArgMatches for BuildOptions<IDE> {
+    fn from_arg_matches(matches: &clap::ArgMatches) -> Result<Self, clap::Error> {
+        // given on the command line, that is; the environment can set them for runs
+        if let Some(arg) = Self::run_only().find(|arg| matches.occurrences_of(arg.get_id()) > 0) {
+            let name = arg.get_long().map_or_else(|| arg.get_id().to_string(), |long| format!("--{}", long));
+            return Err(clap::Error::raw(clap::ErrorKind::ArgumentConflict, format!("{} is for running a script, which this subcommand doesn't", name)))
+        }
+        <Options as clap::FromArgMatches>::from_arg_matches(matches).map(BuildOptions)
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &clap::ArgMatches) -> Result<(), clap::Error> {
+        *self = Self::from_arg_matches(matches)?;
+        Ok(())
+    }
+}
+
+/// Where a subcommand finds the cache and its settings, as a run would.
+#[cfg(feature = "cli")]
+#[derive(clap::Args)]
+pub(crate) struct CacheOptions {
+    #[clap(long, env="CLAWBANG_DIR", default_value=get_default_cache_dir())]
+    pub(crate) cache_dir: PathBuf,
+
+    /// Read settings from this file instead of ~/.clawbang.toml
+    #[clap(long, env="CLAWBANG_CONFIG")]
+    pub(crate) config: Option<PathBuf>,
+}
+
+#[cfg(feature = "cli")]
+impl CacheOptions {
+    /// The same, with both paths made absolute, for a run that doesn't start from here.
+    pub(crate) fn absolute(&self) -> CacheOptions {
+        CacheOptions { cache_dir: absolute(&self.cache_dir), config: self.config.as_deref().map(absolute) }
+    }
+
+    /// The same, as arguments for a run.
+    pub(crate) fn args(&self) -> Vec<OsString> {
+        let mut args = vec!["--cache-dir".into(), self.cache_dir.clone().into_os_string()];
+        if let Some(config) = &self.config {
+            args.extend(["--config".into(), config.clone().into_os_string()]);
+        }
+        args
+    }
+}
+
+#[cfg(feature = "cli")]
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Run a script (the default; spell it out where there are no shebangs, as on Windows)
+    Run(Options),
+
+    /// Build a script, then time repeated runs of it with its output discarded
+    BenchRun {
+        #[clap(flatten)]
+        bench: bench::BenchOptions,
+
+        #[clap(flatten)]
+        opts: Options,
+    },
+
+    /// Build several scripts into one binary that runs each by name, like busybox
+    BundleMulti(bundle::BundleOptions),
+
+    /// Build several scripts ahead of time, compiling the dependencies they share once
+    Prewarm(prewarm::PrewarmOptions),
+
+    /// Keep scripts' builds between CI runs: print a cache key and paths, or restore and
+    /// save the builds with the GitHub Actions cache
+    CiCache(ci_cache::CiCacheOptions),
+
+    /// Write a wrapper that runs a script through clawbang, so it can go on your PATH
+    Shim(shim::ShimOptions),
+
+    /// Print a systemd unit that runs a script as a service, with its restart policy from the
+    /// frontmatter's [service] and its hardening from [permissions]
+    Systemd(systemd::SystemdOptions),
+
+    /// Print a script's cache key without building it; with --explain, what it's made from
+    Key(BuildOptions),
+
+    /// Print a bill of materials (CycloneDX or SPDX) for what a script's build is made of
+    Sbom {
+        #[clap(flatten)]
+        sbom: sbom::SbomOptions,
+
+        #[clap(flatten)]
+        opts: BuildOptions,
+    },
+
+    /// Build a script statically and write a minimal container image of it, for `docker load`
+    Oci(oci::OciOptions),
+
+    /// Write a script out as something that builds it without clawbang (with --nix, a flake)
+    Export {
+        #[clap(flatten)]
+        export: export::ExportOptions,
+
+        #[clap(flatten)]
+        opts: BuildOptions,
+    },
+
+    /// Package a script for a package manager: a Homebrew formula, a .deb, or an .rpm
+    Package {
+        #[clap(flatten)]
+        package: package::PackageOptions,
+
+        #[clap(flatten)]
+        opts: BuildOptions,
+    },
+
+    /// Check that the cache's build of a script is the binary built from it, from the
+    /// lockfile kept for its dependencies (with --rebuild, by building it again)
+    Verify(BuildOptions),
+
+    /// Write a project for a script that rust-analyzer can open (with --watch, keeping it
+    /// up to date as the script changes)
+    Ide(BuildOptions<true>),
+
+    /// Print shell completions for a script's command line, as its [args] describes it:
+    /// `clawbang completions-for script.rs bash` (or zsh, fish, elvish, powershell)
+    CompletionsFor(BuildOptions),
+
+    /// Serve rust-analyzer to an editor, with each script it opens as a project of its own
+    Lsp(lsp::LspOptions),
+
+    /// Describe one of clawbang's error codes (like CB0001), or list them all
+    Explain {
+        code: Option<String>,
+    },
+}
+
+#[derive(Default)]
+#[cfg_attr(feature = "cli", derive(clap::Args))]
+struct Options {
+    /// Show cargo's output as the script builds; -vv and -vvv make cargo more verbose, and
+    /// -vvv logs clawbang's own steps too
+    #[cfg_attr(feature = "cli", clap(short, long, parse(from_occurrences)))]
+    verbose: usize,
+
+    /// Write clawbang's logs as JSON, one event per line
+    #[cfg_attr(feature = "cli", clap(long))]
+    log_json: bool,
+
+    /// Don't show a status line while the script builds
+    #[cfg_attr(feature = "cli", clap(short, long))]
+    quiet: bool,
+
+    /// Say how the cache key was made, and whether the cache had a build for it
+    #[cfg_attr(feature = "cli", clap(long))]
+    explain: bool,
+
+    /// Build the script again, even if the cache has a build of it already
+    #[cfg_attr(feature = "cli", clap(long))]
+    rebuild: bool,
+
+    /// Build the script, or find it in the cache, without running it
+    #[cfg_attr(feature = "cli", clap(long, conflicts_with_all = &["daemon", "watch", "report"]))]
+    build_only: bool,
+
+    /// Show every compiler message from a failed build, rather than the first few
+    #[cfg_attr(feature = "cli", clap(long))]
+    full_errors: bool,
+
+    /// When the script is done, write a JSON report of the build and run to this file
+    #[cfg_attr(feature = "cli", clap(long, conflicts_with_all = &["daemon", "watch"]))]
+    report: Option<PathBuf>,
+
+    // prewarm builds every script into one target directory, so they share dependencies
+    #[cfg_attr(feature = "cli", clap(skip))]
+    target_dir: Option<PathBuf>,
+
+    // ScriptRunner::build: compiler messages are handed back rather than shown, and a fresh
+    // build is unpacked into the cache, since its caller runs it after we're done
+    #[cfg_attr(feature = "cli", clap(skip))]
+    capture: bool,
+
+    // ScriptRunner::cache_store: where builds are kept, in place of the cache directory
+    #[cfg_attr(feature = "cli", clap(skip))]
+    store: Option<Arc<dyn CacheStore>>,
+
+    // ScriptRunner::hook: run after the settings file's hooks
+    #[cfg_attr(feature = "cli", clap(skip))]
+    hooks: Vec<Arc<dyn ProjectHook>>,
+
+    // ScriptRunner::on_event: told what's happening as the script builds
+    #[cfg_attr(feature = "cli", clap(skip))]
+    on_event: Option<events::Callback>,
+
+    // clawbang::cache_key: the script's bytes, which needn't have been saved to its path
+    #[cfg_attr(feature = "cli", clap(skip))]
+    source: Option<Vec<u8>>,
+
+    // completions-for: build the script to print its shell completions, not to run it
+    #[cfg_attr(feature = "cli", clap(skip))]
+    completions: bool,
+
+    // clawbang::cache_key: the `rustc -vV` of the toolchain to key for, in place of ours
+    #[cfg_attr(feature = "cli", clap(skip))]
+    toolchain: Option<String>,
+
+    /// Show how long each of clawbang's own steps took before the script started
+    #[cfg_attr(feature = "cli", clap(long, conflicts_with_all = &["daemon", "watch"]))]
+    self_profile: bool,
+
+    /// Color output: auto (at a terminal, unless NO_COLOR is set), always, or never
+    #[cfg_attr(feature = "cli", clap(long, default_value = "auto"))]
+    color: color::ColorChoice,
+
+    /// How to show compiler messages: human, on stderr, or json, as cargo's messages on stdout
+    #[cfg_attr(feature = "cli", clap(long, default_value = "human"))]
+    message_format: diagnostics::MessageFormat,
+
+    #[cfg_attr(feature = "cli", clap(long, env="CLAWBANG_DIR", default_value=get_default_cache_dir()))]
+    cache_dir: PathBuf,
+
+    /// Refuse scripts without a [permissions] table, and confirm the ones they request
+    #[cfg_attr(feature = "cli", clap(long, env="CLAWBANG_STRICT_PERMISSIONS"))]
+    strict_permissions: bool,
+
+    /// Build without the network: only from crates already downloaded, and, where the system
+    /// allows, with build scripts cut off from the network too
+    #[cfg_attr(feature = "cli", clap(long, env="CLAWBANG_NO_NET_BUILD"))]
+    no_net_build: bool,
+
+    /// Read settings from this file instead of ~/.clawbang.toml
+    #[cfg_attr(feature = "cli", clap(long, env="CLAWBANG_CONFIG"))]
+    config: Option<PathBuf>,
+
+    /// Run the script from this directory, overriding the frontmatter's cwd setting
+    #[cfg_attr(feature = "cli", clap(long))]
+    chdir: Option<PathBuf>,
+
+    /// Load variables for the script from a dotenv file; may be given more than once
+    #[cfg_attr(feature = "cli", clap(long, multiple_occurrences(true)))]
+    env_file: Vec<PathBuf>,
+
+    /// Refuse to build the script if a dependency has a build script or is a proc macro,
+    /// other than those the settings file's [build-scripts] allows
+    #[cfg_attr(feature = "cli", clap(long))]
+    no_build_scripts: bool,
+
+    /// Run the script even if refuse-unsafe-scripts finds it writable by someone else
+    #[cfg_attr(feature = "cli", clap(long))]
+    insecure_ok: bool,
+
+    /// Refuse scripts without a signature beside them (script.rs.sig) by a key the settings
+    /// file's [signatures] trusts
+    #[cfg_attr(feature = "cli", clap(long))]
+    require_signature: bool,
+
+    /// Run the script confined to what its [permissions] table grants it (Linux and macOS)
+    #[cfg_attr(feature = "cli", clap(long))]
+    sandbox: bool,
+
+    /// Run the script even if the settings file's deny-advisories finds critical advisories
+    /// against its dependencies
+    #[cfg_attr(feature = "cli", clap(long))]
+    allow_advisories: bool,
+
+    /// Run a script from a URL or git branch without pinning its hash first
+    #[cfg_attr(feature = "cli", clap(long))]
+    trust: bool,
+
+    /// The SHA-256 a script from a URL or git branch must have; it is refused otherwise
+    #[cfg_attr(feature = "cli", clap(long))]
+    sha256: Option<String>,
+
+    /// Give the script a pseudo-terminal, so it acts as it would at a shell even when
+    /// clawbang's output is piped (Unix only)
+    #[cfg_attr(feature = "cli", clap(long))]
+    tty: bool,
+
+    /// Detach the script from this terminal and session, and return once it has started (Unix only)
+    #[cfg_attr(feature = "cli", clap(long, conflicts_with = "tty"))]
+    daemon: bool,
+
+    /// With --daemon, write the script's pid here
+    #[cfg_attr(feature = "cli", clap(long, requires = "daemon"))]
+    pid_file: Option<PathBuf>,
+
+    /// With --daemon or --cron, append the script's stdout and stderr here instead of
+    /// discarding them (or, for --cron, sending them to syslog)
+    #[cfg_attr(feature = "cli", clap(long))]
+    log_file: Option<PathBuf>,
+
+    /// Run as a crontab entry would want: one run of the script at a time, quietly, without
+    /// color, and with its output timestamped into --log-file or syslog (Unix only)
+    #[cfg_attr(feature = "cli", clap(long, conflicts_with_all = &["daemon", "watch", "tty"]))]
+    cron: bool,
+
+    /// Stay running and start the script again when it fails: on-failure[:max=N,backoff=DURATION]
+    #[cfg_attr(feature = "cli", clap(long, conflicts_with = "tty", parse(try_from_str = restart::parse)))]
+    restart: Option<restart::Policy>,
+
+    /// Rebuild and restart the script whenever it or a file it includes changes
+    #[cfg_attr(feature = "cli", clap(long, conflicts_with_all = &["daemon", "restart", "tty", "timeout"]))]
+    watch: bool,
+
+    /// With --watch, keep the old script running until the new one reports that it's
+    /// ready on the descriptor named by $CLAWBANG_READY_FD (Unix only)
+    #[cfg_attr(feature = "cli", clap(long, requires = "watch"))]
+    hot: bool,
+
+    /// With --hot, give the script its readiness descriptor as this fd number
+    #[cfg_attr(feature = "cli", clap(long, requires = "hot"))]
+    ready_fd: Option<i32>,
+
+    /// Build and run at this niceness, from 0 (the default) to 19 (yield to everything else)
+    #[cfg_attr(feature = "cli", clap(long, allow_hyphen_values = true))]
+    nice: Option<i32>,
+
+    /// Build and run in this I/O scheduling class: idle, or best-effort[:0-7] (Linux only)
+    #[cfg_attr(feature = "cli", clap(long))]
+    ionice: Option<priority::IoClass>,
+
+    /// Run the script as this user (a name or uid) once it's built; needs root (Unix only)
+    #[cfg_attr(feature = "cli", clap(long))]
+    user: Option<String>,
+
+    /// Run the script in this group (a name or gid), rather than the user's own (Unix only)
+    #[cfg_attr(feature = "cli", clap(long))]
+    group: Option<String>,
+
+    /// Stop the script if it runs longer than this (e.g. 90s, 5m, 1h)
+    #[cfg_attr(feature = "cli", clap(long, parse(try_from_str = parse_duration)))]
+    timeout: Option<Duration>,
+
+    /// Run this code instead of a script file; it's wrapped in `fn main` unless it has one
+    #[cfg_attr(feature = "cli", clap(short, long, conflicts_with = "file"))]
+    eval: Option<String>,
+
+    /// Run the script body once per line of stdin, with the line bound to `line: &str`
+    #[cfg_attr(feature = "cli", clap(short = 'n', long))]
+    lines: bool,
+
+    /// Like -n, but print `line` after each pass through the body
+    #[cfg_attr(feature = "cli", clap(short = 'p', long, conflicts_with = "lines"))]
+    print_lines: bool,
+
+    /// The script to run: a file, a directory holding a main.rs, a URL, a git reference
+    /// (gh:user/repo/path.rs@rev), or "-" to read it from stdin
+    #[cfg_attr(feature = "cli", clap(required_unless_present = "eval"))]
+    file: Option<PathBuf>,
+
+    /// Arguments for the script; everything after the script path is passed along untouched
+    #[cfg_attr(feature = "cli", clap(parse(from_os_str), allow_hyphen_values = true, multiple_values = true))]
+    rest: Vec<OsString>,
+}
+
+impl Options {
+    // What the command would parse from no flags besides the script, for the library, which
+    // goes without clap: the same defaults, from the same environment variables.
+    fn library(script: PathBuf) -> Self {
+        let var = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+        // clap takes any value but the falsy ones as setting a flag
+        let flag = |name| var(name)
+            .is_some_and(|value| !["n", "no", "f", "false", "off", "0"].contains(&&*value.to_string_lossy().to_lowercase()));
+        Options {
+            cache_dir: var("CLAWBANG_DIR").map(PathBuf::from).unwrap_or_else(|| get_default_cache_dir().into()),
+            config: var("CLAWBANG_CONFIG").map(PathBuf::from),
+            strict_permissions: flag("CLAWBANG_STRICT_PERMISSIONS"),
+            no_net_build: flag("CLAWBANG_NO_NET_BUILD"),
+            file: Some(script),
+            ..Options::default()
+        }
+    }
+}
+
+struct Tee<Inner: std::io::Write> {
+    accum: Vec<u8>,
+    inner: Inner,
+}
+
+impl<Inner: std::io::Write> Tee<Inner> {
+    pub(crate) fn new(inner: Inner) -> Self {
+        Self {
+            accum: Vec::new(),
+            inner
+        }
+    }
+
+    pub(crate) fn into_inner(self) -> (Vec<u8>, Inner) {
+        (self.accum, self.inner)
+    }
+}
+
+impl<Inner: std::io::Write> std::io::Write for Tee<Inner> {
+    fn write(&mut self, bytes: &[u8]) -> Result<usize, std::io::Error> {
+        self.accum.extend(bytes);
+        self.inner.write(bytes)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The `clawbang` command: read its arguments from the process's, then run, build, or
+/// bundle scripts as they ask.
+#[cfg(feature = "cli")]
+pub fn cli() -> Result<()> {
+    let started = Instant::now();
+    // positional arguments check comes first: are we reading from a file or stdin?
+    let (ours, rest) = split_script_args(split_shebang_options(std::env::args_os().collect()));
+    let (mut opts, bench) = match Cli::parse_from(ours) {
+        Cli { command: Some(Command::Run(opts)), .. } => (opts, None),
+        Cli { command: Some(Command::BenchRun { bench, opts }), .. } => (opts, Some(bench)),
+        Cli { command: Some(Command::BundleMulti(bundle)), .. } => return bundle::run(&bundle),
+        Cli { command: Some(Command::Prewarm(prewarm)), .. } => return prewarm::run(&prewarm),
+        Cli { command: Some(Command::CiCache(ci)), .. } => return ci_cache::run(&ci),
+        Cli { command: Some(Command::Shim(shim)), .. } => return shim::run(&shim),
+        Cli { command: Some(Command::Systemd(systemd)), .. } => return systemd::run(&systemd),
+        Cli { command: Some(Command::Key(BuildOptions(opts))), .. } => return print_key(&opts),
+        Cli { command: Some(Command::Sbom { sbom, opts: BuildOptions(opts) }), .. } => return print_sbom(&sbom, &opts),
+        Cli { command: Some(Command::Oci(oci)), .. } => return oci::run(&oci),
+        Cli { command: Some(Command::Export { export, opts: BuildOptions(opts) }), .. } => return export_project(&export, &opts),
+        Cli { command: Some(Command::Package { package, opts: BuildOptions(opts) }), .. } => return package_script(&package, opts),
+        Cli { command: Some(Command::Verify(BuildOptions(opts))), .. } => return verify_build(&opts),
+        Cli { command: Some(Command::Ide(BuildOptions(opts))), .. } => return ide_project(&opts),
+        Cli { command: Some(Command::Lsp(lsp)), .. } => return lsp::run(&lsp),
+        Cli { command: Some(Command::CompletionsFor(BuildOptions(opts))), .. } => return print_completions(opts, rest),
+        Cli { command: Some(Command::Explain { code }), .. } => return errors::explain(code.as_deref()),
+        Cli { run: opts, .. } => (opts, None),
+    };
+    opts.rest = rest;
+    if opts.log_file.is_some() && !opts.daemon && !opts.cron {
+        return Err(eyre::eyre!("--log-file goes with --daemon or --cron"))
+    }
+    // everything from here on, the build's output included, goes to the log
+    #[cfg(unix)]
+    let _instance = match opts.cron {
+        true => {
+            let script = match (&opts.eval, &opts.file) {
+                (Some(eval), _) => eval.clone(),
+                (None, Some(file)) if file != Path::new("-") && !watch::is_remote(file) => absolute(file).to_string_lossy().into_owned(),
+                (None, file) => file.as_deref().unwrap_or(Path::new("-")).to_string_lossy().into_owned(),
+            };
+            let script_name = opts.file.as_deref().map_or("<eval>".into(), Path::to_string_lossy);
+            match cron::start(&absolute(&opts.cache_dir), &script, &script_name, opts.log_file.as_deref())? {
+                Some(instance) => Some(instance),
+                None => return Ok(()),
+            }
+        },
+        false => None,
+    };
+    #[cfg(not(unix))]
+    if opts.cron {
+        return Err(eyre::eyre!("--cron is only supported on Unix"))
+    }
+    if opts.cron {
+        opts.quiet = true;
+        opts.color = color::ColorChoice::Never;
+    }
+    if opts.self_profile {
+        profile::start(started);
+    }
+    color::init(opts.color);
+    logging::init(opts.verbose, opts.log_json);
+    profile::mark("arguments");
+    let config = config::load(opts.config.as_deref())?;
+    profile::mark("settings");
+
+    if opts.watch {
+        if bench.is_some() {
+            return Err(eyre::eyre!("bench-run can't --watch"))
+        }
+        return watch::run(&opts, &config)
+    }
+    let mut launch = match prepare(&opts, &config)? {
+        Build::Ready(launch) => launch,
+        Build::Failed { exit_code, .. } => {
+            profile::report("reporting the failure");
+            process::exit(exit_code)
+        },
+    };
+    if opts.build_only {
+        profile::report("starting");
+        return Ok(())
+    }
+    if let Some(bench) = &bench {
+        return bench::run(launch.command, &launch.script_name, bench)
+    }
+
+    if opts.cron {
+        launch.command.env("NO_COLOR", "1");
+    }
+
+    #[cfg(unix)]
+    let detached = match opts.daemon {
+        true => Some(daemon::detach(&launch.script_name, opts.pid_file.as_deref(), opts.log_file.as_deref())?),
+        false => None,
+    };
+    #[cfg(not(unix))]
+    if opts.daemon {
+        return Err(eyre::eyre!("--daemon is only supported on Unix"))
+    }
+
+    // nothing is left for clawbang to do, so the script takes over this process:
+    // it keeps our pid, gets signals directly, and its exit status is ours
+    #[cfg(unix)]
+    if launch.tempdir.is_none() && opts.timeout.is_none() && opts.restart.is_none() && !opts.tty && opts.report.is_none() && !opts.cron {
+        use std::os::unix::process::CommandExt;
+        tracing::info!(binary = %launch.binary.display(), "replacing clawbang with the script");
+        let mut command = launch.command;
+        profile::report("starting");
+        let err = command.exec();
+        if let Some(detached) = detached {
+            detached.exec_failed(&err);
+        }
+        return Err(eyre::eyre!("Could not execute {}: {}", launch.binary.display(), err))
+    }
+
+    #[cfg(unix)]
+    if let Some(detached) = detached {
+        detached.started();
+    }
+    tracing::info!(binary = %launch.binary.display(), "running the script as a child");
+    profile::report("starting");
+    run(launch.command, &launch.script_name, launch.tempdir, launch.report, &opts)
+}
+
+// `clawbang key`: the key a run would look the script up by, for whatever fills the cache.
+#[cfg(feature = "cli")]
+fn print_key(opts: &Options) -> Result<()> {
+    color::init(opts.color);
+    logging::init(opts.verbose, opts.log_json);
+    let config = config::load(opts.config.as_deref())?;
+    println!("{}", plan(opts, &config, |planned| Ok(planned.cache_key))?);
+    Ok(())
+}
+
+// The script's dependencies are resolved as its build would resolve them, from the lockfile
+// kept for them if there is one, but nothing's built.
+#[cfg(feature = "cli")]
+fn print_sbom(sbom: &sbom::SbomOptions, opts: &Options) -> Result<()> {
+    color::init(opts.color);
+    logging::init(opts.verbose, opts.log_json);
+    let config = config::load(opts.config.as_deref())?;
+    let document = plan(opts, &config, |planned| {
+        let dir = tempfile::tempdir()?;
+        let cargo_toml = generate(dir.path(), &planned.project)?;
+        let lockfile = dir.path().join("Cargo.lock");
+        let mut offline = std::fs::copy(stored_lockfile(opts.cache_dir.as_path(), &cargo_toml), &lockfile).is_ok();
+        let cargo = find_cargo()?;
+        let metadata = |offline| Exec::cmd(&cargo).args(offline_args(offline, opts.no_net_build)).cwd(dir.path());
+        let version = toolchain::version(opts.cache_dir.as_path())?;
+        let mut resolved = metadata::resolve(&planned.script_name, metadata(offline), toolchain::host(&version));
+        if offline && resolved.is_err() && !opts.no_net_build {
+            let _ = std::fs::remove_file(&lockfile);
+            offline = false;
+            resolved = metadata::resolve(&planned.script_name, metadata(offline), toolchain::host(&version));
+        }
+        let inventory = sbom::Inventory {
+            script_name: &planned.script_name,
+            cache_key: &planned.cache_key,
+            metadata: &resolved?,
+            lockfile: &lockfile,
+        };
+        inventory.render(sbom.format)
+    })?;
+    println!("{}", document);
+    Ok(())
+}
+
+// Build the script first, so there's a lockfile kept for it to export with.
+#[cfg(feature = "cli")]
+fn export_project(export: &export::ExportOptions, opts: &Options) -> Result<()> {
+    color::init(opts.color);
+    logging::init(opts.verbose, opts.log_json);
+    if !export.nix {
+        return Err(eyre::eyre!("Nothing to export as; --nix writes a flake"))
+    }
+    let config = config::load(opts.config.as_deref())?;
+    if let Build::Failed { exit_code, .. } = prepare(opts, &config)? {
+        std::process::exit(exit_code)
+    }
+    plan(opts, &config, |planned| {
+        let dest = export.dest.clone().unwrap_or_else(|| PathBuf::from(format!("{}-flake", planned.project.name)));
+        let lockfile = stored_lockfile(opts.cache_dir.as_path(), &planned.project.cargo_toml()?);
+        for unsupported in export::nix(&dest, &planned.project, &lockfile)? {
+            eprintln!("{} the flake for {} is a start, but {}", color::label("warning"), planned.script_name, unsupported);
+        }
+        eprintln!("Wrote {}; `nix build {}` builds {} there", dest.display(), absolute(&dest).display(), planned.script_name);
+        Ok(())
+    })
+}
+
+// As for export, the script is built first, for its lockfile and binary; then its
+// completions are.
+#[cfg(feature = "cli")]
+fn package_script(package: &package::PackageOptions, mut opts: Options) -> Result<()> {
+    color::init(opts.color);
+    logging::init(opts.verbose, opts.log_json);
+    if !(package.brew || package.deb || package.rpm) {
+        return Err(eyre::eyre!("Nothing to package as; --brew writes a Homebrew formula, --deb a .deb, and --rpm an .rpm"))
+    }
+    let config = config::load(opts.config.as_deref())?;
+    if let Build::Failed { exit_code, .. } = prepare(&opts, &config)? {
+        process::exit(exit_code)
+    }
+    let completions = package::completions(&mut opts, &config)?;
+    let written = plan(&opts, &config, |planned| {
+        let (project, url) = (&planned.project, planned.url.as_deref());
+        let mut written = Vec::new();
+        if package.brew {
+            let lockfile = stored_lockfile(opts.cache_dir.as_path(), &project.cargo_toml()?);
+            written.extend(package::brew(package, project, url, &lockfile, &completions)?);
+        }
+        if package.deb || package.rpm {
+            package::warn_unsupported(project);
+            let binary = materialize(opts.cache_dir.as_path(), cache_store(&opts).as_ref(), &planned.cache_key, &project.name)?;
+            if package.deb {
+                written.push(package::deb(package, project, url, &binary, &completions)?);
+            }
+            if package.rpm {
+                written.push(package::rpm(package, project, url, &binary, &completions)?);
+            }
+        }
+        Ok(written)
+    })?;
+    for path in written {
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "cli")]
+fn verify_build(opts: &Options) -> Result<()> {
+    color::init(opts.color);
+    logging::init(opts.verbose, opts.log_json);
+    let config = config::load(opts.config.as_deref())?;
+    plan(opts, &config, |planned| {
+        let lockfile = stored_lockfile(opts.cache_dir.as_path(), &planned.project.cargo_toml()?);
+        let rebuild = || build_from_lockfile(opts, &planned, &lockfile, None, "again to compare with its cached build")
+            .map(|(binary, project)| verify::Rebuilt { binary, project });
+        let entry = verify::Entry {
+            script_name: &planned.script_name,
+            cache_key: &planned.cache_key,
+            lockfile: lockfile.clone(),
+            unpacked: unpacked_binary(opts.cache_dir.as_path(), &planned.cache_key, &planned.project.name),
+            rebuild: opts.rebuild.then_some(&rebuild as &dyn Fn() -> Result<verify::Rebuilt>),
+        };
+        verify::check(opts.cache_dir.as_path(), cache_store(opts).as_ref(), entry)
+    })
+}
+
+// Build the script from scratch, as populate_cache would, but in a project of its own and
+// from `lockfile` and nothing else: for `verify --rebuild`, to compare with the cached build,
+// and for `clawbang oci`, for another target. `purpose` finishes "building <script> ...".
+#[cfg(feature = "cli")]
+fn build_from_lockfile(opts: &Options, planned: &Planned, lockfile: &Path, target: Option<&str>, purpose: &str) -> Result<(PathBuf, tempfile::TempDir)> {
+    let project = tempfile::tempdir()?;
+    let dir = project.path();
+    generate(dir, &planned.project)?;
+    std::fs::copy(lockfile, dir.join("Cargo.lock"))?;
+
+    let cargo_path = find_cargo()?;
+    let mut cargo = match opts.no_net_build.then(netns::isolated).flatten() {
+        Some(prefix) => Exec::cmd(&prefix[0]).args(&prefix[1..]).arg(&cargo_path),
+        None => Exec::cmd(&cargo_path),
+    };
+    if let Some(target) = target {
+        cargo = cargo.env("CARGO_BUILD_TARGET", target);
+    }
+    // what the lockfile names may have to be downloaded again, unless it's not to go online
+    let locked = match opts.no_net_build {
+        true => &["--locked", "--offline"][..],
+        false => &["--locked"][..],
+    };
+    let target_dir = dir.join("target");
+    let remapping = panic_remapping(&cargo_path, dir, &planned.project);
+    eprintln!("{} building {} {}", color::label("note"), planned.script_name, purpose);
+    let output = cargo_rustc(cargo, opts, &planned.project, &target_dir, locked, &remapping)
+        .stdout(subprocess::Redirection::Pipe)
+        .stderr(subprocess::Redirection::Merge)
+        .cwd(dir)
+        .capture()?;
+    if !output.success() {
+        return Err(eyre::eyre!(
+            "Could not build {} {}:\n{}",
+            planned.script_name, purpose, diagnostics::rendered(&output.stdout, &planned.script_name, planned.tree_name.as_deref()).trim_end()
+        ))
+    }
+    let release = match target {
+        Some(target) => target_dir.join(target).join("release"),
+        None => target_dir.join("release"),
+    };
+    let suffix = match target {
+        Some(target) if !target.contains("windows") => "",
+        _ => std::env::consts::EXE_SUFFIX,
+    };
+    Ok((release.join(format!("bin{}", suffix)), project))
+}
+
+// The script's build under completions-for has a main of its own, which prints the
+// completions for the shell it's given rather than running the script.
+#[cfg(feature = "cli")]
+fn print_completions(mut opts: Options, rest: Vec<OsString>) -> Result<()> {
+    const SHELLS: &[&str] = &["bash", "zsh", "fish", "elvish", "powershell"];
+    let shell = match rest.as_slice() {
+        [shell] if SHELLS.contains(&&*shell.to_string_lossy()) => shell.clone(),
+        _ => return Err(eyre::eyre!("completions-for takes a script, then a shell: one of {}", SHELLS.join(", "))),
+    };
+    color::init(opts.color);
+    logging::init(opts.verbose, opts.log_json);
+    let config = config::load(opts.config.as_deref())?;
+    opts.completions = true;
+    opts.rest = vec![shell];
+    let mut launch = match prepare(&opts, &config)? {
+        Build::Ready(launch) => launch,
+        Build::Failed { exit_code, .. } => process::exit(exit_code),
+    };
+    let status = launch.command.status()?;
+    if !status.success() {
+        return Err(eyre::eyre!("{}'s completions couldn't be printed: it {}", launch.script_name, describe_exit(status)))
+    }
+    Ok(())
+}
+
+// `clawbang ide`: the project goes beside the script, and runs of it rewrite it from then on.
+#[cfg(feature = "cli")]
+fn ide_project(opts: &Options) -> Result<()> {
+    color::init(opts.color);
+    logging::init(opts.verbose, opts.log_json);
+    let config = config::load(opts.config.as_deref())?;
+    let file = match (&opts.eval, &opts.file) {
+        (None, Some(file)) if file != Path::new("-") && !watch::is_remote(file) => file.clone(),
+        _ => return Err(eyre::eyre!("clawbang ide needs a script file or directory on this machine")),
+    };
+    let write = |notes: bool| plan(opts, &config, |planned| {
+        ide::write(&file, &planned.project, opts.cache_dir.as_path())?;
+        for unseen in ide::unseen(&planned.project).into_iter().filter(|_| notes) {
+            eprintln!("{} rust-analyzer sees {} as it's written: {}", color::label("note"), planned.script_name, unseen);
+        }
+        Ok(())
+    });
+    write(true)?;
+
+    let manifest = ide::dir(&file).join("Cargo.toml");
+    println!("{}", manifest.display());
+    eprintln!("Point rust-analyzer at it with its linkedProjects setting, as in VS Code's settings.json:");
+    eprintln!("  \"rust-analyzer.linkedProjects\": [{:?}]", manifest.display().to_string());
+    if !opts.watch {
+        return Ok(())
+    }
+
+    // the frontmatter's in the script, or for a directory, its main.rs and clawbang.toml
+    let watched = match file.is_dir() {
+        true => vec![file.join("main.rs"), file.join("clawbang.toml")],
+        false => vec![file.clone()],
+    };
+    let mut before = watch::snapshot(&watched);
+    loop {
+        std::thread::sleep(std::time::Duration::from_millis(250));
+        let now = watch::snapshot(&watched);
+        if now == before {
+            continue
+        }
+        before = now;
+        if let Err(err) = write(false) {
+            eprintln!("{} {}", color::label("error"), err);
+        }
+    }
+}
+
+/// A built script, ready to start.
+struct Launch {
+    command: process::Command,
+    binary: PathBuf,
+    script_name: String,
+    // a fresh build runs straight out of its tempdir, which has to outlive the script
+    tempdir: Option<tempfile::TempDir>,
+    watched: Vec<PathBuf>,
+    // filled in as the script runs, under --report
+    report: report::Report,
+    hit: bool,
+    // the build's compiler messages, when they're being captured
+    diagnostics: diagnostics::Captured,
+}
+
+enum Build {
+    Ready(Box<Launch>),
+    // the build output has already been shown, unless it's being captured
+    Failed { exit_code: i32, diagnostics: diagnostics::Captured, hit: bool },
+}
+
+/// A script read as far as its cache key, with what building it needs of where it came from.
+struct Planned<'a> {
+    file: PathBuf,
+    url: Option<String>,
+    tree: Option<PathBuf>,
+    tree_name: Option<String>,
+    script_path: PathBuf,
+    script_name: String,
+    local: bool,
+    script_dir: PathBuf,
+    project: Project<'a>,
+    cache_key: String,
+}
+
+// Read the script `opts` names and work out its cache key, then hand that to `then`; the
+// project borrows the script's source, which lives here.
+fn plan<T>(opts: &Options, config: &config::Config, then: impl FnOnce(Planned<'_>) -> Result<T>) -> Result<T> {
+    let mut file = match &opts.eval {
+        Some(_) => PathBuf::new(),
+        None => opts.file.clone().expect("clap requires a script path"),
+    };
+
+    // a URL or git reference can carry the hash it's pinned to
+    let mut pinned = opts.sha256.clone();
+    if let Some((spec, hash)) = file.to_str().and_then(remote::split_pin) {
+        if pinned.as_deref().is_some_and(|pinned| !pinned.eq_ignore_ascii_case(hash)) {
+            return Err(eyre::eyre!("{} is pinned to one hash, and --sha256 gives another", spec))
+        }
+        pinned = Some(hash.to_string());
+        file = PathBuf::from(spec);
+    }
+
+    // a script in a git repository runs from a checkout, as if it were local
+    let git_ref = file.to_str().and_then(git::Reference::parse);
+    let git_spec = git_ref.as_ref().map(|_| file.to_string_lossy().into_owned());
+    if let Some(reference) = &git_ref {
+        file = git::checkout(opts.cache_dir.as_path(), reference)?;
+    }
+
+    let url = file.to_str().filter(|file| remote::is_url(file)).map(String::from);
+
+    // directory-mode scripts are a main.rs plus whatever modules sit beside it
+    let tree = (url.is_none() && file.is_dir()).then(|| file.clone());
+    let script_path = match &tree {
+        Some(tree) => tree.join("main.rs"),
+        None => file.clone(),
+    };
+    let tree_name = tree.as_ref().map(|tree| tree.to_string_lossy().into_owned());
+    let from_stdin = file == Path::new("-");
+    let local = !from_stdin && url.is_none() && opts.eval.is_none();
+    let script_name = if opts.eval.is_some() {
+        "<eval>".to_string()
+    } else if from_stdin {
+        "<stdin>".to_string()
+    } else if let Some(spec) = &git_spec {
+        spec.clone()
+    } else {
+        script_path.to_string_lossy().into_owned()
+    };
+
+    // a signature sits beside a file, so it can't vouch for anything else
+    let signed = opts.require_signature || config.signatures.require;
+    if signed && (opts.eval.is_some() || from_stdin || tree.is_some()) {
+        return Err(errors::SIGNATURE.error(format!("{} can't carry a signature, and signatures are required", script_name)))
+    }
+
+    tracing::debug!(script = %script_name, "reading the script");
+    let mut file_metadata = None;
+    let input = if let Some(code) = &opts.eval {
+        ScriptBytes::Read(code.clone().into_bytes())
+    } else if from_stdin {
+        ScriptBytes::Read(read_stdin_script()?)
+    } else if let Some(source) = &opts.source {
+        ScriptBytes::Read(source.clone())
+    } else if let Some(url) = &url {
+        let input = remote::fetch(opts.cache_dir.as_path(), url)?;
+        // a trusted signature vouches for a script as well as a pinned hash does
+        if signed {
+            let signature = remote::fetch(opts.cache_dir.as_path(), &format!("{}.sig", url)).ok();
+            signature::verify(url, &input, signature.as_deref(), &config.signatures)?;
+        }
+        remote::verify(url, &input, pinned.as_deref(), opts.trust || signed)?;
+        ScriptBytes::Read(input)
+    } else {
+        let handle = std::fs::OpenOptions::new().read(true).open(&script_path)?;
+        let metadata = handle.metadata()?;
+        if config.refuse_unsafe_scripts && !opts.insecure_ok {
+            ownership::check(&script_name, &script_path, &metadata)?;
+        }
+        let input = ScriptBytes::open(handle, &metadata)?;
+        if signed {
+            signature::verify(&script_name, &input, signature::beside(&script_path)?.as_deref(), &config.signatures)?;
+        }
+        if git_ref.as_ref().is_some_and(|reference| pinned.is_some() || !reference.is_pinned()) {
+            remote::verify(&script_name, &input, pinned.as_deref(), opts.trust || signed)?;
+        }
+        file_metadata = Some(metadata);
+        input
+    };
+    profile::mark("reading");
+    let source_digest = match &file_metadata {
+        Some(metadata) => index::source_digest(&opts.cache_dir, &script_path, metadata, &input),
+        None => hex::encode(Sha256::digest(&*input)),
+    };
+    profile::mark("hashing");
+    // the data section stays bytes, so it needn't be text
+    let (text, data) = manifest::split_data(&input);
+    let source = std::str::from_utf8(text).map_err(|err| eyre::eyre!(
+        "{} isn't valid UTF-8 (at byte {}); only its __DATA__ section may hold other bytes", script_name, err.valid_up_to()
+    ))?;
+
+    let normalized = manifest::normalize(source);
+    let mut script = manifest::split_script(&normalized)
+        .map_err(|err| errors::FRONTMATTER.error(err.render(&normalized, &script_name)))?;
+    script.data = data;
+    let mut defaults = config.defaults.clone();
+    if let Some(tree) = &tree {
+        let settings = tree.join("clawbang.toml");
+        if settings.exists() {
+            let settings: toml::value::Table = toml::from_str(&std::fs::read_to_string(&settings)?)
+                .map_err(|err| eyre::eyre!("Could not parse {}: {}", settings.display(), err))?;
+            defaults = manifest::layer(settings, &defaults);
+        }
+    }
+    let mut manifest = manifest::parse(&script, &defaults)
+        .map_err(|err| errors::FRONTMATTER.error(err.render(&normalized, &script_name)))?;
+    if opts.completions && !manifest.complete_args() {
+        return Err(eyre::eyre!(
+            "{} has no [args] in its frontmatter, so clawbang doesn't know its command line; completions-for works for scripts that describe theirs with [args]",
+            script_name
+        ))
+    }
+    for warning in &manifest.warnings {
+        eprintln!("{} {}: {}", color::label("warning"), script_name, warning);
+    }
+
+    let script_dir = if local {
+        script_path.parent().map(Path::to_path_buf).unwrap_or_default()
+    } else {
+        std::env::current_dir()?
+    };
+    // a downloaded script's relative paths mean nothing on this machine
+    if url.is_some() && !(manifest.include.is_empty() && manifest.env_files.is_empty()) {
+        return Err(eyre::eyre!("{} lists local files in its frontmatter, which a script run from a URL can't use", script_name))
+    }
+    let mut includes = collect_includes(url.is_none().then_some(script_dir.as_path()), &script, &manifest)?;
+    if let Some(tree) = &tree {
+        collect_tree(tree, &mut includes)?;
+    }
+    if signed && !includes.is_empty() {
+        return Err(errors::SIGNATURE.error(format!("{} includes other files, which its signature doesn't cover, and signatures are required", script_name)))
+    }
+    let name = match (&url, file.canonicalize().ok().as_deref().and_then(Path::file_stem)) {
+        (Some(url), _) => url_stem(url),
+        (None, Some(stem)) if local => stem.to_string_lossy().into_owned(),
+        _ => "script".to_string(),
+    };
+    // where panics say they happened, in place of the generated project, which is gone by
+    // the time anyone reads them
+    let file_name = match (&url, &tree) {
+        _ if !local && url.is_none() => script_name.clone(),
+        (Some(url), _) => format!("{}.rs", url_stem(url)),
+        (None, Some(tree)) => format!("{}/", tree.canonicalize()?.file_name().unwrap_or_default().to_string_lossy()),
+        (None, None) => script_path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+    };
+    let wrap = wrap::Wrap::choose(opts.lines, opts.print_lines, opts.eval.is_some(), script.body);
+    let mut project = Project {
+        name,
+        file_name,
+        source: &normalized,
+        wrap,
+        script,
+        manifest,
+        includes,
+        incremental: opts.watch,
+        completions: opts.completions,
+        build_scripts: (opts.no_build_scripts || config.build_scripts.deny).then(|| config.build_scripts.allow.clone()),
+        secrets: config.redact.secrets(),
+        hooked: None,
+    };
+    project.hooked = run_hooks(opts, config, &script_name, &project)?;
+
+    profile::mark("frontmatter");
+    let toolchain = match &opts.toolchain {
+        Some(toolchain) => toolchain.clone(),
+        None => toolchain::version(opts.cache_dir.as_path())?,
+    };
+    profile::mark("toolchain");
+    let cache_key = tracing::info_span!("hash").in_scope(|| get_key(&source_digest, &project, &toolchain, &config.defaults));
+    tracing::debug!(%cache_key, includes = project.includes.len(), "hashed the script");
+    profile::mark("cache key");
+    if opts.explain {
+        eprintln!("{} {} has the cache key {}, made from:", color::label("explain"), script_name, cache_key);
+        for input in key_inputs(&source_digest, &project, &toolchain, &config.defaults) {
+            eprintln!("{}   {}", color::label("explain"), input.label);
+        }
+    }
+
+    then(Planned { file, url, tree, tree_name, script_path, script_name, local, script_dir, project, cache_key })
+}
+
+// Read the script `opts` names, then find it in the cache or build it.
+#[tracing::instrument(name = "prepare", skip_all)]
+fn prepare(opts: &Options, config: &config::Config) -> Result<Build> {
+    plan(opts, config, |planned| build(opts, config, planned))
+}
+
+fn build(opts: &Options, config: &config::Config, planned: Planned) -> Result<Build> {
+    let Planned { file, url, tree, tree_name, script_path, script_name, local, script_dir, project, cache_key } = planned;
+    if opts.strict_permissions {
+        permissions::check_strict(&script_name, project.manifest.permissions.as_ref())?;
+    }
+    config.dependency_sources.check(&script_name, &project.cargo_toml()?)?;
+    if local {
+        ide::refresh(&file, &project, opts.cache_dir.as_path());
+    }
+    // cargo and the script both inherit this
+    priority::Priority::new(opts.nice, opts.ionice).or(project.manifest.priority.as_ref()).apply();
+
+    #[cfg(unix)]
+    let identity = {
+        user::check_cache(&opts.cache_dir)?;
+        user::Identity::resolve(opts.user.as_deref(), opts.group.as_deref())?
+    };
+    #[cfg(not(unix))]
+    if opts.user.is_some() || opts.group.is_some() {
+        return Err(eyre::eyre!("--user and --group are only supported on Unix"))
+    }
+
+    let store = cache_store(opts);
+    let lookup = tracing::info_span!("cache_lookup", %cache_key).entered();
+    // a good build that's been unpacked into bin/ is a hit by itself, without reading the
+    // index; only --explain and JSON messages want what else the index records
+    let unpacked = unpacked_binary(opts.cache_dir.as_path(), &cache_key, &project.name);
+    let unpacked_hit = !opts.rebuild && !opts.explain && !opts.capture && opts.message_format == diagnostics::MessageFormat::Human && unpacked.exists();
+    let mut metadata = match opts.rebuild || unpacked_hit {
+        true => None,
+        false => store.metadata(&cache_key)?,
+    };
+    // a script that would rather start quickly than run its latest source may run its last
+    // good build instead, while the changed copy builds for next time
+    let mut stale_key = None;
+    if metadata.is_none() && !unpacked_hit && local && !opts.rebuild && !opts.build_only && project.manifest.background_rebuild {
+        if let Some((key, stale)) = stale_build(opts.cache_dir.as_path(), store.as_ref(), &script_path, &cache_key)? {
+            rebuild_in_background(opts, &file)?;
+            if !opts.quiet {
+                eprintln!("{} {} has changed since it was last built; running that build while it rebuilds in the background", color::label("note"), script_name);
+            }
+            stale_key = Some(key);
+            metadata = Some(stale);
+        }
+    }
+    let mut build_lock = None;
+    if metadata.is_none() && !unpacked_hit {
+        // someone else may have finished the build while we waited on the lock
+        build_lock = Some(lock::acquire(opts.cache_dir.as_path(), &cache_key, &script_name)?);
+        if !opts.rebuild {
+            metadata = store.metadata(&cache_key)?;
+        }
+    }
+    if opts.explain {
+        let cache_dir = absolute(&opts.cache_dir);
+        match (&metadata, build_lock.is_some()) {
+            (Some(metadata), _) if stale_key.is_some() => eprintln!("{} {} has no build with this key, so running the last one, from {}, while it builds", color::label("explain"), cache_dir.display(), format_time(metadata.time)),
+            (Some(metadata), false) => eprintln!("{} found a build from {} in {}", color::label("explain"), format_time(metadata.time), cache_dir.display()),
+            (Some(metadata), true) => eprintln!("{} another clawbang finished building it at {} while this one waited", color::label("explain"), format_time(metadata.time)),
+            (None, _) if opts.rebuild => eprintln!("{} --rebuild was given, so building it without checking {}", color::label("explain"), cache_dir.display()),
+            (None, _) => eprintln!("{} {} has no build with this key, so building it", color::label("explain"), cache_dir.display()),
+        }
+    }
+    let hit = unpacked_hit || metadata.is_some();
+    tracing::info!(hit, rebuild = opts.rebuild, "looked up the build");
+    drop(lookup);
+    profile::mark("cache lookup");
+    let cwd = match &opts.chdir {
+        Some(dir) => Some(dir.clone()),
+        None if project.manifest.cwd == manifest::WorkingDir::ScriptDir && !script_dir.as_os_str().is_empty() => Some(script_dir.clone()),
+        None => None,
+    };
+
+    // where the script came from, which argv[0] and the cwd can't reliably tell it;
+    // the frontmatter's [env] is applied afterwards, so it can override these
+    let mut env = Vec::new();
+    if local {
+        env.push(("CLAWBANG_SCRIPT_PATH".to_string(), absolute(&file).to_string_lossy().into_owned()));
+    }
+    env.push(("CLAWBANG_SCRIPT_DIR".to_string(), absolute(&script_dir).to_string_lossy().into_owned()));
+    env.push(("CLAWBANG_CACHE_DIR".to_string(), absolute(&opts.cache_dir).to_string_lossy().into_owned()));
+    env.push(("CLAWBANG_CACHE_HIT".to_string(), if hit { "1" } else { "0" }.to_string()));
+    #[cfg(unix)]
+    env.extend(identity.iter().flat_map(user::Identity::env));
+    let env_files = project.manifest.env_files.iter().map(|path| script_dir.join(path)).chain(opts.env_file.iter().cloned());
+    for path in env_files {
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|err| eyre::eyre!("Could not read env file {}: {}", path.display(), err))?;
+        env.extend(env::parse_dotenv(&contents).map_err(|err| eyre::eyre!("Could not parse env file {}: {}", path.display(), err))?);
+    }
+    env.extend(project.manifest.env.iter().map(|(name, value)| (name.clone(), env::expand(value))));
+
+    profile::mark("environment");
+    let mut report = report::Report::new(&script_name, &cache_key, hit);
+    let mut captured = diagnostics::Captured::default();
+    let capture = |build_output: &[u8]| match opts.capture {
+        true => diagnostics::capture(build_output, &script_name, tree_name.as_deref()),
+        false => diagnostics::Captured::default(),
+    };
+    #[cfg_attr(not(unix), allow(unused_mut))]
+    let (mut binary, mut tempdir) = if unpacked_hit {
+        (unpacked, None)
+    } else if let Some(cache_entry) = metadata {
+        drop(build_lock);
+        if cache_entry.exit_code != 0 {
+            if opts.explain {
+                eprintln!("{} that build failed with status {}; replaying its output rather than building again", color::label("explain"), cache_entry.exit_code);
+            }
+            // the script hasn't changed, but whatever broke the build (the network, a missing
+            // library, the toolchain) may have been fixed since
+            let build_output = store.read_output(&cache_entry)?;
+            if let Some(on_event) = &opts.on_event {
+                events::replay(&build_output, on_event.as_ref(), &script_name, tree_name.as_deref());
+            }
+            if !opts.capture {
+                eprintln!(
+                    "{} {} failed to build at {}, and hasn't changed since; this is that build's output. \
+                     Run clawbang with --rebuild to try again.",
+                    color::label("note"), script_name, format_time(cache_entry.time)
+                );
+                diagnostics::report(&build_output, opts.message_format, opts.color, opts.full_errors, &script_name, tree_name.as_deref())?;
+            }
+            report.failed_from_cache(cache_entry.exit_code);
+            if let Some(path) = &opts.report {
+                report.write(path)?;
+            }
+            return Ok(Build::Failed { exit_code: cache_entry.exit_code, diagnostics: capture(&build_output), hit })
+        }
+        // editors want the warnings from a build, whether or not it's a fresh one
+        if opts.capture {
+            captured = capture(&store.read_output(&cache_entry)?);
+        } else if opts.message_format == diagnostics::MessageFormat::Json {
+            let build_output = store.read_output(&cache_entry)?;
+            diagnostics::report(&build_output, opts.message_format, opts.color, opts.full_errors, &script_name, tree_name.as_deref())?;
+        }
+
+        (materialize(opts.cache_dir.as_path(), store.as_ref(), stale_key.as_deref().unwrap_or(&cache_key), &project.name)?, None)
+    } else {
+        // --watch rebuilds each script in the same place, so a rebuild starts from the last
+        // one's dependencies and incremental state rather than from scratch
+        let (build_dir, tempdir, _watch_lock) = if opts.watch {
+            let dir = watch_dir(opts.cache_dir.as_path(), &file);
+            let lock = lock::acquire(opts.cache_dir.as_path(), &format!("watch-{}", dir.file_name().unwrap_or_default().to_string_lossy()), &script_name)?;
+            // sources the script no longer has mustn't linger
+            let _ = std::fs::remove_dir_all(dir.join("src"));
+            std::fs::create_dir_all(&dir)?;
+            (dir, None, Some(lock))
+        } else {
+            let tempdir = tempfile::tempdir()?;
+            (tempdir.path().to_path_buf(), Some(tempdir), None)
+        };
+        let started = Instant::now();
+        // -v shows cargo's output as it goes, in place of the status line
+        let streaming = opts.verbose > 0 && !opts.capture && opts.message_format == diagnostics::MessageFormat::Human;
+        let progress = progress::Progress::start(opts.quiet || streaming, build_dir.as_path());
+        let stream: Box<dyn Write> = match streaming {
+            true => Box::new(diagnostics::Stream::new(&script_name, tree_name.as_deref())),
+            false => Box::new(std::io::sink()),
+        };
+        let stream: Box<dyn Write> = match &opts.on_event {
+            Some(on_event) => Box::new(events::Observer::new(stream, on_event.as_ref(), &script_name, tree_name.as_deref())),
+            None => stream,
+        };
+        let (exit_code, build_output) = populate_cache(
+            &cache_key,
+            build_dir.as_path(),
+            stream,
+            &progress,
+            opts,
+            store.as_ref(),
+            &project
+        )?;
+        progress.finish();
+        profile::mark("building");
+        drop(build_lock);
+        report.built(started.elapsed(), exit_code);
+
+        if streaming && exit_code != 0 {
+            diagnostics::print_hints(&build_output)?;
+        } else if !opts.capture && (exit_code != 0 || opts.message_format == diagnostics::MessageFormat::Json) {
+            diagnostics::report(&build_output, opts.message_format, opts.color, opts.full_errors, &script_name, tree_name.as_deref())?;
+        }
+        if exit_code != 0 {
+            if let Some(path) = &opts.report {
+                report.write(path)?;
+            }
+            return Ok(Build::Failed { exit_code, diagnostics: capture(&build_output), hit })
+        }
+
+        let built = target_dir(opts, build_dir.as_path()).join("release").join(format!("bin{}", std::env::consts::EXE_SUFFIX));
+        if !opts.quiet {
+            // enough to notice a small script that has started pulling in half of crates.io
+            let dependencies = progress::package_count(&build_dir.join("Cargo.lock")).unwrap_or(1).saturating_sub(1);
+            let size = std::fs::metadata(&built).map(|metadata| metadata.len()).unwrap_or_default();
+            eprintln!(
+                "built {} in {:.1}s: {} dependenc{}, a {} binary, cache key {}",
+                script_name, started.elapsed().as_secs_f64(), dependencies, if dependencies == 1 { "y" } else { "ies" },
+                format_size(size), &cache_key[..12]
+            );
+        }
+
+        captured = capture(&build_output);
+        // a daemon outlives us, and so has to run from the cache rather than our tempdir, as
+        // does a watched script, whose build directory the next rebuild writes over
+        if opts.daemon || opts.watch || opts.capture {
+            (materialize(opts.cache_dir.as_path(), store.as_ref(), &cache_key, &project.name)?, None)
+        } else {
+            (built, tempdir)
+        }
+    };
+    profile::mark("materializing");
+    if config.deny_advisories && !opts.allow_advisories {
+        let lockfile = stored_lockfile(opts.cache_dir.as_path(), &project.cargo_toml()?);
+        // a script with no dependencies has nothing to audit
+        if progress::package_count(&lockfile).is_none_or(|packages| packages > 1) {
+            advisories::check(&script_name, &find_cargo()?, &lockfile)?;
+        }
+        profile::mark("auditing");
+    }
+    if local && stale_key.is_none() && project.manifest.background_rebuild {
+        index::record_build(opts.cache_dir.as_path(), &script_path, &cache_key);
+    }
+    // root's cache and build directories needn't be readable by whoever the script runs as
+    #[cfg(unix)]
+    if let Some(identity) = &identity {
+        let (staged, dir) = identity.stage(&binary)?;
+        binary = staged;
+        tempdir = Some(dir);
+    }
+    let sandbox = opts.sandbox
+        .then(|| sandbox::Sandbox::new(&script_name, &binary, project.manifest.permissions.as_ref()))
+        .transpose()?;
+    let mut command = command(&binary, &script_name, cwd.as_deref(), &opts.rest, &env, project.manifest.limits.as_ref(), sandbox.as_ref());
+    #[cfg(unix)]
+    if let Some(identity) = &identity {
+        identity.install(&mut command);
+    }
+    if let Some(sandbox) = &sandbox {
+        sandbox.install(&mut command);
+    }
+
+    // what a change to should trigger a rebuild under --watch
+    let mut watched = vec![script_path.clone()];
+    if let Some(tree) = &tree {
+        watched.push(tree.clone());
+        watched.push(tree.join("clawbang.toml"));
+    }
+    if url.is_none() {
+        watched.extend(project.includes.iter().map(|include| script_dir.join(&include.path)));
+    }
+    watched.extend(project.manifest.env_files.iter().map(|path| script_dir.join(path)));
+    watched.extend(opts.env_file.iter().cloned());
+
+    report.binary(&binary);
+    if let Some(on_event) = &opts.on_event {
+        on_event(&BuildEvent::ArtifactReady { binary: binary.clone(), cache_hit: hit });
+    }
+    Ok(Build::Ready(Box::new(Launch { command, binary, script_name, tempdir, watched, report, hit, diagnostics: captured })))
+}
+
+// The last good build of a local script, if it has one under some other key.
+fn stale_build(cache: &Path, store: &dyn CacheStore, script: &Path, cache_key: &str) -> Result<Option<(String, CachedBuild)>> {
+    let key = match index::latest_build(cache, script) {
+        Some(key) if key != cache_key => key,
+        _ => return Ok(None),
+    };
+    Ok(store.metadata(&key)?.filter(|build| build.exit_code == 0).map(|build| (key, build)))
+}
+
+// Build the script as it is now in a clawbang of its own, for the next run to find. It's
+// left to finish after this one has handed the process over to the stale build, in a
+// process group of its own so the terminal's ^C doesn't reach it.
+fn rebuild_in_background(opts: &Options, file: &Path) -> Result<()> {
+    let mut rebuild = process::Command::new(std::env::current_exe()?);
+    rebuild.args(["--quiet", "--build-only", "--cache-dir"]).arg(absolute(&opts.cache_dir));
+    if let Some(config) = &opts.config {
+        rebuild.arg("--config").arg(absolute(config));
+    }
+    rebuild.arg("--").arg(absolute(file))
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        rebuild.process_group(0);
+    }
+    tracing::debug!(script = %file.display(), "rebuilding in the background");
+    rebuild.spawn()?;
+    Ok(())
+}
+
+// The same status coreutils' `timeout` uses, so callers can tell a timeout from a failure.
+pub(crate) const TIMED_OUT_EXIT: i32 = 124;
+
+// How long a timed-out script gets to clean up after SIGTERM before it's killed outright.
+const TIMEOUT_GRACE: Duration = Duration::from_secs(5);
+
+fn wait_until(child: &mut process::Child, deadline: Instant) -> Result<Option<process::ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status))
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None)
+        }
+        std::thread::sleep((deadline - now).min(Duration::from_millis(20)));
+    }
+}
+
+fn stop(child: &mut process::Child) -> Result<()> {
+    #[cfg(unix)]
+    {
+        signals::terminate(child.id());
+        if wait_until(child, Instant::now() + TIMEOUT_GRACE)?.is_some() {
+            return Ok(())
+        }
+    }
+    child.kill()?;
+    child.wait()?;
+    Ok(())
+}
+
+fn parse_duration(input: &str) -> Result<Duration> {
+    let split = input.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(input.len());
+    let (amount, unit) = input.split_at(split);
+    let amount: f64 = amount.parse().map_err(|_| eyre::eyre!("expected a duration like 30s, 5m, or 1h"))?;
+    let seconds = match unit.trim() {
+        "ms" => amount / 1000.0,
+        "" | "s" => amount,
+        "m" => amount * 60.0,
+        "h" => amount * 3600.0,
+        unit => return Err(eyre::eyre!("unknown duration unit \"{}\"; use ms, s, m, or h", unit)),
+    };
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+// `https://example.com/tools/fmt.rs?raw=1` runs as "fmt"
+fn url_stem(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or(url).trim_end_matches('/');
+    let last = path.rsplit('/').next().unwrap_or(path);
+    let stem = last.strip_suffix(".rs").unwrap_or(last);
+    if stem.is_empty() || stem.contains(':') {
+        "script".to_string()
+    } else {
+        stem.to_string()
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    path.canonicalize()
+        .or_else(|_| std::env::current_dir().map(|cwd| cwd.join(path)))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+// The script sees itself under the name it was invoked by, not the cached artifact's,
+// so `ps`, panic messages, and usage lines make sense.
+fn command(binary: &Path, script_name: &str, cwd: Option<&Path>, rest: &[OsString], env: &[(String, String)], limits: Option<&limits::Limits>, sandbox: Option<&sandbox::Sandbox>) -> process::Command {
+    let mut command = match sandbox {
+        Some(sandbox) => sandbox.program(binary),
+        None => process::Command::new(binary),
+    };
+    if let Some(cwd) = cwd {
+        command.current_dir(cwd);
+    }
+    if let Some(limits) = limits {
+        limits.install(&mut command);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.arg0(script_name);
+    }
+    #[cfg(not(unix))]
+    let _ = script_name;
+
+    // stdin is inherited as-is, for `producer | clawbang script.rs | consumer`
+    command.args(rest).envs(env.iter().map(|(name, value)| (name, value)));
+    command
+}
+
+// Run the script as a child process and exit with its status, for when clawbang can't
+// simply exec it: after a fresh build (the tempdir needs cleaning up), when there's a
+// timeout to enforce, a terminal to relay, or a restart policy to follow, and off Unix.
+fn run(mut command: process::Command, script_name: &str, tempdir: Option<tempfile::TempDir>, mut report: report::Report, opts: &Options) -> Result<()> {
+    let mut supervisor = opts.restart.clone().map(restart::Supervisor::new);
+    let first_started = Instant::now();
+    loop {
+        let started = Instant::now();
+        let status = run_once(&mut command, opts)?;
+        let failure = match status {
+            Some(status) if status.success() => None,
+            Some(status) => Some(describe_exit(status)),
+            None => Some(format!("timed out after {:?}", opts.timeout.unwrap_or_default())),
+        };
+
+        let restarting = match (&mut supervisor, &failure) {
+            (Some(supervisor), Some(failure)) => supervisor.restart(script_name, started, failure),
+            _ => false,
+        };
+        if restarting {
+            continue
+        }
+        // exiting skips destructors, so the script's directory goes first
+        drop(tempdir);
+        if let Some(path) = &opts.report {
+            report.exited(status, first_started.elapsed());
+            report.write(path)?;
+        }
+        // a cron log says how each run went, since nobody's watching it happen
+        if opts.cron {
+            let outcome = status.map(describe_exit).or_else(|| failure.clone()).unwrap_or_default();
+            eprintln!("{} {} {} after {:.1}s", color::label("note"), script_name, outcome, first_started.elapsed().as_secs_f64());
+        }
+
+        let status = match status {
+            Some(status) => status,
+            None => {
+                if supervisor.is_none() {
+                    eprintln!("{} {} {}", color::label("error"), script_name, failure.unwrap_or_default());
+                }
+                std::process::exit(TIMED_OUT_EXIT);
+            },
+        };
+        exit_like(status)
+    }
+}
+
+// Whether clawbang has been signalled to stop while it waits on a script.
+#[cfg(unix)]
+fn stop_requested() -> bool {
+    signals::stop_requested()
+}
+
+#[cfg(not(unix))]
+fn stop_requested() -> bool {
+    false
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1048575 => format!("{:.0} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1048576.0),
+    }
+}
+
+// A cache entry's timestamp, in milliseconds since the epoch, as a UTC date and time.
+fn format_time(millis: u128) -> String {
+    let (year, month, day, time) = utc(millis);
+    format!("{:04}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, time / 3600, time % 3600 / 60)
+}
+
+// A timestamp in milliseconds since the epoch, as RFC 3339 wants it.
+#[cfg(feature = "cli")]
+fn rfc3339(millis: u128) -> String {
+    let (year, month, day, time) = utc(millis);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+// The UTC date of a time in milliseconds since the epoch, and the seconds into that day.
+fn utc(millis: u128) -> (i64, i64, i64, i64) {
+    let seconds = (millis / 1000) as i64;
+    let (days, time) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+    // the proleptic Gregorian calendar from a day count, after Howard Hinnant's civil_from_days
+    let shifted = days + 719468;
+    let era = shifted.div_euclid(146097);
+    let day_of_era = shifted.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day, time)
+}
+
+fn describe_exit(status: process::ExitStatus) -> String {
+    match status.code() {
+        Some(code) => format!("exited with status {}", code),
+        None => format!("was stopped by {}", status),
+    }
+}
+
+// Exit with the script's status, or die of the signal that killed it.
+fn exit_like(status: process::ExitStatus) -> ! {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            signals::exit_with(signal as u8);
+        }
+    }
+    std::process::exit(status.code().unwrap_or(-1));
+}
+
+// Start the script once and wait for it; `None` means it ran out of time and was stopped.
+fn run_once(command: &mut process::Command, opts: &Options) -> Result<Option<process::ExitStatus>> {
+    #[cfg(unix)]
+    let (mut child, pty) = if opts.tty {
+        let (child, pty) = pty::spawn(command)?;
+        (child, Some(pty))
+    } else {
+        (command.spawn()?, None)
+    };
+    #[cfg(not(unix))]
+    let mut child = if opts.tty {
+        return Err(eyre::eyre!("--tty is only supported on Unix"))
+    } else {
+        command.spawn()?
+    };
+    #[cfg(unix)]
+    signals::forward_to(child.id());
+
+    let status = match opts.timeout {
+        None => Some(child.wait()?),
+        Some(timeout) => match wait_until(&mut child, Instant::now() + timeout)? {
+            Some(status) => Some(status),
+            None => {
+                stop(&mut child)?;
+                None
+            },
+        },
+    };
+
+    // the terminal has to be put back before exiting, which skips destructors
+    #[cfg(unix)]
+    if let Some(pty) = pty {
+        pty.finish();
+    }
+    Ok(status)
+}
+
+fn cache_error(err: impl std::fmt::Display) -> eyre::Report {
+    errors::CACHE.error(format!("Could not read from the cache: {}", err))
+}
+
+fn cache_store(opts: &Options) -> Arc<dyn CacheStore> {
+    opts.store.clone().unwrap_or_else(|| Arc::new(CacacheStore::new(&opts.cache_dir)))
+}
+
+// Cached binaries are unpacked once into `<cache>/bin/<key>/<name>` so a hit can exec them
+// in place, under the script's own name as far as `ps` and `top` are concerned. The copy is
+// written beside its final name and renamed, so concurrent runs never see a partial file.
+fn unpacked_binary(cache: &Path, cache_key: &str, name: &str) -> PathBuf {
+    cache.join("bin").join(cache_key).join(format!("{}{}", name, std::env::consts::EXE_SUFFIX))
+}
+
+fn materialize(cache: &Path, store: &dyn CacheStore, cache_key: &str, name: &str) -> Result<PathBuf> {
+    let binary = unpacked_binary(cache, cache_key, name);
+    if binary.exists() {
+        return Ok(binary)
+    }
+
+    let dir = binary.parent().expect("unpacked binaries live in a directory");
+    std::fs::create_dir_all(dir)?;
+    // no handle of ours is left open on the copy, since exec refuses a file that something
+    // has open for writing, and a thread starting a script meanwhile would inherit it
+    let partial = tempfile::NamedTempFile::new_in(dir)?.into_temp_path();
+    store.read_artifact(cache_key, &partial)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&partial, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    partial.persist(&binary)?;
+    Ok(binary)
+}
+
+// A store can't take a file in other than by copying it, but the first cache hit
+// needn't copy it back out again: a fresh build's binary is linked to where materialize would
+// put it, replacing any older build under the same key (as from --rebuild). If it can't be
+// linked, across filesystems say, that first hit copies it out after all.
+fn link_binary(cache: &Path, cache_key: &str, name: &str, built: &Path) {
+    let unpacked = unpacked_binary(cache, cache_key, name);
+    let dir = unpacked.parent().expect("unpacked binaries live in a directory");
+    let partial = dir.join(format!(".{}.{}", name, process::id()));
+    let linked = std::fs::create_dir_all(dir)
+        .and_then(|_| std::fs::hard_link(built, &partial))
+        .and_then(|_| std::fs::rename(&partial, &unpacked));
+    if let Err(err) = linked {
+        let _ = std::fs::remove_file(&partial);
+        tracing::debug!(%err, "couldn't link the binary into the cache");
+    }
+}
+
+// Linux (and most other kernels) hand everything after the interpreter in a `#!` line over
+// as one argument, so `#!/usr/bin/clawbang --timeout 5m` arrives as "--timeout 5m". When the
+// first argument looks like that and the script's own first line bears it out, split it.
+#[cfg(feature = "cli")]
+fn split_shebang_options(mut args: Vec<OsString>) -> Vec<OsString> {
+    let blob = match args.get(1).and_then(|arg| arg.to_str()) {
+        Some(blob) if blob.starts_with('-') && blob.contains(char::is_whitespace) => blob.to_string(),
+        _ => return args,
+    };
+    let from_shebang = args.get(2)
+        .and_then(|script| std::fs::File::open(script).ok())
+        .and_then(|script| std::io::BufReader::new(script).lines().next()?.ok())
+        .map(|line| line.starts_with("#!") && line.contains(&blob))
+        .unwrap_or(false);
+    if from_shebang {
+        args.splice(1..2, blob.split_whitespace().map(OsString::from));
+    }
+    args
+}
+
+// Everything after the script path belongs to the script, even if it looks like one of our
+// flags (`clawbang tool.rs --verbose`), so split the command line there before clap sees
+// it. Finding the path means knowing which of our options take a value. With `-e` there is
+// no path, and the script's arguments start at the first positional one.
+#[cfg(feature = "cli")]
+fn split_script_args(mut args: Vec<OsString>) -> (Vec<OsString>, Vec<OsString>) {
+    let cli = <Cli as clap::CommandFactory>::command();
+    // a subcommand may add options of its own, like bench-run's --runs
+    let subcommand = args.get(1).and_then(|arg| cli.find_subcommand(&*arg.to_string_lossy()));
+    let ours = subcommand.unwrap_or(&cli);
+    // and some, like bundle-multi, don't run a script at all
+    if !ours.get_arguments().any(|arg| arg.get_id() == "file") {
+        return (args, Vec::new())
+    }
+    let long_value = |name: &str| ours.get_arguments().any(|arg| arg.get_long() == Some(name) && arg.is_takes_value_set());
+    let short_value = |name: char| ours.get_arguments().any(|arg| arg.get_short() == Some(name) && arg.is_takes_value_set());
+
+    let mut idx = 1;
+    let mut eval = false;
+    while idx < args.len() {
+        let arg = args[idx].to_string_lossy();
+        if arg == "--" {
+            idx += 1;
+            break
+        } else if let Some(long) = arg.strip_prefix("--") {
+            eval |= long.split('=').next() == Some("eval");
+            if !long.contains('=') && long_value(long) {
+                idx += 1;
+            }
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            let shorts = &arg[1..];
+            eval |= shorts.find(short_value).map(|at| &shorts[at..at + 1]) == Some("e");
+            if shorts.find(short_value) == Some(shorts.len() - 1) {
+                idx += 1;
+            }
+        } else if idx == 1 && subcommand.is_some() {
+            // `clawbang run ...`: the subcommand takes the same options
+        } else {
+            break
+        }
+        idx += 1;
+    }
+
+    let script_end = if eval { idx } else { idx + 1 };
+    let rest = if script_end <= args.len() { args.split_off(script_end) } else { Vec::new() };
+    (args, rest)
+}
+
+// Scripts this size and up are mapped rather than read; it's usually a big __DATA__ section,
+// and hashing it or copying its data out needn't hold it all in memory at once.
+const MAP_THRESHOLD: u64 = 1 << 20;
+
+// A local script's bytes, as read or mapped.
+enum ScriptBytes {
+    Read(Vec<u8>),
+    Mapped(memmap2::Mmap),
+}
+
+impl ScriptBytes {
+    fn open(mut handle: std::fs::File, metadata: &std::fs::Metadata) -> Result<Self> {
+        if metadata.len() >= MAP_THRESHOLD {
+            // SAFETY: the mapping is only read. A script truncated while it's mapped would
+            // fault on the missing pages, which is why small scripts (almost all of them,
+            // and all the ones people edit in place) are read instead.
+            if let Ok(mapped) = unsafe { memmap2::Mmap::map(&handle) } {
+                return Ok(ScriptBytes::Mapped(mapped))
+            }
+        }
+        let mut input = Vec::new();
+        handle.read_to_end(&mut input)?;
+        Ok(ScriptBytes::Read(input))
+    }
+}
+
+impl std::ops::Deref for ScriptBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            ScriptBytes::Read(input) => input,
+            ScriptBytes::Mapped(mapped) => mapped,
+        }
+    }
+}
+
+// A line by itself that ends a script read from stdin; anything after it is left
+// unread for the script, so `generate | clawbang - | consume` can still feed it.
+const STDIN_TERMINATOR: &[u8] = b"__END__";
+
+fn read_stdin_script() -> Result<Vec<u8>> {
+    // Read a byte at a time from the raw handle: a buffered reader would swallow input past
+    // the terminator that belongs to the script. The handle stays open for the script.
+    let mut stdin = match raw_stdin() {
+        Some(stdin) => stdin,
+        None => {
+            let mut input = Vec::new();
+            std::io::stdin().lock().read_to_end(&mut input)?;
+            return Ok(input)
+        },
+    };
+
+    let mut input = Vec::new();
+    let mut line_start = 0;
+    let mut byte = [0u8; 1];
+    loop {
+        match stdin.read(&mut byte) {
+            Ok(0) => return Ok(input),
+            Ok(_) => {},
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            // reading from a closed console handle on Windows reports a broken pipe
+            Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => return Ok(input),
+            Err(err) => return Err(err.into()),
+        }
+
+        if byte[0] == b'\n' {
+            let line = &input[line_start..];
+            if line.strip_suffix(b"\r").unwrap_or(line) == STDIN_TERMINATOR {
+                input.truncate(line_start);
+                return Ok(input)
+            }
+            input.push(b'\n');
+            line_start = input.len();
+        } else {
+            input.push(byte[0]);
+        }
+    }
+}
+
+fn raw_stdin() -> Option<std::mem::ManuallyDrop<std::fs::File>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::{ AsRawFd, FromRawFd };
+        let fd = std::io::stdin().as_raw_fd();
+        Some(std::mem::ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(fd) }))
+    }
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::io::{ AsRawHandle, FromRawHandle };
+        let handle = std::io::stdin().as_raw_handle();
+        if handle.is_null() {
+            return None
+        }
+        Some(std::mem::ManuallyDrop::new(unsafe { std::fs::File::from_raw_handle(handle) }))
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    None
+}
+
+/// One of the things a cache key is made from: the bytes that go into the hash, and how
+/// `--explain` describes them.
+struct KeyInput {
+    label: String,
+    bytes: Vec<u8>,
+}
+
+impl KeyInput {
+    fn new(label: impl Into<String>, tag: &[u8], parts: &[&[u8]]) -> Self {
+        let mut bytes = tag.to_vec();
+        for part in parts {
+            bytes.extend_from_slice(part);
+        }
+        KeyInput { label: label.into(), bytes }
+    }
+}
+
+// The source goes in by its digest, which index::source_digest can usually recall without
+// hashing the script again.
+fn key_inputs(source_digest: &str, project: &Project, toolchain: &str, defaults: &toml::value::Table) -> Vec<KeyInput> {
+    let mut inputs = vec![KeyInput::new(format!("the script's source (sha256 {})", &source_digest[..12]), b"", &[source_digest.as_bytes()])];
+
+    // so updating Rust rebuilds everything
+    let release = toolchain.lines().next().unwrap_or(toolchain);
+    inputs.push(KeyInput::new(format!("the toolchain, {}", release), b"\0toolchain\0", &[toolchain.as_bytes()]));
+
+    // an incremental build is a little slower to run, so it isn't what a plain run should find
+    if project.incremental {
+        inputs.push(KeyInput::new("building it incrementally, for --watch", b"\0incremental\0", &[]));
+    }
+
+    if project.completions {
+        inputs.push(KeyInput::new("printing its shell completions, for completions-for", b"\0completions\0", &[]));
+    }
+
+    // it's compiled into panic messages
+    inputs.push(KeyInput::new(format!("its file name, {}", project.file_name), b"\0file\0", &[project.file_name.as_bytes()]));
+
+    if let Some(wrap) = project.wrap {
+        inputs.push(KeyInput::new(format!("wrapping it as a {} body", wrap.name()), b"\0wrap\0", &[wrap.name().as_bytes()]));
+    }
+
+    // defaults change the generated project, so they have to change the key too
+    if !defaults.is_empty() {
+        let defaults = toml::to_string(defaults).unwrap_or_default();
+        inputs.push(KeyInput::new(format!("the [defaults] from the settings file (sha256 {})", short_digest(defaults.as_bytes())), b"\0defaults\0", &[defaults.as_bytes()]));
+    }
+
+    for include in &project.includes {
+        inputs.push(KeyInput::new(
+            format!("included file {} (sha256 {})", include.path, short_digest(&include.contents)),
+            b"\0include\0",
+            &[include.path.as_bytes(), b"\0", &include.contents],
+        ));
+    }
+
+    // whatever hooks make of the project is what gets built
+    if let Some(hooked) = &project.hooked {
+        let digest = short_digest(format!("{}\0{}", hooked.cargo_toml, hooked.main_rs).as_bytes());
+        inputs.push(KeyInput::new(
+            format!("the Cargo.toml and main.rs hooks made for it (sha256 {})", digest),
+            b"\0hooks\0",
+            &[hooked.cargo_toml.as_bytes(), b"\0", hooked.main_rs.as_bytes()],
+        ));
+    }
+    inputs
+}
+
+fn get_key(source_digest: &str, project: &Project, toolchain: &str, defaults: &toml::value::Table) -> String {
+    let mut hasher = Sha256::new();
+    for input in key_inputs(source_digest, project, toolchain, defaults) {
+        hasher.update(&input.bytes);
+    }
+    hex::encode(&hasher.finalize()[..])
+}
+
+fn short_digest(bytes: &[u8]) -> String {
+    hex::encode(&Sha256::digest(bytes)[..6])
+}
+
+/// Everything needed to generate the script's cargo project.
+struct Project<'a> {
+    name: String,
+    file_name: String, // the script's, as panics should report it
+    source: &'a str, // normalized; see manifest::normalize
+    wrap: Option<wrap::Wrap>,
+    script: manifest::Script<'a>,
+    manifest: manifest::Manifest,
+    includes: Vec<Include>,
+    incremental: bool, // under --watch, which rebuilds often
+    completions: bool, // for completions-for, which only prints them
+    build_scripts: Option<Vec<String>>, // the dependencies allowed build scripts, if others are refused
+    secrets: Vec<Vec<u8>>, // kept out of the build output that's recorded
+    hooked: Option<GeneratedProject>, // what's built in place of the generated project, if hooks changed it
+}
+
+impl Project<'_> {
+    /// The Cargo.toml that's built: the generated one, or the hooks' version of it.
+    fn cargo_toml(&self) -> Result<String> {
+        Ok(match &self.hooked {
+            Some(hooked) => hooked.cargo_toml.clone(),
+            None => self.manifest.cargo_toml()?,
+        })
+    }
+}
+
+/// A file the script pulls in at compile time, read from the script's directory.
+struct Include {
+    path: String, // as written in the script
+    contents: Vec<u8>,
+}
+
+// Without a script directory (for downloaded scripts) there's nothing to include.
+fn collect_includes(script_dir: Option<&Path>, script: &manifest::Script, manifest: &manifest::Manifest) -> Result<Vec<Include>> {
+    let mut includes = Vec::new();
+    let script_dir = match script_dir {
+        Some(script_dir) => script_dir,
+        None => return Ok(includes),
+    };
+    for path in &manifest.include {
+        let contents = std::fs::read(script_dir.join(path))
+            .map_err(|err| eyre::eyre!("Could not read \"{}\" listed in the frontmatter's include: {}", path, err))?;
+        includes.push(Include { path: path.clone(), contents });
+    }
+
+    // missing files are left for rustc to report; the scan can't tell code from comments
+    for path in manifest::detect_includes(script.body) {
+        if includes.iter().any(|include| include.path == path) {
+            continue
+        }
+        if let Ok(contents) = std::fs::read(script_dir.join(&path)) {
+            includes.push(Include { path, contents });
+        }
+    }
+
+    Ok(includes)
+}
+
+// Every file under a directory-mode script is part of the build (and the cache key),
+// apart from main.rs, which is the script itself, and build output or hidden files.
+fn collect_tree(tree: &Path, includes: &mut Vec<Include>) -> Result<()> {
+    let walker = walkdir::WalkDir::new(tree)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            let name = entry.file_name().to_string_lossy();
+            entry.depth() == 0 || !(name.starts_with('.') || (entry.depth() == 1 && name == "target"))
+        });
+
+    for entry in walker {
+        let entry = entry?;
+        if !entry.file_type().is_file() || (entry.depth() == 1 && entry.file_name() == "main.rs") {
+            continue
+        }
+
+        let path = entry.path().strip_prefix(tree)?.to_string_lossy().into_owned();
+        if includes.iter().any(|include| include.path == path) {
+            continue
+        }
+        includes.push(Include { contents: std::fs::read(entry.path())?, path });
+    }
+
+    Ok(())
+}
+
+// Relative includes resolve against src/main.rs, so they're placed under src/ in the
+// generated project. Paths that climb out of the script's directory can't be mirrored.
+fn include_destination(src: &Path, path: &str) -> Result<Option<PathBuf>> {
+    let path = Path::new(path);
+    if path.is_absolute() {
+        return Ok(None)
+    }
+
+    let mut dest = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::Normal(part) => dest.push(part),
+            std::path::Component::CurDir => {},
+            _ => return Err(eyre::eyre!(
+                "Included file \"{}\" is outside of the script's directory; use an absolute path instead",
+                path.display()
+            )),
+        }
+    }
+
+    Ok(Some(src.join(dest)))
+}
+
+// Write the script as `main.rs` in `src`, with its includes and data section beside it.
+fn write_sources(src: &Path, project: &Project) -> Result<()> {
+    std::fs::create_dir_all(src)?;
+    for include in &project.includes {
+        if let Some(dest) = include_destination(src, &include.path)? {
+            if let Some(parent) = dest.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(dest, &include.contents)?;
+        }
+    }
+
+    if let Some(data) = project.script.data {
+        std::fs::write(src.join("clawbang_data.bin"), data)?;
+    }
+    let main_rs = match &project.hooked {
+        Some(hooked) => hooked.main_rs.clone(),
+        None => main_rs(project),
+    };
+    std::fs::write(src.join("main.rs"), main_rs)?;
+    Ok(())
+}
+
+// The script as the generated project's main.rs: its body where it was in the script, with
+// whatever clawbang adds around it.
+fn main_rs(project: &Project) -> String {
+    let mut main_rs = manifest::pad_body(project.source, &project.script);
+    if let Some(wrap) = project.wrap {
+        wrap.apply(&mut main_rs, project.script.body.len());
+    }
+    if project.script.data.is_some() {
+        main_rs.push_str("\n#[allow(dead_code)]\nconst CLAWBANG_DATA: &[u8] = include_bytes!(\"clawbang_data.bin\");\n");
+    }
+
+    if let Some(args) = &project.manifest.args {
+        if project.completions {
+            let renamed = args::replace_main(&mut main_rs);
+            main_rs.push_str(&args.generate_completions(&project.name, renamed));
+        } else {
+            let wraps_main = args::wrap_main(&mut main_rs);
+            main_rs.push_str(&args.generate(&project.name, wraps_main));
+        }
+    }
+    main_rs
+}
+
+// Hand the generated project to the settings file's hooks, then the library's, if there are
+// any; what they leave is built in its place.
+fn run_hooks(opts: &Options, config: &config::Config, script_name: &str, project: &Project) -> Result<Option<GeneratedProject>> {
+    if config.hooks.is_empty() && opts.hooks.is_empty() {
+        return Ok(None)
+    }
+    let mut generated = GeneratedProject {
+        script_name: script_name.to_string(),
+        cargo_toml: project.manifest.cargo_toml()?,
+        main_rs: main_rs(project),
+    };
+    if !config.hooks.is_empty() {
+        config.hooks.apply(&mut generated)?;
+    }
+    for hook in &opts.hooks {
+        hook.apply(&mut generated)?;
+    }
+    Ok(Some(generated))
+}
+
+// Have panics (and debug info, if the script asks for it) name the script's files rather
+// than the generated project's. Diagnostics keep the project's paths, which
+// `diagnostics::remap` knows how to fix; limiting the remapping to panics needs a rustc
+// that has --remap-path-scope, so older ones go without.
+fn panic_remapping(cargo: &Path, tempdir: &Path, project: &Project) -> Vec<String> {
+    let scoped = process::Command::new(toolchain::rustc(cargo))
+        .args(["--remap-path-scope=macro", "-V"])
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+    if !scoped {
+        return Vec::new()
+    }
+
+    // the last match wins, so the most specific goes last
+    let mut flags = vec![
+        "--remap-path-scope=macro,debuginfo".to_string(),
+        // debug info records the directory the paths are relative to
+        format!("--remap-path-prefix={}=", tempdir.display()),
+    ];
+    match project.file_name.strip_suffix('/') {
+        Some(tree) => flags.push(format!("--remap-path-prefix=src={}", tree)),
+        None => {
+            flags.push("--remap-path-prefix=src/=".to_string());
+            flags.push(format!("--remap-path-prefix=src/main.rs={}", project.file_name));
+        },
+    }
+    flags
+}
+
+// Where --watch builds a script, under the cache's watch/.
+fn watch_dir(cache: &Path, file: &Path) -> PathBuf {
+    absolute(cache).join("watch").join(&hex::encode(Sha256::digest(absolute(file).as_os_str().as_encoded_bytes()))[..16])
+}
+
+// Write out the script's cargo project in `dir`, returning its Cargo.toml.
+fn generate(dir: &Path, project: &Project) -> Result<String> {
+    let cargo_toml = project.cargo_toml()?;
+    std::fs::write(dir.join("Cargo.toml"), cargo_toml.as_bytes())?;
+    write_sources(&dir.join("src"), project)?;
+    Ok(cargo_toml)
+}
+
+// What keeps cargo offline: given a stored lockfile, that and nothing else; otherwise,
+// under --no-net-build, what's already in cargo's download cache.
+fn offline_args(offline: bool, no_net_build: bool) -> &'static [&'static str] {
+    match (offline, no_net_build) {
+        (true, _) => &["--locked", "--offline"],
+        (false, true) => &["--offline"],
+        (false, false) => &[],
+    }
+}
+
+// Where the lockfile of the last build with this Cargo.toml is kept; see populate_cache.
+fn stored_lockfile(cache: &Path, cargo_toml: &str) -> PathBuf {
+    cache.join("lockfiles").join(hex::encode(Sha256::digest(cargo_toml.as_bytes())))
+}
+
+// Where cargo builds a project: beside it, unless prewarm has a target directory to share.
+fn target_dir(opts: &Options, tempdir: &Path) -> PathBuf {
+    opts.target_dir.clone().unwrap_or_else(|| tempdir.join("target"))
+}
+
+// How a script's project is built, into `target`: `cargo` with its arguments and environment.
+fn cargo_rustc(cargo: Exec, opts: &Options, project: &Project, target: &Path, offline: &[&str], remapping: &[String]) -> Exec {
+    let mut cargo = cargo.env("CARGO_TARGET_DIR", target);
+    if project.incremental {
+        cargo = cargo.env("CARGO_PROFILE_RELEASE_INCREMENTAL", "true");
+    }
+    if !project.manifest.rustflags.is_empty() {
+        cargo = cargo.env("CARGO_ENCODED_RUSTFLAGS", project.manifest.rustflags.join("\x1f"));
+    }
+
+    // cargo's output is colored for the cache; it's stripped again if wherever it's shown
+    // shouldn't get color. `cargo rustc` passes the remapping to the script's crate alone, leaving any RUSTFLAGS
+    // the user has set alone too
+    cargo
+        .arg("--color")
+        .arg("always")
+        .args(match opts.verbose {
+            0 | 1 => &[][..],
+            2 => &["-v"][..],
+            _ => &["-vv"][..],
+        })
+        .arg("rustc")
+        .arg("--release")
+        .args(offline)
+        .arg("--message-format")
+        .arg("json-diagnostic-rendered-ansi")
+        .arg("--")
+        .args(remapping)
+}
+
+fn populate_cache(
+    cache_key: &str,
+    tempdir: impl AsRef<Path>,
+    stdout: impl Write,
+    progress: &progress::Progress,
+    opts: &Options,
+    store: &dyn CacheStore,
+    project: &Project
+) -> Result<(i32, Vec<u8>)> {
+    let cache = opts.cache_dir.as_path();
+    let target = target_dir(opts, tempdir.as_ref());
+    let cargo_path = find_cargo()?;
+    let pb = PathBuf::from(tempdir.as_ref());
+    let span = tracing::info_span!("generate", project = %pb.display()).entered();
+    let cargo_toml = generate(&pb, project)?;
+    drop(span);
+    let _build = tracing::info_span!("cargo", cargo = %cargo_path.display()).entered();
+
+    let remapping = panic_remapping(&cargo_path, tempdir.as_ref(), project);
+    let mut stdout = stdout;
+    let isolated = match opts.no_net_build {
+        true => netns::isolated(),
+        false => None,
+    };
+    if opts.no_net_build && isolated.is_none() {
+        eprintln!("{} --no-net-build can't keep build scripts off the network here; only cargo is kept offline", color::label("warning"));
+    }
+    let cargo = || match isolated {
+        Some(prefix) => Exec::cmd(&prefix[0]).args(&prefix[1..]).arg(&cargo_path),
+        None => Exec::cmd(&cargo_path),
+    };
+    let mut build = |offline: bool| -> Result<(i32, Vec<u8>)> {
+        let mut popen = cargo_rustc(cargo(), opts, project, &target, offline_args(offline, opts.no_net_build), &remapping)
+            .env(lock::BUILD_STACK_VAR, lock::push(cache_key))
+            .stdout(subprocess::Redirection::Pipe)
+            .stderr(subprocess::Redirection::Merge)
+            .cwd(&tempdir)
+            .popen()?;
+
+        let mut out = Tee::new(&mut stdout);
+        if let Some(pstdout) = popen.stdout.take() {
+            for line in std::io::BufReader::new(pstdout).split(b'\n') {
+                let line = line?;
+                progress.observe(&line);
+                // kept as cargo printed it, to be rendered as whichever format asks for it later
+                out.write_all(&line)?;
+                out.write_all(b"\n")?;
+            }
+        }
+        popen.wait()?;
+
+        let exit_code = match popen.exit_status() {
+            Some(subprocess::ExitStatus::Exited(xs)) => xs as i32,
+            Some(subprocess::ExitStatus::Signaled(xs)) => 128 + xs as i32,
+            Some(subprocess::ExitStatus::Other(xs)) => xs,
+            _ => 1
+        };
+        tracing::info!(exit_code, offline, "cargo finished");
+        Ok((exit_code, out.into_inner().0))
+    };
+
+    // Resolving dependencies means updating the registry index, which can take longer than
+    // the rest of a small rebuild. A lockfile kept from an earlier build of the same manifest
+    // makes that unnecessary, unless a crate it names has gone from cargo's download cache:
+    // then the build fails before compiling anything, and is tried again online (unless
+    // it's not to go online at all).
+    let stored_lockfile = stored_lockfile(cache, &cargo_toml);
+    let mut offline = std::fs::copy(&stored_lockfile, pb.join("Cargo.lock")).is_ok();
+    if let Some(allow) = &project.build_scripts {
+        // resolving writes the lockfile the build goes on to use; a stored one that can't
+        // be used offline is resolved again, online, the same as for the build
+        let metadata = |offline| cargo().args(offline_args(offline, opts.no_net_build)).cwd(&tempdir);
+        let version = toolchain::version(cache)?;
+        let host = toolchain::host(&version);
+        let mut resolved = metadata::resolve(&project.file_name, metadata(offline), host);
+        if offline && resolved.is_err() && !opts.no_net_build {
+            let _ = std::fs::remove_file(pb.join("Cargo.lock"));
+            offline = false;
+            resolved = metadata::resolve(&project.file_name, metadata(offline), host);
+        }
+        build_scripts::check(&project.file_name, &resolved?, allow)?;
+    }
+    let (mut exit_code, mut accum) = build(offline)?;
+    if offline && !opts.no_net_build && exit_code != 0 && !diagnostics::compiled(&accum) {
+        tracing::info!("the stored lockfile wasn't enough to build offline; resolving again");
+        let _ = std::fs::remove_file(pb.join("Cargo.lock"));
+        offline = false;
+        (exit_code, accum) = build(offline)?;
+    }
+    if exit_code == 0 && !offline {
+        if let Ok(lockfile) = std::fs::read_to_string(pb.join("Cargo.lock")) {
+            let _ = index::record(&stored_lockfile, &lockfile);
+        }
+    }
+
+    // cargo and build scripts can echo their environment, and the output's kept for good
+    let accum = redact::scrub(accum, &project.secrets);
+    let binary = target.join("release").join(format!("bin{}", std::env::consts::EXE_SUFFIX));
+    store.write_artifact(cache_key, exit_code, &accum, (exit_code == 0).then_some(binary.as_path()))?;
+
+    if exit_code == 0 {
+        // for `clawbang verify`; a build without them only goes unverified
+        let recorded = verify::Digests::of(&pb.join("Cargo.lock"), &binary)
+            .and_then(|digests| digests.record(cache, cache_key));
+        if let Err(err) = recorded {
+            tracing::debug!(%err, "couldn't record the build's digests");
+        }
+    }
+
+    match exit_code {
+        0 => link_binary(cache, cache_key, &project.name, &binary),
+        // an older, good build under this key (before --rebuild) isn't a hit anymore
+        _ => { let _ = std::fs::remove_dir_all(cache.join("bin").join(cache_key)); },
+    }
+
+    Ok((exit_code, accum))
+}
+
+#[cfg(all(test, feature = "cli"))]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    // what `clawbang <args>` leaves to clap, and what it passes on to the script
+    fn split(args: &[&str]) -> (Vec<String>, Vec<String>) {
+        let args = std::iter::once("clawbang").chain(args.iter().copied()).map(OsString::from).collect();
+        let (ours, rest) = split_script_args(args);
+        let strings = |args: Vec<OsString>| args.into_iter().map(|arg| arg.to_string_lossy().into_owned()).collect();
+        (strings(ours), strings(rest))
+    }
+
+    #[test]
+    fn flags_after_the_script_are_the_scripts() {
+        assert_eq!(split(&["s.rs", "--verbose"]), (strings(&["clawbang", "s.rs"]), strings(&["--verbose"])));
+    }
+
+    #[test]
+    fn a_double_dash_ends_our_options() {
+        assert_eq!(split(&["--", "s.rs", "-x"]), (strings(&["clawbang", "--", "s.rs"]), strings(&["-x"])));
+    }
+
+    #[test]
+    fn eval_code_takes_the_scripts_place() {
+        assert_eq!(split(&["-ne", "code", "a.txt"]), (strings(&["clawbang", "-ne", "code"]), strings(&["a.txt"])));
+    }
+
+    #[test]
+    fn options_with_values_are_skipped_over() {
+        assert_eq!(split(&["--cache-dir=x", "s.rs", "-v"]), (strings(&["clawbang", "--cache-dir=x", "s.rs"]), strings(&["-v"])));
+        assert_eq!(split(&["--cache-dir", "x", "s.rs", "-v"]), (strings(&["clawbang", "--cache-dir", "x", "s.rs"]), strings(&["-v"])));
+    }
+
+    #[test]
+    fn a_subcommand_comes_before_the_script() {
+        assert_eq!(split(&["run", "s.rs", "--rebuild"]), (strings(&["clawbang", "run", "s.rs"]), strings(&["--rebuild"])));
+    }
+}
@@ -0,0 +1,91 @@
+use serde::Deserialize;
+
+/// The `[limits]` frontmatter table: resource ceilings applied to the script's process
+/// (and inherited by anything it starts) before it begins running.
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Limits {
+    memory: Option<Size>,
+    cpu_seconds: Option<u64>,
+    open_files: Option<u64>,
+    processes: Option<u64>,
+}
+
+/// A byte count, either as an integer or as a string like `"512MB"`. Units are powers of
+/// 1024, whether written `MB` or `MiB`.
+#[derive(Deserialize, Clone)]
+#[serde(try_from = "toml::Value")]
+struct Size(u64);
+
+impl TryFrom<toml::Value> for Size {
+    type Error = String;
+
+    fn try_from(value: toml::Value) -> Result<Self, Self::Error> {
+        let text = match value {
+            toml::Value::Integer(bytes) if bytes >= 0 => return Ok(Size(bytes as u64)),
+            toml::Value::String(text) => text,
+            _ => return Err("expected a size like \"512MB\"".to_string()),
+        };
+
+        let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+        let (amount, unit) = text.split_at(split);
+        let amount: u64 = amount.parse().map_err(|_| format!("expected a size like \"512MB\", not \"{}\"", text))?;
+        let scale: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+            "" | "B" => 1,
+            "K" | "KB" | "KIB" => 1 << 10,
+            "M" | "MB" | "MIB" => 1 << 20,
+            "G" | "GB" | "GIB" => 1 << 30,
+            "T" | "TB" | "TIB" => 1 << 40,
+            unit => return Err(format!("unknown size unit \"{}\"", unit)),
+        };
+        amount.checked_mul(scale).map(Size).ok_or_else(|| format!("\"{}\" is too large", text))
+    }
+}
+
+impl Limits {
+    /// Arrange for the limits to be set in the script's process just before it starts.
+    #[cfg(unix)]
+    pub(crate) fn install(&self, command: &mut std::process::Command) {
+        use std::os::unix::process::CommandExt;
+        let limits = self.clone();
+        unsafe {
+            command.pre_exec(move || limits.apply());
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn install(&self, _command: &mut std::process::Command) {
+        eprintln!("{} [limits] are only enforced on Unix", crate::color::label("warning"));
+    }
+
+    /// The limits as systemd's `Limit*=` settings, for a unit that runs the binary itself.
+    pub(crate) fn directives(&self) -> Vec<(&'static str, u64)> {
+        let limits = [
+            ("LimitAS", self.memory.as_ref().map(|size| size.0)),
+            ("LimitCPU", self.cpu_seconds),
+            ("LimitNOFILE", self.open_files),
+            ("LimitNPROC", self.processes),
+        ];
+        limits.into_iter().filter_map(|(name, limit)| Some((name, limit?))).collect()
+    }
+
+    // This runs in the child between fork and exec, so it sticks to plain system calls.
+    #[cfg(unix)]
+    fn apply(&self) -> std::io::Result<()> {
+        let limits = [
+            (libc::RLIMIT_AS, self.memory.as_ref().map(|size| size.0)),
+            (libc::RLIMIT_CPU, self.cpu_seconds),
+            (libc::RLIMIT_NOFILE, self.open_files),
+            (libc::RLIMIT_NPROC, self.processes),
+        ];
+        for (resource, limit) in limits {
+            if let Some(limit) = limit {
+                let limit = libc::rlimit { rlim_cur: limit as libc::rlim_t, rlim_max: limit as libc::rlim_t };
+                if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+                    return Err(std::io::Error::last_os_error())
+                }
+            }
+        }
+        Ok(())
+    }
+}
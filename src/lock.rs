@@ -0,0 +1,53 @@
+use eyre::Result;
+use std::path::Path;
+
+/// Set on cargo while clawbang builds a script: the cache keys of every build in progress
+/// above it, comma-separated. A build script (or anything it runs) that calls back into
+/// clawbang for one of those keys would wait forever on our lock.
+pub(crate) const BUILD_STACK_VAR: &str = "CLAWBANG_BUILDING";
+
+/// Held while one cache key is being built, so concurrent cold runs of the same script
+/// wait for the first build instead of repeating it. Released on drop.
+pub(crate) struct BuildLock {
+    _file: std::fs::File,
+}
+
+pub(crate) fn acquire(cache: &Path, cache_key: &str, script_name: &str) -> Result<BuildLock> {
+    if build_stack().iter().any(|key| key == cache_key) {
+        return Err(crate::errors::LOCK.error(format!(
+            "{} is already being built by a clawbang further up this process tree; a script can't run itself from its own build",
+            script_name
+        )))
+    }
+
+    let dir = cache.join("locks");
+    let lock_error = |err: std::io::Error| crate::errors::LOCK.error(format!("Could not lock {} for building: {}", dir.display(), err));
+    std::fs::create_dir_all(&dir).map_err(lock_error)?;
+    let file = std::fs::OpenOptions::new().create(true).truncate(false).write(true).open(dir.join(cache_key)).map_err(lock_error)?;
+
+    match file.try_lock() {
+        Ok(()) => {},
+        Err(std::fs::TryLockError::WouldBlock) => {
+            eprintln!("waiting for another clawbang to finish building {}", script_name);
+            file.lock().map_err(lock_error)?;
+        },
+        Err(std::fs::TryLockError::Error(err)) => return Err(lock_error(err)),
+    }
+
+    Ok(BuildLock { _file: file })
+}
+
+/// The build stack to hand to cargo while building `cache_key`.
+pub(crate) fn push(cache_key: &str) -> String {
+    let mut stack = build_stack();
+    stack.push(cache_key.to_string());
+    stack.join(",")
+}
+
+fn build_stack() -> Vec<String> {
+    std::env::var(BUILD_STACK_VAR).unwrap_or_default()
+        .split(',')
+        .filter(|key| !key.is_empty())
+        .map(String::from)
+        .collect()
+}
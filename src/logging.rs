@@ -0,0 +1,27 @@
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
+
+/// Set up clawbang's own logs on stderr: a span around each step of finding, building, and
+/// starting a script, closed with how long it took. `CLAWBANG_LOG`, or failing that
+/// `RUST_LOG`, picks what's shown with the usual `target=level` directives; the top rung of
+/// `-v`, `-vvv`, shows everything.
+pub(crate) fn init(verbose: usize, json: bool) {
+    let filter = match verbose {
+        0..=2 => match std::env::var("CLAWBANG_LOG").or_else(|_| std::env::var("RUST_LOG")) {
+            Ok(directives) => EnvFilter::new(directives),
+            Err(_) => EnvFilter::new("clawbang=warn"),
+        },
+        _ => EnvFilter::new("clawbang=trace"),
+    };
+
+    let logs = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_ansi(crate::color::stderr_enabled())
+        .with_writer(std::io::stderr);
+    // the subscriber is only ever set once, so this can't fail
+    let _ = match json {
+        true => logs.json().try_init(),
+        false => logs.try_init(),
+    };
+}
@@ -0,0 +1,476 @@
+use eyre::Result;
+use serde_json::{ json, Value };
+use sha2::{ Digest, Sha256 };
+use std::collections::HashMap;
+use std::io::{ BufRead, BufReader, Write };
+use std::path::{ Path, PathBuf };
+use std::process::{ Command, Stdio };
+use std::sync::{ Arc, Mutex };
+
+/// `clawbang lsp`: a language server for editors to start in place of rust-analyzer.
+#[derive(clap::Args)]
+pub(crate) struct LspOptions {
+    /// The rust-analyzer to run behind it
+    #[clap(long, env = "CLAWBANG_RUST_ANALYZER", default_value = "rust-analyzer", parse(from_os_str))]
+    rust_analyzer: PathBuf,
+
+    #[clap(flatten)]
+    cache: crate::CacheOptions,
+}
+
+/// A script the editor has open, and the project rust-analyzer sees in its place.
+struct Script {
+    // the generated main.rs, by URI, and the directory it's in
+    generated: String,
+    dir: PathBuf,
+    // for a directory script, what its files' URIs start with, in the editor and the project
+    tree: Option<(String, String)>,
+    // the script as the editor has it, which needn't have been saved
+    text: String,
+    // where the body starts; before it is the (blanked) frontmatter
+    body_line: u64,
+    // past the script's end, everything is clawbang's addition
+    lines: u64,
+    cargo_toml: String,
+}
+
+#[derive(Clone, Copy)]
+enum Encoding {
+    Utf8,
+    Utf16,
+    Utf32,
+}
+
+/// What the two directions share.
+struct State {
+    scripts: HashMap<String, Script>,
+    encoding: Encoding,
+    // the projects rust-analyzer would link without us: the workspace's own, or the user's
+    linked: Vec<Value>,
+    // rust-analyzer asking the editor for its settings, by request id, with where in the
+    // response its own section is
+    configuration: HashMap<String, usize>,
+    // formatting requests for scripts, by request id, with the script they're for
+    formatting: HashMap<String, String>,
+    next_id: u64,
+}
+
+/// Run rust-analyzer, relaying what the editor and it say to each other. Each script the
+/// editor opens gets a cargo project of its own under the cache's `lsp/`, written from the
+/// script as it's being edited, and rust-analyzer is told to link it; the script's URI
+/// stands for its generated main.rs in everything it's told, and the other way around in
+/// everything it says. The frontmatter is blanked on the way, so lines and columns are the
+/// same in both.
+pub(crate) fn run(lsp: &LspOptions) -> Result<()> {
+    let config = crate::config::load(lsp.cache.config.as_deref())?;
+    let mut server = Command::new(&lsp.rust_analyzer)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| eyre::eyre!("Could not start {}: {}", lsp.rust_analyzer.display(), err))?;
+    let mut to_server = server.stdin.take().expect("piped");
+    let from_server = server.stdout.take().expect("piped");
+
+    let state = Arc::new(Mutex::new(State {
+        scripts: HashMap::new(),
+        encoding: Encoding::Utf16,
+        linked: Vec::new(),
+        configuration: HashMap::new(),
+        formatting: HashMap::new(),
+        next_id: 0,
+    }));
+    let relay = {
+        let state = Arc::clone(&state);
+        std::thread::spawn(move || to_client(BufReader::new(from_server), &state))
+    };
+
+    let mut from_client = BufReader::new(std::io::stdin().lock());
+    while let Some(mut message) = read(&mut from_client)? {
+        let mut state = state.lock().expect("poisoned");
+        let mut after = Vec::new();
+        match message["method"].as_str() {
+            Some("initialize") => state.linked = linked(&message["params"]),
+            Some("textDocument/didOpen") => {
+                let document = &mut message["params"]["textDocument"];
+                let (uri, text) = (document["uri"].as_str().unwrap_or_default().to_string(), document["text"].as_str().unwrap_or_default().to_string());
+                if let Some(path) = path_of(&uri).filter(|path| document["languageId"] == "rust" && is_script(path, &text)) {
+                    let known = state.scripts.contains_key(&uri);
+                    match open(lsp, &config, &path, text) {
+                        Ok(script) => {
+                            document["text"] = json!(std::fs::read_to_string(script.dir.join("src").join("main.rs"))?);
+                            state.scripts.insert(uri.clone(), script);
+                            if !known {
+                                // rust-analyzer asks for its settings again, and gets ours
+                                after.push(json!({ "jsonrpc": "2.0", "method": "workspace/didChangeConfiguration", "params": { "settings": {} } }));
+                            }
+                        },
+                        Err(err) => tracing::debug!(%err, %uri, "couldn't generate a project for the script"),
+                    }
+                }
+            },
+            Some("textDocument/didChange") => {
+                let uri = message["params"]["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+                let encoding = state.encoding;
+                if let Some(script) = state.scripts.get_mut(&uri) {
+                    for change in message["params"]["contentChanges"].as_array().into_iter().flatten() {
+                        apply(&mut script.text, change, encoding);
+                    }
+                    let text = script.text.clone();
+                    match open(lsp, &config, &path_of(&uri).unwrap_or_default(), text.clone()) {
+                        Ok(regenerated) => {
+                            let main_rs = std::fs::read_to_string(regenerated.dir.join("src").join("main.rs"))?;
+                            message["params"]["contentChanges"] = json!([{ "text": main_rs }]);
+                            let reload = regenerated.cargo_toml != script.cargo_toml;
+                            *script = regenerated;
+                            // a new dependency, say
+                            if reload {
+                                after.push(json!({ "jsonrpc": "2.0", "id": format!("clawbang-{}", state.next_id), "method": "rust-analyzer/reloadWorkspace" }));
+                                state.next_id += 1;
+                            }
+                        },
+                        // mid-edit, the frontmatter may not parse; it's passed on as it is
+                        Err(err) => {
+                            tracing::debug!(%err, %uri, "couldn't regenerate the script's project");
+                            message["params"]["contentChanges"] = json!([{ "text": text }]);
+                        },
+                    }
+                }
+            },
+            Some("textDocument/didSave") => {
+                // flycheck reads the project from disk, where a directory script's other
+                // files are copies
+                let uri = message["params"]["textDocument"]["uri"].as_str().unwrap_or_default();
+                if let (Some(from), Some(to)) = (path_of(uri), path_of(&translate_uri(&state, uri, true))) {
+                    if from != to && !state.scripts.contains_key(uri) {
+                        let _ = std::fs::copy(from, to);
+                    }
+                }
+            },
+            Some("textDocument/formatting" | "textDocument/rangeFormatting" | "textDocument/onTypeFormatting") => {
+                let uri = message["params"]["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+                if state.scripts.contains_key(&uri) {
+                    state.formatting.insert(message["id"].to_string(), uri);
+                }
+            },
+            Some(_) => {},
+            // the editor's answer to a request from rust-analyzer
+            None => {
+                if let Some(index) = state.configuration.remove(&message["id"].to_string()) {
+                    link(&state, &mut message, index);
+                }
+            },
+        }
+        translate(&state, &mut message, true);
+        // rust-analyzer may be blocked on writing to us, which the relay needs the lock for
+        drop(state);
+        write(&mut to_server, &message)?;
+        for message in after {
+            write(&mut to_server, &message)?;
+        }
+        // editors needn't close our stdin once they've said that
+        if message["method"] == "exit" {
+            break
+        }
+    }
+
+    drop(to_server);
+    let _ = relay.join();
+    server.wait()?;
+    Ok(())
+}
+
+// rust-analyzer's side: everything goes on to the editor, but what answers our own requests.
+fn to_client(mut from_server: impl BufRead, state: &Mutex<State>) -> Result<()> {
+    let mut stdout = std::io::stdout();
+    while let Some(mut message) = read(&mut from_server)? {
+        let mut state = state.lock().expect("poisoned");
+        if message["id"].as_str().is_some_and(|id| id.starts_with("clawbang-")) && message.get("method").is_none() {
+            continue
+        }
+        if let Some(encoding) = message["result"]["capabilities"]["positionEncoding"].as_str() {
+            state.encoding = match encoding {
+                "utf-8" => Encoding::Utf8,
+                "utf-32" => Encoding::Utf32,
+                _ => Encoding::Utf16,
+            };
+        }
+        if message["method"] == "workspace/configuration" {
+            let items = message["params"]["items"].as_array().cloned().unwrap_or_default();
+            if let Some(index) = items.iter().position(|item| item["section"] == "rust-analyzer") {
+                state.configuration.insert(message["id"].to_string(), index);
+            }
+        }
+        if let Some(uri) = state.formatting.remove(&message["id"].to_string()) {
+            // rustfmt would tidy away the blanked frontmatter
+            let body_line = state.scripts.get(&uri).map_or(0, |script| script.body_line);
+            if let Some(edits) = message["result"].as_array_mut() {
+                edits.retain(|edit| edit["range"]["start"]["line"].as_u64().is_some_and(|line| line >= body_line));
+            }
+        }
+
+        translate(&state, &mut message, false);
+        if message["method"] == "textDocument/publishDiagnostics" {
+            let lines = message["params"]["uri"].as_str().and_then(|uri| state.scripts.get(uri)).map(|script| script.lines);
+            if let (Some(lines), Some(diagnostics)) = (lines, message["params"]["diagnostics"].as_array_mut()) {
+                diagnostics.retain(|diagnostic| diagnostic["range"]["start"]["line"].as_u64().is_some_and(|line| line < lines));
+            }
+        }
+        drop(state);
+        write(&mut stdout, &message)?;
+    }
+    Ok(())
+}
+
+// Write out the project for the script at `path`, as `text` has it: with clawbang's own
+// Cargo.toml and main.rs, just as it would build.
+fn open(lsp: &LspOptions, config: &crate::config::Config, path: &Path, text: String) -> Result<Script> {
+    let dir = crate::absolute(&lsp.cache.cache_dir).join("lsp").join(&hex::encode(Sha256::digest(crate::absolute(path).as_os_str().as_encoded_bytes()))[..16]);
+    std::fs::create_dir_all(&dir)?;
+    // a directory script's main.rs stands for the directory
+    let tree = (path.file_name() == Some("main.rs".as_ref()) && path.with_file_name("clawbang.toml").exists()).then(|| path.parent().unwrap_or(path));
+    let mut args = vec!["clawbang".into()];
+    args.extend(lsp.cache.args());
+    args.extend(["--".into(), tree.unwrap_or(path).as_os_str().to_owned()]);
+    let mut opts = <crate::Cli as clap::Parser>::parse_from(args).run;
+    opts.source = Some(text.clone().into_bytes());
+
+    let (cargo_toml, body_line) = crate::plan(&opts, config, |planned| {
+        let cargo_toml = crate::generate(&dir, &planned.project)?;
+        let project = &planned.project;
+        Ok((cargo_toml, project.source[..project.script.body_offset].matches('\n').count() as u64))
+    })?;
+    if !dir.join("Cargo.lock").exists() {
+        let _ = std::fs::copy(crate::stored_lockfile(&lsp.cache.cache_dir, &cargo_toml), dir.join("Cargo.lock"));
+    }
+
+    let generated = uri_of(&dir.join("src").join("main.rs"));
+    let tree = tree.map(|tree| (uri_of(tree) + "/", uri_of(&dir.join("src")) + "/"));
+    Ok(Script { generated, dir, tree, lines: text.lines().count() as u64, text, body_line, cargo_toml })
+}
+
+// The user's own linkedProjects, or failing that, what rust-analyzer would find in the
+// workspace by itself, as linking ours stops it looking.
+fn linked(initialize: &Value) -> Vec<Value> {
+    if let Some(linked) = initialize["initializationOptions"]["linkedProjects"].as_array() {
+        return linked.clone()
+    }
+    let folders = initialize["workspaceFolders"].as_array().into_iter().flatten().filter_map(|folder| folder["uri"].as_str());
+    folders.chain(initialize["rootUri"].as_str())
+        .filter_map(path_of)
+        .map(|folder| folder.join("Cargo.toml"))
+        .filter(|manifest| manifest.exists())
+        .map(|manifest| json!(manifest.to_string_lossy()))
+        .fold(Vec::new(), |mut linked, manifest| {
+            if !linked.contains(&manifest) {
+                linked.push(manifest);
+            }
+            linked
+        })
+}
+
+// Add the scripts' projects to the editor's answer for rust-analyzer's settings.
+fn link(state: &State, response: &mut Value, index: usize) {
+    if state.scripts.is_empty() {
+        return
+    }
+    let mut linked = state.linked.clone();
+    linked.extend(state.scripts.values().map(|script| json!(script.dir.join("Cargo.toml").to_string_lossy())));
+    // an editor that can't say gets rust-analyzer's defaults, plus these
+    if response.get("error").is_some() || !response["result"].is_array() {
+        response.as_object_mut().map(|response| response.remove("error"));
+        response["result"] = json!([]);
+    }
+    let result = response["result"].as_array_mut().expect("just made one");
+    while result.len() <= index {
+        result.push(Value::Null);
+    }
+    if !result[index].is_object() {
+        result[index] = json!({});
+    }
+    result[index]["linkedProjects"] = json!(linked);
+}
+
+// Swap each script's URI for its project's, or back again, wherever one appears.
+fn translate(state: &State, value: &mut Value, to_server: bool) {
+    match value {
+        Value::String(text) if text.starts_with("file://") => *text = translate_uri(state, text, to_server),
+        Value::Array(items) => items.iter_mut().for_each(|item| translate(state, item, to_server)),
+        Value::Object(fields) => fields.values_mut().for_each(|field| translate(state, field, to_server)),
+        _ => {},
+    }
+}
+
+fn translate_uri(state: &State, uri: &str, to_server: bool) -> String {
+    for (editor, script) in &state.scripts {
+        let (from, to) = match to_server {
+            true => (editor.as_str(), script.generated.as_str()),
+            false => (script.generated.as_str(), editor.as_str()),
+        };
+        if uri == from {
+            return to.to_string()
+        }
+        if let Some((editor, project)) = &script.tree {
+            let (from, to) = if to_server { (editor, project) } else { (project, editor) };
+            if let Some(rest) = uri.strip_prefix(from.as_str()) {
+                return format!("{}{}", to, rest)
+            }
+        }
+    }
+    uri.to_string()
+}
+
+// Worth a project: a script that says it's for clawbang in its shebang, or has frontmatter,
+// or a directory script's main.rs.
+fn is_script(path: &Path, text: &str) -> bool {
+    let mut lines = text.lines();
+    let first = lines.next().unwrap_or_default();
+    if first.starts_with("#!") && first.contains("clawbang") {
+        return true
+    }
+    let opening = if first.starts_with("#!") { lines.next().unwrap_or_default() } else { first };
+    opening.trim_start_matches(['/', '*', '!', ' ', '\t']).trim_end() == "+++"
+        || (path.file_name() == Some("main.rs".as_ref()) && path.with_file_name("clawbang.toml").exists())
+}
+
+// Apply one of the editor's changes to a document: the text for a range, or all of it.
+fn apply(text: &mut String, change: &Value, encoding: Encoding) {
+    let replacement = change["text"].as_str().unwrap_or_default();
+    if change.get("range").is_none() {
+        *text = replacement.to_string();
+        return
+    }
+    let start = offset(text, &change["range"]["start"], encoding);
+    let end = offset(text, &change["range"]["end"], encoding).max(start);
+    text.replace_range(start..end, replacement);
+}
+
+// A position's byte offset into `text`, with its character counted in the encoding's units.
+fn offset(text: &str, position: &Value, encoding: Encoding) -> usize {
+    let line = position["line"].as_u64().unwrap_or_default() as usize;
+    let character = position["character"].as_u64().unwrap_or_default() as usize;
+    let start = match line {
+        0 => 0,
+        line => match text.match_indices('\n').nth(line - 1) {
+            Some((at, _)) => at + 1,
+            None => return text.len(),
+        },
+    };
+    let mut units = 0;
+    for (at, c) in text[start..].char_indices() {
+        if units >= character || c == '\n' {
+            return start + at
+        }
+        units += match encoding {
+            Encoding::Utf8 => c.len_utf8(),
+            Encoding::Utf16 => c.len_utf16(),
+            Encoding::Utf32 => 1,
+        };
+    }
+    text.len()
+}
+
+fn path_of(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut bytes = path.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'%' => {
+                let hex: Vec<u8> = bytes.by_ref().take(2).collect();
+                decoded.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            },
+            byte => decoded.push(byte),
+        }
+    }
+    Some(PathBuf::from(String::from_utf8(decoded).ok()?))
+}
+
+fn uri_of(path: &Path) -> String {
+    let mut uri = "file://".to_string();
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'-' | b'.' | b'_' | b'~' => uri.push(byte as char),
+            byte => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+// A message, framed as the protocol frames them, or None once there are no more.
+fn read(input: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None)
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = Some(value.trim().parse::<usize>()?);
+            }
+        }
+    }
+    let length = length.ok_or_else(|| eyre::eyre!("a language server message came without a Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn write(output: &mut impl Write, message: &Value) -> Result<()> {
+    let body = serde_json::to_vec(message)?;
+    write!(output, "Content-Length: {}\r\n\r\n", body.len())?;
+    output.write_all(&body)?;
+    output.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 🦀 is 4 bytes in UTF-8 and 2 units in UTF-16; é is 2 bytes, and 1 unit in UTF-16
+    const TEXT: &str = "fn main() {\n    let s = \"é🦀x\";\n}\n";
+
+    fn at(line: u64, character: u64) -> Value {
+        json!({ "line": line, "character": character })
+    }
+
+    #[test]
+    fn offsets_count_characters_in_the_encodings_units() {
+        let x = TEXT.find('x').unwrap();
+        assert_eq!(offset(TEXT, &at(1, 19), Encoding::Utf8), x);
+        assert_eq!(offset(TEXT, &at(1, 16), Encoding::Utf16), x);
+        assert_eq!(offset(TEXT, &at(1, 15), Encoding::Utf32), x);
+        assert_eq!(offset(TEXT, &at(0, 0), Encoding::Utf16), 0);
+        // past the end of a line is its end, and past the last line, the text's
+        assert_eq!(offset(TEXT, &at(0, 80), Encoding::Utf16), TEXT.find('\n').unwrap());
+        assert_eq!(offset(TEXT, &at(9, 0), Encoding::Utf16), TEXT.len());
+    }
+
+    #[test]
+    fn applies_ranged_and_whole_changes() {
+        let mut text = TEXT.to_string();
+        // replace the crab (characters 14 to 16 of line 1, in UTF-16) with a word
+        apply(&mut text, &json!({ "range": { "start": at(1, 14), "end": at(1, 16) }, "text": "crab" }), Encoding::Utf16);
+        assert_eq!(text, "fn main() {\n    let s = \"écrabx\";\n}\n");
+        apply(&mut text, &json!({ "range": { "start": at(2, 1), "end": at(2, 1) }, "text": "\n// done" }), Encoding::Utf32);
+        assert_eq!(text, "fn main() {\n    let s = \"écrabx\";\n}\n// done\n");
+        apply(&mut text, &json!({ "text": "fn main() {}\n" }), Encoding::Utf8);
+        assert_eq!(text, "fn main() {}\n");
+    }
+
+    #[test]
+    fn uris_and_paths_round_trip() {
+        let path = Path::new("/home/me/my scripts/tål#1.rs");
+        let uri = uri_of(path);
+        assert_eq!(uri, "file:///home/me/my%20scripts/t%C3%A5l%231.rs");
+        assert_eq!(path_of(&uri).as_deref(), Some(path));
+        assert_eq!(path_of("file:///tmp/a%2Fb%20c.rs").as_deref(), Some(Path::new("/tmp/a/b c.rs")));
+        assert_eq!(path_of("https://example.com/a.rs"), None);
+        assert_eq!(path_of("file:///tmp/%zz.rs"), None);
+    }
+}
@@ -0,0 +1,787 @@
+//! Reading clawbang scripts: their shebang, frontmatter, Rust body, and `__DATA__` section,
+//! and what the frontmatter sets, for tools that want to understand a script without
+//! building it.
+//!
+//! ```
+//! let source = "#!/usr/bin/env clawbang\n// +++\n// [dependencies]\n// anyhow = \"1\"\n// +++\nfn main() {}\n";
+//! let script = clawbang::manifest::split_script(source)?;
+//! assert_eq!(script.shebang, Some("#!/usr/bin/env clawbang"));
+//! assert_eq!(&source[script.body_span()], "fn main() {}");
+//!
+//! let manifest = clawbang::manifest::parse(&script, &Default::default())?;
+//! assert!(manifest.cargo()["dependencies"].get("anyhow").is_some());
+//! # Ok::<(), clawbang::manifest::FrontmatterError>(())
+//! ```
+//!
+//! Offsets and spans are bytes into the text given to [`split_script`]. Scripts are
+//! [`normalize`]d before they're split, so for a file with CRLF line endings or a byte order
+//! mark, they're offsets into the normalized text.
+
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::ops::Range;
+
+use crate::args::ArgsSpec;
+use crate::limits::Limits;
+use crate::priority::Priority;
+use crate::service::Service;
+use crate::permissions::Permissions;
+
+/// A clawbang script split into its frontmatter and rust body.
+pub struct Script<'a> {
+    pub shebang: Option<&'a str>, // the `#!` line, without its newline
+    pub frontmatter: Cow<'a, str>, // same byte layout as the source, comment markers blanked
+    pub frontmatter_offset: usize, // byte offsets within the original source
+    pub body: &'a str,
+    pub body_offset: usize,
+    pub data: Option<&'a [u8]>, // everything after a `__DATA__` (or `#----`) line; see split_data
+}
+
+impl Script<'_> {
+    /// Where the frontmatter's TOML sits, between its delimiters; empty if there's none.
+    pub fn frontmatter_span(&self) -> Range<usize> {
+        self.frontmatter_offset..self.frontmatter_offset + self.frontmatter.len()
+    }
+
+    pub fn body_span(&self) -> Range<usize> {
+        self.body_offset..self.body_offset + self.body.len()
+    }
+
+    /// The line of the frontmatter that introduces a top-level key, as `key = ...` or as a
+    /// `[key]` (or `[key.sub]`) table header.
+    pub fn key_span(&self, key: &str) -> Option<Range<usize>> {
+        let mut line_start = 0;
+        for line in self.frontmatter.split_inclusive('\n') {
+            let trimmed = line.trim_start();
+            let candidate = trimmed.strip_prefix('[').unwrap_or(trimmed).trim_start();
+            if let Some(after) = candidate.strip_prefix(key) {
+                if after.trim_start().starts_with(['=', ']', '.']) {
+                    let start = self.frontmatter_offset + line_start + (line.len() - trimmed.len());
+                    return Some(start..start + trimmed.trim_end().len())
+                }
+            }
+            line_start += line.len();
+        }
+        None
+    }
+}
+
+/// A problem with the script's frontmatter, pointing at a byte offset in the original source.
+#[derive(Debug)]
+pub struct FrontmatterError {
+    pub message: String,
+    pub offset: usize,
+}
+
+impl std::fmt::Display for FrontmatterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for FrontmatterError {}
+
+impl FrontmatterError {
+    fn new(message: impl Into<String>, offset: usize) -> Self {
+        Self { message: message.into(), offset }
+    }
+
+    /// Render the error rustc-style, with the offending line of the script and a caret.
+    pub fn render(&self, source: &str, script_name: &str) -> String {
+        let offset = self.offset.min(source.len());
+        let line_start = source[..offset].rfind('\n').map(|idx| idx + 1).unwrap_or(0);
+        let line_end = source[offset..].find('\n').map(|idx| idx + offset).unwrap_or(source.len());
+        let line = source[..offset].matches('\n').count() + 1;
+        let column = source[line_start..offset].chars().count() + 1;
+
+        let text = source[line_start..line_end].trim_end_matches('\r');
+        let gutter = " ".repeat(line.to_string().len());
+        let caret = " ".repeat(column - 1);
+
+        let mut out = String::new();
+        let _ = writeln!(out, "{}", self.message);
+        let _ = writeln!(out, "{}--> {}:{}:{}", gutter, script_name, line, column);
+        let _ = writeln!(out, "{} |", gutter);
+        let _ = writeln!(out, "{} | {}", line, text);
+        let _ = write!(out, "{} | {}^", gutter, caret);
+        out
+    }
+}
+
+/// Strip a UTF-8 byte order mark and convert CRLF line endings, so scripts edited on
+/// Windows parse the same as everywhere else. Cache keys are computed from the original
+/// bytes, not this.
+pub fn normalize(source: &str) -> Cow<'_, str> {
+    let source = source.strip_prefix('\u{feff}').unwrap_or(source);
+    if source.contains("\r\n") {
+        Cow::Owned(source.replace("\r\n", "\n"))
+    } else {
+        Cow::Borrowed(source)
+    }
+}
+
+/// Split a script into its shebang line, frontmatter, and body. The only way this fails is
+/// frontmatter that's opened and never closed.
+pub fn split_script(source: &str) -> Result<Script<'_>, FrontmatterError> {
+    let mut start = 0;
+    let mut shebang = None;
+    if source.trim_start().starts_with("#!") {
+        start = source.find('\n').map(|idx| idx + 1).unwrap_or(source.len());
+        shebang = Some(source[..start].trim());
+    }
+    let rest = source[start..].trim_start();
+    let start = source.len() - rest.len();
+    let rest = rest.trim_end();
+
+    let (frontmatter, frontmatter_offset, body_offset) = match find_frontmatter(rest) {
+        Ok(Some((frontmatter, frontmatter_start, body_start))) => (frontmatter, start + frontmatter_start, start + body_start),
+        Ok(None) => (Cow::Borrowed(&rest[0..0]), start, start),
+        Err(()) => return Err(FrontmatterError::new("Hit EOF before finding end of frontmatter delimeter, \"+++\".", start)),
+    };
+
+    Ok(Script {
+        shebang,
+        frontmatter,
+        frontmatter_offset,
+        body: &source[body_offset..start + rest.len()],
+        body_offset,
+        data: None,
+    })
+}
+
+// Frontmatter comes in three forms, the latter two of which keep the file valid Rust:
+//
+//     +++          /* +++          // +++
+//     ...          ...             // ...
+//     +++          +++ */          // +++
+//
+// Returns the frontmatter plus the offsets where it and the body start, relative to `rest`.
+fn find_frontmatter(rest: &str) -> Result<Option<(Cow<'_, str>, usize, usize)>, ()> {
+    if let Some(after) = rest.strip_prefix("+++\n") {
+        return match after.find("\n+++\n") {
+            Some(end) => Ok(Some((Cow::Borrowed(&after[..end]), 4, 4 + end + 5))),
+            None if after.ends_with("\n+++") => Ok(Some((Cow::Borrowed(&after[..after.len() - 4]), 4, rest.len()))),
+            None => Err(()),
+        }
+    }
+
+    if let Some(inner) = rest.strip_prefix("/*").and_then(|after| after.trim_start_matches([' ', '\t']).strip_prefix("+++\n")) {
+        let frontmatter_start = rest.len() - inner.len();
+        let mut line_start = 0;
+        for line in inner.split_inclusive('\n') {
+            let closing = line.trim().strip_prefix("+++").map(str::trim_start);
+            if closing == Some("*/") {
+                return Ok(Some((Cow::Borrowed(&inner[..line_start]), frontmatter_start, frontmatter_start + line_start + line.len())))
+            }
+            line_start += line.len();
+        }
+        return Err(())
+    }
+
+    let first_line = rest.split_inclusive('\n').next().unwrap_or("");
+    if comment_text(first_line).map(str::trim) == Some("+++") {
+        let mut frontmatter = String::new();
+        let mut line_start = first_line.len();
+        for line in rest[first_line.len()..].split_inclusive('\n') {
+            let text = comment_text(line).ok_or(())?;
+            if text.trim() == "+++" {
+                return Ok(Some((Cow::Owned(frontmatter), first_line.len(), line_start + line.len())))
+            }
+            frontmatter.push_str(&" ".repeat(line.len() - text.len()));
+            frontmatter.push_str(text);
+            line_start += line.len();
+        }
+        return Err(())
+    }
+
+    Ok(None)
+}
+
+// The text of a `//`, `///`, or `//!` comment line.
+fn comment_text(line: &str) -> Option<&str> {
+    let text = line.trim_start().strip_prefix("//")?;
+    Some(text.strip_prefix(['!', '/']).unwrap_or(text))
+}
+
+/// Split a script's bytes at its `__DATA__` (or `#----`) line, if it has one, before they're
+/// read as text: the script before the line has to be UTF-8, but the data after it is kept
+/// exactly as it is in the file, whatever it holds. A marker line only counts once any
+/// frontmatter has closed, since `#----` could just as well be a TOML comment, and outside
+/// the body's string literals and block comments.
+pub fn split_data(input: &[u8]) -> (&[u8], Option<&[u8]>) {
+    let mut line_start = 0;
+    for line in input.split_inclusive(|byte| *byte == b'\n') {
+        let marker = line.trim_ascii_end();
+        if marker == b"__DATA__" || marker == b"#----" {
+            // the line itself can't close frontmatter, so if the script up to and including
+            // it splits, the line is in the body
+            let through = &input[..line_start + line.len()];
+            let splits = |text: &str| split_script(&normalize(text)).is_ok_and(|script| !unclosed(script.body));
+            if std::str::from_utf8(through).is_ok_and(splits) {
+                return (&input[..line_start], Some(&input[line_start + line.len()..]))
+            }
+        }
+        line_start += line.len();
+    }
+    (input, None)
+}
+
+// Whether Rust source ends partway through a string or character literal or a block
+// comment, as it does when it's cut off at a line that's inside one.
+fn unclosed(code: &str) -> bool {
+    let bytes = code.as_bytes();
+    let ident = |at: usize| bytes[at].is_ascii_alphanumeric() || bytes[at] == b'_';
+    let (mut at, mut comments) = (0, 0);
+    while at < bytes.len() {
+        let rest = &bytes[at..];
+        // block comments nest
+        if comments > 0 {
+            if rest.starts_with(b"*/") {
+                comments -= 1;
+                at += 2;
+            } else if rest.starts_with(b"/*") {
+                comments += 1;
+                at += 2;
+            } else {
+                at += 1;
+            }
+            continue
+        }
+        match rest {
+            [b'/', b'/', ..] => at += rest.iter().position(|byte| *byte == b'\n').unwrap_or(rest.len()),
+            [b'/', b'*', ..] => {
+                comments = 1;
+                at += 2;
+            },
+            [b'"', ..] => {
+                let mut end = 1;
+                while end < rest.len() && rest[end] != b'"' {
+                    end += if rest[end] == b'\\' { 2 } else { 1 };
+                }
+                if end >= rest.len() {
+                    return true
+                }
+                at += end + 1;
+            },
+            // r"...", r#"..."#, and br"...", but not the end of an identifier like `for`
+            [b'r', ..] | [b'b', b'r', ..] if at == 0 || !ident(at - 1) => {
+                let prefix = if rest[0] == b'b' { 2 } else { 1 };
+                let hashes = rest[prefix..].iter().take_while(|byte| **byte == b'#').count();
+                if rest.get(prefix + hashes) != Some(&b'"') {
+                    at += prefix;
+                    continue
+                }
+                let opening = prefix + hashes + 1;
+                let closing = [&b"\""[..], &rest[prefix..prefix + hashes]].concat();
+                match rest[opening..].windows(closing.len()).position(|window| window == closing) {
+                    Some(end) => at += opening + end + closing.len(),
+                    None => return true,
+                }
+            },
+            // a character literal, or else a lifetime or label
+            [b'\'', b'\\', ..] => match rest[2..].iter().skip(1).position(|byte| *byte == b'\'') {
+                Some(end) => at += end + 4,
+                None => return true,
+            },
+            [b'\'', ..] => {
+                let width = code[at + 1..].chars().next().map_or(0, char::len_utf8);
+                at += match rest.get(1 + width) {
+                    Some(b'\'') => 2 + width,
+                    _ => 1,
+                };
+            },
+            _ => at += 1,
+        }
+    }
+    comments > 0
+}
+
+// Blank out everything before the body (keeping newlines) so rustc's line and column
+// numbers match the original script.
+pub(crate) fn pad_body(source: &str, script: &Script) -> String {
+    let mut padded: String = source[..script.body_offset]
+        .chars()
+        .map(|c| if c == '\n' { '\n' } else { ' ' })
+        .collect();
+    padded.push_str(script.body);
+    padded
+}
+
+/// The newest frontmatter schema this build of clawbang understands. Scripts may pin the
+/// version they were written for with a top-level `clawbang-version` key.
+pub const SCHEMA_VERSION: i64 = 1;
+
+// Top-level keys clawbang consumes itself; everything else is passed through to cargo.
+const CLAWBANG_KEYS: &[&str] = &["clawbang-version", "include", "args", "env", "permissions", "build", "cwd", "env-files", "limits", "priority", "service"];
+
+// The subset of those that may also be given per-platform, under `[target.'cfg(...)']`.
+const TARGETED_KEYS: &[&str] = &["env", "permissions", "build", "limits", "priority"];
+
+const CARGO_KEYS: &[&str] = &[
+    "cargo-features", "package", "lib", "bin", "example", "test", "bench", "dependencies",
+    "dev-dependencies", "build-dependencies", "target", "badges", "features", "lints",
+    "patch", "replace", "profile", "workspace",
+];
+
+/// The script's frontmatter, layered over the configured defaults, with clawbang's own
+/// keys pulled out of what gets handed to cargo.
+pub struct Manifest {
+    pub(crate) cargo: toml::Value,
+    pub(crate) include: Vec<String>, // extra files to copy next to the script's main.rs
+    pub(crate) args: Option<ArgsSpec>,
+    pub(crate) env: Vec<(String, String)>, // set on the script at run time, before expansion
+    pub(crate) env_files: Vec<String>, // dotenv files loaded before `env`, relative to the script
+    pub(crate) permissions: Option<Permissions>,
+    pub(crate) limits: Option<Limits>,
+    pub(crate) priority: Option<Priority>,
+    pub(crate) service: Option<Service>, // for `clawbang systemd`
+    pub(crate) rustflags: Vec<String>,
+    pub(crate) background_rebuild: bool, // run the last build while the changed script rebuilds
+    pub(crate) cwd: WorkingDir,
+    pub(crate) warnings: Vec<String>,
+}
+
+/// Where the script runs from, per the frontmatter's `cwd` key.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WorkingDir {
+    Caller,    // "caller", the default: wherever clawbang was invoked
+    ScriptDir, // "script-dir": the directory holding the script
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct BuildSettings {
+    #[serde(default)]
+    rustflags: Vec<String>,
+    #[serde(default)]
+    background: bool,
+}
+
+impl Manifest {
+    /// The Cargo.toml the script builds with, as it would be written out.
+    pub fn cargo_toml(&self) -> Result<String, toml::ser::Error> {
+        toml::to_string_pretty(&self.cargo)
+    }
+
+    /// What's handed to cargo: the frontmatter and defaults, less clawbang's own keys, with
+    /// the `[package]` and `[[bin]]` every script builds under.
+    pub fn cargo(&self) -> &toml::Value {
+        &self.cargo
+    }
+
+    /// The `include` list: files copied beside the script's main.rs, relative to the script.
+    pub fn include(&self) -> &[String] {
+        &self.include
+    }
+
+    /// The `[env]` table, in order, before `$VAR` references are expanded.
+    pub fn env(&self) -> &[(String, String)] {
+        &self.env
+    }
+
+    /// The `env-files` list, relative to the script.
+    pub fn env_files(&self) -> &[String] {
+        &self.env_files
+    }
+
+    pub fn cwd(&self) -> WorkingDir {
+        self.cwd
+    }
+
+    /// Problems that don't stop the script from building, like unknown keys.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// For `clawbang completions-for`: add the completion generator that printing
+    /// completions for the script's `[args]` takes. False if it has no `[args]`.
+    pub(crate) fn complete_args(&mut self) -> bool {
+        if self.args.is_none() {
+            return false
+        }
+        // parsing `[args]` made sure there's a table, for clap
+        if let Some(deps) = self.cargo.get_mut("dependencies").and_then(toml::Value::as_table_mut) {
+            deps.entry("clap_complete").or_insert_with(ArgsSpec::completions_dependency);
+        }
+        true
+    }
+}
+
+/// Read a script's frontmatter, layered over `defaults` (a settings file's `[defaults]`,
+/// as `~/.clawbang.toml` has, or an empty table).
+pub fn parse(script: &Script, defaults: &toml::value::Table) -> Result<Manifest, FrontmatterError> {
+    let mut frontmatter: toml::Value = toml::from_str(&script.frontmatter)
+        .map_err(|err| toml_error(script, &err))?;
+
+    let tbl = frontmatter.as_table_mut().ok_or_else(|| {
+        FrontmatterError::new("Expected frontmatter to contain valid TOML, but the top level is not a table", script.frontmatter_offset)
+    })?;
+
+    let mut warnings = Vec::new();
+    let version = match tbl.remove("clawbang-version") {
+        None => SCHEMA_VERSION,
+        Some(toml::Value::Integer(version)) => version,
+        Some(_) => return Err(FrontmatterError::new("Expected \"clawbang-version\" in frontmatter to be an integer", key_offset(script, "clawbang-version"))),
+    };
+
+    // A script written for a newer clawbang may use keys we've never heard of. Rather than
+    // refuse it outright, drop those keys and say so.
+    let unknown: Vec<String> = tbl.keys().filter(|key| !CLAWBANG_KEYS.contains(&key.as_str()) && !CARGO_KEYS.contains(&key.as_str())).cloned().collect();
+    if version > SCHEMA_VERSION {
+        warnings.push(format!(
+            "this script targets frontmatter version {}, but this clawbang only understands version {}; it may not build as intended",
+            version, SCHEMA_VERSION
+        ));
+        for key in unknown {
+            warnings.push(format!("ignoring unrecognized frontmatter key \"{}\"", key));
+            tbl.remove(&key);
+        }
+    } else if let Some(key) = unknown.first() {
+        let mut message = format!("Unknown frontmatter key \"{}\"", key);
+        if let Some(suggestion) = suggest(key) {
+            let _ = write!(message, "; did you mean \"{}\"?", suggestion);
+        }
+        return Err(FrontmatterError::new(message, key_offset(script, key)))
+    }
+
+    merge_defaults(tbl, defaults);
+    apply_targets(tbl, &mut warnings);
+
+    let include = path_list(script, tbl, "include")?;
+    let env_files = path_list(script, tbl, "env-files")?;
+
+    let env = match tbl.remove("env") {
+        None => Vec::new(),
+        Some(toml::Value::Table(vars)) => vars.into_iter().map(|(name, value)| match value {
+            toml::Value::String(value) => Ok((name, value)),
+            toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Boolean(_) => Ok((name, value.to_string())),
+            _ => Err(FrontmatterError::new(format!("Expected env var \"{}\" in frontmatter to be a string", name), key_offset(script, "env"))),
+        }).collect::<Result<_, _>>()?,
+        Some(_) => return Err(FrontmatterError::new("Expected \"env\" in frontmatter to be a table", key_offset(script, "env"))),
+    };
+
+    let build: BuildSettings = match tbl.remove("build") {
+        None => BuildSettings::default(),
+        Some(value) => value.try_into().map_err(|err| {
+            FrontmatterError::new(format!("Invalid \"build\" in frontmatter: {}", err), key_offset(script, "build"))
+        })?,
+    };
+
+    let permissions = match tbl.remove("permissions") {
+        None => None,
+        Some(value) => Some(value.try_into().map_err(|err| {
+            FrontmatterError::new(format!("Invalid \"permissions\" in frontmatter: {}", err), key_offset(script, "permissions"))
+        })?),
+    };
+
+    let limits = match tbl.remove("limits") {
+        None => None,
+        Some(value) => Some(value.try_into().map_err(|err| {
+            FrontmatterError::new(format!("Invalid \"limits\" in frontmatter: {}", err), key_offset(script, "limits"))
+        })?),
+    };
+
+    let priority = match tbl.remove("priority") {
+        None => None,
+        Some(value) => Some(value.try_into().map_err(|err| {
+            FrontmatterError::new(format!("Invalid \"priority\" in frontmatter: {}", err), key_offset(script, "priority"))
+        })?),
+    };
+
+    let service = match tbl.remove("service") {
+        None => None,
+        Some(value) => Some(value.try_into().map_err(|err| {
+            FrontmatterError::new(format!("Invalid \"service\" in frontmatter: {}", err), key_offset(script, "service"))
+        })?),
+    };
+
+    let cwd = match tbl.remove("cwd") {
+        None => WorkingDir::Caller,
+        Some(toml::Value::String(cwd)) if cwd == "caller" => WorkingDir::Caller,
+        Some(toml::Value::String(cwd)) if cwd == "script-dir" => WorkingDir::ScriptDir,
+        Some(_) => return Err(FrontmatterError::new("Expected \"cwd\" in frontmatter to be \"caller\" or \"script-dir\"", key_offset(script, "cwd"))),
+    };
+
+    let args = match tbl.remove("args") {
+        None => None,
+        Some(value) => {
+            let spec: ArgsSpec = value.try_into().map_err(|err| {
+                FrontmatterError::new(format!("Invalid \"args\" in frontmatter: {}", err), key_offset(script, "args"))
+            })?;
+            spec.check().map_err(|err| {
+                FrontmatterError::new(format!("Invalid \"args\" in frontmatter: {}", err), key_offset(script, "args"))
+            })?;
+
+            let deps = tbl.entry("dependencies").or_insert(toml::Value::Table(toml::map::Map::new())).as_table_mut().ok_or_else(|| {
+                FrontmatterError::new("Expected \"dependencies\" in frontmatter to be a table", key_offset(script, "dependencies"))
+            })?;
+            deps.entry("clap").or_insert_with(ArgsSpec::dependency);
+            Some(spec)
+        },
+    };
+
+    let cargo_toml_pkg = tbl.entry("package").or_insert(toml::Value::Table(toml::map::Map::new())).as_table_mut().ok_or_else(|| {
+        FrontmatterError::new("Expected \"package\" in frontmatter to be a table", key_offset(script, "package"))
+    })?;
+    cargo_toml_pkg.insert("name".to_string(), toml::Value::String("bin".to_string()));
+    cargo_toml_pkg.insert("version".to_string(), toml::Value::String("0.0.1".to_string()));
+    cargo_toml_pkg.entry("edition").or_insert(toml::Value::String("2021".to_string()));
+
+    Ok(Manifest {
+        cargo: frontmatter,
+        include,
+        args,
+        env,
+        env_files,
+        permissions,
+        limits,
+        priority,
+        service,
+        rustflags: build.rustflags,
+        background_rebuild: build.background,
+        cwd,
+        warnings,
+    })
+}
+
+fn path_list(script: &Script, tbl: &mut toml::value::Table, key: &str) -> Result<Vec<String>, FrontmatterError> {
+    let invalid = || FrontmatterError::new(format!("Expected \"{}\" in frontmatter to be a list of paths", key), key_offset(script, key));
+    match tbl.remove(key) {
+        None => Ok(Vec::new()),
+        Some(toml::Value::Array(items)) => items.into_iter().map(|item| match item {
+            toml::Value::String(path) => Ok(path),
+            _ => Err(invalid()),
+        }).collect(),
+        Some(_) => Err(invalid()),
+    }
+}
+
+/// Find the relative paths passed to `include!`, `include_str!`, and `include_bytes!`.
+///
+/// This is a textual scan, so it will also pick up mentions inside comments and strings;
+/// callers should tolerate paths that don't exist.
+pub(crate) fn detect_includes(body: &str) -> Vec<String> {
+    let mut found = Vec::new();
+    for (idx, _) in body.match_indices("include") {
+        let rest = &body[idx + "include".len()..];
+        let rest = rest.strip_prefix("_str").or_else(|| rest.strip_prefix("_bytes")).unwrap_or(rest);
+        let rest = match rest.strip_prefix('!').map(str::trim_start).and_then(|rest| rest.strip_prefix('(')) {
+            Some(rest) => rest.trim_start(),
+            None => continue,
+        };
+
+        let literal = if let Some(rest) = rest.strip_prefix("r\"") {
+            rest.find('"').map(|end| &rest[..end])
+        } else if let Some(rest) = rest.strip_prefix('"') {
+            rest.find(['"', '\\']).filter(|end| rest[*end..].starts_with('"')).map(|end| &rest[..end])
+        } else {
+            None
+        };
+
+        if let Some(path) = literal {
+            if !found.iter().any(|seen| seen == path) {
+                found.push(path.to_string());
+            }
+        }
+    }
+    found
+}
+
+// Pull clawbang's sections out of `[target.*]` (cargo would warn about them) and layer the
+// ones whose cfg matches this host over the top-level sections, the way cargo's own
+// `target.<cfg>.rustflags` wins over `build.rustflags`.
+fn apply_targets(tbl: &mut toml::value::Table, warnings: &mut Vec<String>) {
+    let mut overlays = Vec::new();
+    if let Some(targets) = tbl.get_mut("target").and_then(toml::Value::as_table_mut) {
+        for (key, section) in targets.iter_mut() {
+            let section = match section.as_table_mut() {
+                Some(section) => section,
+                None => continue,
+            };
+
+            let overlay: toml::value::Table = TARGETED_KEYS.iter()
+                .filter_map(|name| section.remove(*name).map(|value| (name.to_string(), value)))
+                .collect();
+            if overlay.is_empty() {
+                continue
+            }
+
+            match crate::cfg::matches_host(key) {
+                Some(true) => overlays.push(overlay),
+                Some(false) => {},
+                None => warnings.push(format!("ignoring clawbang settings under target \"{}\"; only cfg(...) targets are supported", key)),
+            }
+        }
+
+        let emptied: Vec<String> = targets.iter()
+            .filter(|(_, section)| matches!(section, toml::Value::Table(section) if section.is_empty()))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in emptied {
+            targets.remove(&key);
+        }
+        if targets.is_empty() {
+            tbl.remove("target");
+        }
+    }
+
+    for overlay in overlays {
+        for (name, mut value) in overlay {
+            if let (toml::Value::Table(value), Some(toml::Value::Table(base))) = (&mut value, tbl.get(&name)) {
+                merge_defaults(value, base);
+            }
+            tbl.insert(name, value);
+        }
+    }
+}
+
+fn suggest(key: &str) -> Option<&'static str> {
+    CLAWBANG_KEYS.iter().chain(CARGO_KEYS)
+        .map(|candidate| (strsim::jaro_winkler(key, candidate), *candidate))
+        .filter(|(score, _)| *score > 0.8)
+        .max_by(|(lhs, _), (rhs, _)| lhs.total_cmp(rhs))
+        .map(|(_, candidate)| candidate)
+}
+
+/// Layer `over` on top of `under`, as the script's frontmatter is layered over defaults.
+pub fn layer(mut over: toml::value::Table, under: &toml::value::Table) -> toml::value::Table {
+    merge_defaults(&mut over, under);
+    over
+}
+
+// Tables merge key by key; for anything else the script's value wins.
+fn merge_defaults(tbl: &mut toml::value::Table, defaults: &toml::value::Table) {
+    for (key, default) in defaults {
+        match (tbl.get_mut(key), default) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(default)) => merge_defaults(existing, default),
+            (Some(_), _) => {},
+            (None, _) => {
+                tbl.insert(key.clone(), default.clone());
+            },
+        }
+    }
+}
+
+fn toml_error(script: &Script, err: &toml::de::Error) -> FrontmatterError {
+    // toml appends its own position, relative to the frontmatter; we report one relative to the script.
+    let message = err.to_string();
+    let message = match message.rfind(" at line ") {
+        Some(idx) => message[..idx].to_string(),
+        None => message,
+    };
+
+    let offset = match err.line_col() {
+        Some((line, col)) => {
+            let line_start: usize = script.frontmatter.split_inclusive('\n').take(line).map(str::len).sum();
+            (line_start + col).min(script.frontmatter.len())
+        },
+        None => 0,
+    };
+
+    FrontmatterError::new(format!("Invalid frontmatter: {}", message), script.frontmatter_offset + offset)
+}
+
+// Where to point an error about a top-level key: the line introducing it, or failing that,
+// the top of the frontmatter.
+fn key_offset(script: &Script, key: &str) -> usize {
+    script.key_span(key).map(|span| span.start).unwrap_or(script.frontmatter_offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frontmatter(source: &str) -> (String, &str) {
+        let script = split_script(source).unwrap();
+        (script.frontmatter.into_owned(), script.body)
+    }
+
+    #[test]
+    fn reads_each_form_of_frontmatter() {
+        let bare = "#!/usr/bin/env clawbang\n+++\n[dependencies]\nanyhow = \"1\"\n+++\nfn main() {}\n";
+        assert_eq!(frontmatter(bare), ("[dependencies]\nanyhow = \"1\"".to_string(), "fn main() {}"));
+
+        let block = "/* +++\n[dependencies]\nanyhow = \"1\"\n+++ */\nfn main() {}\n";
+        assert_eq!(frontmatter(block), ("[dependencies]\nanyhow = \"1\"\n".to_string(), "fn main() {}"));
+
+        // comment markers are blanked, so the TOML keeps the script's columns
+        let lines = "// +++\n//! [dependencies]\n/// anyhow = \"1\"\n// +++\nfn main() {}\n";
+        assert_eq!(frontmatter(lines), ("    [dependencies]\n    anyhow = \"1\"\n".to_string(), "fn main() {}"));
+
+        let script = split_script(lines).unwrap();
+        assert_eq!(&lines[script.frontmatter_span()][4..18], "[dependencies]");
+        assert_eq!(&lines[script.body_span()], "fn main() {}");
+    }
+
+    #[test]
+    fn reads_scripts_without_frontmatter() {
+        let script = split_script("#!/usr/bin/env clawbang\n\nfn main() {}\n").unwrap();
+        assert_eq!(script.shebang, Some("#!/usr/bin/env clawbang"));
+        assert_eq!(script.frontmatter, "");
+        assert_eq!(script.body, "fn main() {}");
+    }
+
+    #[test]
+    fn refuses_frontmatter_left_open() {
+        for source in ["+++\na = 1\nfn main() {}\n", "/* +++\na = 1\n*/\nfn main() {}\n", "// +++\n// a = 1\nfn main() {}\n"] {
+            assert!(split_script(source).is_err(), "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn reads_scripts_with_a_byte_order_mark_and_crlf_line_endings() {
+        let source = "\u{feff}#!/usr/bin/env clawbang\r\n// +++\r\n// [dependencies]\r\n// anyhow = \"1\"\r\n// +++\r\nfn main() {}\r\n";
+        let normalized = normalize(source);
+        assert_eq!(normalized, "#!/usr/bin/env clawbang\n// +++\n// [dependencies]\n// anyhow = \"1\"\n// +++\nfn main() {}\n");
+        let script = split_script(&normalized).unwrap();
+        assert_eq!(script.shebang, Some("#!/usr/bin/env clawbang"));
+        assert_eq!(script.body, "fn main() {}");
+        let manifest = parse(&script, &Default::default()).unwrap();
+        assert!(manifest.cargo()["dependencies"].get("anyhow").is_some());
+    }
+
+    #[test]
+    fn splits_data_off_the_end() {
+        let (code, data) = split_data(b"fn main() {}\n__DATA__\n\x00\xff binary\n");
+        assert_eq!(code, b"fn main() {}\n");
+        assert_eq!(data, Some(&b"\x00\xff binary\n"[..]));
+
+        // a marker on the last line, with nothing after it, still splits
+        assert_eq!(split_data(b"fn main() {}\n__DATA__"), (&b"fn main() {}\n"[..], Some(&b""[..])));
+        assert_eq!(split_data(b"fn main() {}\r\n#----\r\nrows\r\n"), (&b"fn main() {}\r\n"[..], Some(&b"rows\r\n"[..])));
+        assert_eq!(split_data(b"fn main() {}\n"), (&b"fn main() {}\n"[..], None));
+    }
+
+    #[test]
+    fn leaves_markers_in_frontmatter_strings_and_comments() {
+        // a TOML comment in the frontmatter
+        let source = b"+++\n#----\n[dependencies]\n+++\nfn main() {}\n";
+        assert_eq!(split_data(source), (&source[..], None));
+
+        for source in [
+            "fn main() {\n    let s = \"__DATA__\";\n}\n",
+            "fn main() {\n    let s = \"a\n__DATA__\nb\";\n}\n",
+            "fn main() {\n    let s = \"\\\"\n__DATA__\n\";\n}\n",
+            "fn main() {\n    let s = r#\"\"\n__DATA__\n\"#;\n}\n",
+            "fn main() {\n    /* /* */\n__DATA__\n*/\n}\n",
+        ] {
+            assert_eq!(split_data(source.as_bytes()), (source.as_bytes(), None), "{:?}", source);
+        }
+
+        // neither lifetimes nor character literals leave anything open
+        let source = b"fn first<'a>(s: &'a str) -> char { let _ = ('\"', '\\'', b'\\\\'); s.chars().next().unwrap_or('\"') }\n// \"\n__DATA__\nrows\n";
+        assert_eq!(split_data(source).1, Some(&b"rows\n"[..]));
+    }
+
+    #[test]
+    fn points_toml_errors_into_the_script() {
+        let source = "#!/usr/bin/env clawbang\n// +++\n// [dependencies]\n// anyhow = = \"1\"\n// +++\nfn main() {}\n";
+        let script = split_script(source).unwrap();
+        let err = parse(&script, &Default::default()).err().unwrap();
+        assert!(err.message.starts_with("Invalid frontmatter: "), "{}", err.message);
+        assert!(!err.message.contains(" at line "), "{}", err.message);
+        let rendered = err.render(source, "s.rs");
+        let lines: Vec<_> = rendered.lines().skip(1).collect();
+        assert_eq!(lines, [" --> s.rs:4:13", "  |", "4 | // anyhow = = \"1\"", "  |             ^"]);
+    }
+}
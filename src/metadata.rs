@@ -0,0 +1,102 @@
+use eyre::Result;
+use serde::Deserialize;
+use std::collections::HashSet;
+
+/// How cargo resolves a script's dependencies, from `cargo metadata`.
+#[derive(Deserialize)]
+pub(crate) struct Metadata {
+    pub(crate) packages: Vec<Package>,
+    resolve: Resolve,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Package {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) version: String,
+    // None for a path dependency (or the script itself)
+    pub(crate) source: Option<String>,
+    pub(crate) license: Option<String>,
+    targets: Vec<Target>,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    kind: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Resolve {
+    root: Option<String>,
+    nodes: Vec<Node>,
+}
+
+#[derive(Deserialize)]
+struct Node {
+    id: String,
+    deps: Vec<NodeDep>,
+}
+
+#[derive(Deserialize)]
+struct NodeDep {
+    pkg: String,
+    dep_kinds: Vec<DepKind>,
+}
+
+#[derive(Deserialize)]
+struct DepKind {
+    kind: Option<String>,
+}
+
+/// Resolve a script's dependencies, as they'd be built for `host`, without building them.
+/// `metadata` runs cargo for the script's project however its build would.
+pub(crate) fn resolve(script_name: &str, metadata: subprocess::Exec, host: &str) -> Result<Metadata> {
+    let _span = tracing::info_span!("resolve").entered();
+    let output = metadata
+        .args(&["metadata", "--format-version", "1", "--filter-platform", host])
+        .stdout(subprocess::Redirection::Pipe)
+        .stderr(subprocess::Redirection::Pipe)
+        .capture()?;
+    if !output.success() {
+        return Err(eyre::eyre!("Could not resolve {}'s dependencies:\n{}", script_name, output.stderr_str().trim_end()))
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+impl Metadata {
+    /// The script's own package.
+    pub(crate) fn root(&self) -> Option<&Package> {
+        let root = self.resolve.root.as_deref()?;
+        self.packages.iter().find(|package| package.id == root)
+    }
+
+    /// The packages the script's build builds, by id: what's reachable from the script other
+    /// than by its dev-dependencies. Those for other platforms were left out as it resolved.
+    pub(crate) fn built(&self) -> HashSet<&str> {
+        let mut built = HashSet::new();
+        let mut pending: Vec<_> = self.resolve.root.as_deref().into_iter().collect();
+        while let Some(id) = pending.pop() {
+            for dep in self.dependencies(id) {
+                if built.insert(dep) {
+                    pending.push(dep);
+                }
+            }
+        }
+        built
+    }
+
+    /// The ids of what the package `id` depends on to build, leaving out dev-dependencies.
+    pub(crate) fn dependencies<'a>(&'a self, id: &str) -> impl Iterator<Item = &'a str> {
+        self.resolve.nodes.iter().find(|node| node.id == id).into_iter().flat_map(|node| &node.deps)
+            .filter(|dep| !dep.dep_kinds.iter().all(|kind| kind.kind.as_deref() == Some("dev")))
+            .map(|dep| dep.pkg.as_str())
+    }
+}
+
+impl Package {
+    /// Whether it has a target of this kind, like "custom-build" for a build script, or
+    /// "proc-macro".
+    pub(crate) fn has_target(&self, kind: &str) -> bool {
+        self.targets.iter().any(|target| target.kind.iter().any(|found| found == kind))
+    }
+}
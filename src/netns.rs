@@ -0,0 +1,41 @@
+use std::ffi::OsString;
+use std::sync::OnceLock;
+
+/// Under `--no-net-build`: what to put before cargo's command line so that neither it nor
+/// the build scripts and proc macros it runs can reach the network. That's `unshare` into a
+/// network namespace of its own, with nothing in it but a loopback device that isn't up.
+/// None where there's no way to do that (off Linux, or without unshare or user namespaces).
+pub(crate) fn isolated() -> Option<&'static [OsString]> {
+    static PREFIX: OnceLock<Option<Vec<OsString>>> = OnceLock::new();
+    PREFIX.get_or_init(find).as_deref()
+}
+
+#[cfg(target_os = "linux")]
+fn find() -> Option<Vec<OsString>> {
+    use std::process::{ Command, Stdio };
+
+    // root can make a network namespace outright; anyone else needs a user namespace to make
+    // it in, preferably one where they're still themselves
+    let candidates: [&[&str]; 3] = [
+        &["unshare", "--net"],
+        &["unshare", "--map-current-user", "--net"],
+        &["unshare", "--map-root-user", "--net"],
+    ];
+    let found = candidates.into_iter().find(|prefix| {
+        Command::new(prefix[0])
+            .args(&prefix[1..])
+            .arg("true")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    })?;
+    tracing::debug!(?found, "building in a network namespace");
+    Some(found.iter().map(OsString::from).collect())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn find() -> Option<Vec<OsString>> {
+    None
+}
@@ -0,0 +1,226 @@
+use eyre::Result;
+use serde_json::json;
+use sha2::{ Digest, Sha256 };
+use std::ffi::OsString;
+use std::path::{ Path, PathBuf };
+
+// Where the binary goes in the image.
+const BIN_DIR: &str = "usr/local/bin";
+// Certificates for scripts that talk TLS, taken from the build machine when it has them.
+const CA_CERTIFICATES: &str = "etc/ssl/certs/ca-certificates.crt";
+// The image runs as nobody, rather than root.
+const NOBODY: &str = "65534:65534";
+
+/// `clawbang oci`: a container image of a script.
+#[derive(clap::Args)]
+pub(crate) struct OciOptions {
+    /// The script to build: a file, a directory, a URL, or a git reference
+    #[clap(parse(from_os_str))]
+    script: OsString,
+
+    /// The image's name, as in me/tool:1 (or registry.example.com/me/tool:1)
+    #[clap(long)]
+    tag: String,
+
+    /// Where to write the image, as an archive for `docker load` [default: <script>.tar]
+    #[clap(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// The target to build the static binary for [default: this machine's architecture,
+    /// as <arch>-unknown-linux-musl]
+    #[clap(long)]
+    target: Option<String>,
+
+    #[clap(flatten)]
+    cache: crate::CacheOptions,
+
+    /// Options for clawbang to build the script with, after a `--` (e.g. -- --no-net-build)
+    #[clap(last = true, parse(from_os_str))]
+    options: Vec<OsString>,
+}
+
+/// Build the script as a run would, through the cache, so everything a run checks is
+/// checked, then again, statically, in a project of its own, and write the image of that.
+pub(crate) fn run(oci: &OciOptions) -> Result<()> {
+    let config = crate::config::load(oci.cache.config.as_deref())?;
+    reference(&oci.tag)?;
+    let target = oci.target();
+    architecture(&target)?;
+
+    let mut args: Vec<OsString> = vec!["clawbang".into(), "--build-only".into()];
+    args.extend(oci.cache.args());
+    args.extend(oci.options.iter().cloned());
+    args.extend(["--".into(), oci.script.clone()]);
+    let opts = <crate::Cli as clap::Parser>::parse_from(args).run;
+    if let crate::Build::Failed { exit_code, .. } = crate::prepare(&opts, &config)? {
+        std::process::exit(exit_code)
+    }
+
+    crate::plan(&opts, &config, |planned| {
+        let lockfile = crate::stored_lockfile(opts.cache_dir.as_path(), &planned.project.cargo_toml()?);
+        let (binary, _project) = crate::build_from_lockfile(&opts, &planned, &lockfile, Some(&target), &format!("statically, for {}", target))?;
+        let manifest = &planned.project.manifest;
+        if !manifest.env_files.is_empty() {
+            eprintln!("{} the image doesn't carry {}'s env-files; give the container those variables as it runs", crate::color::label("warning"), planned.script_name);
+        }
+        let image = Image {
+            name: &planned.project.name,
+            binary: &binary,
+            target: &target,
+            env: manifest.env.iter().map(|(name, value)| (name.clone(), crate::env::expand(value))).collect(),
+        };
+        let output = oci.output.clone().unwrap_or_else(|| PathBuf::from(format!("{}.tar", planned.project.name)));
+        image.write(&oci.tag, &output)?;
+        eprintln!("wrote {}, an image of {} as {}; load it with `docker load -i {}`", output.display(), planned.script_name, oci.tag, output.display());
+        Ok(())
+    })
+}
+
+impl OciOptions {
+    // The target triple the image's binary is built for.
+    fn target(&self) -> String {
+        self.target.clone().unwrap_or_else(|| {
+            let arch = match std::env::consts::ARCH {
+                "x86" => "i686",
+                arch => arch,
+            };
+            format!("{}-unknown-linux-musl", arch)
+        })
+    }
+}
+
+/// An image holding one static binary, as its entrypoint, on an otherwise empty filesystem.
+struct Image<'a> {
+    name: &'a str,
+    binary: &'a Path,
+    target: &'a str,
+    env: Vec<(String, String)>,
+}
+
+impl Image<'_> {
+    /// Write the image as an OCI image layout in a tar archive, with the manifest.json that
+    /// `docker load` reads from `docker save`'s archives too, so either loads it. Timestamps
+    /// are SOURCE_DATE_EPOCH (or the epoch), so the same build makes the same image.
+    fn write(&self, tag: &str, output: &Path) -> Result<()> {
+        let architecture = architecture(self.target)?;
+        let (name, repository, reference) = reference(tag)?;
+        let epoch = std::env::var("SOURCE_DATE_EPOCH").ok().and_then(|epoch| epoch.parse::<u64>().ok()).unwrap_or(0);
+        let created = crate::rfc3339(u128::from(epoch) * 1000);
+
+        let mut layer = crate::tar::Tar::new(epoch);
+        for dir in ["etc/", "tmp/", "usr/", "usr/local/", "usr/local/bin/"] {
+            layer.dir(dir, if dir == "tmp/" { 0o1777 } else { 0o755 });
+        }
+        layer.file("etc/passwd", 0o644, b"root:x:0:0:root:/:/sbin/nologin\nnobody:x:65534:65534:nobody:/:/sbin/nologin\n");
+        layer.file("etc/group", 0o644, b"root:x:0:\nnobody:x:65534:\n");
+        let mut env = vec![format!("PATH=/{}", BIN_DIR)];
+        if let Ok(certificates) = std::fs::read(Path::new("/").join(CA_CERTIFICATES)) {
+            layer.dir("etc/ssl/", 0o755);
+            layer.dir("etc/ssl/certs/", 0o755);
+            layer.file(CA_CERTIFICATES, 0o644, &certificates);
+            env.push(format!("SSL_CERT_FILE=/{}", CA_CERTIFICATES));
+        }
+        env.extend(self.env.iter().map(|(name, value)| format!("{}={}", name, value)));
+        let binary = std::fs::read(self.binary)?;
+        let entrypoint = format!("{}/{}", BIN_DIR, self.name);
+        layer.file(&entrypoint, 0o755, &binary);
+        let layer = layer.finish();
+
+        let mut config = json!({
+            "created": created,
+            "architecture": architecture.0,
+            "os": "linux",
+            "config": {
+                "Entrypoint": [format!("/{}", entrypoint)],
+                "Env": env,
+                "User": NOBODY,
+                "WorkingDir": "/",
+            },
+            "rootfs": { "type": "layers", "diff_ids": [digest(&layer)] },
+            "history": [{ "created": created, "created_by": format!("clawbang oci {}", self.name) }],
+        });
+        if let Some(variant) = architecture.1 {
+            config["variant"] = json!(variant);
+        }
+        let config = serde_json::to_vec(&config)?;
+        let manifest = serde_json::to_vec(&json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "config": { "mediaType": "application/vnd.oci.image.config.v1+json", "digest": digest(&config), "size": config.len() },
+            "layers": [{ "mediaType": "application/vnd.oci.image.layer.v1.tar", "digest": digest(&layer), "size": layer.len() }],
+        }))?;
+        let index = serde_json::to_vec(&json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.index.v1+json",
+            "manifests": [{
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "digest": digest(&manifest),
+                "size": manifest.len(),
+                "annotations": {
+                    "io.containerd.image.name": format!("{}:{}", repository, reference),
+                    "org.opencontainers.image.ref.name": reference,
+                },
+            }],
+        }))?;
+        let blob = |bytes: &[u8]| format!("blobs/sha256/{}", hex::encode(Sha256::digest(bytes)));
+        let docker = serde_json::to_vec(&json!([{
+            "Config": blob(&config),
+            "RepoTags": [format!("{}:{}", name, reference)],
+            "Layers": [blob(&layer)],
+        }]))?;
+
+        let mut archive = crate::tar::Tar::new(epoch);
+        archive.file("oci-layout", 0o644, br#"{"imageLayoutVersion":"1.0.0"}"#);
+        archive.file("index.json", 0o644, &index);
+        archive.file("manifest.json", 0o644, &docker);
+        archive.dir("blobs/", 0o755);
+        archive.dir("blobs/sha256/", 0o755);
+        for contents in [&layer, &config, &manifest] {
+            archive.file(&blob(contents), 0o644, contents);
+        }
+        std::fs::write(output, archive.finish())
+            .map_err(|err| eyre::eyre!("Could not write {}: {}", output.display(), err))?;
+        Ok(())
+    }
+}
+
+// The image's architecture (and variant), as OCI names them, from the target triple.
+fn architecture(target: &str) -> Result<(&'static str, Option<&'static str>)> {
+    if !target.contains("-linux-") {
+        return Err(eyre::eyre!("images run Linux binaries, and {} isn't a Linux target", target))
+    }
+    Ok(match target.split('-').next().unwrap_or_default() {
+        "x86_64" => ("amd64", None),
+        "aarch64" => ("arm64", None),
+        "i586" | "i686" => ("386", None),
+        "armv7" => ("arm", Some("v7")),
+        "arm" => ("arm", Some("v6")),
+        "riscv64gc" => ("riscv64", None),
+        "powerpc64le" => ("ppc64le", None),
+        "s390x" => ("s390x", None),
+        arch => return Err(eyre::eyre!("Don't know what images call the {} architecture", arch)),
+    })
+}
+
+// `me/tool:1` is docker.io/me/tool, tagged 1; without a tag it's tagged latest. This is the
+// name as given, the repository in full, and the tag.
+fn reference(tag: &str) -> Result<(&str, String, &str)> {
+    let (name, reference) = match tag.rsplit_once(':') {
+        Some((name, reference)) if !reference.contains('/') => (name, reference),
+        _ => (tag, "latest"),
+    };
+    let valid = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "._-".contains(c));
+    let (domain, path) = match name.split_once('/') {
+        Some((domain, path)) if domain.contains(['.', ':']) || domain == "localhost" => (domain, path.to_string()),
+        Some(_) => ("docker.io", name.to_string()),
+        None => ("docker.io", format!("library/{}", name)),
+    };
+    if !path.split('/').all(valid) || reference.is_empty() || reference.len() > 128 || !reference.chars().all(|c| c.is_ascii_alphanumeric() || "._-".contains(c)) {
+        return Err(eyre::eyre!("\"{}\" isn't an image name; expected something like me/tool:1, in lowercase", tag))
+    }
+    Ok((name, format!("{}/{}", domain, path), reference))
+}
+
+fn digest(bytes: &[u8]) -> String {
+    format!("sha256:{}", hex::encode(Sha256::digest(bytes)))
+}
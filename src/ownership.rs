@@ -0,0 +1,96 @@
+use eyre::Result;
+use std::path::Path;
+
+/// Under `refuse-unsafe-scripts = true`: refuse a script that someone besides whoever's
+/// running it (or root) could have changed, as sshd and sudo do with their files. That's a
+/// script writable by its group or anyone, or owned by another user, or in a directory that
+/// is (or can't be looked at): any of those could swap the script for their own. `metadata`
+/// is the open script's, so it's the file that's about to be read that's checked.
+#[cfg(unix)]
+pub(crate) fn check(script_name: &str, script_path: &Path, metadata: &std::fs::Metadata) -> Result<()> {
+    use crate::errors;
+    use std::os::unix::fs::MetadataExt;
+
+    let me = unsafe { libc::geteuid() };
+    let mut found = problem(script_name, metadata.mode(), metadata.uid(), me);
+    if found.is_none() {
+        let dir = script_path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let what = format!("its directory, {},", dir.display());
+        found = match std::fs::metadata(dir) {
+            Ok(dir_metadata) => problem(&what, dir_metadata.mode(), dir_metadata.uid(), me),
+            Err(err) => Some(format!("{} can't be checked ({})", what, err)),
+        };
+    }
+    match found {
+        Some(problem) => Err(errors::OWNERSHIP.error(format!(
+            "Refusing to run {}: {}, so someone else could change what runs. Run it anyway with --insecure-ok",
+            script_name, problem
+        ))),
+        None => Ok(()),
+    }
+}
+
+// S_IFDIR, in the file type bits of a mode (S_IFMT, 0o170000).
+#[cfg(unix)]
+const DIRECTORY: u32 = 0o040000;
+
+// What's wrong with a file or directory of this mode and owner, for the user `me`. A sticky
+// directory, as /tmp is, only lets people replace their own files, so it can be shared.
+#[cfg(unix)]
+fn problem(what: &str, mode: u32, uid: u32, me: u32) -> Option<String> {
+    let sticky = mode & 0o170000 == DIRECTORY && mode & 0o1000 != 0;
+    if mode & 0o002 != 0 && !sticky {
+        Some(format!("{} is writable by anyone", what))
+    } else if mode & 0o020 != 0 && !sticky {
+        Some(format!("{} is writable by its group", what))
+    } else if uid != me && uid != 0 {
+        Some(format!("{} is owned by another user (uid {})", what, uid))
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn check(_script_name: &str, _script_path: &Path, _metadata: &std::fs::Metadata) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    const FILE: u32 = 0o100000;
+    const DIR: u32 = DIRECTORY;
+
+    #[test]
+    fn passes_what_only_its_owner_or_root_can_change() {
+        assert_eq!(problem("s.rs", FILE | 0o644, 1000, 1000), None);
+        assert_eq!(problem("s.rs", FILE | 0o755, 0, 1000), None);
+        assert_eq!(problem("dir", DIR | 0o700, 1000, 1000), None);
+        assert_eq!(problem("/tmp", DIR | 0o1777, 0, 1000), None);
+    }
+
+    #[test]
+    fn refuses_what_its_group_or_anyone_can_write() {
+        assert_eq!(problem("s.rs", FILE | 0o646, 1000, 1000).as_deref(), Some("s.rs is writable by anyone"));
+        assert_eq!(problem("s.rs", FILE | 0o664, 1000, 1000).as_deref(), Some("s.rs is writable by its group"));
+        assert_eq!(problem("dir", DIR | 0o775, 1000, 1000).as_deref(), Some("dir is writable by its group"));
+        assert_eq!(problem("dir", DIR | 0o777, 0, 1000).as_deref(), Some("dir is writable by anyone"));
+        // only a directory is sticky
+        assert_eq!(problem("s.rs", FILE | 0o1666, 1000, 1000).as_deref(), Some("s.rs is writable by anyone"));
+    }
+
+    #[test]
+    fn refuses_what_another_user_owns() {
+        assert_eq!(problem("s.rs", FILE | 0o644, 1001, 1000).as_deref(), Some("s.rs is owned by another user (uid 1001)"));
+        assert_eq!(problem("dir", DIR | 0o1777, 1001, 1000).as_deref(), Some("dir is owned by another user (uid 1001)"));
+        assert_eq!(problem("s.rs", FILE | 0o644, 1000, 0).as_deref(), Some("s.rs is owned by another user (uid 1000)"));
+    }
+
+    #[test]
+    fn refuses_a_directory_it_cannot_look_at() {
+        let metadata = std::fs::metadata("/").unwrap();
+        let err = check("gone.rs", Path::new("/no/such/dir/gone.rs"), &metadata).unwrap_err().to_string();
+        assert!(err.contains("its directory, /no/such/dir, can't be checked"), "{}", err);
+    }
+}
@@ -0,0 +1,412 @@
+use crate::export;
+use crate::tar::Tar;
+use crate::Project;
+use eyre::Result;
+use sha2::{ Digest, Sha256 };
+use std::io::Write;
+use std::path::{ Path, PathBuf };
+
+// The shells completions are packaged for, as completions-for names them.
+const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+/// `clawbang package`: the usual options, plus what to package the script as.
+#[derive(clap::Args)]
+pub(crate) struct PackageOptions {
+    /// Write a Homebrew formula that builds the script, and the source archive it builds from
+    #[clap(long)]
+    pub(crate) brew: bool,
+
+    /// Write a .deb of the script's binary, for this machine's architecture
+    #[clap(long)]
+    pub(crate) deb: bool,
+
+    /// Write an .rpm of the script's binary, for this machine's architecture
+    #[clap(long)]
+    pub(crate) rpm: bool,
+
+    /// The package's version [default: the version in the script's [args], or 0.0.1]
+    #[clap(long)]
+    pub(crate) package_version: Option<String>,
+
+    /// Where the formula downloads the source archive from [default: the archive, as a file:// URL]
+    #[clap(long)]
+    pub(crate) url: Option<String>,
+
+    /// The directory to write the package to [default: the current one]
+    #[clap(long, parse(from_os_str))]
+    pub(crate) dest: Option<PathBuf>,
+}
+
+/// What a package says of the script: its `[package]` table's description, homepage,
+/// license, and authors, falling back on its `[args]` for the description.
+pub(crate) struct Metadata {
+    pub(crate) name: String, // the binary's
+    pub(crate) version: String,
+    pub(crate) description: Option<String>,
+    pub(crate) homepage: Option<String>,
+    pub(crate) license: Option<String>,
+    pub(crate) maintainer: Option<String>, // the first of the authors
+}
+
+impl Metadata {
+    /// `url` is where a downloaded script came from, which stands in for a homepage.
+    pub(crate) fn new(project: &Project, url: Option<&str>, version: Option<&str>) -> Metadata {
+        let package = project.manifest.cargo.get("package");
+        let text = |key| package.and_then(|package| package.get(key)).and_then(toml::Value::as_str).map(String::from);
+        let args = project.manifest.args.as_ref();
+        let authors = package.and_then(|package| package.get("authors")).and_then(toml::Value::as_array);
+        Metadata {
+            name: export::binary_name(project),
+            version: version.or_else(|| args.and_then(|args| args.version())).unwrap_or("0.0.1").to_string(),
+            description: text("description").or_else(|| args.and_then(|args| args.about()).map(String::from)),
+            homepage: text("homepage").or_else(|| text("repository")).or_else(|| url.map(String::from)),
+            license: text("license"),
+            maintainer: authors.and_then(|authors| authors.first()).and_then(toml::Value::as_str).map(String::from),
+        }
+    }
+
+    /// The package's name, as package managers want them: lowercase, with dashes.
+    pub(crate) fn package_name(&self) -> String {
+        self.name.to_lowercase().replace('_', "-")
+    }
+
+    fn summary(&self) -> String {
+        self.description.clone().unwrap_or_else(|| format!("{}, packaged by clawbang", self.name))
+    }
+}
+
+/// A file a package installs, at its absolute path.
+pub(crate) struct File {
+    pub(crate) path: String,
+    pub(crate) mode: u32, // with the type's bits, as in st_mode
+    pub(crate) contents: Vec<u8>,
+    pub(crate) doc: bool,
+}
+
+/// The script's completions for each of [`SHELLS`], printed by the build completions-for
+/// makes of it; none for a script without `[args]`, whose command line clawbang can't know.
+pub(crate) fn completions(opts: &mut crate::Options, config: &crate::config::Config) -> Result<Vec<(&'static str, Vec<u8>)>> {
+    if !crate::plan(opts, config, |planned| Ok(planned.project.manifest.args.is_some()))? {
+        return Ok(Vec::new())
+    }
+    opts.completions = true;
+    let mut completions = Vec::new();
+    for shell in SHELLS {
+        opts.rest = vec![shell.into()];
+        let mut launch = match crate::prepare(opts, config)? {
+            crate::Build::Ready(launch) => launch,
+            crate::Build::Failed { .. } => return Err(eyre::eyre!("The script's completions couldn't be built")),
+        };
+        let output = launch.command.stdout(std::process::Stdio::piped()).output()?;
+        if !output.status.success() {
+            return Err(eyre::eyre!("{}'s {} completions couldn't be printed: it {}", launch.script_name, shell, crate::describe_exit(output.status)))
+        }
+        completions.push((*shell, output.stdout));
+    }
+    opts.completions = false;
+    opts.rest = Vec::new();
+    Ok(completions)
+}
+
+/// What a completions file for `shell` is called, where each shell looks for them.
+pub(crate) fn completions_file(shell: &str, name: &str) -> String {
+    match shell {
+        "zsh" => format!("_{}", name),
+        "fish" => format!("{}.fish", name),
+        _ => name.to_string(),
+    }
+}
+
+/// Write a Homebrew formula for the script, and the source archive it builds from: the
+/// project clawbang generates for it, with the lockfile kept for its dependencies and its
+/// completions. The formula builds it with Homebrew's Rust, so `brew install
+/// --build-bottle` and `brew bottle` make a bottle of it as they would of any other.
+pub(crate) fn brew(package: &PackageOptions, project: &Project, url: Option<&str>, lockfile: &Path, completions: &[(&str, Vec<u8>)]) -> Result<Vec<PathBuf>> {
+    let metadata = Metadata::new(project, url, package.package_version.as_deref());
+    let lock = export::lockfile(lockfile)?;
+    let formula = metadata.package_name();
+    let stem = format!("{}-{}", formula, metadata.version);
+    let dest = package.dest.clone().unwrap_or_default();
+    std::fs::create_dir_all(&dest)?;
+
+    // Homebrew unpacks the archive's one directory, and builds in it
+    let dir = tempfile::tempdir()?;
+    let cargo = export::write_project(dir.path(), project, &metadata.name, &lock)?;
+    if export::has_path_dependency(&cargo) {
+        eprintln!("{} {}'s path dependencies aren't in the archive; the formula can't build without them", crate::color::label("warning"), formula);
+    }
+    let mut archive = Tar::new(epoch());
+    archive.dir(&format!("{}/", stem), 0o755);
+    for entry in walkdir::WalkDir::new(dir.path()).min_depth(1).sort_by_file_name() {
+        let entry = entry?;
+        let path = format!("{}/{}", stem, entry.path().strip_prefix(dir.path())?.to_string_lossy());
+        match entry.file_type().is_dir() {
+            true => archive.dir(&format!("{}/", path), 0o755),
+            false => archive.file(&path, 0o644, &std::fs::read(entry.path())?),
+        }
+    }
+    if !completions.is_empty() {
+        archive.dir(&format!("{}/completions/", stem), 0o755);
+    }
+    for (shell, script) in completions {
+        archive.file(&format!("{}/completions/{}", stem, completions_file(shell, &metadata.name)), 0o644, script);
+    }
+    let archive = archive.finish();
+    let archive_path = dest.join(format!("{}.tar", stem));
+    std::fs::write(&archive_path, &archive)?;
+
+    let url = package.url.clone().unwrap_or_else(|| format!("file://{}", crate::absolute(&archive_path).display()));
+    let mut lines = vec![format!("# written by `clawbang package --brew` for {}", project.name)];
+    lines.push(format!("class {} < Formula", class_name(&formula)));
+    match &metadata.description {
+        Some(description) => lines.push(format!("  desc {}", string(description.trim_end_matches('.')))),
+        None => eprintln!("{} {} has no description for the formula; give it one with `description` under [package]", crate::color::label("warning"), formula),
+    }
+    match &metadata.homepage {
+        Some(homepage) => lines.push(format!("  homepage {}", string(homepage))),
+        None => eprintln!("{} `brew audit` wants a homepage for {}; give it one with `homepage` under [package]", crate::color::label("warning"), formula),
+    }
+    lines.push(format!("  url {}", string(&url)));
+    if !url.contains(&metadata.version) {
+        lines.push(format!("  version {}", string(&metadata.version)));
+    }
+    lines.push(format!("  sha256 {}", string(&hex::encode(Sha256::digest(&archive)))));
+    if let Some(license) = &metadata.license {
+        lines.push(format!("  license {}", string(license)));
+    }
+    lines.push(String::new());
+    lines.push("  depends_on \"rust\" => :build".to_string());
+    lines.push(String::new());
+
+    lines.push("  def install".to_string());
+    let manifest = &project.manifest;
+    if !manifest.rustflags.is_empty() {
+        lines.push(format!("    ENV[\"RUSTFLAGS\"] = {}", string(&manifest.rustflags.join(" "))));
+    }
+    let binary = string(&metadata.name);
+    if manifest.env.is_empty() {
+        lines.push("    system \"cargo\", \"install\", *std_cargo_args".to_string());
+    } else {
+        // the script's [env], set by a wrapper as clawbang would, with its ${VAR}s expanded
+        // from wherever it runs
+        lines.push("    system \"cargo\", \"install\", *std_cargo_args(root: libexec)".to_string());
+        let env: Vec<String> = manifest.env.iter().map(|(name, value)| {
+            let word = export::shell(value);
+            format!("{} => {}", string(name), string(&word[1..word.len() - 1]))
+        }).collect();
+        lines.push(format!("    (bin/{}).write_env_script libexec/\"bin\"/{}, {{ {} }}", binary, binary, env.join(", ")));
+    }
+    for (shell, _) in completions {
+        let file = string(&format!("completions/{}", completions_file(shell, &metadata.name)));
+        lines.push(match *shell {
+            "bash" => format!("    bash_completion.install {} => {}", file, binary),
+            shell => format!("    {}_completion.install {}", shell, file),
+        });
+    }
+    lines.push("  end".to_string());
+    lines.push(String::new());
+    lines.push("  test do".to_string());
+    lines.push(match manifest.args.is_some() {
+        true => format!("    system bin/{}, \"--help\"", binary),
+        false => format!("    assert_path_exists bin/{}", binary),
+    });
+    lines.push("  end".to_string());
+    lines.push("end".to_string());
+    let formula_path = dest.join(format!("{}.rb", formula));
+    std::fs::write(&formula_path, lines.join("\n") + "\n")?;
+
+    if !manifest.env_files.is_empty() || manifest.limits.is_some() || manifest.cwd != crate::manifest::WorkingDir::Caller {
+        eprintln!("{} the formula doesn't carry {}'s env-files, [limits], or cwd; only [env]", crate::color::label("warning"), project.name);
+    }
+    Ok(vec![formula_path, archive_path])
+}
+
+// As Homebrew names a formula's class after its file: `my-tool` is MyTool.
+fn class_name(formula: &str) -> String {
+    formula.split(['-', '.']).map(|part| {
+        let mut chars = part.chars();
+        chars.next().map(|first| first.to_ascii_uppercase().to_string() + chars.as_str()).unwrap_or_default()
+    }).collect()
+}
+
+// A Ruby string literal, which interpolates nothing.
+fn string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', r"\\").replace('"', "\\\"").replace('#', "\\#").replace('\n', r"\n"))
+}
+
+/// Write a .deb of the script's `binary`, as it's built here, with a man page and its
+/// completions, for apt and dpkg.
+pub(crate) fn deb(package: &PackageOptions, project: &Project, url: Option<&str>, binary: &Path, completions: &[(&str, Vec<u8>)]) -> Result<PathBuf> {
+    let metadata = Metadata::new(project, url, package.package_version.as_deref());
+    if !metadata.version.starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(eyre::eyre!("A .deb's version has to start with a digit, which {:?} doesn't; give another with --package-version", metadata.version))
+    }
+    let name = metadata.package_name();
+    let arch = match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "i386",
+        "arm" => "armhf",
+        arch => arch,
+    };
+    let files = files(&metadata, project, binary, completions, "/usr/share/zsh/vendor-completions")?;
+    let epoch = epoch();
+
+    let mut data = Tar::new(epoch);
+    let mut dirs: Vec<&str> = files.iter()
+        .flat_map(|file| file.path.match_indices('/').map(|(at, _)| &file.path[..=at]))
+        .collect();
+    dirs.sort_unstable();
+    dirs.dedup();
+    for dir in dirs {
+        data.dir(&format!(".{}", dir), 0o755);
+    }
+    for file in &files {
+        data.file(&format!(".{}", file.path), file.mode & 0o7777, &file.contents);
+    }
+
+    let size: usize = files.iter().map(|file| file.contents.len()).sum();
+    let summary = metadata.summary();
+    let mut control = vec![
+        format!("Package: {}", name),
+        format!("Version: {}", metadata.version),
+        format!("Architecture: {}", arch),
+        format!("Maintainer: {}", maintainer(&metadata)),
+        format!("Installed-Size: {}", size.div_ceil(1024)),
+        "Section: utils".to_string(),
+        "Priority: optional".to_string(),
+    ];
+    if let Some(homepage) = &metadata.homepage {
+        control.push(format!("Homepage: {}", homepage));
+    }
+    control.push(format!("Description: {}", summary.lines().next().unwrap_or_default()));
+    let mut control_tar = Tar::new(epoch);
+    control_tar.dir("./", 0o755);
+    control_tar.file("./control", 0o644, (control.join("\n") + "\n").as_bytes());
+
+    let mut deb = b"!<arch>\n".to_vec();
+    ar(&mut deb, "debian-binary", epoch, b"2.0\n");
+    ar(&mut deb, "control.tar.gz", epoch, &gzip(&control_tar.finish())?);
+    ar(&mut deb, "data.tar.gz", epoch, &gzip(&data.finish())?);
+    let path = package.dest.clone().unwrap_or_default().join(format!("{}_{}_{}.deb", name, metadata.version, arch));
+    write(&path, &deb)?;
+    Ok(path)
+}
+
+/// Write an .rpm of the script's `binary`, as it's built here, with a man page and its
+/// completions, for dnf, yum, and zypper.
+pub(crate) fn rpm(package: &PackageOptions, project: &Project, url: Option<&str>, binary: &Path, completions: &[(&str, Vec<u8>)]) -> Result<PathBuf> {
+    let metadata = Metadata::new(project, url, package.package_version.as_deref());
+    if metadata.version.contains('-') {
+        return Err(eyre::eyre!("An .rpm's version can't have a `-` in it, which {:?} does; give another with --package-version", metadata.version))
+    }
+    let name = metadata.package_name();
+    let arch = match std::env::consts::ARCH {
+        "x86" => "i686",
+        "arm" => "armv7hl",
+        arch => arch,
+    };
+    let mut files = files(&metadata, project, binary, completions, "/usr/share/zsh/site-functions")?;
+    if !project.manifest.env.is_empty() {
+        // the directory of its own the binary goes in, behind the wrapper
+        files.push(File { path: format!("/usr/lib/{}", name), mode: 0o040755, contents: Vec::new(), doc: false });
+    }
+    let summary = metadata.summary();
+    let maintainer = maintainer(&metadata);
+    let rpm = crate::rpm::Package {
+        name: &name,
+        version: &metadata.version,
+        release: "1",
+        arch,
+        summary: summary.lines().next().unwrap_or_default(),
+        description: &summary,
+        license: metadata.license.as_deref(),
+        url: metadata.homepage.as_deref(),
+        packager: Some(&maintainer),
+        requires: if project.manifest.env.is_empty() { Vec::new() } else { vec!["/bin/sh".to_string()] },
+        mtime: epoch() as u32,
+        files,
+    };
+    let path = package.dest.clone().unwrap_or_default().join(format!("{}-{}-1.{}.rpm", name, metadata.version, arch));
+    write(&path, &rpm.write()?)?;
+    Ok(path)
+}
+
+// What a .deb or .rpm installs: the binary (behind a wrapper that sets the script's [env],
+// if it has one), a man page for it, and its completions.
+fn files(metadata: &Metadata, project: &Project, binary: &Path, completions: &[(&str, Vec<u8>)], zsh: &str) -> Result<Vec<File>> {
+    if std::env::consts::OS != "linux" {
+        return Err(eyre::eyre!("The packages are of the binary built here, so they're made on Linux, for Linux"))
+    }
+    let name = &metadata.name;
+    let file = |path: String, mode: u32, contents: Vec<u8>| File { path, mode: 0o100000 | mode, contents, doc: false };
+    let mut files = Vec::new();
+    let env = &project.manifest.env;
+    if env.is_empty() {
+        files.push(file(format!("/usr/bin/{}", name), 0o755, std::fs::read(binary)?));
+    } else {
+        let installed = format!("/usr/lib/{}/{}", metadata.package_name(), name);
+        let mut wrapper = format!("#!/bin/sh\n# written by `clawbang package`: {}'s [env], then {}\n", project.name, name);
+        for (variable, value) in env {
+            wrapper.push_str(&format!("export {}={}\n", variable, export::shell(value)));
+        }
+        wrapper.push_str(&format!("exec {} \"$@\"\n", installed));
+        files.push(file(format!("/usr/bin/{}", name), 0o755, wrapper.into_bytes()));
+        files.push(file(installed, 0o755, std::fs::read(binary)?));
+    }
+    let man = crate::args::man_page(project.manifest.args.as_ref(), name, &metadata.version, &metadata.summary());
+    files.push(File { doc: true, ..file(format!("/usr/share/man/man1/{}.1.gz", name), 0o644, gzip(man.as_bytes())?) });
+    for (shell, script) in completions {
+        let dir = match *shell {
+            "bash" => "/usr/share/bash-completion/completions",
+            "zsh" => zsh,
+            _ => "/usr/share/fish/vendor_completions.d",
+        };
+        files.push(file(format!("{}/{}", dir, completions_file(shell, name)), 0o644, script.clone()));
+    }
+    Ok(files)
+}
+
+fn maintainer(metadata: &Metadata) -> String {
+    metadata.maintainer.clone().unwrap_or_else(|| "unknown <unknown@localhost>".to_string())
+}
+
+/// Warn of what a .deb or .rpm of the script leaves out of how clawbang runs it.
+pub(crate) fn warn_unsupported(project: &Project) {
+    let label = crate::color::label("warning");
+    let manifest = &project.manifest;
+    if Metadata::new(project, None, None).maintainer.is_none() {
+        eprintln!("{} {} has no maintainer for its packages; give it one as the first of `authors` under [package]", label, project.name);
+    }
+    if !manifest.env_files.is_empty() || manifest.limits.is_some() || manifest.cwd != crate::manifest::WorkingDir::Caller {
+        eprintln!("{} the packages don't carry {}'s env-files, [limits], or cwd; only [env]", label, project.name);
+    }
+}
+
+fn write(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    Ok(std::fs::write(path, contents)?)
+}
+
+// Timestamps in what's written are SOURCE_DATE_EPOCH (or the epoch), so the same build makes
+// the same package.
+fn epoch() -> u64 {
+    std::env::var("SOURCE_DATE_EPOCH").ok().and_then(|epoch| epoch.parse::<u64>().ok()).unwrap_or(0)
+}
+
+fn gzip(contents: &[u8]) -> Result<Vec<u8>> {
+    let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    gzip.write_all(contents)?;
+    Ok(gzip.finish()?)
+}
+
+// A member of an ar archive, as a .deb is.
+fn ar(archive: &mut Vec<u8>, name: &str, mtime: u64, contents: &[u8]) {
+    archive.extend_from_slice(format!("{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n", name, mtime, 0, 0, 100644, contents.len()).as_bytes());
+    archive.extend_from_slice(contents);
+    if contents.len() % 2 == 1 {
+        archive.push(b'\n');
+    }
+}
@@ -0,0 +1,115 @@
+use eyre::Result;
+use serde::Deserialize;
+use std::io::{ BufRead, Write };
+
+/// The `[permissions]` frontmatter table: a reviewable statement of what the script touches.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Permissions {
+    #[serde(default)]
+    pub(crate) net: Grant,
+    #[serde(default)]
+    pub(crate) fs_read: Grant,
+    #[serde(default)]
+    pub(crate) fs_write: Grant,
+    #[serde(default)]
+    pub(crate) exec: Grant,
+    #[serde(default)]
+    pub(crate) env: Grant,
+}
+
+/// Either blanket access (`net = true`) or access scoped to a list (`fs-read = ["/etc"]`).
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Grant {
+    All(bool),
+    Only(Vec<String>),
+}
+
+impl Default for Grant {
+    fn default() -> Self {
+        Grant::All(false)
+    }
+}
+
+impl Grant {
+    /// Whether the grant allows anything at all.
+    pub(crate) fn granted(&self) -> bool {
+        match self {
+            Grant::All(all) => *all,
+            Grant::Only(items) => !items.is_empty(),
+        }
+    }
+
+    fn describe(&self, name: &str) -> Option<String> {
+        match self {
+            Grant::All(false) => None,
+            Grant::All(true) => Some(format!("{} (unrestricted)", name)),
+            Grant::Only(items) if items.is_empty() => None,
+            Grant::Only(items) => Some(format!("{}: {}", name, items.join(", "))),
+        }
+    }
+}
+
+impl Permissions {
+    /// One line per requested permission; empty if the script asks for nothing.
+    pub(crate) fn describe(&self) -> Vec<String> {
+        [
+            self.net.describe("net"),
+            self.fs_read.describe("fs-read"),
+            self.fs_write.describe("fs-write"),
+            self.exec.describe("exec"),
+            self.env.describe("env"),
+        ].into_iter().flatten().collect()
+    }
+}
+
+/// Under `--strict-permissions`, a script has to declare what it needs and someone at a
+/// terminal has to agree to it. Only `--sandbox` holds the script to the declaration, so
+/// otherwise this is a gate on running the script at all.
+pub(crate) fn check_strict(script_name: &str, permissions: Option<&Permissions>) -> Result<()> {
+    let permissions = permissions.ok_or_else(|| eyre::eyre!(
+        "{} does not declare a [permissions] table, which --strict-permissions requires", script_name
+    ))?;
+
+    let requested = permissions.describe();
+    if requested.is_empty() {
+        return Ok(())
+    }
+
+    let summary = requested.iter().map(|line| format!("  - {}\n", line)).collect::<String>();
+    let (mut tty, input) = match open_terminal() {
+        Ok(terminal) => terminal,
+        Err(_) => return Err(eyre::eyre!(
+            "{} requests the following permissions, and there is no terminal to confirm them:\n{}", script_name, summary
+        )),
+    };
+
+    write!(tty, "{} requests the following permissions:\n{}Allow? [y/N] ", script_name, summary)?;
+    tty.flush()?;
+
+    let mut answer = String::new();
+    std::io::BufReader::new(input).read_line(&mut answer)?;
+    if matches!(answer.trim(), "y" | "Y" | "yes") {
+        Ok(())
+    } else {
+        Err(eyre::eyre!("Permissions for {} were not granted", script_name))
+    }
+}
+
+// The controlling terminal, even when stdin and stdout are redirected: one handle to
+// write the prompt to and one to read the answer from.
+fn open_terminal() -> std::io::Result<(std::fs::File, std::fs::File)> {
+    #[cfg(windows)]
+    {
+        let output = std::fs::OpenOptions::new().write(true).open("CONOUT$")?;
+        let input = std::fs::OpenOptions::new().read(true).open("CONIN$")?;
+        Ok((output, input))
+    }
+
+    #[cfg(not(windows))]
+    {
+        let tty = std::fs::OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+        Ok((tty.try_clone()?, tty))
+    }
+}
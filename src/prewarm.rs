@@ -0,0 +1,55 @@
+use eyre::Result;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// `clawbang prewarm`: build scripts ahead of time, as for a CI image or a fresh machine.
+#[derive(clap::Args)]
+pub(crate) struct PrewarmOptions {
+    /// The scripts to build: files, directories, URLs, or git references
+    #[clap(required = true, parse(from_os_str))]
+    scripts: Vec<PathBuf>,
+
+    /// Don't show status lines or summaries as the scripts build
+    #[clap(short, long)]
+    quiet: bool,
+
+    #[clap(flatten)]
+    cache: crate::CacheOptions,
+}
+
+/// Build each script that isn't cached yet, one after another, as `--build-only` would.
+///
+/// Each still gets a project of its own, built just as it would be alone, but they all
+/// share one target directory, so a dependency several of them use (at the same version,
+/// with the same features) compiles once. A workspace would do the same, but its members
+/// can't share the package name every script builds under, and would lose their own
+/// `[profile]` settings.
+pub(crate) fn run(prewarm: &PrewarmOptions) -> Result<()> {
+    let config = crate::config::load(prewarm.cache.config.as_deref())?;
+    let target = tempfile::tempdir()?;
+    let (mut built, mut cached, mut failed) = (0, 0, Vec::new());
+    for script in &prewarm.scripts {
+        let mut args: Vec<OsString> = vec!["clawbang".into(), "--build-only".into()];
+        args.extend(prewarm.cache.args());
+        if prewarm.quiet {
+            args.push("--quiet".into());
+        }
+        args.extend(["--".into(), script.clone().into()]);
+        let mut opts = <crate::Cli as clap::Parser>::parse_from(args).run;
+        opts.target_dir = Some(target.path().to_path_buf());
+
+        match crate::prepare(&opts, &config)? {
+            crate::Build::Ready(launch) if launch.tempdir.is_some() => built += 1,
+            crate::Build::Ready(_) => cached += 1,
+            crate::Build::Failed { .. } => failed.push(script.display().to_string()),
+        }
+    }
+
+    if !prewarm.quiet {
+        eprintln!("built {} script{}; {} {} already cached", built, if built == 1 { "" } else { "s" }, cached, if cached == 1 { "was" } else { "were" });
+    }
+    if !failed.is_empty() {
+        return Err(eyre::eyre!("{} of {} scripts failed to build: {}", failed.len(), prewarm.scripts.len(), failed.join(", ")))
+    }
+    Ok(())
+}
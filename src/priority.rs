@@ -0,0 +1,117 @@
+use serde::Deserialize;
+
+/// The `[priority]` frontmatter table, or `--nice` and `--ionice`: how much of the machine
+/// the build and the script get when something else wants it too.
+#[derive(Deserialize, Clone, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Priority {
+    nice: Option<i32>,
+    io: Option<IoClass>,
+}
+
+/// An I/O scheduling class: `idle`, or `best-effort` with an optional level from 0
+/// (first served) to 7, as in `best-effort:7`.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(try_from = "String")]
+pub(crate) enum IoClass {
+    Idle,
+    BestEffort(u8),
+}
+
+impl TryFrom<String> for IoClass {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl std::str::FromStr for IoClass {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once(':') {
+            None if value == "idle" => Ok(IoClass::Idle),
+            None if value == "best-effort" => Ok(IoClass::BestEffort(4)),
+            Some(("best-effort", level)) => match level.parse() {
+                Ok(level) if level <= 7 => Ok(IoClass::BestEffort(level)),
+                _ => Err(format!("expected a best-effort level from 0 to 7, not \"{}\"", level)),
+            },
+            _ => Err(format!("unknown I/O class \"{}\"; expected idle or best-effort[:0-7]", value)),
+        }
+    }
+}
+
+impl Priority {
+    pub(crate) fn new(nice: Option<i32>, io: Option<IoClass>) -> Self {
+        Priority { nice, io }
+    }
+
+    /// These settings, with any left unset taken from `defaults`.
+    pub(crate) fn or(&self, defaults: Option<&Priority>) -> Priority {
+        Priority {
+            nice: self.nice.or(defaults.and_then(|defaults| defaults.nice)),
+            io: self.io.or(defaults.and_then(|defaults| defaults.io)),
+        }
+    }
+
+    /// The settings as systemd's, for a unit that runs the binary itself.
+    pub(crate) fn directives(&self) -> Vec<(&'static str, String)> {
+        let mut directives = Vec::new();
+        if let Some(nice) = self.nice {
+            directives.push(("Nice", nice.to_string()));
+        }
+        match self.io {
+            Some(IoClass::Idle) => directives.push(("IOSchedulingClass", "idle".to_string())),
+            Some(IoClass::BestEffort(level)) => {
+                directives.push(("IOSchedulingClass", "best-effort".to_string()));
+                directives.push(("IOSchedulingPriority", level.to_string()));
+            },
+            None => {},
+        }
+        directives
+    }
+
+    /// Lower clawbang's own priority, which cargo and the script inherit. Niceness is only
+    /// ever raised: a script can't talk its way past a `nice` it was started under.
+    #[cfg(unix)]
+    pub(crate) fn apply(&self) {
+        if let Some(nice) = self.nice {
+            unsafe {
+                let current = libc::getpriority(libc::PRIO_PROCESS, 0);
+                if nice > current && libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                    eprintln!("{} could not set niceness {}: {}", crate::color::label("warning"), nice, std::io::Error::last_os_error());
+                }
+            }
+        }
+        if let Some(io) = self.io {
+            apply_io(io);
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn apply(&self) {
+        if self.nice.is_some() || self.io.is_some() {
+            eprintln!("{} process priority is only supported on Unix", crate::color::label("warning"));
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn apply_io(io: IoClass) {
+    // from linux/ioprio.h, which libc doesn't carry
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+    const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+    let (class, level) = match io {
+        IoClass::BestEffort(level) => (2, level as libc::c_long),
+        IoClass::Idle => (3, 0),
+    };
+    if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, (class << IOPRIO_CLASS_SHIFT) | level) } != 0 {
+        eprintln!("{} could not set I/O class {:?}: {}", crate::color::label("warning"), io, std::io::Error::last_os_error());
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn apply_io(_io: IoClass) {
+    eprintln!("{} I/O priority is only supported on Linux", crate::color::label("warning"));
+}
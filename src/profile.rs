@@ -0,0 +1,56 @@
+use std::sync::Mutex;
+use std::time::{ Duration, Instant };
+
+static PROFILE: Mutex<Option<Profile>> = Mutex::new(None);
+
+/// `--self-profile`: how long each of clawbang's own steps took, from the moment it started
+/// to the moment it handed over to the script.
+struct Profile {
+    started: Instant,
+    last: Instant,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+/// Start timing steps, counting from `started`, when clawbang's `main` began.
+pub(crate) fn start(started: Instant) {
+    let mut profile = PROFILE.lock().unwrap_or_else(|err| err.into_inner());
+    *profile = Some(Profile { started, last: started, phases: Vec::new() });
+}
+
+/// Close the current step, naming it; a name given twice adds up. Does nothing unless
+/// `start` was called.
+pub(crate) fn mark(phase: &'static str) {
+    let mut profile = PROFILE.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some(profile) = profile.as_mut() {
+        let now = Instant::now();
+        let elapsed = now - profile.last;
+        profile.last = now;
+        match profile.phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += elapsed,
+            None => profile.phases.push((phase, elapsed)),
+        }
+    }
+}
+
+/// Print the steps so far on stderr, closing the last as `phase`.
+pub(crate) fn report(phase: &'static str) {
+    mark(phase);
+    let profile = PROFILE.lock().unwrap_or_else(|err| err.into_inner());
+    let Some(profile) = profile.as_ref() else { return };
+    let label = crate::color::label("profile");
+    eprintln!("{} {} from starting clawbang to handing over to the script", label, seconds(profile.started.elapsed().as_secs_f64()));
+    let width = profile.phases.iter().map(|(name, _)| name.len()).max().unwrap_or_default();
+    for (name, elapsed) in &profile.phases {
+        eprintln!("{}   {:width$}  {:>8}", label, name, seconds(elapsed.as_secs_f64()), width = width);
+    }
+}
+
+pub(crate) fn seconds(secs: f64) -> String {
+    if secs >= 1.0 {
+        format!("{:.3}s", secs)
+    } else if secs >= 0.001 {
+        format!("{:.2}ms", secs * 1e3)
+    } else {
+        format!("{:.1}µs", secs * 1e6)
+    }
+}
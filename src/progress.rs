@@ -0,0 +1,103 @@
+use std::io::{ IsTerminal, Write };
+use std::path::PathBuf;
+use std::sync::{ Arc, Mutex };
+use std::time::Duration;
+
+const FRAMES: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+#[derive(Default)]
+struct State {
+    started: usize,
+    total: Option<usize>,
+    current: Option<String>,
+    done: bool,
+}
+
+/// A one-line status for a cold build ("compiling 12/37: serde"), redrawn in place on stderr
+/// while cargo runs. It's only shown at a terminal, and never under `--quiet`.
+pub(crate) struct Progress {
+    state: Option<Arc<Mutex<State>>>,
+    lockfile: PathBuf,
+}
+
+impl Progress {
+    pub(crate) fn start(quiet: bool, project: impl Into<PathBuf>) -> Self {
+        let lockfile = project.into().join("Cargo.lock");
+        if quiet || !std::io::stderr().is_terminal() {
+            return Progress { state: None, lockfile }
+        }
+
+        let state = Arc::new(Mutex::new(State::default()));
+        let ticking = state.clone();
+        std::thread::spawn(move || {
+            for frame in FRAMES.iter().cycle() {
+                {
+                    let state = ticking.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                    if state.done {
+                        break
+                    }
+                    draw(&state, *frame);
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        });
+        Progress { state: Some(state), lockfile }
+    }
+
+    /// Note one line of cargo's output: its `Compiling` status lines say what's being built.
+    pub(crate) fn observe(&self, line: &[u8]) {
+        let state = match &self.state {
+            Some(state) => state,
+            None => return,
+        };
+        let line = crate::color::strip(&String::from_utf8_lossy(line));
+        let name = match line.trim_start().strip_prefix("Compiling ") {
+            Some(rest) => rest.split_whitespace().next().unwrap_or_default().to_string(),
+            None => return,
+        };
+
+        let mut state = state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // cargo has resolved the whole graph by the time it starts compiling
+        if state.total.is_none() {
+            state.total = package_count(&self.lockfile);
+        }
+        state.started += 1;
+        state.current = Some(name);
+    }
+
+    /// Stop drawing and clear the status line, before anything else is written to stderr.
+    pub(crate) fn finish(&self) {
+        if let Some(state) = &self.state {
+            let mut state = state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if !state.done {
+                state.done = true;
+                eprint!("\r\x1b[2K");
+            }
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.finish()
+    }
+}
+
+/// How many packages a cargo project's lockfile lists, the project's own included.
+pub(crate) fn package_count(lockfile: &std::path::Path) -> Option<usize> {
+    let lockfile = std::fs::read_to_string(lockfile).ok()?;
+    Some(lockfile.lines().filter(|line| *line == "[[package]]").count())
+}
+
+fn draw(state: &State, frame: char) {
+    let mut status = match (&state.current, state.total) {
+        (None, _) => "resolving dependencies".to_string(),
+        (Some(current), Some(total)) => format!("compiling {}/{}: {}", state.started.min(total), total, current),
+        (Some(current), None) => format!("compiling {}: {}", state.started, current),
+    };
+    // a line that wraps can't be redrawn in place
+    status.truncate(status.char_indices().nth(70).map(|(idx, _)| idx).unwrap_or(status.len()));
+    let mut stderr = std::io::stderr().lock();
+    let _ = write!(stderr, "\r\x1b[2K{} {}", frame, status);
+    let _ = stderr.flush();
+}
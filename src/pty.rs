@@ -0,0 +1,115 @@
+use eyre::Result;
+use std::fs::File;
+use std::io::{ Read, Write };
+use std::os::unix::io::{ AsRawFd, FromRawFd };
+use std::os::unix::process::CommandExt;
+use std::process::{ Child, Command, Stdio };
+
+/// The parent's side of a script running under a pseudo-terminal. Output is relayed to our
+/// stdout and our stdin to the script until the script and everything sharing its terminal
+/// let go of it.
+pub(crate) struct Pty {
+    output: std::sync::mpsc::Receiver<()>,
+    restore: Option<libc::termios>,
+}
+
+/// Start the script with a fresh pseudo-terminal as its stdin, stdout, stderr, and
+/// controlling terminal, so it behaves as it would typed at a shell even when clawbang's
+/// own output is a pipe.
+pub(crate) fn spawn(command: &mut Command) -> Result<(Child, Pty)> {
+    let (mut master, mut slave) = (0, 0);
+    let size = window_size();
+    let opened = unsafe {
+        libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), size.as_ref().map_or(std::ptr::null(), |size| size as *const _))
+    };
+    if opened != 0 {
+        return Err(eyre::eyre!("Could not open a pseudo-terminal: {}", std::io::Error::last_os_error()))
+    }
+    let master = unsafe { File::from_raw_fd(master) };
+    let slave = unsafe { File::from_raw_fd(slave) };
+
+    command
+        .stdin(Stdio::from(slave.try_clone()?))
+        .stdout(Stdio::from(slave.try_clone()?))
+        .stderr(Stdio::from(slave));
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                return Err(std::io::Error::last_os_error())
+            }
+            Ok(())
+        });
+    }
+    let child = command.spawn()?;
+    // the Command still holds the slave descriptors; until they close, reads never see EOF
+    command.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+
+    // keystrokes go to the script as typed; its terminal does the line editing and echo
+    let restore = raw_stdin();
+
+    let mut input = master.try_clone()?;
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        loop {
+            match std::io::stdin().read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(len) => if input.write_all(&buf[..len]).is_err() { return },
+            }
+        }
+        // stdin ran out: pass that on the way a terminal would, with ^D
+        let _ = input.write_all(&[4]);
+    });
+
+    let (done, output) = std::sync::mpsc::channel();
+    let mut reader = master;
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        let mut stdout = std::io::stdout();
+        // once nothing holds the terminal open, reads fail with EIO
+        while let Ok(len) = reader.read(&mut buf) {
+            if len == 0 || stdout.write_all(&buf[..len]).and_then(|_| stdout.flush()).is_err() {
+                break
+            }
+        }
+        let _ = done.send(());
+    });
+
+    Ok((child, Pty { output, restore }))
+}
+
+impl Pty {
+    /// Wait for the last of the script's output, giving up after a moment if something it
+    /// left running in the background is still holding the terminal.
+    pub(crate) fn finish(self) {
+        let _ = self.output.recv_timeout(std::time::Duration::from_millis(500));
+    }
+}
+
+impl Drop for Pty {
+    fn drop(&mut self) {
+        if let Some(termios) = &self.restore {
+            unsafe { libc::tcsetattr(std::io::stdin().as_raw_fd(), libc::TCSANOW, termios) };
+        }
+    }
+}
+
+fn window_size() -> Option<libc::winsize> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    [libc::STDOUT_FILENO, libc::STDIN_FILENO, libc::STDERR_FILENO].into_iter()
+        .any(|fd| unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } == 0)
+        .then_some(size)
+}
+
+fn raw_stdin() -> Option<libc::termios> {
+    let fd = std::io::stdin().as_raw_fd();
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::isatty(fd) == 0 || libc::tcgetattr(fd, &mut termios) != 0 {
+            return None
+        }
+        let original = termios;
+        libc::cfmakeraw(&mut termios);
+        libc::tcsetattr(fd, libc::TCSANOW, &termios);
+        Some(original)
+    }
+}
@@ -0,0 +1,134 @@
+use serde::Deserialize;
+
+// What in a variable's name says its value is a secret, matched without regard to case.
+const SENSITIVE: &[&str] = &["TOKEN", "KEY", "SECRET", "PASSWORD", "PASSWD", "CREDENTIAL"];
+
+// Values shorter than this are left in place: redacting "1" or "yes" everywhere would wreck
+// the output without hiding anything.
+const SHORTEST: usize = 6;
+
+const REDACTED: &[u8] = b"[redacted]";
+
+/// The settings file's `[redact]`: more variables whose values are kept out of the build
+/// output the cache records, by what their names contain, like the built-in TOKEN or KEY.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Redact {
+    #[serde(default)]
+    env: Vec<String>,
+}
+
+impl Redact {
+    /// The values of variables in clawbang's environment, which cargo and build scripts
+    /// inherit, that look like secrets: as they are, and as they'd be escaped in a JSON
+    /// message from cargo.
+    pub(crate) fn secrets(&self) -> Vec<Vec<u8>> {
+        self.secrets_in(std::env::vars_os().filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?))))
+    }
+
+    fn secrets_in(&self, vars: impl Iterator<Item = (String, String)>) -> Vec<Vec<u8>> {
+        let mut secrets = Vec::new();
+        for (name, value) in vars {
+            let name = name.to_uppercase();
+            let sensitive = SENSITIVE.iter().any(|part| name.contains(part))
+                || self.env.iter().any(|part| name.contains(&part.to_uppercase()));
+            if !sensitive || value.len() < SHORTEST {
+                continue
+            }
+            let escaped = serde_json::to_string(&value).unwrap_or_default();
+            let escaped = escaped.trim_matches('"');
+            if escaped != value {
+                secrets.push(escaped.as_bytes().to_vec());
+            }
+            secrets.push(value.into_bytes());
+        }
+        // the longest first, should one secret contain another
+        secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+        secrets.dedup();
+        secrets
+    }
+}
+
+/// `output` with every occurrence of the secrets replaced.
+pub(crate) fn scrub(output: Vec<u8>, secrets: &[Vec<u8>]) -> Vec<u8> {
+    secrets.iter().fold(output, |output, secret| {
+        if secret.is_empty() || !output.windows(secret.len()).any(|window| window == secret.as_slice()) {
+            return output
+        }
+        let mut scrubbed = Vec::with_capacity(output.len());
+        let mut rest = output.as_slice();
+        while let Some(at) = rest.windows(secret.len()).position(|window| window == secret.as_slice()) {
+            scrubbed.extend_from_slice(&rest[..at]);
+            scrubbed.extend_from_slice(REDACTED);
+            rest = &rest[at + secret.len()..];
+        }
+        scrubbed.extend_from_slice(rest);
+        scrubbed
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secrets(redact: &Redact, vars: &[(&str, &str)]) -> Vec<Vec<u8>> {
+        redact.secrets_in(vars.iter().map(|(name, value)| (name.to_string(), value.to_string())))
+    }
+
+    fn scrubbed(output: &str, secrets: &[Vec<u8>]) -> String {
+        String::from_utf8(scrub(output.as_bytes().to_vec(), secrets)).unwrap()
+    }
+
+    #[test]
+    fn finds_secrets_by_what_their_names_contain() {
+        let vars = [
+            ("GITHUB_TOKEN", "ghp_abcdef123"),
+            ("aws_secret_access_key", "wJalrXUtnFEMI"),
+            ("DB_PASSWORD", "hunter2hunter2"),
+            ("HOME", "/home/someone"),
+            ("INTERNAL_THING", "not-so-secret"),
+        ];
+        let mut found = secrets(&Redact::default(), &vars);
+        found.sort();
+        assert_eq!(found, vec![b"ghp_abcdef123".to_vec(), b"hunter2hunter2".to_vec(), b"wJalrXUtnFEMI".to_vec()]);
+
+        let redact = Redact { env: vec!["internal".to_string()] };
+        assert!(secrets(&redact, &vars).contains(&b"not-so-secret".to_vec()));
+    }
+
+    #[test]
+    fn leaves_short_values_alone() {
+        assert!(secrets(&Redact::default(), &[("API_KEY", ""), ("USE_KEY", "1"), ("TOKEN_SET", "yes"), ("KEY", "12345")]).is_empty());
+        // an empty secret, however it got there, redacts nothing
+        assert_eq!(scrubbed("output", &[Vec::new()]), "output");
+    }
+
+    #[test]
+    fn scrubs_secrets_as_escaped_in_cargo_messages() {
+        let found = secrets(&Redact::default(), &[("API_KEY", "pa\"ss\\word")]);
+        let line = format!(
+            r#"{{"reason":"build-script-executed","message":{{"rendered":"warning: key is {}"}}}}"#,
+            serde_json::to_string("pa\"ss\\word").unwrap().trim_matches('"'),
+        );
+        assert_eq!(
+            scrubbed(&line, &found),
+            r#"{"reason":"build-script-executed","message":{"rendered":"warning: key is [redacted]"}}"#,
+        );
+        assert_eq!(scrubbed("warning: key is pa\"ss\\word", &found), "warning: key is [redacted]");
+    }
+
+    #[test]
+    fn scrubs_every_occurrence() {
+        let found = secrets(&Redact::default(), &[("TOKEN", "abcabc")]);
+        assert_eq!(scrubbed("abcabc and abcabc", &found), "[redacted] and [redacted]");
+        // overlapping occurrences go one whole secret at a time, left to right
+        assert_eq!(scrubbed("abcabcabc", &found), "[redacted]abc");
+        assert_eq!(scrubbed("abcabcabcabc", &found), "[redacted][redacted]");
+    }
+
+    #[test]
+    fn scrubs_the_longest_secret_first() {
+        let found = secrets(&Redact::default(), &[("KEY", "secret"), ("LONG_KEY", "secret-and-more")]);
+        assert_eq!(scrubbed("secret-and-more, secret", &found), "[redacted], [redacted]");
+    }
+}
@@ -0,0 +1,148 @@
+use eyre::Result;
+use serde::{ Deserialize, Serialize };
+use sha2::{ Digest, Sha256 };
+use std::io::{ Read, Write };
+use std::path::Path;
+
+// Downloads share the build cache, under keys that can't collide with a source hash.
+const DOWNLOAD_PREFIX: &str = "download:";
+
+#[derive(Serialize, Deserialize)]
+struct Download {
+    etag: Option<String>,
+    #[serde(default)]
+    last_modified: Option<String>,
+}
+
+pub(crate) fn is_url(file: &str) -> bool {
+    file.starts_with("https://") || file.starts_with("http://")
+}
+
+/// A URL or git reference with the script's hash on the end, as `…/tool.rs#sha256=<hex>`,
+/// split into the two; `None` for anything else.
+pub(crate) fn split_pin(spec: &str) -> Option<(&str, &str)> {
+    let (rest, hash) = spec.rsplit_once("#sha256=")?;
+    let remote = is_url(rest) || crate::git::Reference::parse(rest).is_some();
+    (remote && hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())).then_some((rest, hash))
+}
+
+/// Fetch a script, revalidating any copy downloaded earlier with its ETag (or its date, for
+/// servers that don't send one). If the server
+/// can't be reached, an earlier copy is used (with a warning) rather than failing outright.
+pub(crate) fn fetch(cache: &Path, url: &str) -> Result<Vec<u8>> {
+    let key = format!("{}{}", DOWNLOAD_PREFIX, url);
+    let cached = cacache::metadata_sync(cache, &key)?;
+    let previous = cached.as_ref()
+        .and_then(|metadata| serde_json::from_value::<Download>(metadata.metadata.clone()).ok());
+
+    let mut request = ureq::get(url);
+    match previous {
+        Some(Download { etag: Some(etag), .. }) => request = request.set("If-None-Match", &etag),
+        Some(Download { last_modified: Some(date), .. }) => request = request.set("If-Modified-Since", &date),
+        _ => {},
+    }
+
+    let response = match request.call() {
+        Ok(response) => response,
+        Err(err) if cached.is_some() => {
+            eprintln!("{} could not revalidate {}; using the copy downloaded earlier", crate::color::label("warning"), err);
+            return Ok(cacache::read_sync(cache, &key)?)
+        },
+        Err(err) => return Err(eyre::eyre!("Could not download {}", err)),
+    };
+
+    if response.status() == 304 && cached.is_some() {
+        return Ok(cacache::read_sync(cache, &key)?)
+    }
+
+    let download = Download {
+        etag: response.header("ETag").map(String::from),
+        last_modified: response.header("Last-Modified").map(String::from),
+    };
+    let mut contents = Vec::new();
+    response.into_reader().read_to_end(&mut contents)?;
+
+    let mut writer = cacache::WriteOpts::new()
+        .algorithm(cacache::Algorithm::Sha256)
+        .metadata(serde_json::to_value(&download)?)
+        .open_sync(cache, &key)?;
+    writer.write_all(&contents)?;
+    writer.commit()?;
+
+    Ok(contents)
+}
+
+/// A script from the network only runs if its hash was pinned ahead of time, or with
+/// `--trust`. Either way a pinned hash has to match.
+pub(crate) fn verify(url: &str, contents: &[u8], pinned: Option<&str>, trust: bool) -> Result<()> {
+    let actual = hex::encode(Sha256::digest(contents));
+    match pinned {
+        Some(pinned) if pinned.eq_ignore_ascii_case(&actual) => Ok(()),
+        Some(pinned) => Err(eyre::eyre!(
+            "{} does not match the pinned hash\n  expected sha256: {}\n    actual sha256: {}",
+            url, pinned, actual
+        )),
+        None if trust => Ok(()),
+        None => Err(eyre::eyre!(
+            "Refusing to run {} without a pinned hash; review it, then rerun it as {}#sha256={} or with `--sha256 {}` (or `--trust` to skip the check)",
+            url, url, actual, actual
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The sha256 of "fn main() {}\n".
+    const DIGEST: &str = "536e506bb90914c243a12b397b9a998f85ae2cbd9ba02dfd03a9e155ca5ca0f4";
+
+    #[test]
+    fn splits_a_pin_off_a_url() {
+        let spec = format!("https://example.com/tool.rs#sha256={}", DIGEST);
+        assert_eq!(split_pin(&spec), Some(("https://example.com/tool.rs", DIGEST)));
+        let upper = DIGEST.to_ascii_uppercase();
+        let spec = format!("https://example.com/tool.rs#sha256={}", upper);
+        assert_eq!(split_pin(&spec), Some(("https://example.com/tool.rs", upper.as_str())));
+    }
+
+    #[test]
+    fn splits_a_pin_off_a_git_reference() {
+        let spec = format!("git+https://example.com/tools.git#bin/tool.rs@v1.2#sha256={}", DIGEST);
+        assert_eq!(split_pin(&spec), Some(("git+https://example.com/tools.git#bin/tool.rs@v1.2", DIGEST)));
+        let spec = format!("gh:me/tools/tool.rs#sha256={}", DIGEST);
+        assert_eq!(split_pin(&spec), Some(("gh:me/tools/tool.rs", DIGEST)));
+    }
+
+    #[test]
+    fn leaves_malformed_pins_alone() {
+        for hash in [&DIGEST[..63], &format!("{}0", DIGEST), &format!("{}g", &DIGEST[..63]), ""] {
+            let spec = format!("https://example.com/tool.rs#sha256={}", hash);
+            assert_eq!(split_pin(&spec), None, "{}", spec);
+        }
+        // only remote scripts take a pin; a file could be named anything
+        assert_eq!(split_pin(&format!("tool.rs#sha256={}", DIGEST)), None);
+        assert_eq!(split_pin("https://example.com/tool.rs"), None);
+    }
+
+    #[test]
+    fn verifies_contents_against_the_pin() {
+        let url = "https://example.com/tool.rs";
+        assert!(verify(url, b"fn main() {}\n", Some(DIGEST), false).is_ok());
+        assert!(verify(url, b"fn main() {}\n", Some(&DIGEST.to_ascii_uppercase()), false).is_ok());
+
+        let err = verify(url, b"fn main() { evil() }\n", Some(DIGEST), true).unwrap_err().to_string();
+        assert!(err.starts_with("https://example.com/tool.rs does not match the pinned hash\n"), "{}", err);
+        assert!(err.contains(&format!("expected sha256: {}", DIGEST)), "{}", err);
+        // a pin that couldn't match anything is just a mismatch
+        assert!(verify(url, b"fn main() {}\n", Some("not-hex"), false).unwrap_err().to_string().contains("does not match"));
+    }
+
+    #[test]
+    fn refuses_unpinned_contents_unless_trusted() {
+        let url = "https://example.com/tool.rs";
+        assert!(verify(url, b"fn main() {}\n", None, true).is_ok());
+        let err = verify(url, b"fn main() {}\n", None, false).unwrap_err().to_string();
+        assert!(err.contains(&format!("{}#sha256={}", url, DIGEST)), "{}", err);
+    }
+}
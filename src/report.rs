@@ -0,0 +1,82 @@
+use eyre::Result;
+use serde::Serialize;
+use std::path::{ Path, PathBuf };
+use std::time::Duration;
+
+/// `--report`: what happened to one run of a script, written as JSON once it's over, for CI
+/// and orchestration tools to collect.
+#[derive(Serialize)]
+pub(crate) struct Report {
+    script: String,
+    cache_key: String,
+    cache_hit: bool,
+    // only for fresh builds
+    build_seconds: Option<f64>,
+    // only when the build failed: cargo's status
+    build_exit_code: Option<i32>,
+    binary: Option<PathBuf>,
+    binary_size: Option<u64>,
+    exit_code: Option<i32>,
+    // Unix only: the signal the script died of, if that's how it ended
+    signal: Option<i32>,
+    timed_out: bool,
+    run_seconds: Option<f64>,
+}
+
+impl Report {
+    pub(crate) fn new(script: &str, cache_key: &str, cache_hit: bool) -> Self {
+        Report {
+            script: script.to_string(),
+            cache_key: cache_key.to_string(),
+            cache_hit,
+            build_seconds: None,
+            build_exit_code: None,
+            binary: None,
+            binary_size: None,
+            exit_code: None,
+            signal: None,
+            timed_out: false,
+            run_seconds: None,
+        }
+    }
+
+    pub(crate) fn built(&mut self, took: Duration, exit_code: i32) {
+        self.build_seconds = Some(took.as_secs_f64());
+        if exit_code != 0 {
+            self.build_exit_code = Some(exit_code);
+        }
+    }
+
+    pub(crate) fn failed_from_cache(&mut self, exit_code: i32) {
+        self.build_exit_code = Some(exit_code);
+    }
+
+    pub(crate) fn binary(&mut self, binary: &Path) {
+        self.binary = Some(crate::absolute(binary));
+        self.binary_size = std::fs::metadata(binary).map(|metadata| metadata.len()).ok();
+    }
+
+    /// How the script ended: its exit status, or `None` if it ran out of time.
+    pub(crate) fn exited(&mut self, status: Option<std::process::ExitStatus>, took: Duration) {
+        self.run_seconds = Some(took.as_secs_f64());
+        match status {
+            Some(status) => {
+                self.exit_code = status.code();
+                #[cfg(unix)]
+                {
+                    use std::os::unix::process::ExitStatusExt;
+                    self.signal = status.signal();
+                }
+            },
+            None => {
+                self.timed_out = true;
+                self.exit_code = Some(crate::TIMED_OUT_EXIT);
+            },
+        }
+    }
+
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+            .map_err(|err| eyre::eyre!("Could not write the report to {}: {}", path.display(), err))
+    }
+}
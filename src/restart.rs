@@ -0,0 +1,75 @@
+use crate::stop_requested;
+use eyre::Result;
+use std::time::{ Duration, Instant };
+
+// However many times a script has failed in a row, it's never left down longer than this.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// `--restart on-failure[:max=N,backoff=DURATION]`: start the script again whenever it
+/// exits unsuccessfully, up to `max` times (forever by default), waiting `backoff` (1s by
+/// default) before the first restart and twice as long before each one after that.
+#[derive(Clone, Debug)]
+pub(crate) struct Policy {
+    max: Option<u32>,
+    backoff: Duration,
+}
+
+pub(crate) fn parse(input: &str) -> Result<Policy> {
+    let (mode, settings) = input.split_once(':').unwrap_or((input, ""));
+    if mode != "on-failure" {
+        return Err(eyre::eyre!("unknown restart policy \"{}\"; expected on-failure[:max=N,backoff=DURATION]", mode))
+    }
+
+    let mut policy = Policy { max: None, backoff: Duration::from_secs(1) };
+    for setting in settings.split(',').filter(|setting| !setting.is_empty()) {
+        match setting.split_once('=') {
+            Some(("max", max)) => policy.max = Some(max.parse().map_err(|_| eyre::eyre!("expected a number of restarts, not \"{}\"", max))?),
+            Some(("backoff", backoff)) => policy.backoff = crate::parse_duration(backoff)?,
+            _ => return Err(eyre::eyre!("unknown restart setting \"{}\"; expected max=N or backoff=DURATION", setting)),
+        }
+    }
+    Ok(policy)
+}
+
+/// Keeps count of a supervised script's restarts.
+pub(crate) struct Supervisor {
+    policy: Policy,
+    restarts: u32,
+    delay: Duration,
+}
+
+impl Supervisor {
+    pub(crate) fn new(policy: Policy) -> Self {
+        let delay = policy.backoff;
+        Supervisor { policy, restarts: 0, delay }
+    }
+
+    /// After a failed run that began at `started`, wait out the backoff and return true if
+    /// the script should be started again. A run that stayed up longer than the longest
+    /// backoff was healthy for a while, so the delay starts over from the shortest.
+    pub(crate) fn restart(&mut self, script_name: &str, started: Instant, failure: &str) -> bool {
+        // once clawbang has been told to stop, a script that exits is not brought back
+        if stop_requested() {
+            return false
+        }
+        if self.policy.max.map(|max| self.restarts >= max).unwrap_or(false) {
+            eprintln!("{} {} {}; giving up after {} restart{}", crate::color::label("error"), script_name, failure, self.restarts, if self.restarts == 1 { "" } else { "s" });
+            return false
+        }
+        if started.elapsed() > MAX_BACKOFF {
+            self.delay = self.policy.backoff;
+        }
+
+        eprintln!("{} {} {}; restarting in {:?}", crate::color::label("warning"), script_name, failure, self.delay);
+        let deadline = Instant::now() + self.delay;
+        while Instant::now() < deadline {
+            if stop_requested() {
+                return false
+            }
+            std::thread::sleep((deadline - Instant::now()).min(Duration::from_millis(20)));
+        }
+        self.restarts += 1;
+        self.delay = (self.delay * 2).min(MAX_BACKOFF);
+        true
+    }
+}
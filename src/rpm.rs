@@ -0,0 +1,353 @@
+use crate::package::File;
+use eyre::Result;
+use sha2::{ Digest, Sha256 };
+use std::io::Write;
+
+// Header data types, as rpm numbers them.
+const INT16: u32 = 3;
+const INT32: u32 = 4;
+const STRING: u32 = 6;
+const BIN: u32 = 7;
+const STRING_ARRAY: u32 = 8;
+const I18NSTRING: u32 = 9;
+
+// What a file's flags can say of it, and a requirement's of what it requires.
+const FILE_DOC: u32 = 1 << 1;
+const SENSE_LESS: u32 = 1 << 1;
+const SENSE_EQUAL: u32 = 1 << 3;
+const SENSE_RPMLIB: u32 = 1 << 24;
+
+// Digests are SHA-256, as PGPHASHALGO_SHA256.
+const SHA256: u32 = 8;
+
+/// A binary RPM of `files`, which are owned by root.
+pub(crate) struct Package<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) version: &'a str,
+    pub(crate) release: &'a str,
+    pub(crate) arch: &'a str,
+    pub(crate) summary: &'a str,
+    pub(crate) description: &'a str,
+    pub(crate) license: Option<&'a str>,
+    pub(crate) url: Option<&'a str>,
+    pub(crate) packager: Option<&'a str>,
+    pub(crate) requires: Vec<String>,
+    pub(crate) mtime: u32,
+    pub(crate) files: Vec<File>,
+}
+
+impl Package<'_> {
+    /// The package as rpm reads it: the lead, a signature header holding its digests, the
+    /// header, and the files as a gzipped cpio archive.
+    pub(crate) fn write(mut self) -> Result<Vec<u8>> {
+        self.files.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut archive = Vec::new();
+        for (inode, file) in self.files.iter().enumerate() {
+            cpio(&mut archive, &format!(".{}", file.path), inode as u32 + 1, file.mode, self.mtime, &file.contents);
+        }
+        cpio(&mut archive, "TRAILER!!!", 0, 0, 0, &[]);
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        gzip.write_all(&archive)?;
+        let payload = gzip.finish()?;
+
+        let header = self.header(&payload).finish(63);
+        let mut signature = Header::default();
+        signature.add(1000, INT32, Data::Int32(vec![(header.len() + payload.len()) as u32]));
+        signature.add(1007, INT32, Data::Int32(vec![archive.len() as u32]));
+        signature.add(273, STRING, Data::String(hex::encode(Sha256::digest(&header))));
+        let mut signature = signature.finish(62);
+        signature.resize(signature.len().next_multiple_of(8), 0);
+
+        let mut rpm = lead(&format!("{}-{}-{}", self.name, self.version, self.release));
+        rpm.extend(signature);
+        rpm.extend(header);
+        rpm.extend(payload);
+        Ok(rpm)
+    }
+
+    fn header(&self, payload: &[u8]) -> Header {
+        let mut header = Header::default();
+        let text = |value: &str| Data::String(value.to_string());
+        let each = |field: &dyn Fn(&File) -> String| Data::Strings(self.files.iter().map(field).collect());
+        let numbers = |field: &dyn Fn(&File) -> u32| Data::Int32(self.files.iter().map(field).collect());
+        header.add(100, STRING_ARRAY, Data::Strings(vec!["C".to_string()]));
+        header.add(1000, STRING, text(self.name));
+        header.add(1001, STRING, text(self.version));
+        header.add(1002, STRING, text(self.release));
+        header.add(1004, I18NSTRING, text(self.summary));
+        header.add(1005, I18NSTRING, text(self.description));
+        header.add(1006, INT32, Data::Int32(vec![self.mtime]));
+        header.add(1009, INT32, Data::Int32(vec![self.files.iter().map(|file| file.contents.len() as u32).sum()]));
+        if let Some(license) = self.license {
+            header.add(1014, STRING, text(license));
+        }
+        if let Some(packager) = self.packager {
+            header.add(1015, STRING, text(packager));
+        }
+        header.add(1016, I18NSTRING, text("Unspecified"));
+        if let Some(url) = self.url {
+            header.add(1020, STRING, text(url));
+        }
+        header.add(1021, STRING, text("linux"));
+        header.add(1022, STRING, text(self.arch));
+        header.add(1028, INT32, numbers(&|file| file.contents.len() as u32));
+        header.add(1030, INT16, Data::Int16(self.files.iter().map(|file| file.mode as u16).collect()));
+        header.add(1033, INT16, Data::Int16(vec![0; self.files.len()]));
+        header.add(1034, INT32, numbers(&|_| self.mtime));
+        header.add(1035, STRING_ARRAY, each(&|file| match file.mode & 0o170000 == 0o100000 {
+            true => hex::encode(Sha256::digest(&file.contents)),
+            false => String::new(),
+        }));
+        header.add(1036, STRING_ARRAY, each(&|_| String::new()));
+        header.add(1037, INT32, numbers(&|file| if file.doc { FILE_DOC } else { 0 }));
+        header.add(1039, STRING_ARRAY, each(&|_| "root".to_string()));
+        header.add(1040, STRING_ARRAY, each(&|_| "root".to_string()));
+        // rpm tells a binary package from a source one by this
+        header.add(1044, STRING, Data::String(format!("{}-{}-{}.src.rpm", self.name, self.version, self.release)));
+        header.add(1047, STRING_ARRAY, Data::Strings(vec![self.name.to_string()]));
+
+        // what reading this package needs of rpm, and the package itself of the system
+        let rpmlib = [("CompressedFileNames", "3.0.4-1"), ("FileDigests", "4.6.0-1"), ("PayloadFilesHavePrefix", "4.0-1")];
+        let mut requires: Vec<(String, u32, String)> = self.requires.iter().map(|name| (name.clone(), 0, String::new())).collect();
+        requires.extend(rpmlib.iter().map(|(feature, version)| (format!("rpmlib({})", feature), SENSE_RPMLIB | SENSE_LESS | SENSE_EQUAL, version.to_string())));
+        header.add(1048, INT32, Data::Int32(requires.iter().map(|(_, flags, _)| *flags).collect()));
+        header.add(1049, STRING_ARRAY, Data::Strings(requires.iter().map(|(name, _, _)| name.clone()).collect()));
+        header.add(1050, STRING_ARRAY, Data::Strings(requires.iter().map(|(_, _, version)| version.clone()).collect()));
+
+        header.add(1095, INT32, numbers(&|_| 1));
+        header.add(1096, INT32, Data::Int32((1..=self.files.len() as u32).collect()));
+        header.add(1097, STRING_ARRAY, each(&|_| String::new()));
+        header.add(1112, INT32, Data::Int32(vec![SENSE_EQUAL]));
+        header.add(1113, STRING_ARRAY, Data::Strings(vec![format!("{}-{}", self.version, self.release)]));
+
+        // paths go in two, with each directory named once
+        let split = |path: &str| path.rsplit_once('/').map(|(dir, base)| (format!("{}/", dir), base.to_string())).unwrap_or_default();
+        let mut dirs: Vec<String> = self.files.iter().map(|file| split(&file.path).0).collect();
+        dirs.sort();
+        dirs.dedup();
+        header.add(1116, INT32, numbers(&|file| dirs.iter().position(|dir| *dir == split(&file.path).0).unwrap_or_default() as u32));
+        header.add(1117, STRING_ARRAY, each(&|file| split(&file.path).1));
+        header.add(1118, STRING_ARRAY, Data::Strings(dirs.clone()));
+
+        header.add(1124, STRING, text("cpio"));
+        header.add(1125, STRING, text("gzip"));
+        header.add(1126, STRING, text("9"));
+        header.add(5011, INT32, Data::Int32(vec![SHA256]));
+        header.add(5092, STRING_ARRAY, Data::Strings(vec![hex::encode(Sha256::digest(payload))]));
+        header.add(5093, INT32, Data::Int32(vec![SHA256]));
+        header
+    }
+}
+
+enum Data {
+    Int16(Vec<u16>),
+    Int32(Vec<u32>),
+    String(String),
+    Strings(Vec<String>),
+}
+
+// A header's entries, as tag, type, and data.
+#[derive(Default)]
+struct Header {
+    entries: Vec<(u32, u32, Data)>,
+}
+
+impl Header {
+    fn add(&mut self, tag: u32, kind: u32, data: Data) {
+        self.entries.push((tag, kind, data));
+    }
+
+    // The header, all of it under one immutable region, whose tag is `region`: rpm wants
+    // the entries by tag, their data in the same order, and the region's trailer last.
+    fn finish(mut self, region: u32) -> Vec<u8> {
+        self.entries.sort_by_key(|(tag, _, _)| *tag);
+        let mut index = Vec::new();
+        let mut store = Vec::new();
+        let entry = |index: &mut Vec<u8>, tag: u32, kind: u32, offset: u32, count: u32| {
+            for field in [tag, kind, offset, count] {
+                index.extend_from_slice(&field.to_be_bytes());
+            }
+        };
+        for (tag, kind, data) in &self.entries {
+            let count = match data {
+                Data::Int16(values) => {
+                    store.resize(store.len().next_multiple_of(2), 0);
+                    entry(&mut index, *tag, *kind, store.len() as u32, values.len() as u32);
+                    values.iter().for_each(|value| store.extend_from_slice(&value.to_be_bytes()));
+                    continue
+                },
+                Data::Int32(values) => {
+                    store.resize(store.len().next_multiple_of(4), 0);
+                    entry(&mut index, *tag, *kind, store.len() as u32, values.len() as u32);
+                    values.iter().for_each(|value| store.extend_from_slice(&value.to_be_bytes()));
+                    continue
+                },
+                Data::String(_) => 1,
+                Data::Strings(values) => values.len() as u32,
+            };
+            entry(&mut index, *tag, *kind, store.len() as u32, count);
+            let strings = match data {
+                Data::String(value) => std::slice::from_ref(value),
+                Data::Strings(values) => values.as_slice(),
+                _ => &[],
+            };
+            for value in strings {
+                store.extend_from_slice(value.as_bytes());
+                store.push(0);
+            }
+        }
+
+        let entries = self.entries.len() as u32 + 1;
+        let mut region_entry = Vec::new();
+        entry(&mut region_entry, region, BIN, store.len() as u32, 16);
+        // the trailer's offset counts back over the region's entries
+        entry(&mut store, region, BIN, (entries * 16).wrapping_neg(), 16);
+
+        let mut header = vec![0x8e, 0xad, 0xe8, 0x01, 0, 0, 0, 0];
+        header.extend_from_slice(&entries.to_be_bytes());
+        header.extend_from_slice(&(store.len() as u32).to_be_bytes());
+        header.extend(region_entry);
+        header.extend(index);
+        header.extend(store);
+        header
+    }
+}
+
+// The lead rpm still starts a package with, though it reads nothing but its magic.
+fn lead(name: &str) -> Vec<u8> {
+    let mut lead = vec![0xed, 0xab, 0xee, 0xdb, 3, 0, 0, 0, 0, 0];
+    let mut field = [0u8; 66];
+    let name = &name.as_bytes()[..name.len().min(65)];
+    field[..name.len()].copy_from_slice(name);
+    lead.extend_from_slice(&field);
+    lead.extend_from_slice(&[0, 1, 0, 5]);
+    lead.resize(96, 0);
+    lead
+}
+
+// A file in a "newc" cpio archive.
+fn cpio(archive: &mut Vec<u8>, name: &str, inode: u32, mode: u32, mtime: u32, contents: &[u8]) {
+    let links = if mode & 0o170000 == 0o040000 { 2 } else { 1 };
+    let fields = [inode, mode, 0, 0, links, mtime, contents.len() as u32, 0, 0, 0, 0, name.len() as u32 + 1, 0];
+    archive.extend_from_slice(b"070701");
+    for field in fields {
+        archive.extend_from_slice(format!("{:08x}", field).as_bytes());
+    }
+    archive.extend_from_slice(name.as_bytes());
+    archive.push(0);
+    archive.resize(archive.len().next_multiple_of(4), 0);
+    archive.extend_from_slice(contents);
+    archive.resize(archive.len().next_multiple_of(4), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    // an entry in a header's index: its tag, type, offset, and count
+    type Entry = (u32, u32, u32, u32);
+
+    // A header's entries and its store, checked as they're read for what rpm refuses a
+    // header over.
+    fn header(bytes: &[u8], region: u32) -> (Vec<Entry>, &[u8]) {
+        assert_eq!(bytes[..8], [0x8e, 0xad, 0xe8, 0x01, 0, 0, 0, 0]);
+        let (count, size) = (u32_at(bytes, 8) as usize, u32_at(bytes, 12) as usize);
+        let entries: Vec<_> = (0..count).map(|n| {
+            let at = 16 + n * 16;
+            (u32_at(bytes, at), u32_at(bytes, at + 4), u32_at(bytes, at + 8), u32_at(bytes, at + 12))
+        }).collect();
+        let store = &bytes[16 + count * 16..][..size];
+
+        // the region first, whose trailer ends the store and counts back over every entry
+        assert_eq!(entries[0], (region, BIN, size as u32 - 16, 16));
+        let trailer = &store[size - 16..];
+        assert_eq!((u32_at(trailer, 0), u32_at(trailer, 4), u32_at(trailer, 8) as i32, u32_at(trailer, 12)), (region, BIN, -(count as i32 * 16), 16));
+        let mut previous = (0, 0);
+        for &(tag, kind, offset, count) in &entries[1..] {
+            assert!(tag > previous.0, "tag {} out of order", tag);
+            assert!(offset >= previous.1 && (offset as usize) < size - 16, "tag {}'s data at {} out of place", tag, offset);
+            match kind {
+                INT16 => assert_eq!(offset % 2, 0, "tag {}", tag),
+                INT32 => assert_eq!(offset % 4, 0, "tag {}", tag),
+                STRING | I18NSTRING => assert_eq!(count, 1, "tag {}", tag),
+                _ => {},
+            }
+            previous = (tag, offset);
+        }
+        (entries[1..].to_vec(), store)
+    }
+
+    fn strings(store: &[u8], (_, _, offset, count): Entry) -> Vec<String> {
+        store[offset as usize..].split(|&b| b == 0).take(count as usize).map(|s| String::from_utf8(s.to_vec()).unwrap()).collect()
+    }
+
+    fn entry(entries: &[Entry], tag: u32) -> Entry {
+        *entries.iter().find(|entry| entry.0 == tag).unwrap_or_else(|| panic!("no tag {}", tag))
+    }
+
+    #[test]
+    fn writes_a_package_rpm_can_read() {
+        let file = |path: &str, mode, contents: &[u8]| File { path: path.to_string(), mode, contents: contents.to_vec(), doc: false };
+        let package = Package {
+            name: "tool",
+            version: "1.2.0",
+            release: "1",
+            arch: "x86_64",
+            summary: "A tool",
+            description: "A tool, packaged",
+            license: Some("MIT"),
+            url: None,
+            packager: Some("Ops <ops@example.com>"),
+            requires: vec!["/bin/sh".to_string()],
+            mtime: 1_700_000_000,
+            files: vec![
+                file("/usr/share/man/man1/tool.1.gz", 0o100644, b"man"),
+                file("/usr/bin/tool", 0o100755, b"\x7fELF..."),
+                file("/usr/lib/tool", 0o040755, b""),
+            ],
+        };
+        let rpm = package.write().unwrap();
+        assert_eq!(rpm[..4], [0xed, 0xab, 0xee, 0xdb]);
+        assert_eq!(&rpm[10..23], b"tool-1.2.0-1\0");
+
+        let (signature, signature_store) = header(&rpm[96..], 62);
+        let signature_end = (96 + 16 + signature.len() * 16 + 16 + signature_store.len()).next_multiple_of(8);
+        let (entries, store) = header(&rpm[signature_end..], 63);
+        let header_end = signature_end + 16 + (entries.len() + 1) * 16 + store.len();
+        let (header_bytes, payload) = (&rpm[signature_end..header_end], &rpm[header_end..]);
+
+        // the signature's digest and sizes are of what follows it
+        let (_, _, size_at, _) = entry(&signature, 1000);
+        assert_eq!(u32_at(signature_store, size_at as usize) as usize, header_bytes.len() + payload.len());
+        assert_eq!(strings(signature_store, entry(&signature, 273)), [hex::encode(Sha256::digest(header_bytes))]);
+        assert_eq!(strings(store, entry(&entries, 5092)), [hex::encode(Sha256::digest(payload))]);
+
+        assert_eq!(strings(store, entry(&entries, 1000)), ["tool"]);
+        assert_eq!(strings(store, entry(&entries, 1049))[0], "/bin/sh");
+        // files by path, each named by its directory and base name
+        assert_eq!(strings(store, entry(&entries, 1117)), ["tool", "tool", "tool.1.gz"]);
+        assert_eq!(strings(store, entry(&entries, 1118)), ["/usr/bin/", "/usr/lib/", "/usr/share/man/man1/"]);
+        let (_, _, modes, count) = entry(&entries, 1030);
+        let modes: Vec<u16> = (0..count as usize).map(|n| u16::from_be_bytes(store[modes as usize + n * 2..][..2].try_into().unwrap())).collect();
+        assert_eq!(modes, [0o100755, 0o040755, 0o100644]);
+
+        let mut archive = Vec::new();
+        flate2::read::GzDecoder::new(payload).read_to_end(&mut archive).unwrap();
+        let (_, _, archive_size, _) = entry(&signature, 1007);
+        assert_eq!(u32_at(signature_store, archive_size as usize) as usize, archive.len());
+        let (mut names, mut at) = (Vec::new(), 0);
+        while at < archive.len() {
+            assert_eq!(&archive[at..at + 6], b"070701");
+            let field = |n: usize| usize::from_str_radix(std::str::from_utf8(&archive[at + 6 + n * 8..][..8]).unwrap(), 16).unwrap();
+            let (size, name_size) = (field(6), field(11));
+            names.push(std::str::from_utf8(&archive[at + 110..at + 110 + name_size - 1]).unwrap());
+            at = (at + 110 + name_size).next_multiple_of(4) + size.next_multiple_of(4);
+        }
+        assert_eq!(names, ["./usr/bin/tool", "./usr/lib/tool", "./usr/share/man/man1/tool.1.gz", "TRAILER!!!"]);
+    }
+}
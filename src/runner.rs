@@ -0,0 +1,233 @@
+use eyre::Result;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::process::{ ExitStatus, Output };
+use std::sync::Arc;
+
+/// Build and run a script the way the `clawbang` command does, from another program:
+///
+/// ```no_run
+/// let status = clawbang::ScriptRunner::new("tools/report.rs")
+///     .cache_dir("/var/cache/clawbang")
+///     .args(["--since", "yesterday"])
+///     .run()?;
+/// # Ok::<(), eyre::Report>(())
+/// ```
+///
+/// The script may be anything the command takes: a file, a directory holding a `main.rs`,
+/// a URL, or a git reference. It shares the command's cache, so a script built by either is
+/// a cache hit for the other. Compiler messages from a failed build are shown on stderr, as
+/// they are at the command line.
+///
+/// With the `tokio` feature, each way of building or running a script has an `_async`
+/// variant, for servers that build scripts as they're submitted.
+#[derive(Clone)]
+pub struct ScriptRunner {
+    script: PathBuf,
+    args: Vec<OsString>,
+    cache_dir: Option<PathBuf>,
+    config: Option<PathBuf>,
+    store: Option<Arc<dyn crate::CacheStore>>,
+    hooks: Vec<Arc<dyn crate::ProjectHook>>,
+    on_event: Option<crate::events::Callback>,
+    quiet: bool,
+    rebuild: bool,
+}
+
+/// What came of building a script, from [`ScriptRunner::build`].
+#[derive(Debug)]
+pub struct BuildOutcome {
+    /// The built program, unpacked in the cache; `None` if the build failed.
+    pub binary_path: Option<PathBuf>,
+    /// cargo's exit status: 0 for a good build.
+    pub exit_code: i32,
+    /// The compiler's errors and warnings, as they'd be shown at a terminal (without color),
+    /// pointed at the script.
+    pub diagnostics: String,
+    /// The same messages as values, each with the spot in the script it points at, for
+    /// editors to underline.
+    pub messages: Vec<crate::Diagnostic>,
+    /// Whether the cache already had this build, good or failed.
+    pub cache_hit: bool,
+}
+
+impl ScriptRunner {
+    pub fn new(script: impl Into<PathBuf>) -> Self {
+        Self {
+            script: script.into(),
+            args: Vec::new(),
+            cache_dir: None,
+            config: None,
+            store: None,
+            hooks: Vec::new(),
+            on_event: None,
+            quiet: false,
+            rebuild: false,
+        }
+    }
+
+    /// Where builds are cached; $CLAWBANG_DIR, or `~/.clawbang-cache`, by default.
+    pub fn cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(dir.into());
+        self
+    }
+
+    /// Keep builds in `store` rather than the cache directory, which still holds clawbang's
+    /// own state. See [`CacheStore`](crate::CacheStore).
+    pub fn cache_store(mut self, store: impl crate::CacheStore + 'static) -> Self {
+        self.store = Some(Arc::new(store));
+        self
+    }
+
+    /// Change the generated project before it builds, after any hooks the settings file
+    /// names; hooks added earlier run first. See [`ProjectHook`](crate::ProjectHook).
+    pub fn hook(mut self, hook: impl crate::ProjectHook + 'static) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Call `on_event` as the script builds: as cargo starts and finishes each package, for
+    /// each warning and error, and once the binary is ready. It's called from the thread
+    /// doing the build; to have a stream of events instead, send them down a channel.
+    pub fn on_event(mut self, on_event: impl Fn(&crate::BuildEvent) + Send + Sync + 'static) -> Self {
+        self.on_event = Some(Arc::new(on_event));
+        self
+    }
+
+    /// Read settings from this file instead of ~/.clawbang.toml.
+    pub fn config(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config = Some(path.into());
+        self
+    }
+
+    /// The script's arguments, after its name.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Leave out the status line and summary shown while the script builds.
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Build the script again, even if the cache has a build of it already.
+    pub fn rebuild(mut self, rebuild: bool) -> Self {
+        self.rebuild = rebuild;
+        self
+    }
+
+    /// Build the script if it isn't cached, then run it as a child process, with this
+    /// process's stdio, and wait for it. A script that fails to build is an error.
+    pub fn run(&self) -> Result<ExitStatus> {
+        let mut launch = self.launch(false)?;
+        let status = launch.command.status()
+            .map_err(|err| eyre::eyre!("Could not run {}: {}", launch.binary.display(), err))?;
+        // a fresh build runs out of its tempdir, which can go once the script is done
+        drop(launch.tempdir.take());
+        Ok(status)
+    }
+
+    /// Build the script if it isn't cached, without running it, and say how that went. No
+    /// compiler messages are shown; they're in the outcome instead.
+    pub fn build(&self) -> Result<BuildOutcome> {
+        Ok(match self.prepare(true)? {
+            crate::Build::Ready(launch) => BuildOutcome {
+                binary_path: Some(launch.binary),
+                exit_code: 0,
+                diagnostics: launch.diagnostics.rendered,
+                messages: launch.diagnostics.messages,
+                cache_hit: launch.hit,
+            },
+            crate::Build::Failed { exit_code, diagnostics, hit } => BuildOutcome {
+                binary_path: None,
+                exit_code,
+                diagnostics: diagnostics.rendered,
+                messages: diagnostics.messages,
+                cache_hit: hit,
+            },
+        })
+    }
+
+    /// Build the script if it isn't cached, then run it with its stdout and stderr
+    /// collected (and nothing on its stdin), as `std::process::Command::output` does. A
+    /// script that fails to build is an error, carrying its compiler messages.
+    pub fn output(&self) -> Result<Output> {
+        let mut launch = self.launch(true)?;
+        launch.command.output()
+            .map_err(|err| eyre::eyre!("Could not run {}: {}", launch.binary.display(), err))
+    }
+
+    // Build, or find the build, with compiler messages shown (like the command) or captured.
+    fn prepare(&self, capture: bool) -> Result<crate::Build> {
+        let mut opts = self.options()?;
+        opts.capture = capture;
+        let config = crate::config::load(opts.config.as_deref())?;
+        crate::prepare(&opts, &config)
+    }
+
+    // A script ready to start; failing to build is an error, with the messages if captured.
+    fn launch(&self, capture: bool) -> Result<Box<crate::Launch>> {
+        match self.prepare(capture)? {
+            crate::Build::Ready(launch) => Ok(launch),
+            crate::Build::Failed { exit_code, diagnostics, .. } => Err(eyre::eyre!(
+                "{} failed to build (cargo exited with status {}){}",
+                self.script.display(), exit_code, if diagnostics.rendered.is_empty() { String::new() } else { format!(":\n{}", diagnostics.rendered) }
+            )),
+        }
+    }
+
+    // The options the command would have parsed, environment variables and all.
+    fn options(&self) -> Result<crate::Options> {
+        let mut opts = crate::Options::library(self.script.clone());
+        if let Some(cache_dir) = &self.cache_dir {
+            opts.cache_dir = cache_dir.clone();
+        }
+        if let Some(config) = &self.config {
+            opts.config = Some(config.clone());
+        }
+        opts.quiet = self.quiet;
+        opts.rebuild = self.rebuild;
+        opts.rest = self.args.clone();
+        opts.store = self.store.clone();
+        opts.hooks = self.hooks.clone();
+        opts.on_event = self.on_event.clone();
+        Ok(opts)
+    }
+}
+
+// Builds are the same pipeline as ever, locks and all, so they run on tokio's blocking pool;
+// the script itself runs under tokio::process, so waiting on it holds up no thread.
+#[cfg(feature = "tokio")]
+impl ScriptRunner {
+    /// [`run`](Self::run), without blocking the runtime.
+    pub async fn run_async(&self) -> Result<ExitStatus> {
+        let runner = self.clone();
+        let launch = tokio::task::spawn_blocking(move || runner.launch(false)).await??;
+        let crate::Launch { command, binary, tempdir, .. } = *launch;
+        let status = tokio::process::Command::from(command).status().await
+            .map_err(|err| eyre::eyre!("Could not run {}: {}", binary.display(), err))?;
+        drop(tempdir);
+        Ok(status)
+    }
+
+    /// [`build`](Self::build), without blocking the runtime.
+    pub async fn build_async(&self) -> Result<BuildOutcome> {
+        let runner = self.clone();
+        tokio::task::spawn_blocking(move || runner.build()).await?
+    }
+
+    /// [`output`](Self::output), without blocking the runtime.
+    pub async fn output_async(&self) -> Result<Output> {
+        let runner = self.clone();
+        let launch = tokio::task::spawn_blocking(move || runner.launch(true)).await??;
+        let crate::Launch { command, binary, .. } = *launch;
+        tokio::process::Command::from(command).output().await
+            .map_err(|err| eyre::eyre!("Could not run {}: {}", binary.display(), err))
+    }
+}
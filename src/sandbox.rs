@@ -0,0 +1,386 @@
+use crate::permissions::{ Grant, Permissions };
+use eyre::Result;
+use std::path::{ Path, PathBuf };
+use std::process;
+
+// Variables a sandboxed script still gets from clawbang's environment without an env grant,
+// on top of clawbang's own and the frontmatter's [env].
+const BASE_ENV: &[&str] = &["PATH", "HOME", "USER", "LOGNAME", "TERM", "LANG", "LC_ALL", "TZ"];
+
+// What any program needs to read to start at all: the dynamic loader and shared libraries.
+#[cfg(target_os = "linux")]
+const SYSTEM_LIBRARIES: &[&str] = &["/lib", "/lib32", "/lib64", "/usr/lib", "/usr/lib32", "/usr/lib64", "/etc/ld.so.cache"];
+#[cfg(target_os = "macos")]
+const SYSTEM_LIBRARIES: &[&str] = &["/usr/lib", "/System", "/Library/Apple", "/private/var/db/dyld", "/private/var/db/timezone"];
+
+// The devices scripts expect to be able to use, and what little of /etc the C library reads
+// regardless of what the script is doing.
+const DEVICES: &[&str] = &["/dev/null", "/dev/zero", "/dev/tty"];
+const SYSTEM_FILES: &[&str] = &["/dev/urandom", "/dev/random", "/etc/localtime"];
+
+// What's read to look up hosts and check certificates, for scripts that use the network.
+const NETWORK_FILES: &[&str] = &[
+    "/etc/resolv.conf", "/etc/hosts", "/etc/host.conf", "/etc/nsswitch.conf", "/etc/gai.conf", "/etc/services",
+    "/etc/ssl", "/etc/pki", "/etc/ca-certificates", "/usr/share/ca-certificates",
+];
+
+/// `--sandbox`: the script is confined to what its `[permissions]` grant, enforced by the
+/// kernel (Landlock and seccomp on Linux) or by `sandbox-exec` (macOS). A script without the
+/// table gets nothing beyond starting up: no files, no network, no programs of its own to run.
+pub(crate) struct Sandbox {
+    // the environment variables it may have; None for all of them
+    env: Option<Vec<String>>,
+    #[cfg(target_os = "linux")]
+    confinement: std::sync::Arc<linux::Confinement>,
+    #[cfg(target_os = "macos")]
+    profile: String,
+}
+
+/// Where a sandboxed script may go and what it may do, as paths rather than grants.
+struct Rules {
+    read: Vec<PathBuf>,
+    write: Vec<PathBuf>,
+    exec: Vec<PathBuf>,
+    // anywhere, for blanket grants
+    read_all: bool,
+    write_all: bool,
+    exec_all: bool,
+    net: bool,
+}
+
+impl Sandbox {
+    pub(crate) fn new(script_name: &str, binary: &Path, permissions: Option<&Permissions>) -> Result<Self> {
+        let none = Permissions::default();
+        let permissions = permissions.unwrap_or(&none);
+        if matches!(&permissions.net, Grant::Only(hosts) if !hosts.is_empty()) {
+            eprintln!(
+                "{} --sandbox can't hold {} to the hosts its [permissions] name; it may reach any of them",
+                crate::color::label("warning"), script_name
+            );
+        }
+
+        let mut rules = Rules {
+            read: vec![binary.to_path_buf()],
+            write: Vec::new(),
+            exec: vec![binary.to_path_buf()],
+            read_all: matches!(permissions.fs_read, Grant::All(true)),
+            write_all: matches!(permissions.fs_write, Grant::All(true)),
+            exec_all: matches!(permissions.exec, Grant::All(true)),
+            net: permissions.net.granted(),
+        };
+        if let Grant::Only(paths) = &permissions.fs_read {
+            rules.read.extend(paths.iter().map(PathBuf::from));
+        }
+        if let Grant::Only(paths) = &permissions.fs_write {
+            rules.write.extend(paths.iter().map(PathBuf::from));
+        }
+        // programs are named as they'd be run, so they're found the way a shell would
+        if let Grant::Only(programs) = &permissions.exec {
+            for program in programs {
+                match find_program(program) {
+                    Some(path) => rules.exec.push(path),
+                    None => tracing::debug!(%program, "the sandbox can't find a program [permissions] lets the script run"),
+                }
+            }
+        }
+        rules.read.extend(SYSTEM_FILES.iter().map(PathBuf::from));
+        if rules.net {
+            rules.read.extend(NETWORK_FILES.iter().map(PathBuf::from));
+        }
+
+        let env = match &permissions.env {
+            Grant::All(true) => None,
+            Grant::All(false) => Some(Vec::new()),
+            Grant::Only(names) => Some(names.clone()),
+        };
+        Self::confine(env, rules)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn confine(env: Option<Vec<String>>, rules: Rules) -> Result<Self> {
+        Ok(Sandbox { env, confinement: std::sync::Arc::new(linux::Confinement::new(&rules)?) })
+    }
+
+    #[cfg(target_os = "macos")]
+    fn confine(env: Option<Vec<String>>, rules: Rules) -> Result<Self> {
+        Ok(Sandbox { env, profile: macos::profile(&rules) })
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    fn confine(_env: Option<Vec<String>>, _rules: Rules) -> Result<Self> {
+        Err(eyre::eyre!("--sandbox is only supported on Linux and macOS"))
+    }
+
+    /// What starts the script: the binary itself, or on macOS, `sandbox-exec` running it.
+    pub(crate) fn program(&self, binary: &Path) -> process::Command {
+        #[cfg(target_os = "macos")]
+        {
+            let mut command = process::Command::new("/usr/bin/sandbox-exec");
+            command.arg("-p").arg(&self.profile).arg(binary);
+            command
+        }
+
+        #[cfg(not(target_os = "macos"))]
+        process::Command::new(binary)
+    }
+
+    /// Confine the script's process as it starts. This goes last, after anything else that
+    /// sets the process up, since afterwards there's little it's allowed to do.
+    pub(crate) fn install(&self, command: &mut process::Command) {
+        if let Some(allowed) = &self.env {
+            let set: Vec<_> = command.get_envs().map(|(name, value)| (name.to_os_string(), value.map(|value| value.to_os_string()))).collect();
+            command.env_clear();
+            for name in BASE_ENV.iter().copied().chain(allowed.iter().map(String::as_str)) {
+                if let Some(value) = std::env::var_os(name) {
+                    command.env(name, value);
+                }
+            }
+            for (name, value) in set {
+                match value {
+                    Some(value) => command.env(name, value),
+                    None => command.env_remove(name),
+                };
+            }
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::process::CommandExt;
+            let confinement = self.confinement.clone();
+            unsafe {
+                command.pre_exec(move || confinement.apply());
+            }
+        }
+    }
+}
+
+// A program as a shell would find it: a path as it is, or a name on PATH.
+fn find_program(program: &str) -> Option<PathBuf> {
+    if program.contains('/') {
+        return Path::new(program).canonicalize().ok()
+    }
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|candidate| candidate.is_file())
+        .and_then(|found| found.canonicalize().ok())
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{ Rules, DEVICES, SYSTEM_LIBRARIES };
+    use eyre::Result;
+    use std::os::fd::{ AsRawFd, FromRawFd, OwnedFd };
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    // Landlock's interface, from linux/landlock.h.
+    const CREATE_RULESET_VERSION: u32 = 1;
+    const RULE_PATH_BENEATH: u32 = 1;
+    const EXECUTE: u64 = 1 << 0;
+    const WRITE_FILE: u64 = 1 << 1;
+    const READ_FILE: u64 = 1 << 2;
+    const READ_DIR: u64 = 1 << 3;
+    // removing and making directories, files, devices, sockets, pipes, and symlinks
+    const CHANGE_DIR: u64 = 0b1_1111_1111 << 4;
+    const REFER: u64 = 1 << 13;
+    const TRUNCATE: u64 = 1 << 14;
+    const FILE_ACCESS: u64 = EXECUTE | WRITE_FILE | READ_FILE | TRUNCATE;
+    const NET_BIND_TCP: u64 = 1 << 0;
+    const NET_CONNECT_TCP: u64 = 1 << 1;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+        handled_access_net: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    // seccomp's, from linux/audit.h and linux/seccomp.h; the filter is checked against the
+    // architecture it was written for, since system call numbers differ between them.
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    const AUDIT_ARCH: Option<u32> = None;
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    /// A Landlock ruleset and a seccomp filter, both built before the script's process is
+    /// forked, so that the child only has to switch them on before it execs the script.
+    pub(super) struct Confinement {
+        ruleset: OwnedFd,
+        filter: Option<Vec<libc::sock_filter>>,
+    }
+
+    impl Confinement {
+        pub(super) fn new(rules: &Rules) -> Result<Self> {
+            let abi = unsafe { libc::syscall(libc::SYS_landlock_create_ruleset, std::ptr::null::<RulesetAttr>(), 0, CREATE_RULESET_VERSION) };
+            if abi < 1 {
+                return Err(eyre::eyre!("--sandbox needs Landlock (Linux 5.13 or later, with Landlock enabled), which this system doesn't have"))
+            }
+            let mut handled = EXECUTE | WRITE_FILE | READ_FILE | READ_DIR | CHANGE_DIR;
+            if abi >= 2 {
+                handled |= REFER;
+            }
+            if abi >= 3 {
+                handled |= TRUNCATE;
+            }
+            // Landlock only knows TCP; seccomp turns away the rest
+            let handled_net = if abi >= 4 && !rules.net { NET_BIND_TCP | NET_CONNECT_TCP } else { 0 };
+            let filter = (!rules.net).then(deny_network).flatten();
+            if !rules.net && filter.is_none() && handled_net == 0 {
+                return Err(eyre::eyre!("--sandbox can't keep scripts off the network on this system; grant them net in [permissions] to run them sandboxed"))
+            }
+
+            let attr = RulesetAttr { handled_access_fs: handled, handled_access_net: handled_net };
+            let fd = unsafe { libc::syscall(libc::SYS_landlock_create_ruleset, &attr, std::mem::size_of::<RulesetAttr>(), 0) };
+            if fd < 0 {
+                return Err(eyre::eyre!("Could not set up the sandbox: {}", std::io::Error::last_os_error()))
+            }
+            let ruleset = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+
+            let read = READ_FILE | READ_DIR;
+            let write = read | WRITE_FILE | CHANGE_DIR | REFER | TRUNCATE;
+            let roots: [(&[&str], u64); 2] = [(SYSTEM_LIBRARIES, read | EXECUTE), (DEVICES, READ_FILE | WRITE_FILE | TRUNCATE)];
+            for (paths, access) in roots {
+                for path in paths {
+                    allow(&ruleset, Path::new(path), access & handled)?;
+                }
+            }
+            let everything = [(rules.read_all, read), (rules.write_all, write), (rules.exec_all, read | EXECUTE)];
+            for (all, access) in everything {
+                if all {
+                    allow(&ruleset, Path::new("/"), access & handled)?;
+                }
+            }
+            let listed = [(&rules.read, read), (&rules.write, write), (&rules.exec, READ_FILE | EXECUTE)];
+            for (paths, access) in listed {
+                for path in paths {
+                    allow(&ruleset, path, access & handled)?;
+                }
+            }
+            Ok(Confinement { ruleset, filter })
+        }
+
+        // Confine the calling process: first no new privileges, which an unprivileged process
+        // needs before it can take on either, then the seccomp filter, then the ruleset.
+        // Nothing here allocates, since another thread may have held the allocator's lock
+        // when the process forked.
+        pub(super) fn apply(&self) -> std::io::Result<()> {
+            unsafe {
+                if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                    return Err(std::io::Error::last_os_error())
+                }
+                if let Some(filter) = &self.filter {
+                    let program = libc::sock_fprog { len: filter.len() as u16, filter: filter.as_ptr() as *mut _ };
+                    if libc::prctl(libc::PR_SET_SECCOMP, libc::SECCOMP_MODE_FILTER, &program as *const libc::sock_fprog) != 0 {
+                        return Err(std::io::Error::last_os_error())
+                    }
+                }
+                if libc::syscall(libc::SYS_landlock_restrict_self, self.ruleset.as_raw_fd(), 0) != 0 {
+                    return Err(std::io::Error::last_os_error())
+                }
+            }
+            Ok(())
+        }
+    }
+
+    // Give the sandboxed process `access` to `path` (and everything beneath it, for a
+    // directory). Paths that don't exist on this system are passed over.
+    fn allow(ruleset: &OwnedFd, path: &Path, access: u64) -> Result<()> {
+        let name = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+        let fd = unsafe { libc::open(name.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Ok(())
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        // only some kinds of access make sense for a file, rather than a directory
+        let access = match std::fs::metadata(path) {
+            Ok(metadata) if metadata.is_dir() => access,
+            _ => access & FILE_ACCESS,
+        };
+        let rule = PathBeneathAttr { allowed_access: access, parent_fd: fd.as_raw_fd() };
+        if unsafe { libc::syscall(libc::SYS_landlock_add_rule, ruleset.as_raw_fd(), RULE_PATH_BENEATH, &rule, 0) } != 0 {
+            return Err(eyre::eyre!("Could not let the sandbox at {}: {}", path.display(), std::io::Error::last_os_error()))
+        }
+        Ok(())
+    }
+
+    // A seccomp filter refusing internet sockets (and io_uring, which could make them
+    // behind the filter's back), for this architecture if it's one clawbang knows.
+    fn deny_network() -> Option<Vec<libc::sock_filter>> {
+        let arch = AUDIT_ARCH?;
+        let load = |offset| libc::sock_filter { code: (libc::BPF_LD | libc::BPF_W | libc::BPF_ABS) as u16, jt: 0, jf: 0, k: offset };
+        let jump = |op: u32, k, jt, jf| libc::sock_filter { code: (libc::BPF_JMP | op | libc::BPF_K) as u16, jt, jf, k };
+        let ret = |k| libc::sock_filter { code: (libc::BPF_RET | libc::BPF_K) as u16, jt: 0, jf: 0, k };
+        // offsets into struct seccomp_data: nr, arch, then the arguments
+        Some(vec![
+            load(4),
+            jump(libc::BPF_JEQ, arch, 1, 0),
+            ret(libc::SECCOMP_RET_KILL_PROCESS),
+            load(0),
+            jump(libc::BPF_JGE, X32_SYSCALL_BIT, 5, 0),
+            jump(libc::BPF_JEQ, libc::SYS_io_uring_setup as u32, 4, 0),
+            jump(libc::BPF_JEQ, libc::SYS_socket as u32, 0, 4),
+            load(16),
+            jump(libc::BPF_JEQ, libc::AF_INET as u32, 1, 0),
+            jump(libc::BPF_JEQ, libc::AF_INET6 as u32, 0, 1),
+            ret(libc::SECCOMP_RET_ERRNO | libc::EACCES as u32),
+            ret(libc::SECCOMP_RET_ALLOW),
+        ])
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod macos {
+    use super::{ Rules, DEVICES, SYSTEM_FILES, SYSTEM_LIBRARIES };
+    use std::fmt::Write;
+    use std::path::Path;
+
+    /// A sandbox-exec profile allowing what the rules do, and the little else any program
+    /// needs to start.
+    pub(super) fn profile(rules: &Rules) -> String {
+        let mut profile = String::from(
+            "(version 1)\n(deny default)\n(allow process-fork)\n(allow signal (target self))\n\
+             (allow sysctl-read)\n(allow mach-lookup)\n(allow ipc-posix-shm)\n(allow file-read-metadata)\n"
+        );
+        for path in SYSTEM_LIBRARIES.iter().chain(SYSTEM_FILES) {
+            let _ = writeln!(profile, "(allow file-read* (subpath {}))", quote(Path::new(path)));
+        }
+        for path in DEVICES {
+            let _ = writeln!(profile, "(allow file-read* file-write* (literal {}))", quote(Path::new(path)));
+        }
+        let everything = [(rules.read_all, "file-read*"), (rules.write_all, "file-read* file-write*"), (rules.exec_all, "file-read* process-exec")];
+        for (all, operations) in everything {
+            if all {
+                let _ = writeln!(profile, "(allow {})", operations);
+            }
+        }
+        for path in &rules.read {
+            let _ = writeln!(profile, "(allow file-read* (subpath {}))", quote(path));
+        }
+        for path in &rules.write {
+            let _ = writeln!(profile, "(allow file-read* file-write* (subpath {}))", quote(path));
+        }
+        for path in &rules.exec {
+            let _ = writeln!(profile, "(allow file-read* process-exec (literal {}))", quote(path));
+        }
+        if rules.net {
+            profile.push_str("(allow network*)\n");
+        }
+        profile
+    }
+
+    // A path as a profile string. sandbox-exec matches real paths, so /tmp has to be
+    // spelled /private/tmp, and so on.
+    fn quote(path: &Path) -> String {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        format!("\"{}\"", path.to_string_lossy().replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
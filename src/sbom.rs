@@ -0,0 +1,215 @@
+use crate::metadata::{ Metadata, Package };
+use eyre::Result;
+use serde_json::{ json, Value };
+use std::collections::HashMap;
+use std::path::Path;
+
+// How cargo names crates.io as a source, by git index and by sparse one.
+const CRATES_IO: &[&str] = &["registry+https://github.com/rust-lang/crates.io-index", "sparse+https://index.crates.io/"];
+
+/// `clawbang sbom`: the usual options, plus which kind of bill of materials to write.
+#[derive(clap::Args)]
+pub(crate) struct SbomOptions {
+    /// cyclonedx (CycloneDX 1.5) or spdx (SPDX 2.3), either as JSON
+    #[clap(long, default_value = "cyclonedx")]
+    pub(crate) format: Format,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum Format {
+    CycloneDx,
+    Spdx,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "cyclonedx" => Ok(Format::CycloneDx),
+            "spdx" => Ok(Format::Spdx),
+            _ => Err(format!("unknown SBOM format \"{}\"; expected cyclonedx or spdx", value)),
+        }
+    }
+}
+
+/// What a script ships: the packages its build builds, as `metadata` resolved them, with
+/// the checksums its lockfile records.
+pub(crate) struct Inventory<'a> {
+    pub(crate) script_name: &'a str,
+    pub(crate) cache_key: &'a str,
+    pub(crate) metadata: &'a Metadata,
+    pub(crate) lockfile: &'a Path,
+}
+
+impl Inventory<'_> {
+    /// The bill of materials, in `format`, as JSON.
+    pub(crate) fn render(&self, format: Format) -> Result<String> {
+        let root = self.metadata.root().ok_or_else(|| eyre::eyre!("cargo didn't say which package is {}", self.script_name))?;
+        let built = self.metadata.built();
+        // in a stable order, so the same script makes the same document
+        let mut packages: Vec<_> = self.metadata.packages.iter().filter(|package| built.contains(package.id.as_str())).collect();
+        packages.sort_by(|a, b| (&a.name, &a.version, &a.source).cmp(&(&b.name, &b.version, &b.source)));
+        let checksums = checksums(self.lockfile);
+        let checksum = |package: &Package| checksums.get(&(package.name.clone(), package.version.clone(), package.source.clone())).cloned();
+
+        let created = now();
+        let document = match format {
+            Format::CycloneDx => self.cyclonedx(root, &packages, &checksum, &created),
+            Format::Spdx => self.spdx(root, &packages, &checksum, &created),
+        };
+        Ok(serde_json::to_string_pretty(&document)?)
+    }
+
+    fn cyclonedx(&self, root: &Package, packages: &[&Package], checksum: &dyn Fn(&Package) -> Option<String>, created: &str) -> Value {
+        // the script's package id names the temporary directory it was resolved in
+        let reference = |id: &str| if id == root.id { self.script_name.to_string() } else { id.to_string() };
+        let components: Vec<_> = packages.iter().map(|package| {
+            let mut component = json!({
+                "type": "library",
+                "bom-ref": package.id,
+                "name": package.name,
+                "version": package.version,
+            });
+            if let Some(purl) = purl(package) {
+                component["purl"] = json!(purl);
+            }
+            if let Some(license) = &package.license {
+                component["licenses"] = json!([{ "expression": license }]);
+            }
+            if let Some(checksum) = checksum(package) {
+                component["hashes"] = json!([{ "alg": "SHA-256", "content": checksum }]);
+            }
+            component
+        }).collect();
+        let dependencies: Vec<_> = std::iter::once(root).chain(packages.iter().copied()).map(|package| json!({
+            "ref": reference(&package.id),
+            "dependsOn": self.metadata.dependencies(&package.id).map(reference).collect::<Vec<_>>(),
+        })).collect();
+        json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "version": 1,
+            "metadata": {
+                "timestamp": created,
+                "tools": { "components": [{ "type": "application", "name": "clawbang", "version": env!("CARGO_PKG_VERSION") }] },
+                "component": {
+                    "type": "application",
+                    "bom-ref": self.script_name,
+                    "name": self.script_name,
+                    "version": root.version,
+                    "properties": [{ "name": "clawbang:cache-key", "value": self.cache_key }],
+                },
+            },
+            "components": components,
+            "dependencies": dependencies,
+        })
+    }
+
+    fn spdx(&self, root: &Package, packages: &[&Package], checksum: &dyn Fn(&Package) -> Option<String>, created: &str) -> Value {
+        // SPDX ids only allow letters, digits, dots, and dashes
+        let ids: HashMap<&str, String> = std::iter::once(root).chain(packages.iter().copied()).enumerate()
+            .map(|(index, package)| {
+                let name: String = package.name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '-' }).collect();
+                (package.id.as_str(), format!("SPDXRef-Package-{}-{}", index, name))
+            })
+            .collect();
+        let spdx_packages: Vec<_> = std::iter::once(root).chain(packages.iter().copied()).map(|package| {
+            let is_root = package.id == root.id;
+            let mut entry = json!({
+                "SPDXID": ids[package.id.as_str()],
+                "name": if is_root { self.script_name } else { &package.name },
+                "versionInfo": package.version,
+                "downloadLocation": download_location(package),
+                "filesAnalyzed": false,
+                "licenseConcluded": "NOASSERTION",
+                "licenseDeclared": package.license.as_deref().unwrap_or("NOASSERTION"),
+                "copyrightText": "NOASSERTION",
+            });
+            if let Some(purl) = purl(package).filter(|_| !is_root) {
+                entry["externalRefs"] = json!([{ "referenceCategory": "PACKAGE-MANAGER", "referenceType": "purl", "referenceLocator": purl }]);
+            }
+            if let Some(checksum) = checksum(package) {
+                entry["checksums"] = json!([{ "algorithm": "SHA256", "checksumValue": checksum }]);
+            }
+            entry
+        }).collect();
+
+        let mut relationships = vec![json!({
+            "spdxElementId": "SPDXRef-DOCUMENT",
+            "relationshipType": "DESCRIBES",
+            "relatedSpdxElement": ids[root.id.as_str()],
+        })];
+        for package in std::iter::once(root).chain(packages.iter().copied()) {
+            for dependency in self.metadata.dependencies(&package.id) {
+                if let Some(dependency) = ids.get(dependency) {
+                    relationships.push(json!({
+                        "spdxElementId": ids[package.id.as_str()],
+                        "relationshipType": "DEPENDS_ON",
+                        "relatedSpdxElement": dependency,
+                    }));
+                }
+            }
+        }
+        json!({
+            "spdxVersion": "SPDX-2.3",
+            "dataLicense": "CC0-1.0",
+            "SPDXID": "SPDXRef-DOCUMENT",
+            "name": self.script_name,
+            "documentNamespace": format!("https://spdx.org/spdxdocs/clawbang/{}", self.cache_key),
+            "creationInfo": {
+                "created": created,
+                "creators": [format!("Tool: clawbang-{}", env!("CARGO_PKG_VERSION"))],
+            },
+            "packages": spdx_packages,
+            "relationships": relationships,
+        })
+    }
+}
+
+// A package URL: pkg:cargo/name@version, saying where it's from unless that's crates.io.
+fn purl(package: &Package) -> Option<String> {
+    let purl = format!("pkg:cargo/{}@{}", package.name, package.version);
+    match package.source.as_deref()? {
+        source if CRATES_IO.contains(&source) => Some(purl),
+        source => match source.split_once('+') {
+            Some(("git", url)) => Some(format!("{}?vcs_url={}", purl, encode(&format!("git+{}", url)))),
+            Some((_, url)) => Some(format!("{}?repository_url={}", purl, encode(url))),
+            None => None,
+        },
+    }
+}
+
+fn download_location(package: &Package) -> String {
+    match package.source.as_deref() {
+        Some(source) if CRATES_IO.contains(&source) => format!("https://crates.io/api/v1/crates/{}/{}/download", package.name, package.version),
+        Some(source) if source.starts_with("git+") => source.to_string(),
+        _ => "NOASSERTION".to_string(),
+    }
+}
+
+// Percent-encoding for a purl qualifier's value.
+fn encode(value: &str) -> String {
+    value.chars().map(|c| match c {
+        '%' | '#' | '?' | '&' | '=' | '+' | ' ' => format!("%{:02X}", c as u32),
+        c => c.to_string(),
+    }).collect()
+}
+
+// The checksums a lockfile records, by package name, version, and source.
+fn checksums(lockfile: &Path) -> HashMap<(String, String, Option<String>), String> {
+    let lockfile: toml::Value = match std::fs::read_to_string(lockfile).ok().and_then(|contents| toml::from_str(&contents).ok()) {
+        Some(lockfile) => lockfile,
+        None => return HashMap::new(),
+    };
+    let text = |package: &toml::Value, key| package.get(key).and_then(toml::Value::as_str).map(String::from);
+    lockfile.get("package").and_then(toml::Value::as_array).into_iter().flatten()
+        .filter_map(|package| Some(((text(package, "name")?, text(package, "version")?, text(package, "source")), text(package, "checksum")?)))
+        .collect()
+}
+
+// Now, as RFC 3339 wants it.
+fn now() -> String {
+    let millis = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_millis();
+    crate::rfc3339(millis)
+}
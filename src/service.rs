@@ -0,0 +1,60 @@
+use serde::Deserialize;
+use std::time::Duration;
+
+/// The `[service]` frontmatter table: how `clawbang systemd` has systemd run the script.
+#[derive(Deserialize, Clone, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Service {
+    pub(crate) description: Option<String>,
+    #[serde(default)]
+    pub(crate) restart: Restart,
+    pub(crate) restart_sec: Option<Delay>,
+}
+
+/// When systemd starts the script again after it exits: "no", "on-failure" (the default),
+/// or "always".
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(try_from = "String")]
+pub(crate) enum Restart {
+    No,
+    #[default]
+    OnFailure,
+    Always,
+}
+
+impl TryFrom<String> for Restart {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "no" => Ok(Restart::No),
+            "on-failure" => Ok(Restart::OnFailure),
+            "always" => Ok(Restart::Always),
+            _ => Err(format!("unknown restart policy \"{}\"; expected no, on-failure, or always", value)),
+        }
+    }
+}
+
+impl Restart {
+    /// As systemd's `Restart=` spells it.
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Restart::No => "no",
+            Restart::OnFailure => "on-failure",
+            Restart::Always => "always",
+        }
+    }
+}
+
+/// A duration written the way `--timeout` takes one: `500ms`, `90s`, `5m`, `1h`.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(try_from = "String")]
+pub(crate) struct Delay(pub(crate) Duration);
+
+impl TryFrom<String> for Delay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        crate::parse_duration(&value).map(Delay).map_err(|err| err.to_string())
+    }
+}
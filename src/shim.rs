@@ -0,0 +1,96 @@
+use eyre::Result;
+use std::ffi::OsString;
+use std::path::{ Path, PathBuf };
+
+// Marks a file as one of ours, so rewriting a shim never clobbers anything else.
+const MARKER: &str = "generated by clawbang shim";
+
+/// `clawbang shim`: a wrapper that runs a script through clawbang from anywhere.
+#[derive(clap::Args)]
+pub(crate) struct ShimOptions {
+    /// The script the shim runs: a file, a directory, a URL, or a git reference
+    script: String,
+
+    /// Where to write the shim, e.g. ~/.local/bin/mytool
+    #[clap(long, parse(from_os_str))]
+    dest: PathBuf,
+
+    /// Replace whatever is at --dest, even if clawbang didn't write it
+    #[clap(long)]
+    force: bool,
+
+    /// The cache directory and settings the shim runs with
+    #[clap(flatten)]
+    cache: crate::CacheOptions,
+
+    /// Options for clawbang to run the script with, after a `--` (e.g. -- --timeout 5m)
+    #[clap(last = true, parse(from_os_str))]
+    options: Vec<OsString>,
+}
+
+/// Write a small wrapper script that runs clawbang on the script with this cache directory,
+/// settings, and options. Unlike a copy of the built binary, it picks up edits to the
+/// script, rebuilding on the next run.
+pub(crate) fn run(opts: &ShimOptions) -> Result<()> {
+    let clawbang = std::env::current_exe()?;
+    // a path has to keep working from wherever the shim is run; URLs and git refs already do
+    let script = match Path::new(&opts.script).canonicalize() {
+        Ok(path) => path.into_os_string(),
+        Err(_) => OsString::from(&opts.script),
+    };
+
+    let mut command = vec![clawbang.into_os_string()];
+    command.extend(opts.cache.absolute().args());
+    command.extend(opts.options.iter().cloned());
+    command.push(OsString::from("--"));
+    command.push(script);
+
+    let dest = if cfg!(windows) && opts.dest.extension().is_none() {
+        opts.dest.with_extension("cmd")
+    } else {
+        opts.dest.clone()
+    };
+    if let Ok(existing) = std::fs::read_to_string(&dest) {
+        if !opts.force && !existing.contains(MARKER) {
+            return Err(eyre::eyre!("{} already exists and isn't a clawbang shim; pass --force to replace it", dest.display()))
+        }
+    } else if dest.exists() && !opts.force {
+        return Err(eyre::eyre!("{} already exists and isn't a clawbang shim; pass --force to replace it", dest.display()))
+    }
+
+    if let Some(parent) = dest.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&dest, render(&command)?)
+        .map_err(|err| eyre::eyre!("Could not write {}: {}", dest.display(), err))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&dest, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    eprintln!("wrote {}, which runs {}", dest.display(), opts.script);
+    Ok(())
+}
+
+#[cfg(not(windows))]
+fn render(command: &[OsString]) -> Result<String> {
+    let words: Vec<String> = command.iter().map(|word| {
+        // single quotes keep everything literal, apart from single quotes themselves
+        format!("'{}'", word.to_string_lossy().replace('\'', r"'\''"))
+    }).collect();
+    Ok(format!("#!/bin/sh\n# {}; edit the script, not this file\nexec {} \"$@\"\n", MARKER, words.join(" ")))
+}
+
+#[cfg(windows)]
+fn render(command: &[OsString]) -> Result<String> {
+    let mut words = Vec::new();
+    for word in command {
+        let word = word.to_string_lossy();
+        if word.contains(['"', '%']) {
+            return Err(eyre::eyre!("Can't write a shim for {:?}: batch files can't pass along '\"' or '%'", word))
+        }
+        words.push(format!("\"{}\"", word));
+    }
+    Ok(format!("@echo off\r\nrem {}; edit the script, not this file\r\n{} %*\r\n", MARKER, words.join(" ")))
+}
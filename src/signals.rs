@@ -0,0 +1,65 @@
+use std::sync::atomic::{ AtomicBool, AtomicI32, Ordering };
+
+// The pid signals are relayed to; zero while there's no child to relay to.
+static CHILD: AtomicI32 = AtomicI32::new(0);
+
+// Set once clawbang itself has been asked to stop, however the signal got here.
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+const FORWARDED: [libc::c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+/// While clawbang waits on a script it has spawned, pass SIGINT, SIGTERM, and SIGHUP on
+/// to it instead of dying and leaving it orphaned. The script decides what the signal
+/// means; clawbang exits once the script does.
+///
+/// Signals the terminal raises (Ctrl-C, a hangup) already reach the whole foreground
+/// process group, script included, so only ones sent by another process are relayed.
+pub(crate) fn forward_to(pid: u32) {
+    CHILD.store(pid as i32, Ordering::SeqCst);
+
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = forward as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        for signal in FORWARDED {
+            libc::sigaction(signal, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+extern "C" fn forward(signal: libc::c_int, info: *mut libc::siginfo_t, _context: *mut libc::c_void) {
+    STOPPING.store(true, Ordering::SeqCst);
+    let pid = CHILD.load(Ordering::SeqCst);
+    // SI_USER and friends are zero or negative; the kernel's own codes are positive
+    let from_process = unsafe { info.as_ref() }.map(|info| info.si_code <= 0).unwrap_or(true);
+    if pid > 0 && from_process {
+        unsafe { libc::kill(pid, signal) };
+    }
+}
+
+/// Whether a signal has asked clawbang to stop since it started relaying them.
+pub(crate) fn stop_requested() -> bool {
+    STOPPING.load(Ordering::SeqCst)
+}
+
+/// Ask the script to stop; it gets the chance to clean up that SIGKILL wouldn't give it.
+pub(crate) fn terminate(pid: u32) {
+    unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
+}
+
+/// Die the way the script did. A shell can only tell "killed by a signal" from a plain
+/// exit code if we're killed by it too; if the signal doesn't take us down, fall back to
+/// the conventional `128 + signal` status.
+pub(crate) fn exit_with(signal: u8) -> ! {
+    let signal = signal as libc::c_int;
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        let mut set: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, signal);
+        libc::sigprocmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut());
+        libc::raise(signal);
+    }
+    std::process::exit(128 + signal)
+}
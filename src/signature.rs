@@ -0,0 +1,108 @@
+use crate::errors;
+use eyre::Result;
+use serde::Deserialize;
+use std::io::Write;
+use std::path::{ Path, PathBuf };
+use std::process::{ Command, Stdio };
+
+// What `ssh-keygen -Y sign` signs for unless told otherwise.
+const SSH_NAMESPACE: &str = "file";
+
+/// The settings file's `[signatures]`: whether scripts have to be signed, and by whom.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Trusted {
+    #[serde(default)]
+    pub(crate) require: bool,
+    // minisign public keys, as the second line of a minisign.pub has them
+    #[serde(default)]
+    minisign_keys: Vec<String>,
+    // an allowed_signers file, in the format `ssh-keygen -Y verify` reads
+    #[serde(default)]
+    ssh_allowed_signers: Option<PathBuf>,
+}
+
+/// The detached signature beside a script, at its path with `.sig` added, if there is one.
+pub(crate) fn beside(script: &Path) -> Result<Option<Vec<u8>>> {
+    let mut path = script.as_os_str().to_os_string();
+    path.push(".sig");
+    match std::fs::read(&path) {
+        Ok(signature) => Ok(Some(signature)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(errors::SIGNATURE.error(format!("Could not read {}: {}", Path::new(&path).display(), err))),
+    }
+}
+
+/// Refuse `contents` unless `signature` is a minisign or SSH signature of it by one of the
+/// trusted keys.
+pub(crate) fn verify(script_name: &str, contents: &[u8], signature: Option<&[u8]>, trusted: &Trusted) -> Result<()> {
+    let signature = signature.ok_or_else(|| errors::SIGNATURE.error(format!(
+        "{} has no signature beside it ({}.sig), and signatures are required", script_name, script_name
+    )))?;
+    let signature = std::str::from_utf8(signature)
+        .map_err(|_| errors::SIGNATURE.error(format!("{}.sig isn't a minisign or SSH signature", script_name)))?;
+    if trusted.minisign_keys.is_empty() && trusted.ssh_allowed_signers.is_none() {
+        return Err(errors::SIGNATURE.error(format!(
+            "Signatures are required, but the settings file's [signatures] trusts no keys to check {} with", script_name
+        )))
+    }
+
+    let verified = if signature.trim_start().starts_with("-----BEGIN SSH SIGNATURE-----") {
+        let signers = trusted.ssh_allowed_signers.as_deref().ok_or_else(|| errors::SIGNATURE.error(format!(
+            "{} has an SSH signature, but the settings file's [signatures] has no ssh-allowed-signers", script_name
+        )))?;
+        verify_ssh(contents, signature, &expand_home(signers))?
+    } else {
+        let decoded = minisign_verify::Signature::decode(signature)
+            .map_err(|err| errors::SIGNATURE.error(format!("{}.sig isn't a minisign or SSH signature: {}", script_name, err)))?;
+        trusted.minisign_keys.iter().any(|key| {
+            minisign_verify::PublicKey::from_base64(key.trim())
+                .is_ok_and(|key| key.verify(contents, &decoded, false).is_ok())
+        })
+    };
+    match verified {
+        true => Ok(()),
+        false => Err(errors::SIGNATURE.error(format!("{}'s signature doesn't match it, or isn't by a trusted key", script_name))),
+    }
+}
+
+// ssh-keygen checks SSH signatures: first for who made it, by the allowed signers, and then
+// that they made it over these contents.
+fn verify_ssh(contents: &[u8], signature: &str, signers: &Path) -> Result<bool> {
+    let mut file = tempfile::NamedTempFile::new()?;
+    file.write_all(signature.as_bytes())?;
+    let principal = match ssh_keygen("find-principals", file.path(), signers, None, None)? {
+        Some(found) => found.lines().next().unwrap_or_default().to_string(),
+        None => return Ok(false),
+    };
+    Ok(ssh_keygen("verify", file.path(), signers, Some(&principal), Some(contents))?.is_some())
+}
+
+// `ssh-keygen -Y <operation>` on a signature; its stdout, if it succeeded.
+fn ssh_keygen(operation: &str, signature: &Path, signers: &Path, principal: Option<&str>, stdin: Option<&[u8]>) -> Result<Option<String>> {
+    let mut command = Command::new("ssh-keygen");
+    command.args(["-Y", operation, "-s"]).arg(signature).arg("-f").arg(signers);
+    if let Some(principal) = principal {
+        command.args(["-I", principal, "-n", SSH_NAMESPACE]);
+    }
+    let mut child = command
+        .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| errors::SIGNATURE.error(format!("Could not run ssh-keygen to check an SSH signature: {}", err)))?;
+    if let Some(stdin) = stdin {
+        // ssh-keygen that stops reading early is judged by its exit status
+        let _ = child.stdin.take().expect("stdin is piped").write_all(stdin);
+    }
+    let output = child.wait_with_output()?;
+    Ok(output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned()))
+}
+
+// `~/.ssh/allowed_signers`, as settings files tend to spell it.
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), home::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
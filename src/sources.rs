@@ -0,0 +1,194 @@
+use crate::errors;
+use crate::permissions::Grant;
+use eyre::Result;
+use serde::Deserialize;
+
+// Tables in a Cargo.toml that name dependencies, at the top level and under each [target].
+const DEPENDENCY_TABLES: &[&str] = &["dependencies", "build-dependencies", "dev-dependencies"];
+
+/// The settings file's `[dependency-sources]`: where scripts' dependencies may come from.
+/// Anything left unset is allowed.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Policy {
+    // registries by the names cargo knows them by; crates.io is "crates-io"
+    #[serde(default)]
+    registries: Option<Vec<String>>,
+    // false to refuse git dependencies, or the URL prefixes they have to start with
+    #[serde(default)]
+    git: Option<Grant>,
+    #[serde(default)]
+    path: Option<bool>,
+}
+
+impl Policy {
+    /// Refuse a Cargo.toml with a dependency (or a patch, or a replacement) from a source the
+    /// policy doesn't allow. Only the script's own are checked: a dependency's dependencies
+    /// are up to the registry or repository it comes from.
+    pub(crate) fn check(&self, script_name: &str, cargo_toml: &str) -> Result<()> {
+        if self.registries.is_none() && self.git.is_none() && self.path.is_none() {
+            return Ok(())
+        }
+        let manifest: toml::Value = toml::from_str(cargo_toml)?;
+        let mut refused = Vec::new();
+        let mut check_table = |table: Option<&toml::Value>, registry: Option<&str>| {
+            for (name, dependency) in table.and_then(toml::Value::as_table).into_iter().flatten() {
+                if let Some(problem) = self.refuse(dependency, registry) {
+                    refused.push(format!("  {}: {}", name, problem));
+                }
+            }
+        };
+
+        let targets = manifest.get("target").and_then(toml::Value::as_table).into_iter().flat_map(|targets| targets.values());
+        for tables in std::iter::once(&manifest).chain(targets) {
+            for table in DEPENDENCY_TABLES {
+                check_table(tables.get(table), None);
+            }
+        }
+        // [patch.crates-io] and the like stand in for dependencies from that source
+        for (source, patches) in manifest.get("patch").and_then(toml::Value::as_table).into_iter().flatten() {
+            check_table(Some(patches), (!source.contains("://")).then_some(source.as_str()));
+        }
+        check_table(manifest.get("replace"), None);
+
+        if refused.is_empty() {
+            return Ok(())
+        }
+        Err(errors::SOURCE.error(format!(
+            "{} has dependencies from sources the settings file's [dependency-sources] doesn't allow:\n{}",
+            script_name, refused.join("\n")
+        )))
+    }
+
+    // What's wrong with where a dependency comes from, if anything. `registry` is the one
+    // it'd come from without saying, for a patch of a registry's crates. One that names both
+    // a git repository and a path is refused if either is.
+    fn refuse(&self, dependency: &toml::Value, registry: Option<&str>) -> Option<String> {
+        let table = match dependency {
+            toml::Value::Table(table) => table,
+            // "1.0", or `name = "1.0"` under [replace], from the registry
+            _ => return self.refuse_registry(registry.unwrap_or("crates-io")),
+        };
+        let text = |key| table.get(key).and_then(toml::Value::as_str);
+        let git = text("git").and_then(|url| match &self.git {
+            None | Some(Grant::All(true)) => None,
+            Some(Grant::All(false)) => Some(format!("from git ({}), and git dependencies aren't allowed", url)),
+            Some(Grant::Only(prefixes)) if prefixes.iter().any(|prefix| url.starts_with(prefix.as_str())) => None,
+            Some(Grant::Only(_)) => Some(format!("from git ({}), which isn't under any of the allowed URLs", url)),
+        });
+        let path = text("path").and_then(|path| match self.path {
+            Some(false) => Some(format!("from a path ({}), and path dependencies aren't allowed", path)),
+            _ => None,
+        });
+        if text("git").is_some() || text("path").is_some() {
+            return git.or(path)
+        }
+        if let Some(index) = text("registry-index") {
+            return self.refuse_registry(index)
+        }
+        self.refuse_registry(text("registry").or(registry).unwrap_or("crates-io"))
+    }
+
+    fn refuse_registry(&self, registry: &str) -> Option<String> {
+        match &self.registries {
+            Some(allowed) if !allowed.iter().any(|name| name == registry) => Some(format!("from the registry {}, which isn't allowed", registry)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(settings: &str) -> Policy {
+        toml::from_str(settings).unwrap()
+    }
+
+    // Whether each dependency, in a [dependencies] table of its own, gets past the policy.
+    fn allowed(settings: &str, dependencies: &[&str]) -> Vec<bool> {
+        let policy = policy(settings);
+        dependencies.iter().map(|dependency| {
+            policy.check("s.rs", &format!("[dependencies]\ndep = {}\n", dependency)).is_ok()
+        }).collect()
+    }
+
+    #[test]
+    fn allows_everything_unless_told_otherwise() {
+        assert_eq!(allowed("", &[
+            r#""1.0""#,
+            r#"{ version = "1.0", registry = "internal" }"#,
+            r#"{ git = "https://example.com/dep" }"#,
+            r#"{ path = "../dep" }"#,
+        ]), [true, true, true, true]);
+    }
+
+    #[test]
+    fn checks_registries() {
+        let settings = r#"registries = ["crates-io", "internal"]"#;
+        assert_eq!(allowed(settings, &[
+            r#""1.0""#,
+            r#"{ version = "1.0" }"#,
+            r#"{ version = "1.0", registry = "internal" }"#,
+            r#"{ version = "1.0", registry = "elsewhere" }"#,
+            r#"{ version = "1.0", registry-index = "https://example.com/index" }"#,
+            // a registry list says nothing of git or paths
+            r#"{ git = "https://example.com/dep" }"#,
+            r#"{ path = "../dep" }"#,
+        ]), [true, true, true, false, false, true, true]);
+        assert_eq!(allowed("registries = []", &[r#""1.0""#]), [false]);
+    }
+
+    #[test]
+    fn checks_git() {
+        let dependencies = [
+            r#"{ git = "https://github.com/me/dep" }"#,
+            r#"{ git = "https://github.com/someone/dep", branch = "main" }"#,
+            r#""1.0""#,
+        ];
+        assert_eq!(allowed("git = false", &dependencies), [false, false, true]);
+        assert_eq!(allowed("git = true", &dependencies), [true, true, true]);
+        assert_eq!(allowed(r#"git = ["https://github.com/me/"]"#, &dependencies), [true, false, true]);
+    }
+
+    #[test]
+    fn checks_paths() {
+        let dependencies = [r#"{ path = "../dep" }"#, r#"{ path = "../dep", version = "1.0" }"#, r#""1.0""#];
+        assert_eq!(allowed("path = false", &dependencies), [false, false, true]);
+        assert_eq!(allowed("path = true", &dependencies), [true, true, true]);
+    }
+
+    #[test]
+    fn refuses_what_any_rule_refuses() {
+        // naming both a repository and a path, it's refused if either is
+        let both = r#"{ git = "https://example.com/dep", path = "../dep" }"#;
+        assert_eq!(allowed("git = false\npath = true", &[both]), [false]);
+        assert_eq!(allowed("git = true\npath = false", &[both]), [false]);
+        assert_eq!(allowed("git = true\npath = true", &[both]), [true]);
+
+        // allowed where it's a dependency doesn't let it through as a patch or under a target
+        let policy = policy("git = false");
+        let cargo_toml = r#"
+            [dependencies]
+            dep = "1.0"
+
+            [target.'cfg(unix)'.dependencies]
+            unix-dep = { git = "https://example.com/unix-dep" }
+
+            [patch.crates-io]
+            dep = { git = "https://example.com/dep" }
+        "#;
+        let err = policy.check("s.rs", cargo_toml).unwrap_err().to_string();
+        assert!(err.contains("  unix-dep: from git (https://example.com/unix-dep), and git dependencies aren't allowed"), "{}", err);
+        assert!(err.contains("  dep: from git (https://example.com/dep), and git dependencies aren't allowed"), "{}", err);
+    }
+
+    #[test]
+    fn checks_patches_against_the_registry_they_patch() {
+        let policy = policy(r#"registries = ["crates-io"]"#);
+        assert!(policy.check("s.rs", "[patch.crates-io]\ndep = \"1.0\"\n").is_ok());
+        assert!(policy.check("s.rs", "[patch.internal]\ndep = \"1.0\"\n").is_err());
+        // a patch naming a URL is for a git source, not a registry
+        assert!(policy.check("s.rs", "[patch.'https://example.com/dep']\ndep = { path = \"../dep\" }\n").is_ok());
+    }
+}
@@ -0,0 +1,96 @@
+use eyre::Result;
+use serde::{ Deserialize, Serialize };
+use std::path::{ Path, PathBuf };
+
+/// Where builds are kept, by cache key: each build's exit code and compiler output, and for
+/// a good build, its binary. [`CacacheStore`], the cache directory itself, is the default;
+/// others (a remote store, a read-only one, a test double) go to
+/// [`ScriptRunner::cache_store`](crate::ScriptRunner::cache_store).
+///
+/// Whatever the store, clawbang keeps its own state in the cache directory: the binaries it
+/// has unpacked from the store, its build locks, and its indexes of sources and lockfiles.
+pub trait CacheStore: Send + Sync {
+    /// The build recorded under `key`, if there is one.
+    fn metadata(&self, key: &str) -> Result<Option<CachedBuild>>;
+
+    /// The compiler output recorded with a build, as cargo printed it.
+    fn read_output(&self, build: &CachedBuild) -> Result<Vec<u8>>;
+
+    /// Copy the binary of the good build under `key` to the file `to`.
+    fn read_artifact(&self, key: &str, to: &Path) -> Result<()>;
+
+    /// Record a build under `key`, replacing any already there: cargo's exit code and
+    /// output, and for a good build, the binary at `binary`.
+    fn write_artifact(&self, key: &str, exit_code: i32, output: &[u8], binary: Option<&Path>) -> Result<()>;
+}
+
+/// A build recorded in a [`CacheStore`].
+#[derive(Clone, Debug)]
+pub struct CachedBuild {
+    /// cargo's exit status: 0 for a good build.
+    pub exit_code: i32,
+    /// What the store calls the build's compiler output, for [`CacheStore::read_output`].
+    pub output_id: String,
+    /// When the build was recorded, in milliseconds since the Unix epoch.
+    pub time: u128,
+}
+
+/// The cache directory as a [`CacheStore`], by way of cacache.
+pub struct CacacheStore {
+    dir: PathBuf,
+}
+
+// What's kept as a cacache entry's metadata.
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    output_id: String, // content ref of the output
+    exit_code: i32,
+}
+
+impl CacacheStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl CacheStore for CacacheStore {
+    fn metadata(&self, key: &str) -> Result<Option<CachedBuild>> {
+        let metadata = match cacache::metadata_sync(&self.dir, key).map_err(crate::cache_error)? {
+            Some(metadata) => metadata,
+            None => return Ok(None),
+        };
+        let entry: CacheEntry = serde_json::from_value(metadata.metadata).map_err(crate::cache_error)?;
+        Ok(Some(CachedBuild { exit_code: entry.exit_code, output_id: entry.output_id, time: metadata.time }))
+    }
+
+    fn read_output(&self, build: &CachedBuild) -> Result<Vec<u8>> {
+        let integrity = build.output_id.parse().map_err(crate::cache_error)?;
+        cacache::read_hash_sync(&self.dir, &integrity).map_err(crate::cache_error)
+    }
+
+    fn read_artifact(&self, key: &str, to: &Path) -> Result<()> {
+        cacache::copy_sync(&self.dir, key, to).map_err(crate::cache_error)?;
+        Ok(())
+    }
+
+    fn write_artifact(&self, key: &str, exit_code: i32, output: &[u8], binary: Option<&Path>) -> Result<()> {
+        let output_id = cacache::write_hash_sync(&self.dir, output)?.to_string();
+        // failed builds are cached too, with an empty artifact, so the failure replays quickly
+        let binary = binary.map(|binary| std::fs::OpenOptions::new().read(true).open(binary)).transpose()?;
+        // knowing the size up front lets cacache map its copy into memory rather than write it
+        let size = match &binary {
+            Some(file) => file.metadata()?.len() as usize,
+            None => 0,
+        };
+        let mut writer = cacache::WriteOpts::new()
+            .algorithm(cacache::Algorithm::Sha256)
+            .size(size)
+            .metadata(serde_json::to_value(CacheEntry { output_id, exit_code })?)
+            .open_sync(&self.dir, key)?;
+        if let Some(mut binary) = binary {
+            std::io::copy(&mut binary, &mut writer)?;
+        }
+        writer.commit()?;
+        Ok(())
+    }
+}
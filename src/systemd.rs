@@ -0,0 +1,209 @@
+use crate::manifest::WorkingDir;
+use crate::permissions::{ Grant, Permissions };
+use crate::priority::Priority;
+use eyre::Result;
+use std::ffi::OsString;
+use std::path::{ Path, PathBuf };
+
+/// `clawbang systemd`: a service unit that runs a script, written to stdout.
+#[derive(clap::Args)]
+pub(crate) struct SystemdOptions {
+    /// The script the service runs: a file, a directory, a URL, or a git reference
+    script: String,
+
+    /// The service's name, as in <name>.service [default: the script's]
+    #[clap(long)]
+    name: Option<String>,
+
+    /// Run the script's binary as it's built now, rather than the script through clawbang
+    #[clap(long)]
+    frozen: bool,
+
+    /// Write a unit for the user's own service manager (systemctl --user)
+    #[clap(long)]
+    user: bool,
+
+    /// The cache directory and settings the service runs with
+    #[clap(flatten)]
+    cache: crate::CacheOptions,
+
+    /// Options for clawbang to build and run the script with, after a `--` (e.g. -- --timeout 5m)
+    #[clap(last = true, parse(from_os_str))]
+    options: Vec<OsString>,
+}
+
+/// Build the script, so the service starts from a warm cache (and a script that doesn't
+/// build never becomes one), then print a unit for it. By default the unit runs clawbang on
+/// the script, as a shim does, so edits take effect when the service restarts. With
+/// `--frozen` it runs the binary just built, and spells out in the unit what clawbang would
+/// have set up for it: its environment, working directory, limits, and priority.
+pub(crate) fn run(systemd: &SystemdOptions) -> Result<()> {
+    let cache = systemd.cache.absolute();
+    let config = crate::config::load(cache.config.as_deref())?;
+    let cache_dir = cache.cache_dir.clone();
+    // a path has to keep working from wherever systemd starts it; URLs and git refs already do
+    let script = match Path::new(&systemd.script).canonicalize() {
+        Ok(path) => path.into_os_string(),
+        Err(_) => OsString::from(&systemd.script),
+    };
+
+    let mut clawbang = vec![std::env::current_exe()?.into_os_string()];
+    clawbang.extend(cache.args());
+    clawbang.extend(systemd.options.iter().cloned());
+    clawbang.push(OsString::from("--"));
+    clawbang.push(script);
+    let mut args = clawbang.clone();
+    args.insert(1, OsString::from("--build-only"));
+    let opts = <crate::Cli as clap::Parser>::parse_from(args).run;
+
+    let unit = crate::plan(&opts, &config, |planned| {
+        let manifest = &planned.project.manifest;
+        let service = manifest.service.clone().unwrap_or_default();
+        let name = systemd.name.clone().unwrap_or_else(|| planned.project.name.clone());
+        let mut settings: Vec<(&str, String)> = Vec::new();
+
+        let exec_start: Vec<String> = if systemd.frozen {
+            // what clawbang sets up just before the script starts, as build does
+            let cwd = match &opts.chdir {
+                Some(dir) => Some(crate::absolute(dir)),
+                None if manifest.cwd == WorkingDir::ScriptDir && !planned.script_dir.as_os_str().is_empty() => Some(crate::absolute(&planned.script_dir)),
+                None => None,
+            };
+            if let Some(cwd) = cwd {
+                settings.push(("WorkingDirectory", escape(&cwd.to_string_lossy())));
+            }
+            let mut env = Vec::new();
+            if planned.local {
+                env.push(("CLAWBANG_SCRIPT_PATH".to_string(), crate::absolute(&planned.file).to_string_lossy().into_owned()));
+            }
+            env.push(("CLAWBANG_SCRIPT_DIR".to_string(), crate::absolute(&planned.script_dir).to_string_lossy().into_owned()));
+            env.push(("CLAWBANG_CACHE_DIR".to_string(), cache_dir.to_string_lossy().into_owned()));
+            env.push(("CLAWBANG_CACHE_HIT".to_string(), "1".to_string()));
+            env.extend(manifest.env.iter().map(|(name, value)| (name.clone(), crate::env::expand(value))));
+            for (name, value) in env {
+                settings.push(("Environment", quote(&format!("{}={}", name, value), false)));
+            }
+            // systemd reads these as it starts the service, so their secrets stay in them
+            let env_files = manifest.env_files.iter().map(|path| planned.script_dir.join(path)).chain(opts.env_file.iter().cloned());
+            for path in env_files {
+                settings.push(("EnvironmentFile", escape(&crate::absolute(&path).to_string_lossy())));
+            }
+            for (directive, limit) in manifest.limits.iter().flat_map(|limits| limits.directives()) {
+                settings.push((directive, limit.to_string()));
+            }
+            settings.extend(Priority::new(opts.nice, opts.ionice).or(manifest.priority.as_ref()).directives());
+            let binary = crate::unpacked_binary(&cache_dir, &planned.cache_key, &planned.project.name);
+            vec![quote(&binary.to_string_lossy(), true)]
+        } else {
+            clawbang.iter().map(|word| quote(&word.to_string_lossy(), true)).collect()
+        };
+        settings.insert(0, ("ExecStart", exec_start.join(" ")));
+        settings.push(("Restart", service.restart.as_str().to_string()));
+        if let Some(delay) = service.restart_sec {
+            let millis = delay.0.as_millis();
+            settings.push(("RestartSec", if millis % 1000 == 0 { format!("{}s", millis / 1000) } else { format!("{}ms", millis) }));
+        }
+        settings.push(("SyslogIdentifier", escape(&name)));
+        if let Some(permissions) = &manifest.permissions {
+            // clawbang itself writes to its cache, and cargo to its home, as the script rebuilds
+            let mut writable = Vec::new();
+            if !systemd.frozen {
+                writable.push(cache_dir.clone());
+                writable.extend(home::cargo_home().ok());
+                writable.extend(home::rustup_home().ok());
+            }
+            settings.extend(hardening(permissions, writable, &planned.script_name));
+        }
+
+        let description = service.description.clone().unwrap_or_else(|| format!("{}, run by clawbang", planned.script_name));
+        let online = manifest.permissions.as_ref().is_none_or(|permissions| permissions.net.granted());
+        let script_name = planned.script_name.clone();
+        let (cache_key, binary_name) = (planned.cache_key.clone(), planned.project.name.clone());
+        match crate::build(&opts, &config, planned)? {
+            crate::Build::Ready(_) => {},
+            crate::Build::Failed { .. } => return Err(eyre::eyre!("{} doesn't build, so there's no service to run", script_name)),
+        }
+        if systemd.frozen {
+            // a fresh build is linked into the cache as it's stored, unless it couldn't be
+            crate::materialize(&cache_dir, crate::cache_store(&opts).as_ref(), &cache_key, &binary_name)?;
+        }
+
+        let mut unit = format!(
+            "# {}.service, written by `clawbang systemd` for {}\n[Unit]\nDescription={}\n",
+            name, script_name, escape(&description)
+        );
+        if online && !systemd.user {
+            unit.push_str("Wants=network-online.target\nAfter=network-online.target\n");
+        }
+        unit.push_str("\n[Service]\n");
+        for (directive, value) in settings {
+            unit.push_str(&format!("{}={}\n", directive, value));
+        }
+        unit.push_str(&format!("\n[Install]\nWantedBy={}\n", if systemd.user { "default.target" } else { "multi-user.target" }));
+        Ok((name, unit))
+    })?;
+
+    let (name, unit) = unit;
+    print!("{}", unit);
+    let dir = match systemd.user {
+        true => "~/.config/systemd/user",
+        false => "/etc/systemd/system",
+    };
+    eprintln!("Save it as {}/{}.service, then `systemctl{} enable --now {}`", dir, name, if systemd.user { " --user" } else { "" }, name);
+    Ok(())
+}
+
+// What a `[permissions]` table keeps the script from touching, as systemd can hold it there.
+// Reads, programs, and variables can't be scoped this way; --sandbox does that in the shim.
+fn hardening(permissions: &Permissions, mut writable: Vec<PathBuf>, script_name: &str) -> Vec<(&'static str, String)> {
+    let mut settings = vec![("NoNewPrivileges", "yes".to_string())];
+    let write_all = matches!(permissions.fs_write, Grant::All(true));
+    if let Grant::Only(paths) = &permissions.fs_write {
+        for path in paths.iter().map(PathBuf::from) {
+            match path.is_absolute() {
+                true => writable.push(path),
+                false => eprintln!(
+                    "{} the unit can't grant {} the relative path {} it may write to; give it as an absolute one",
+                    crate::color::label("warning"), script_name, path.display()
+                ),
+            }
+        }
+    }
+    if !write_all {
+        if !writable.iter().any(|path| path.starts_with("/tmp")) {
+            settings.push(("PrivateTmp", "yes".to_string()));
+        }
+        settings.push(("ProtectSystem", "strict".to_string()));
+        settings.push(("ProtectHome", "read-only".to_string()));
+        if !writable.is_empty() {
+            // a path that isn't there yet doesn't keep the service from starting
+            let paths: Vec<_> = writable.iter().map(|path| format!("-{}", quote(&path.to_string_lossy(), false))).collect();
+            settings.push(("ReadWritePaths", paths.join(" ")));
+        }
+    }
+    if !permissions.net.granted() {
+        settings.push(("PrivateNetwork", "yes".to_string()));
+    }
+    for directive in ["ProtectKernelTunables", "ProtectKernelModules", "ProtectControlGroups", "RestrictSUIDSGID", "LockPersonality"] {
+        settings.push((directive, "yes".to_string()));
+    }
+    settings
+}
+
+// A word of a command line or a list, as systemd splits them: in double quotes if it has to
+// be, and with `$` doubled where variables would otherwise expand.
+fn quote(word: &str, expands: bool) -> String {
+    let mut escaped = escape(word);
+    if expands {
+        escaped = escaped.replace('$', "$$");
+    }
+    if !escaped.is_empty() && !escaped.contains(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | ';')) {
+        return escaped
+    }
+    format!("\"{}\"", escaped.replace('\\', r"\\").replace('"', "\\\"").replace('\n', r"\n"))
+}
+
+// `%` starts a specifier anywhere in a unit file.
+fn escape(value: &str) -> String {
+    value.replace('%', "%%")
+}
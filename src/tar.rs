@@ -0,0 +1,127 @@
+use eyre::Result;
+
+/// Just enough of a ustar writer for an image or a cache archive: directories and files,
+/// owned by root.
+pub(crate) struct Tar {
+    bytes: Vec<u8>,
+    mtime: u64,
+}
+
+impl Tar {
+    pub(crate) fn new(mtime: u64) -> Self {
+        Tar { bytes: Vec::new(), mtime }
+    }
+
+    pub(crate) fn dir(&mut self, path: &str, mode: u32) {
+        self.header(path, mode, 0, b'5');
+    }
+
+    pub(crate) fn file(&mut self, path: &str, mode: u32, contents: &[u8]) {
+        self.header(path, mode, contents.len() as u64, b'0');
+        self.bytes.extend_from_slice(contents);
+        self.bytes.resize(self.bytes.len().next_multiple_of(512), 0);
+    }
+
+    fn header(&mut self, path: &str, mode: u32, size: u64, kind: u8) {
+        let mut header = [0u8; 512];
+        let mut field = |offset: usize, value: &[u8]| header[offset..offset + value.len()].copy_from_slice(value);
+        // a path too long for the name field goes in two, split at a `/` into the prefix field
+        let split = path.match_indices('/').map(|(at, _)| at).find(|&at| at <= 155 && path.len() - at - 1 <= 100);
+        let (prefix, name) = match split {
+            Some(at) if path.len() > 100 => (&path[..at], &path[at + 1..]),
+            _ => ("", path),
+        };
+        field(0, &name.as_bytes()[..name.len().min(100)]);
+        field(345, prefix.as_bytes());
+        field(100, format!("{:07o}\0", mode).as_bytes());
+        field(108, b"0000000\0");
+        field(116, b"0000000\0");
+        field(124, format!("{:011o}\0", size).as_bytes());
+        field(136, format!("{:011o}\0", self.mtime).as_bytes());
+        field(148, b"        ");
+        field(156, &[kind]);
+        field(257, b"ustar\0");
+        field(263, b"00");
+        field(265, b"root");
+        field(297, b"root");
+        let checksum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+        self.bytes.extend_from_slice(&header);
+    }
+
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        self.bytes.extend_from_slice(&[0; 1024]);
+        self.bytes
+    }
+}
+
+/// The files in a ustar archive [`Tar`] wrote, by path; anything else in it is skipped.
+pub(crate) fn files(bytes: &[u8]) -> Result<Vec<(String, &[u8])>> {
+    let mut files = Vec::new();
+    let mut at = 0;
+    while let Some(header) = bytes.get(at..at + 512) {
+        if header.iter().all(|&byte| byte == 0) {
+            break
+        }
+        let text = |range: std::ops::Range<usize>| String::from_utf8_lossy(&header[range]).trim_end_matches(['\0', ' ']).to_string();
+        let size = u64::from_str_radix(text(124..136).trim_start(), 8)
+            .map_err(|_| eyre::eyre!("Not a tar archive, or a damaged one"))? as usize;
+        let contents = bytes.get(at + 512..at + 512 + size).ok_or_else(|| eyre::eyre!("The tar archive is cut short"))?;
+        if header[156] == b'0' || header[156] == 0 {
+            let path = match text(345..500) {
+                prefix if prefix.is_empty() => text(0..100),
+                prefix => format!("{}/{}", prefix, text(0..100)),
+            };
+            files.push((path, contents));
+        }
+        at += 512 + size.next_multiple_of(512);
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn octal(field: &[u8]) -> u64 {
+        u64::from_str_radix(std::str::from_utf8(field).unwrap().trim_end_matches(['\0', ' ']), 8).unwrap()
+    }
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let long = format!("builds/{}/binary", "7f".repeat(60));
+        assert!(long.len() > 100);
+        let mut tar = Tar::new(1_700_000_000);
+        tar.dir("builds/", 0o755);
+        tar.file("output", 0o644, b"compiled");
+        tar.file(&long, 0o755, &[1; 700]);
+        let bytes = tar.finish();
+        assert_eq!(bytes.len() % 512, 0);
+
+        let files = files(&bytes).unwrap();
+        assert_eq!(files, [("output".to_string(), &b"compiled"[..]), (long.clone(), &[1; 700][..])]);
+
+        // each header: its mode, owner, and mtime, and a checksum that sums it
+        for (at, mode, kind) in [(0, 0o755, b'5'), (512, 0o644, b'0'), (1024 + 512, 0o755, b'0')] {
+            let header = &bytes[at..at + 512];
+            assert_eq!(octal(&header[100..108]), mode);
+            assert_eq!((octal(&header[108..116]), octal(&header[116..124])), (0, 0));
+            assert_eq!(octal(&header[136..148]), 1_700_000_000);
+            assert_eq!(header[156], kind);
+            let summed: u64 = header.iter().enumerate().map(|(at, &byte)| if (148..156).contains(&at) { 32 } else { u64::from(byte) }).sum();
+            assert_eq!(octal(&header[148..156]), summed);
+        }
+        // the long path, split at a `/` between the prefix and name fields
+        let header = &bytes[1024 + 512..];
+        assert_eq!(header[0..100].split(|&b| b == 0).next().unwrap(), b"binary");
+        assert_eq!(header[345..500].split(|&b| b == 0).next().unwrap(), long.rsplit_once('/').unwrap().0.as_bytes());
+    }
+
+    #[test]
+    fn refuses_archives_cut_short() {
+        let mut tar = Tar::new(0);
+        tar.file("output", 0o644, &[0; 600]);
+        let bytes = tar.finish();
+        assert!(files(&bytes[..800]).is_err());
+    }
+}
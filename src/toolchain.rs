@@ -0,0 +1,79 @@
+use crate::errors;
+use eyre::Result;
+use std::path::{ Path, PathBuf };
+use std::process;
+
+/// The rustc cargo builds with: $RUSTC, the one beside cargo (where rustup puts it), or
+/// whichever is on PATH.
+pub(crate) fn rustc(cargo: &Path) -> PathBuf {
+    std::env::var_os("RUSTC").map(PathBuf::from).unwrap_or_else(|| {
+        let beside_cargo = cargo.with_file_name(format!("rustc{}", std::env::consts::EXE_SUFFIX));
+        if beside_cargo.is_file() { beside_cargo } else { PathBuf::from("rustc") }
+    })
+}
+
+/// The platform a toolchain builds for, from its `rustc -vV`.
+pub(crate) fn host(version: &str) -> &str {
+    version.lines().find_map(|line| line.strip_prefix("host: ")).unwrap_or("")
+}
+
+/// The toolchain scripts build with, as `rustc -vV` describes it, which is part of every
+/// script's cache key. Asking rustc takes longer than the rest of a cache hit put together,
+/// so its answer is kept in the cache's `toolchain` file, with a fingerprint of whatever
+/// decides which toolchain runs: the binaries, rustup's settings and update records, and
+/// $RUSTUP_TOOLCHAIN. rustc is only asked again once one of those changes.
+pub(crate) fn version(cache: &Path) -> Result<String> {
+    let cargo = crate::find_cargo()?;
+    let rustc = rustc(&cargo);
+    let fingerprint = fingerprint(&cargo, &rustc);
+    let recorded = cache.join("toolchain");
+    if let Ok(contents) = std::fs::read_to_string(&recorded) {
+        if let Some((recorded_fingerprint, version)) = contents.split_once("\n\n") {
+            if recorded_fingerprint == fingerprint {
+                return Ok(version.trim_end().to_string())
+            }
+        }
+    }
+
+    // builds run from a temporary directory, so rustup's directory overrides are resolved from one too
+    tracing::debug!(rustc = %rustc.display(), "asking rustc for its version");
+    let output = process::Command::new(&rustc)
+        .arg("-vV")
+        .current_dir(std::env::temp_dir())
+        .output()
+        .map_err(|err| errors::TOOLCHAIN.error(format!("Could not run {}: {}", rustc.display(), err)))?;
+    if !output.status.success() {
+        return Err(errors::TOOLCHAIN.error(format!(
+            "{} -vV failed: {}", rustc.display(), String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+    let version = String::from_utf8_lossy(&output.stdout).trim_end().to_string();
+    // only ever saves work, so failing to keep it doesn't matter
+    let _ = crate::index::record(&recorded, &format!("{}\n\n{}\n", fingerprint, version));
+    Ok(version)
+}
+
+fn fingerprint(cargo: &Path, rustc: &Path) -> String {
+    let stamp = |path: &Path| {
+        let stamp = std::fs::metadata(path).ok().and_then(|metadata| crate::index::stamp(&metadata));
+        format!("{} {}", path.display(), stamp.as_deref().unwrap_or("-"))
+    };
+    let mut lines = vec![
+        stamp(cargo),
+        stamp(rustc),
+        format!("RUSTUP_TOOLCHAIN={}", std::env::var("RUSTUP_TOOLCHAIN").unwrap_or_default()),
+    ];
+    if let Ok(rustup) = home::rustup_home() {
+        // the default toolchain and overrides
+        lines.push(stamp(&rustup.join("settings.toml")));
+        // rewritten for each toolchain that's installed or updated
+        let mut updates: Vec<String> = std::fs::read_dir(rustup.join("update-hashes")).into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| stamp(&entry.path()))
+            .collect();
+        updates.sort();
+        lines.extend(updates);
+    }
+    lines.join("\n")
+}
@@ -0,0 +1,166 @@
+use eyre::Result;
+use std::ffi::{ CStr, CString };
+use std::path::{ Path, PathBuf };
+
+/// Who the script runs as under `--user` and `--group`: clawbang builds as itself (root),
+/// then drops to this identity in the script's process just before it starts.
+pub(crate) struct Identity {
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+    groups: Vec<libc::gid_t>,
+    name: Option<String>,
+    home: Option<PathBuf>,
+}
+
+impl Identity {
+    /// Look up the user and group, given as names or numeric ids. Without `--group`, the
+    /// user's own primary group is used, along with the groups they're a member of.
+    pub(crate) fn resolve(user: Option<&str>, group: Option<&str>) -> Result<Option<Identity>> {
+        if user.is_none() && group.is_none() {
+            return Ok(None)
+        }
+        if unsafe { libc::geteuid() } != 0 {
+            return Err(eyre::eyre!("--user and --group need clawbang to run as root"))
+        }
+
+        let passwd = match user {
+            Some(user) => Some(lookup_user(user)?),
+            None => None,
+        };
+        let gid = match (group, passwd.as_ref().and_then(|passwd| passwd.gid)) {
+            (Some(group), _) => lookup_group(group)?,
+            (None, Some(gid)) => gid,
+            (None, None) => return Err(eyre::eyre!("User {} has no account to take a group from; pass --group too", user.unwrap_or_default())),
+        };
+        let groups = match (&passwd, group) {
+            (Some(Passwd { name: Some(name), .. }), None) => group_list(name, gid)?,
+            _ => vec![gid],
+        };
+
+        Ok(Some(match passwd {
+            Some(passwd) => Identity { uid: passwd.uid, gid, groups, name: passwd.name, home: passwd.home },
+            // just a group: stay root (or whoever), only in that group
+            None => Identity { uid: unsafe { libc::geteuid() }, gid, groups, name: None, home: None },
+        }))
+    }
+
+    /// The variables a login as this user would set, for the script's environment.
+    pub(crate) fn env(&self) -> Vec<(String, String)> {
+        let mut env = Vec::new();
+        if let Some(name) = &self.name {
+            env.push(("USER".to_string(), name.clone()));
+            env.push(("LOGNAME".to_string(), name.clone()));
+        }
+        if let Some(home) = &self.home {
+            env.push(("HOME".to_string(), home.to_string_lossy().into_owned()));
+        }
+        env
+    }
+
+    /// Copy the built binary somewhere the user can run it from: the cache and build
+    /// directories belong to root and needn't be readable by anyone else. The copy lives as
+    /// long as the returned directory.
+    pub(crate) fn stage(&self, binary: &Path) -> Result<(PathBuf, tempfile::TempDir)> {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::Builder::new().prefix("clawbang-").tempdir()?;
+        let staged = dir.path().join(binary.file_name().unwrap_or_default());
+        std::fs::copy(binary, &staged)?;
+        for (path, mode) in [(dir.path(), 0o700), (staged.as_path(), 0o500)] {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+            let path = CString::new(path.as_os_str().as_encoded_bytes())?;
+            if unsafe { libc::chown(path.as_ptr(), self.uid, self.gid) } != 0 {
+                return Err(eyre::eyre!("Could not hand the script's binary to its user: {}", std::io::Error::last_os_error()))
+            }
+        }
+        Ok((staged, dir))
+    }
+
+    /// Arrange for the script's process to switch to this identity just before it starts.
+    /// Groups go first, while there's still the privilege to change them.
+    pub(crate) fn install(&self, command: &mut std::process::Command) {
+        use std::os::unix::process::CommandExt;
+        let (uid, gid, groups) = (self.uid, self.gid, self.groups.clone());
+        unsafe {
+            command.pre_exec(move || {
+                if libc::setgroups(groups.len() as _, groups.as_ptr()) != 0 || libc::setgid(gid) != 0 || libc::setuid(uid) != 0 {
+                    return Err(std::io::Error::last_os_error())
+                }
+                Ok(())
+            });
+        }
+    }
+}
+
+struct Passwd {
+    uid: libc::uid_t,
+    gid: Option<libc::gid_t>,
+    name: Option<String>,
+    home: Option<PathBuf>,
+}
+
+fn lookup_user(user: &str) -> Result<Passwd> {
+    let entry = match user.parse::<libc::uid_t>() {
+        Ok(uid) => unsafe { libc::getpwuid(uid) },
+        Err(_) => unsafe { libc::getpwnam(CString::new(user)?.as_ptr()) },
+    };
+    match unsafe { entry.as_ref() } {
+        Some(entry) => Ok(Passwd {
+            uid: entry.pw_uid,
+            gid: Some(entry.pw_gid),
+            name: Some(unsafe { CStr::from_ptr(entry.pw_name) }.to_string_lossy().into_owned()),
+            home: Some(PathBuf::from(unsafe { CStr::from_ptr(entry.pw_dir) }.to_string_lossy().into_owned())),
+        }),
+        // a bare uid with no account still works, as long as a group is given too
+        None => match user.parse::<libc::uid_t>() {
+            Ok(uid) => Ok(Passwd { uid, gid: None, name: None, home: None }),
+            Err(_) => Err(eyre::eyre!("There's no user named \"{}\"", user)),
+        },
+    }
+}
+
+fn lookup_group(group: &str) -> Result<libc::gid_t> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid)
+    }
+    match unsafe { libc::getgrnam(CString::new(group)?.as_ptr()).as_ref() } {
+        Some(entry) => Ok(entry.gr_gid),
+        None => Err(eyre::eyre!("There's no group named \"{}\"", group)),
+    }
+}
+
+fn group_list(name: &str, gid: libc::gid_t) -> Result<Vec<libc::gid_t>> {
+    let name = CString::new(name)?;
+    let mut groups: Vec<libc::gid_t> = vec![0; 64];
+    loop {
+        let mut count = groups.len() as libc::c_int;
+        let found = unsafe { libc::getgrouplist(name.as_ptr(), gid as _, groups.as_mut_ptr() as *mut _, &mut count) };
+        if found >= 0 {
+            groups.truncate(count as usize);
+            return Ok(groups)
+        }
+        groups.resize(count.max(groups.len() as libc::c_int * 2) as usize, 0);
+    }
+}
+
+/// Refuse to use a cache that someone other than root could write to while running as
+/// root: they could swap in whatever binary they liked, and root would run it.
+pub(crate) fn check_cache(cache: &Path) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    if unsafe { libc::geteuid() } != 0 {
+        return Ok(())
+    }
+    // directories that don't exist yet will be created by root; the rest must belong to it
+    let absolute = crate::absolute(cache);
+    for dir in absolute.ancestors().filter(|dir| dir.exists()) {
+        let metadata = std::fs::metadata(dir)?;
+        let writable_by_others = metadata.mode() & 0o022 != 0 && metadata.mode() & 0o1000 == 0;
+        if metadata.uid() != 0 || writable_by_others {
+            return Err(eyre::eyre!(
+                "Refusing to run as root from the cache at {}: {} can be written to by users other than root. \
+                 Point --cache-dir (or CLAWBANG_DIR) at a directory only root can write to.",
+                cache.display(), dir.display()
+            ))
+        }
+    }
+    Ok(())
+}
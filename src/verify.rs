@@ -0,0 +1,119 @@
+use crate::errors;
+use crate::store::CacheStore;
+use eyre::Result;
+use sha2::{ Digest, Sha256 };
+use std::path::{ Path, PathBuf };
+
+/// What a good build was made from and what it made: the SHA-256 of the lockfile it
+/// resolved, and of its binary. They're kept in the cache's `digests/`, by cache key, for
+/// `clawbang verify` to hold the cache to.
+pub(crate) struct Digests {
+    lockfile: String,
+    binary: String,
+}
+
+impl Digests {
+    pub(crate) fn of(lockfile: &Path, binary: &Path) -> std::io::Result<Self> {
+        Ok(Digests { lockfile: digest(lockfile)?, binary: digest(binary)? })
+    }
+
+    pub(crate) fn record(&self, cache: &Path, cache_key: &str) -> std::io::Result<()> {
+        crate::index::record(&entry(cache, cache_key), &format!("lockfile {}\nbinary {}\n", self.lockfile, self.binary))
+    }
+
+    fn recorded(cache: &Path, cache_key: &str) -> Option<Self> {
+        let recorded = std::fs::read_to_string(entry(cache, cache_key)).ok()?;
+        let mut lines = recorded.lines().filter_map(|line| line.split_once(' '));
+        match (lines.next()?, lines.next()?) {
+            (("lockfile", lockfile), ("binary", binary)) => Some(Digests { lockfile: lockfile.to_string(), binary: binary.to_string() }),
+            _ => None,
+        }
+    }
+}
+
+/// What `clawbang verify` checks a script's cache entry for.
+pub(crate) struct Entry<'a> {
+    pub(crate) script_name: &'a str,
+    pub(crate) cache_key: &'a str,
+    // the lockfile the next build with these dependencies would start from
+    pub(crate) lockfile: PathBuf,
+    // where the binary is unpacked to, to run from
+    pub(crate) unpacked: PathBuf,
+    // under --rebuild, how to build the script again from scratch
+    pub(crate) rebuild: Option<&'a dyn Fn() -> Result<Rebuilt>>,
+}
+
+/// The script built again, in a project of its own that's removed once this is dropped.
+pub(crate) struct Rebuilt {
+    pub(crate) binary: PathBuf,
+    pub(crate) project: tempfile::TempDir,
+}
+
+/// `clawbang verify`: check that the cache's build of the script, as it is now, is the
+/// binary that was built from it, and that the lockfile it was built with is still the one
+/// kept for its dependencies, against the digests recorded when it was built. With
+/// `--rebuild`, also check that building it again from that lockfile makes the same binary.
+pub(crate) fn check(cache: &Path, store: &dyn CacheStore, entry: Entry) -> Result<()> {
+    let Entry { script_name, cache_key, lockfile, unpacked, rebuild } = entry;
+    match store.metadata(cache_key)? {
+        Some(build) if build.exit_code == 0 => {},
+        Some(_) => return Err(eyre::eyre!("{}'s last build (cache key {}) failed, so there's no binary to verify", script_name, &cache_key[..12])),
+        None => return Err(eyre::eyre!("The cache has no build of {} as it is now (cache key {}), so there's nothing to verify", script_name, &cache_key[..12])),
+    }
+    let recorded = Digests::recorded(cache, cache_key).ok_or_else(|| eyre::eyre!(
+        "{}'s build (cache key {}) has no digests recorded, having been built by an older clawbang; rebuild it with --rebuild to record them",
+        script_name, &cache_key[..12]
+    ))?;
+
+    let mut problems = Vec::new();
+    match digest(&lockfile) {
+        Ok(found) if found == recorded.lockfile => {},
+        Ok(_) => problems.push(format!("the lockfile kept for its dependencies ({}) isn't the one it was built with", lockfile.display())),
+        Err(err) => problems.push(format!("the lockfile it was built with can't be read from {}: {}", lockfile.display(), err)),
+    }
+    // another lockfile would make another binary, which wouldn't say anything
+    let rebuild = rebuild.filter(|_| problems.is_empty());
+    // the store's copy is what later runs unpack, and the unpacked one is what runs now
+    let copy = tempfile::NamedTempFile::new()?.into_temp_path();
+    match store.read_artifact(cache_key, &copy).and_then(|_| Ok(digest(&copy)?)) {
+        Ok(found) if found == recorded.binary => {},
+        Ok(_) => problems.push("the binary in the cache's store isn't the one that was built".to_string()),
+        Err(err) => problems.push(format!("the binary in the cache's store can't be read: {}", err)),
+    }
+    if unpacked.exists() && digest(&unpacked).ok().as_deref() != Some(recorded.binary.as_str()) {
+        problems.push(format!("the binary unpacked to {} isn't the one that was built", unpacked.display()));
+    }
+    if let Some(rebuild) = rebuild {
+        let Rebuilt { binary, project: _project } = rebuild()?;
+        let rebuilt = digest(&binary)?;
+        if rebuilt != recorded.binary {
+            problems.push(format!(
+                "built again from its source and lockfile, it makes a different binary ({}, not {}): either its build isn't reproducible, or the cached binary wasn't built from this source",
+                &rebuilt[..12], &recorded.binary[..12]
+            ));
+        }
+    }
+
+    if !problems.is_empty() {
+        return Err(errors::CACHE.error(format!(
+            "{}'s build (cache key {}) doesn't match what was recorded when it was built:\n  {}",
+            script_name, &cache_key[..12], problems.join("\n  ")
+        )))
+    }
+    println!(
+        "{} matches its build (cache key {}): lockfile {}, binary {}{}",
+        script_name, &cache_key[..12], &recorded.lockfile[..12], &recorded.binary[..12],
+        if rebuild.is_some() { ", which building it again reproduces" } else { "" }
+    );
+    Ok(())
+}
+
+fn entry(cache: &Path, cache_key: &str) -> PathBuf {
+    cache.join("digests").join(cache_key)
+}
+
+fn digest(path: &Path) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
@@ -0,0 +1,206 @@
+use crate::{ Build, Launch, Options };
+use eyre::Result;
+use std::fs::File;
+use std::io::Read;
+use std::path::{ Path, PathBuf };
+use std::process::Child;
+use std::sync::mpsc;
+use std::time::{ Duration, Instant, SystemTime };
+
+// How often the watched files are checked for changes.
+const POLL: Duration = Duration::from_millis(250);
+
+// How long a new version gets to report that it's ready before the old one is stopped anyway.
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A version of the script that's running, along with the build it runs from.
+struct Running {
+    child: Child,
+    launch: Launch,
+}
+
+/// `--watch`: build and start the script, then rebuild and restart it each time one of its
+/// files changes, until clawbang is told to stop. A failed build leaves the previous
+/// version running.
+pub(crate) fn run(opts: &Options, config: &crate::config::Config) -> Result<()> {
+    let file = match (&opts.eval, &opts.file) {
+        (None, Some(file)) if file != Path::new("-") && !is_remote(file) => file.clone(),
+        _ => return Err(eyre::eyre!("--watch needs a script file or directory on this machine")),
+    };
+    if opts.hot && !cfg!(unix) {
+        return Err(eyre::eyre!("--hot is only supported on Unix"))
+    }
+
+    let mut watched = vec![file.clone()];
+    let mut running: Option<Running> = None;
+    loop {
+        let mut before = snapshot(&watched);
+        match crate::prepare(opts, config) {
+            Ok(Build::Ready(launch)) => {
+                if launch.watched != watched {
+                    watched = launch.watched.clone();
+                    before = snapshot(&watched);
+                }
+                running = start(*launch, running.take(), opts)?;
+            },
+            Ok(Build::Failed { .. }) => eprintln!("{}: the build failed; waiting for changes", file.display()),
+            Err(err) => eprintln!("{} {}", crate::color::label("error"), err),
+        }
+
+        wait_for_change(&watched, &before, &mut running)?;
+        eprintln!("{} changed; rebuilding", file.display());
+    }
+}
+
+pub(crate) fn is_remote(file: &Path) -> bool {
+    file.to_str().map(|file| crate::remote::is_url(file) || crate::git::Reference::parse(file).is_some()).unwrap_or(false)
+}
+
+// The modification times of the watched files; a file that's gone counts as a change too.
+pub(crate) fn snapshot(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths.iter().map(|path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()).collect()
+}
+
+fn wait_for_change(watched: &[PathBuf], before: &[Option<SystemTime>], running: &mut Option<Running>) -> Result<()> {
+    loop {
+        let deadline = Instant::now() + POLL;
+        while Instant::now() < deadline && !crate::stop_requested() {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        if crate::stop_requested() {
+            shutdown(running.take())
+        }
+
+        if let Some(current) = running {
+            if let Some(status) = current.child.try_wait()? {
+                // the signal may have arrived, and been passed on, since we last looked
+                if crate::stop_requested() {
+                    crate::exit_like(status)
+                }
+                eprintln!("{} {}; waiting for changes", current.launch.script_name, crate::describe_exit(status));
+                *running = None;
+                #[cfg(unix)]
+                crate::signals::forward_to(0);
+            }
+        }
+        if snapshot(watched) != before {
+            return Ok(())
+        }
+    }
+}
+
+// Swap the new build in for the old one. Without --hot, the old version is stopped first;
+// with it, the old version keeps running until the new one is ready to take over.
+fn start(launch: Launch, old: Option<Running>, opts: &Options) -> Result<Option<Running>> {
+    match old {
+        Some(old) if opts.hot => hot_swap(launch, old, opts),
+        old => {
+            if let Some(mut old) = old {
+                crate::stop(&mut old.child)?;
+            }
+            Ok(Some(spawn(launch, opts)?.0))
+        },
+    }
+}
+
+fn hot_swap(launch: Launch, mut old: Running, opts: &Options) -> Result<Option<Running>> {
+    let (mut new, ready) = spawn(launch, opts)?;
+    let ready = ready.expect("--hot always sets up a readiness pipe");
+    let deadline = Instant::now() + READY_TIMEOUT;
+    loop {
+        if crate::stop_requested() {
+            crate::stop(&mut new.child)?;
+            shutdown(Some(old))
+        }
+        if let Some(status) = new.child.try_wait()? {
+            eprintln!("{} {} before it was ready; the previous version is still running", new.launch.script_name, crate::describe_exit(status));
+            #[cfg(unix)]
+            crate::signals::forward_to(old.child.id());
+            return Ok(Some(old))
+        }
+        match ready.recv_timeout(Duration::from_millis(20)) {
+            Ok(true) => break,
+            // closing the descriptor without writing to it counts, once we know it didn't
+            // close because the script exited
+            Ok(false) | Err(mpsc::RecvTimeoutError::Disconnected) => {
+                std::thread::sleep(Duration::from_millis(50));
+                if new.child.try_wait()?.is_none() {
+                    break
+                }
+            },
+            Err(mpsc::RecvTimeoutError::Timeout) if Instant::now() >= deadline => {
+                eprintln!("{} {} didn't report that it was ready within {:?}; stopping the previous version anyway", crate::color::label("warning"), new.launch.script_name, READY_TIMEOUT);
+                break
+            },
+            Err(mpsc::RecvTimeoutError::Timeout) => {},
+        }
+    }
+
+    crate::stop(&mut old.child)?;
+    Ok(Some(new))
+}
+
+// Start a version of the script. Under --hot it's given a pipe to report readiness on;
+// the receiver hears `true` once it writes to it, or `false` if it closes it first.
+fn spawn(mut launch: Launch, opts: &Options) -> Result<(Running, Option<mpsc::Receiver<bool>>)> {
+    let pipe = match opts.hot {
+        true => Some(readiness_pipe(&mut launch.command, opts.ready_fd)?),
+        false => None,
+    };
+    let child = launch.command.spawn()?;
+    #[cfg(unix)]
+    crate::signals::forward_to(child.id());
+
+    let ready = pipe.map(|(mut read, write)| {
+        drop(write);
+        let (ready, waiting) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut byte = [0u8; 1];
+            let _ = ready.send(matches!(read.read(&mut byte), Ok(1)));
+        });
+        waiting
+    });
+    Ok((Running { child, launch }, ready))
+}
+
+// The write end is left open across exec, at `fd` if one was asked for, and its number is
+// passed along in CLAWBANG_READY_FD.
+#[cfg(unix)]
+fn readiness_pipe(command: &mut std::process::Command, fd: Option<i32>) -> Result<(File, File)> {
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::process::CommandExt;
+    let (read, write) = crate::daemon::pipe()?;
+    let source = write.as_raw_fd();
+    let target = fd.unwrap_or(source);
+    command.env("CLAWBANG_READY_FD", target.to_string());
+    unsafe {
+        command.pre_exec(move || {
+            let placed = match target == source {
+                true => libc::fcntl(source, libc::F_SETFD, 0),
+                false => libc::dup2(source, target),
+            };
+            if placed < 0 {
+                return Err(std::io::Error::last_os_error())
+            }
+            Ok(())
+        });
+    }
+    Ok((read, write))
+}
+
+#[cfg(not(unix))]
+fn readiness_pipe(_command: &mut std::process::Command, _fd: Option<i32>) -> Result<(File, File)> {
+    Err(eyre::eyre!("--hot is only supported on Unix"))
+}
+
+// Stop whatever is running and exit the way it did.
+fn shutdown(running: Option<Running>) -> ! {
+    let status = running.and_then(|mut running| {
+        crate::stop(&mut running.child).ok()?;
+        running.child.wait().ok()
+    });
+    match status {
+        Some(status) => crate::exit_like(status),
+        None => std::process::exit(0),
+    }
+}
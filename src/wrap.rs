@@ -0,0 +1,53 @@
+/// How a script body that isn't a whole program is made into one: given with `-e`, it
+/// becomes the body of `main`; with `-n` or `-p`, the body of a loop over stdin's lines.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Wrap {
+    Main,
+    Lines,
+    PrintLines,
+}
+
+impl Wrap {
+    pub(crate) fn choose(lines: bool, print_lines: bool, eval: bool, body: &str) -> Option<Wrap> {
+        if print_lines {
+            Some(Wrap::PrintLines)
+        } else if lines {
+            Some(Wrap::Lines)
+        } else if eval && !body.contains("fn main(") {
+            Some(Wrap::Main)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Wrap::Main => "main",
+            Wrap::Lines => "lines",
+            Wrap::PrintLines => "print-lines",
+        }
+    }
+
+    /// Wrap the last `body_len` bytes of `main_rs`, which are the script body. The opening
+    /// goes on the body's first line, so rustc's line numbers still match the script.
+    pub(crate) fn apply(self, main_rs: &mut String, body_len: usize) {
+        let open = match self {
+            Wrap::Main => "fn main() -> Result<(), Box<dyn std::error::Error>> { ",
+            Wrap::Lines | Wrap::PrintLines => concat!(
+                "fn main() -> Result<(), Box<dyn std::error::Error>> { ",
+                "for line in std::io::BufRead::lines(std::io::stdin().lock()) { ",
+                "let line = line?; #[allow(unused_variables)] let line: &str = &line; ",
+            ),
+        };
+        let body_start = main_rs.len() - body_len;
+        let ended = main_rs.trim_end().ends_with([';', '}']);
+        main_rs.insert_str(body_start, open);
+
+        main_rs.push_str(if ended { "\n" } else { "\n;\n" });
+        main_rs.push_str(match self {
+            Wrap::Main => "Ok(())\n}\n",
+            Wrap::Lines => "}\nOk(())\n}\n",
+            Wrap::PrintLines => "println!(\"{}\", line);\n}\nOk(())\n}\n",
+        });
+    }
+}